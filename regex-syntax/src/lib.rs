@@ -138,6 +138,14 @@ pub enum Expr {
         /// least).
         greedy: bool,
     },
+    /// A backreference to a named capture group, e.g., `(?P=name)` or
+    /// `\k<name>`. It matches the same text the group last matched.
+    Backref {
+        /// The name of the group, which comes before the backreference.
+        name: String,
+        /// Whether to match case insensitively.
+        casei: bool,
+    },
     /// A concatenation of expressions. Must be matched one after the other.
     ///
     /// N.B. A concat expression can only appear at the top-level or
@@ -223,12 +231,93 @@ pub struct ClassRange {
     pub end: char,
 }
 
+/// A builder for parsing regular expressions with flags set in advance.
+///
+/// Parsing with a flag set is the same as parsing the expression prefixed
+/// with the corresponding inline flag (e.g., `(?i)` for
+/// `case_insensitive`), except that error positions still refer to the
+/// expression as given. Inline flags in the expression can still clear any
+/// of them.
+///
+/// # Example
+///
+/// ```rust
+/// use regex_syntax::{Expr, ExprBuilder};
+///
+/// let expr = ExprBuilder::new().case_insensitive(true).parse("a").unwrap();
+/// assert_eq!(expr, Expr::parse("(?i)a").unwrap());
+/// ```
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ExprBuilder {
+    flags: parser::Flags,
+}
+
+impl ExprBuilder {
+    /// Creates a new builder with every flag cleared.
+    pub fn new() -> ExprBuilder {
+        ExprBuilder::default()
+    }
+
+    /// Sets the case insensitive flag (`i`).
+    pub fn case_insensitive(mut self, yes: bool) -> ExprBuilder {
+        self.flags.casei = yes;
+        self
+    }
+
+    /// Sets the multi-line flag (`m`).
+    pub fn multi_line(mut self, yes: bool) -> ExprBuilder {
+        self.flags.multi = yes;
+        self
+    }
+
+    /// Sets the flag that lets `.` match `\n` (`s`).
+    pub fn dot_matches_new_line(mut self, yes: bool) -> ExprBuilder {
+        self.flags.dotnl = yes;
+        self
+    }
+
+    /// Sets the flag that swaps the meaning of greedy and lazy repetition
+    /// operators (`U`).
+    pub fn swap_greed(mut self, yes: bool) -> ExprBuilder {
+        self.flags.swap_greed = yes;
+        self
+    }
+
+    /// Sets the flag that ignores whitespace and allows comments (`x`).
+    pub fn ignore_whitespace(mut self, yes: bool) -> ExprBuilder {
+        self.flags.ignore_space = yes;
+        self
+    }
+
+    /// Parses a string in a regular expression syntax tree with the flags
+    /// set so far.
+    pub fn parse(self, s: &str) -> Result<Expr> {
+        parser::Parser::parse_with_flags(s, self.flags).map(|e| e.simplify())
+    }
+}
+
 impl Expr {
     /// Parses a string in a regular expression syntax tree.
     pub fn parse(s: &str) -> Result<Expr> {
         parser::Parser::parse(s).map(|e| e.simplify())
     }
 
+    /// Parses a string and returns where each capture group is in it.
+    ///
+    /// A group's span is the byte range from its opening parenthesis to
+    /// (and including) its closing one. The spans are ordered by capture
+    /// index, so the span of group `i` is at index `i - 1`.
+    ///
+    /// ```rust
+    /// use regex_syntax::Expr;
+    ///
+    /// let spans = Expr::capture_spans(r"(a(?:b)(?P<c>c))").unwrap();
+    /// assert_eq!(spans, vec![(0, 16), (7, 15)]);
+    /// ```
+    pub fn capture_spans(s: &str) -> Result<Vec<(usize, usize)>> {
+        parser::Parser::parse_spans(s)
+    }
+
     /// Returns true iff the expression can be repeated by a quantifier.
     fn can_repeat(&self) -> bool {
         match *self {
@@ -239,6 +328,7 @@ impl Expr {
             | StartLine | EndLine | StartText | EndText
             | WordBoundary | NotWordBoundary
             | Group{..}
+            | Backref{..}
             => true,
             _ => false,
         }
@@ -338,8 +428,9 @@ impl CharClass {
     }
 
     /// Merge two classes and canonicalize them.
-    #[cfg(test)]
-    fn merge(mut self, other: CharClass) -> CharClass {
+    ///
+    /// The result matches every character matched by either class.
+    pub fn merge(mut self, other: CharClass) -> CharClass {
         self.ranges.extend(other);
         self.canonicalize()
     }
@@ -565,6 +656,12 @@ impl fmt::Display for Expr {
             Group { ref e, name: Some(ref n), .. } => {
                 write!(f, "(?P<{}>{})", n, e)
             }
+            Backref { ref name, casei } => {
+                if casei { try!(write!(f, "(?i:")); }
+                try!(write!(f, "(?P={})", name));
+                if casei { try!(write!(f, ")")); }
+                Ok(())
+            }
             Repeat { ref e, r, greedy } => {
                 match &**e {
                     &Literal { ref chars, .. } if chars.len() > 1 => {
@@ -706,6 +803,9 @@ pub enum ErrorKind {
     UnexpectedFlagEof,
     /// Saw end of regex before two hexadecimal digits were seen. e.g., `\xA`.
     UnexpectedTwoDigitHexEof,
+    /// A backreference to a capture name that isn't defined before it.
+    /// e.g., `(?P=a)(?P<a>b)`.
+    UnknownCaptureName(String),
     /// Unopened parenthesis. e.g., `)`.
     UnopenedParen,
     /// Unrecognized escape sequence. e.g., `\q`.
@@ -768,6 +868,7 @@ impl ErrorKind {
             UnexpectedEscapeEof => "unexpected EOF in escape sequence",
             UnexpectedFlagEof => "unexpected EOF in flags",
             UnexpectedTwoDigitHexEof => "unexpected EOF in hex literal",
+            UnknownCaptureName(_) => "unknown capture name",
             UnopenedParen => "unopened parenthesis",
             UnrecognizedEscape(_) => "unrecognized escape sequence",
             UnrecognizedFlag(_) => "unrecognized flag",
@@ -857,6 +958,9 @@ impl fmt::Display for ErrorKind {
                            of the regex (missing a ')' or ':')."),
             UnexpectedTwoDigitHexEof =>
                 write!(f, "Unexpected end of two digit hexadecimal literal."),
+            UnknownCaptureName(ref s) =>
+                write!(f, "Backreference to capture name '{}', which isn't \
+                           defined before it.", s),
             UnopenedParen =>
                 write!(f, "Unopened parenthesis."),
             UnrecognizedEscape(c) =>
@@ -978,6 +1082,23 @@ mod tests {
 
     fn classi(ranges: &[(char, char)]) -> CharClass { class(ranges) }
 
+    #[test]
+    fn expr_builder_flags() {
+        use {Expr, ExprBuilder};
+
+        let b = ExprBuilder::new();
+        assert_eq!(b.multi_line(true).dot_matches_new_line(true)
+                    .parse("^.").unwrap(),
+                   Expr::parse("(?ms)^.").unwrap());
+        assert_eq!(b.swap_greed(true).ignore_whitespace(true)
+                    .parse("a* # b").unwrap(),
+                   Expr::parse("(?Ux)a* # b").unwrap());
+        assert_eq!(b.case_insensitive(true).parse("a(?-i)b").unwrap(),
+                   Expr::parse("(?i)a(?-i)b").unwrap());
+        let err = b.case_insensitive(true).parse("a(").unwrap_err();
+        assert_eq!(err.position(), 1);
+    }
+
     #[test]
     fn class_canon_no_change() {
         let cls = class(&[('a', 'c'), ('x', 'z')]);
@@ -30,6 +30,7 @@ pub struct Parser {
     stack: Vec<Build>,
     caps: usize,
     names: Vec<String>, // to check for duplicates
+    spans: Vec<(usize, usize)>, // char offsets, by capture index minus 1
     flags: Flags,
 }
 
@@ -51,39 +52,54 @@ enum Build {
 }
 
 /// Flag state.
-#[derive(Clone, Copy, Debug)]
-struct Flags {
-    casei: bool,
-    multi: bool,
-    dotnl: bool,
-    swap_greed: bool,
-    ignore_space: bool,
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Flags {
+    pub casei: bool,
+    pub multi: bool,
+    pub dotnl: bool,
+    pub swap_greed: bool,
+    pub ignore_space: bool,
 }
 
 // Primary expression parsing routines.
 impl Parser {
     pub fn parse(s: &str) -> Result<Expr> {
+        Parser::parse_with_flags(s, Flags::default())
+    }
+
+    /// Parses `s` with the given flags set at the start, as if by `(?flags)`.
+    pub fn parse_with_flags(s: &str, flags: Flags) -> Result<Expr> {
+        Parser::new(s, flags).parse_expr()
+    }
+
+    /// Parses `s` and returns the byte span of every capture group in it,
+    /// from its opening parenthesis to its closing one, ordered by capture
+    /// index (starting at `1`).
+    pub fn parse_spans(s: &str) -> Result<Vec<(usize, usize)>> {
+        let mut p = Parser::new(s, Flags::default());
+        try!(p.parse_expr());
+        let offsets: Vec<usize> =
+            s.char_indices().map(|(i, _)| i).chain(Some(s.len())).collect();
+        Ok(p.spans.iter().map(|&(s, e)| (offsets[s], offsets[e])).collect())
+    }
+
+    fn new(s: &str, flags: Flags) -> Parser {
         Parser {
             chars: s.chars().collect(),
             chari: 0,
             stack: vec![],
             caps: 0,
             names: vec![],
-            flags: Flags {
-                casei: false,
-                multi: false,
-                dotnl: false,
-                swap_greed: false,
-                ignore_space: false,
-            },
-        }.parse_expr()
+            spans: vec![],
+            flags: flags,
+        }
     }
 
     // Top-level expression parser.
     //
     // Starts at the beginning of the input and consumes until either the end
     // of input or an error.
-    fn parse_expr(mut self) -> Result<Expr> {
+    fn parse_expr(&mut self) -> Result<Expr> {
         while !self.eof() {
             let build_expr = match self.cur() {
                 '\\' => try!(self.parse_escape()),
@@ -152,6 +168,9 @@ impl Parser {
                 casei: self.flags.casei,
             }));
         }
+        if self.bump_if("k<") {
+            return self.parse_backref('>');
+        }
 
         fn lit(c: char) -> Build {
             Build::Expr(Expr::Literal { chars: vec![c], casei: false })
@@ -192,16 +211,18 @@ impl Parser {
     // Start: `(`
     // End:   `a`
     fn parse_group(&mut self) -> Result<Build> {
-        let chari = self.chari;
+        let chari = self.cur_chari();
         let mut name: CaptureName = None;
         self.bump();
         if self.bump_if("?P<") {
-            let n = try!(self.parse_group_name());
+            let n = try!(self.parse_group_name('>'));
             if self.names.iter().any(|n2| n2 == &n) {
                 return Err(self.err(ErrorKind::DuplicateCaptureName(n)));
             }
             self.names.push(n.clone());
             name = Some(n);
+        } else if self.bump_if("?P=") {
+            return self.parse_backref(')');
         } else if self.bump_if("?") {
             // This can never be capturing. It's either setting flags for
             // the current group, or it's opening a non-capturing group or
@@ -294,14 +315,19 @@ impl Parser {
     //
     // Start: `f`
     // End:   `a`
-    fn parse_group_name(&mut self) -> Result<String> {
+    fn parse_group_name(&mut self, close: char) -> Result<String> {
         let mut name = String::new();
-        while !self.eof() && !self.peek_is('>') {
+        while !self.eof() && !self.peek_is(close) {
             name.push(self.bump());
         }
         if self.eof() {
             // e.g., (?P<a
-            return Err(self.err(ErrorKind::UnclosedCaptureName(name)));
+            return Err(self.err(if close == '>' {
+                ErrorKind::UnclosedCaptureName(name)
+            } else {
+                // e.g., (?P=a
+                ErrorKind::UnclosedParen
+            }));
         }
         let all_valid = name.chars().all(is_valid_capture_char);
         match name.chars().next() {
@@ -313,12 +339,28 @@ impl Parser {
                 Err(self.err(ErrorKind::InvalidCaptureName(name)))
             }
             _ => {
-                self.bump(); // for `>`
+                self.bump(); // for `>` (or `)`)
                 Ok(name)
             }
         }
     }
 
+    // Parses a backreference to a named group, e.g., `(?P=foo)` or
+    // `\k<foo>`. The group must come before it.
+    //
+    // Start: `f`
+    // End:   `)` (or `>`)
+    fn parse_backref(&mut self, close: char) -> Result<Build> {
+        let name = try!(self.parse_group_name(close));
+        if !self.names.iter().any(|n| n == &name) {
+            return Err(self.err(ErrorKind::UnknownCaptureName(name)));
+        }
+        Ok(Build::Expr(Expr::Backref {
+            name: name,
+            casei: self.flags.casei,
+        }))
+    }
+
     // Parses a counted repeition operator, e.g., `a{2,4}?z`.
     //
     // Start: `{`
@@ -694,6 +736,12 @@ impl Parser {
 
     fn cur(&self) -> char { self.chars().next().unwrap() }
 
+    // The offset of the current character, past any whitespace and comments
+    // that are ignored before it.
+    fn cur_chari(&self) -> usize {
+        self.chari + self.chars().next_count() - 1
+    }
+
     fn eof(&self) -> bool { self.chars().next().is_none() }
 
     fn bump_get<B: Bumpable>(&mut self, s: B) -> Option<String> {
@@ -903,11 +951,12 @@ impl Parser {
             match self.stack.pop() {
                 // e.g., )
                 None => return Err(self.err(ErrorKind::UnopenedParen)),
-                Some(Build::LeftParen { i, name, old_flags, .. }) => {
+                Some(Build::LeftParen { i, name, chari, old_flags }) => {
                     if concat.is_empty() {
                         // e.g., ()
                         return Err(self.err(ErrorKind::EmptyGroup));
                     }
+                    self.close_span(i, chari);
                     return Ok((old_flags, Build::Expr(Expr::Group {
                         e: Box::new(rev_concat(concat)),
                         i: i,
@@ -924,7 +973,10 @@ impl Parser {
                         // e.g., a|b)
                         None => return Err(self.err(ErrorKind::UnopenedParen)),
                         Some(Build::Expr(_)) => unreachable!(),
-                        Some(Build::LeftParen { i, name, old_flags, .. }) => {
+                        Some(Build::LeftParen {
+                            i, name, chari, old_flags,
+                        }) => {
+                            self.close_span(i, chari);
                             return Ok((old_flags, Build::Expr(Expr::Group {
                                 e: Box::new(Expr::Alternate(es)),
                                 i: i,
@@ -938,6 +990,17 @@ impl Parser {
         }
     }
 
+    // Records the span of a capture group, which opens at `chari` and closes
+    // at the current position.
+    fn close_span(&mut self, i: CaptureIndex, chari: usize) {
+        if let Some(i) = i {
+            if self.spans.len() < i {
+                self.spans.resize(i, (0, 0));
+            }
+            self.spans[i - 1] = (chari, self.cur_chari() + 1);
+        }
+    }
+
     // Called only when the parser reaches the end of input.
     //
     // This pops the expression stack until:
@@ -1161,6 +1224,17 @@ mod tests {
         cls.canonicalize()
     }
 
+    #[test]
+    fn capture_spans() {
+        let spans = |s| Parser::parse_spans(s).unwrap();
+        assert_eq!(spans("a"), vec![]);
+        assert_eq!(spans("(a)(?:b)(c|d)"), vec![(0, 3), (8, 13)]);
+        assert_eq!(spans("((a)b)"), vec![(0, 6), (1, 4)]);
+        assert_eq!(spans("(?P<x>☃)"), vec![(0, 10)]);
+        assert_eq!(spans(r"\((a\))[(]"), vec![(2, 7)]);
+        assert_eq!(spans("(?x) # (\n (a)"), vec![(10, 13)]);
+    }
+
     #[test]
     fn empty() {
         assert_eq!(p(""), Expr::Empty);
@@ -1420,6 +1494,23 @@ mod tests {
         });
     }
 
+    #[test]
+    fn backref() {
+        let group = Expr::Group {
+            e: b(lit('a')),
+            i: Some(1),
+            name: Some("x".into()),
+        };
+        let backref = Expr::Backref { name: "x".into(), casei: false };
+        assert_eq!(p("(?P<x>a)(?P=x)"), c(&[group.clone(), backref.clone()]));
+        assert_eq!(p(r"(?P<x>a)\k<x>"), c(&[group.clone(), backref]));
+        assert_eq!(p(r"(?P<x>a)(?i)\k<x>+"), c(&[group, Expr::Repeat {
+            e: b(Expr::Backref { name: "x".into(), casei: true }),
+            r: Repeater::OneOrMore,
+            greedy: true,
+        }]));
+    }
+
     #[test]
     fn group_no_capture() {
         assert_eq!(p("(?:a)"), Expr::Group {
@@ -2149,6 +2240,18 @@ mod tests {
         test_err!("(?P<>a)", 4, ErrorKind::EmptyCaptureName);
     }
 
+    #[test]
+    fn error_backref() {
+        test_err!("(a)(?P=a)", 9, ErrorKind::UnknownCaptureName("a".into()));
+        test_err!(r"\k<a>(?P<a>b)", 5,
+                  ErrorKind::UnknownCaptureName("a".into()));
+        test_err!("(?P<a>b)(?P=a", 13, ErrorKind::UnclosedParen);
+        test_err!(r"(?P<a>b)\k<a", 12,
+                  ErrorKind::UnclosedCaptureName("a".into()));
+        test_err!("(?P<a>b)(?P=)", 12, ErrorKind::EmptyCaptureName);
+        test_err!(r"\k", 1, ErrorKind::UnrecognizedEscape('k'));
+    }
+
     #[test]
     fn error_group_opts_unrecognized_flag() {
         test_err!("(?z:a)", 2, ErrorKind::UnrecognizedFlag('z'));
@@ -136,7 +136,7 @@ impl Arbitrary for Expr {
         let es: Box<Iterator<Item=Expr>> = match *self {
             Empty | AnyChar | AnyCharNoNL
             | StartLine | EndLine | StartText | EndText
-            | WordBoundary | NotWordBoundary => nada(),
+            | WordBoundary | NotWordBoundary | Backref { .. } => nada(),
             Literal { ref chars, .. } if chars.len() == 1 => nada(),
             Literal { ref chars, casei } => {
                 Box::new((chars.clone(), casei)
@@ -138,7 +138,7 @@ fn exec<'t>(
     use regex::internal::{Char, CharInput, InputAt, Input, Inst};
 
     let input = CharInput::new(input);
-    let at = input.at(start);
+    let at = input.decode_at(start);
     return Nfa {
         input: input,
         ncaps: caps.len(),
@@ -172,7 +172,7 @@ fn exec<'t>(
                 if clist.size == 0 || (!$prefix_anchor && !matched) {
                     self.add(clist, &mut caps, 0, at);
                 }
-                let at_next = self.input.at(at.next_pos());
+                let at_next = self.input.decode_at(at.next_pos());
                 for i in 0..clist.size {
                     let pc = clist.pc(i);
                     let tcaps = clist.caps(i);
@@ -321,11 +321,11 @@ fn exec<'t>(
         let arms = self.prog.insts.iter().enumerate().map(|(pc, inst)| {
             let body = match *inst {
                 Inst::EmptyLook(ref inst) => {
-                    let nextpc = inst.goto;
+                    let nextpc = inst.goto as usize;
                     match inst.look {
                         EmptyLook::StartLine => {
                             quote_expr!(self.cx, {
-                                let prev = self.input.previous_at(at.pos());
+                                let prev = self.input.decode_before(at.pos());
                                 if prev.char().is_none() || prev.char() == '\n' {
                                     self.add(nlist, thread_caps, $nextpc, at);
                                 }
@@ -340,7 +340,7 @@ fn exec<'t>(
                         }
                         EmptyLook::StartText => {
                             quote_expr!(self.cx, {
-                                let prev = self.input.previous_at(at.pos());
+                                let prev = self.input.decode_before(at.pos());
                                 if prev.char().is_none() {
                                     self.add(nlist, thread_caps, $nextpc, at);
                                 }
@@ -361,7 +361,7 @@ fn exec<'t>(
                                 quote_expr!(self.cx, { !(w1 ^ w2) })
                             };
                             quote_expr!(self.cx, {
-                                let prev = self.input.previous_at(at.pos());
+                                let prev = self.input.decode_before(at.pos());
                                 let w1 = prev.char().is_word_char();
                                 let w2 = at.char().is_word_char();
                                 if $m {
@@ -372,8 +372,8 @@ fn exec<'t>(
                     }
                 }
                 Inst::Save(ref inst) => {
-                    let nextpc = inst.goto;
-                    let slot = inst.slot;
+                    let nextpc = inst.goto as usize;
+                    let slot = inst.slot as usize;
                     quote_expr!(self.cx, {
                         if $slot >= self.ncaps {
                             self.add(nlist, thread_caps, $nextpc, at);
@@ -386,12 +386,22 @@ fn exec<'t>(
                     })
                 }
                 Inst::Split(ref inst) => {
-                    let (x, y) = (inst.goto1, inst.goto2);
+                    let x = inst.goto1 as usize;
+                    let y = inst.goto2 as usize;
                     quote_expr!(self.cx, {
                         self.add(nlist, thread_caps, $x, at);
                         self.add(nlist, thread_caps, $y, at);
                     })
                 }
+                Inst::SplitMany(ref inst) => {
+                    let adds = inst.gotos.iter().map(|&goto| {
+                        let goto = goto as usize;
+                        quote_stmt!(self.cx,
+                            self.add(nlist, thread_caps, $goto, at);
+                        ).unwrap()
+                    }).collect::<Vec<_>>();
+                    quote_expr!(self.cx, { $adds })
+                }
                 // For Match, Char, Ranges
                 _ => quote_expr!(self.cx, {
                     let mut t = &mut nlist.thread(ti);
@@ -410,14 +420,14 @@ fn exec<'t>(
     fn step_insts(&self) -> P<ast::Expr> {
         let arms = self.prog.insts.iter().enumerate().map(|(pc, inst)| {
             let body = match *inst {
-                Inst::Match => quote_expr!(self.cx, {
+                Inst::Match(_) => quote_expr!(self.cx, {
                     for (slot, val) in caps.iter_mut().zip(thread_caps.iter()) {
                         *slot = *val;
                     }
                     return true;
                 }),
                 Inst::Char(ref inst) => {
-                    let nextpc = inst.goto;
+                    let nextpc = inst.goto as usize;
                     let c = inst.c;
                     quote_expr!(self.cx, {
                         if $c == at.char() {
@@ -427,8 +437,9 @@ fn exec<'t>(
                     })
                 }
                 Inst::Ranges(ref inst) => {
-                    let match_class = self.match_class(&inst.ranges);
-                    let nextpc = inst.goto;
+                    let ranges = inst.ranges(&self.prog.ranges);
+                    let match_class = self.match_class(ranges);
+                    let nextpc = inst.goto as usize;
                     quote_expr!(self.cx, {
                         let mut c = at.char();
                         if let Some(c) = c.as_char() {
@@ -439,7 +450,7 @@ fn exec<'t>(
                         return false;
                     })
                 }
-                // EmptyLook, Save, Jump, Split
+                // EmptyLook, Save, Jump, Split, SplitMany
                 _ => quote_expr!(self.cx, { return false; }),
             };
             self.arm_inst(pc, body)
@@ -14,8 +14,10 @@ extern crate regex;
 
 macro_rules! regex {
     ($re:expr) => {{
-        let e = Some(::regex::internal::MatchEngine::Backtrack);
-        ::regex::Regex::with_engine(e, 10 * (1 << 20), $re).unwrap()
+        ::regex::RegexBuilder::new($re)
+            .engine(::regex::Engine::Backtrack)
+            .compile()
+            .unwrap()
     }}
 }
 
@@ -8,13 +8,53 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use regex::{Regex, NoExpand};
+use regex::{
+    Regex, NoExpand, Numbered, Captures, CaptureError, FromCaptures,
+    ReplaceSet, MatchStep, RegexBuilder, Error, TemplateError, SplitOptions,
+};
 
 #[test]
 fn eq() {
     assert_eq!(regex!(r"[a-z]+"), Regex::new("[a-z]+").unwrap());
 }
 
+#[test]
+fn hash() {
+    use std::collections::HashSet;
+
+    let mut set = HashSet::new();
+    assert!(set.insert(regex!(r"(?i)[a-z]+")));
+    assert!(!set.insert(Regex::new(r"(?i)[a-z]+").unwrap()));
+    assert!(set.insert(Regex::new(r"[a-z]+").unwrap()));
+    assert_eq!(set.len(), 2);
+}
+
+#[test]
+fn new_many() {
+    let res = Regex::new_many(&[r"\d+", r"(?P<w>[a-z]+)", ""]).unwrap();
+    assert_eq!(res.len(), 3);
+    assert_eq!(res[0], Regex::new(r"\d+").unwrap());
+    assert_eq!(res[1].find("12ab"), Some((2, 4)));
+    assert_eq!(res[1].captures("12ab").unwrap().name("w"), Some("ab"));
+    assert_eq!(res[2].find("ab"), Some((0, 0)));
+    assert!(Regex::new_many(&["a", "("]).is_err());
+}
+
+#[test]
+fn group_info() {
+    let re = regex!(r"(?P<a>x)(y)(?P<c>z)?");
+    let groups = re.group_info();
+    assert_eq!(groups.len(), 4);
+    assert_eq!(groups.names(), &[None, Some("a"), None, Some("c")]);
+    assert_eq!(groups.index("c"), Some(3));
+    assert_eq!(groups.index("b"), None);
+    assert_eq!(groups.name(0), None);
+    assert_eq!(groups.name(1), Some("a"));
+    assert_eq!(groups.name(9), None);
+    assert!(groups.contains("a") && groups.contains("3"));
+    assert!(!groups.contains("b") && !groups.contains("4"));
+}
+
 #[test]
 fn splitn() {
     let re = regex!(r"\d+");
@@ -31,6 +71,81 @@ fn split() {
     assert_eq!(subs, vec!("cauchy", "plato", "tyler", "binx"));
 }
 
+#[test]
+fn split_inclusive() {
+    let re = regex!(r"[,;]");
+    let subs: Vec<_> = re.split_inclusive("a,b;;c").collect();
+    assert_eq!(subs, vec![("a", Some(",")), ("b", Some(";")),
+                          ("", Some(";")), ("c", None)]);
+    let subs: Vec<_> = re.split_inclusive("a,").collect();
+    assert_eq!(subs, vec![("a", Some(","))]);
+    assert_eq!(re.split_inclusive("").count(), 0);
+}
+
+#[test]
+fn split_positions() {
+    let re = regex!(r"\d+");
+    let text = "cauchy123plato456tyler789binx";
+    let spans: Vec<_> = re.split_positions(text).collect();
+    assert_eq!(spans, vec![(0, 6), (9, 14), (17, 22), (25, 29)]);
+    let subs: Vec<&str> = spans.iter().map(|&(s, e)| &text[s..e]).collect();
+    assert_eq!(subs, re.split(text).collect::<Vec<_>>());
+    let spans: Vec<_> = re.split_positions("1a22").collect();
+    assert_eq!(spans, vec![(0, 0), (1, 2)]);
+}
+
+#[test]
+fn split_with() {
+    fn split(re: &Regex, text: &str, opts: SplitOptions) -> Vec<String> {
+        re.split_with(text, opts).map(|s| s.to_owned()).collect()
+    }
+    let re = regex!(",");
+    let all = SplitOptions::new();
+    assert_eq!(split(&re, ",a,,b,,", all), vec!["", "a", "", "b", "", ""]);
+    assert_eq!(split(&re, "", all), vec![""]);
+    let trim = all.drop_leading_empty(true).drop_trailing_empty(true);
+    assert_eq!(split(&re, ",a,,b,,", trim), vec!["a", "", "b"]);
+    assert_eq!(split(&re, ",,", trim), Vec::<String>::new());
+    assert_eq!(split(&re, "", trim), Vec::<String>::new());
+    let trailing = all.drop_trailing_empty(true);
+    assert_eq!(split(&re, ",,a", trailing), vec!["", "", "a"]);
+
+    let three = all.max_fields(3);
+    assert_eq!(split(&re, "a,b,c,d", three), vec!["a", "b", "c,d"]);
+    assert_eq!(split(&re, "a,b", three), vec!["a", "b"]);
+    assert_eq!(split(&re, "a,b,", three), vec!["a", "b", ""]);
+    assert_eq!(split(&re, "a,b,c", all.max_fields(1)), vec!["a,b,c"]);
+    // Dropped substrings don't count.
+    let re = regex!(r"\s+");
+    let awk = three.drop_leading_empty(true);
+    assert_eq!(split(&re, "  a b c d", awk), vec!["a", "b", "c d"]);
+}
+
+#[test]
+fn anchored_matches() {
+    let re = regex!(r"a|ab");
+    assert!(re.starts_with("abc"));
+    assert!(!re.starts_with("cab"));
+    assert!(re.ends_with("cab"));
+    assert!(!re.ends_with("abc"));
+    assert!(re.is_full_match("ab"));
+    assert!(!re.is_full_match("abab"));
+
+    let re = regex!(r"\b\w+(?:\.\w+)*");
+    assert!(re.starts_with("foo.bar baz"));
+    assert!(!re.starts_with(" foo"));
+    assert!(re.ends_with("x = foo.bar"));
+    assert!(!re.ends_with("foo.bar."));
+    assert!(re.is_full_match("foo.bar"));
+    assert!(!re.is_full_match("foo bar"));
+
+    let re = regex!(r"x*");
+    assert!(re.is_full_match(""));
+    assert!(re.starts_with("☃"));
+    assert!(re.ends_with("☃"));
+    assert!(!re.is_full_match("☃"));
+}
+
 #[test]
 fn empty_regex_empty_match() {
     let re = regex!("");
@@ -205,6 +320,140 @@ replace!(rep_trim, replace_all, "^[ \t]+|[ \t]+$", " \t  trim me\t   \t",
          "", "trim me");
 replace!(rep_number_hypen, replace, r"(.)(.)", "ab", "$1-$2", "a-b");
 replace!(rep_number_underscore, replace, r"(.)(.)", "ab", "$1_$2", "a_b");
+replace!(rep_conditional, replace_all, r"(a)|b", "ab",
+         "${1:+A:B}", "AB");
+replace!(rep_conditional_no_else, replace_all, r"(?P<x>a)|b", "ab",
+         "${x:+[$x]}", "[a]");
+replace!(rep_conditional_adjacent, replace_all, r"(a)?(b)?", "a",
+         "${1:+1}${2:+2:-}", "1-");
+replace!(rep_conditional_escaped, replace, r"(a)", "a",
+         "$${1:+x}", "${1:+x}");
+replace!(rep_conditional_missing_group, replace, r"(a)", "a",
+         "${9:+x:y}", "y");
+
+#[test]
+fn replace_count() {
+    let re = regex!(r"\d");
+    assert_eq!(re.replace_all_count("age: 26", "Z"), ("age: ZZ".into(), 2));
+    assert_eq!(re.replace_all_count("age: ?", "Z"), ("age: ?".into(), 0));
+    assert_eq!(re.replacen_count("1 2 3", 2, NoExpand("$")),
+               ("$ $ 3".into(), 2));
+    assert_eq!(re.replacen_count("1 2", 5, "<$0>"), ("<1> <2>".into(), 2));
+}
+
+#[test]
+fn replace_numbered() {
+    let re = regex!(r"\[\^(\w+)\]");
+    let notes = re.replace_all(
+        "a[^x] b[^y] c[^z]",
+        Numbered::new(|i, caps: &Captures| {
+            format!("[{}:{}]", i + 1, caps.at(1).unwrap())
+        }));
+    assert_eq!(notes, "a[1:x] b[2:y] c[3:z]");
+    // Only the matches that are replaced are counted.
+    let mut seen = vec![];
+    let result = re.replacen("[^a][^b][^c]", 2,
+                             Numbered::new(|i, _: &Captures| {
+                                 seen.push(i);
+                                 "-".to_owned()
+                             }));
+    assert_eq!(result, "--[^c]");
+    assert_eq!(seen, vec![0, 1]);
+}
+
+#[test]
+fn try_replace_all() {
+    let re = regex!(r"\w+");
+    let upper = |caps: &Captures| {
+        let word = caps.at(0).unwrap();
+        if word == "stop" {
+            Err(caps.pos(0).unwrap())
+        } else if word.len() < 3 {
+            Ok(None)
+        } else {
+            Ok(Some(word.to_uppercase()))
+        }
+    };
+    assert_eq!(re.try_replace_all("a cat is, on a mat.", upper),
+               Ok("a CAT is, on a MAT.".to_owned()));
+    assert_eq!(re.try_replace_all("cat stop mat", upper), Err((4, 8)));
+    assert_eq!(re.try_replace_all("", upper), Ok("".to_owned()));
+}
+
+#[test]
+fn check_template() {
+    let re = regex!(r"(?P<first>\w+) (\w+)?");
+    let groups = re.group_info();
+    assert_eq!(groups.check_template(""), Ok(()));
+    assert_eq!(groups.check_template("$2 $first $0 $$3 ${2:+$1:-}"), Ok(()));
+    assert_eq!(groups.check_template("$3 $last $1"), Err(vec![
+        TemplateError::UnknownGroup { name: "3".to_owned(), pos: 0 },
+        TemplateError::UnknownGroup { name: "last".to_owned(), pos: 3 },
+    ]));
+    // References in the branches of a condition are checked too.
+    assert_eq!(groups.check_template("${x:+$y:$2}"), Err(vec![
+        TemplateError::UnknownGroup { name: "x".to_owned(), pos: 0 },
+        TemplateError::UnknownGroup { name: "y".to_owned(), pos: 5 },
+    ]));
+    assert_eq!(groups.check_template("${first} $"), Err(vec![
+        TemplateError::NotAReference { pos: 0 },
+        TemplateError::NotAReference { pos: 9 },
+    ]));
+}
+
+#[test]
+fn replace_append() {
+    let re = regex!(r"\d");
+    let mut buf = "> ".to_owned();
+    assert_eq!(re.replace_append("age: 26", "Z", &mut buf), 2);
+    assert_eq!(buf, "> age: ZZ");
+    buf.clear();
+    assert_eq!(re.replacen_append("1 2 3", 2, "<$0>", &mut buf), 2);
+    assert_eq!(buf, "<1> <2> 3");
+    assert_eq!(re.replace_append("none", "Z", &mut buf), 0);
+    assert_eq!(buf, "<1> <2> 3none");
+}
+
+#[test]
+fn replace_set_swap() {
+    let mut rules = ReplaceSet::new(vec![("cat", "dog"), ("dog", "cat")])
+                               .unwrap();
+    rules.push(regex!(r"(\d+)"), "<$1>").unwrap();
+    assert_eq!(rules.replace_all("cat 1 dog 22 cat"),
+               "dog <1> cat <22> dog");
+}
+
+#[test]
+fn replace_set_priority() {
+    let mut rules = ReplaceSet::new(vec![("ab", "X"), ("a", "Y"), ("b", "Z")])
+                               .unwrap();
+    assert_eq!(rules.replace_all("abba"), "XZY");
+    // Leftmost wins over priority.
+    let mut rules = ReplaceSet::new(vec![("b", "1"), ("a", "2")]).unwrap();
+    assert_eq!(rules.replace_all("ab"), "21");
+}
+
+#[test]
+fn replace_set_empty_matches() {
+    let mut rules = ReplaceSet::new(vec![("a", "A"), ("x*", "-")]).unwrap();
+    // Like `find_iter`, an empty match right after a match is skipped.
+    assert_eq!(rules.replace_all("bab"), "-bAb-");
+    let mut rules: ReplaceSet = ReplaceSet::new(Vec::<(&str, &str)>::new())
+                                           .unwrap();
+    assert!(rules.is_empty());
+    assert_eq!(rules.replace_all("abc"), "abc");
+}
+
+#[test]
+fn replace_set_like_alternation() {
+    // The rules match like the alternates of a single regex.
+    let mut rules = ReplaceSet::new(vec![("x*", "-"), ("b", "B")]).unwrap();
+    assert_eq!(rules.replace_all("bab"),
+               regex!("x*|b").replace_all("bab", "-"));
+    let mut rules = ReplaceSet::new(vec![(r"(\w)(\d)", "$2$1"), ("b", "B")])
+                               .unwrap();
+    assert_eq!(rules.replace_all("a1 b2 b"), "1a 2b B");
+}
 
 macro_rules! noparse(
     ($name:ident, $re:expr) => (
@@ -250,6 +499,8 @@ noparse!(fail_double_neg, "(?-i-i)");
 noparse!(fail_neg_empty, "(?i-)");
 noparse!(fail_empty_group, "()");
 noparse!(fail_dupe_named, "(?P<a>.)(?P<a>.)");
+noparse!(fail_backreference_unknown, "(?P=a)(?P<a>.)");
+noparse!(fail_backreference_k_unknown, r"(?P<a>.)\k<b>");
 noparse!(fail_range_end_no_class, "[a-[:lower:]]");
 noparse!(fail_range_end_no_begin, r"[a-\A]");
 noparse!(fail_range_end_no_end, r"[a-\z]");
@@ -308,10 +559,20 @@ mat!(match_date3,
      r"^(19|20)\d\d[- /.](0[1-9]|1[012])[- /.](0[1-9]|[12][0-9]|3[01])$",
      "1900-13-01", None);
 
+// Literals after a fixed number of leading characters.
+mat!(match_offset_prefix1, r"..foo", "xfoo fooafoo", Some((3, 8)));
+mat!(match_offset_prefix2, r"\w\wfoo", "xfoo ☃foo éafoo", Some((12, 18)));
+mat!(match_offset_prefix3, r"\b.(?:ab|cd)", "xxabcd cd", Some((6, 9)));
+mat!(match_offset_prefix4, r".\d{2}:", "x1:: é12:", Some((5, 10)));
+
 // Exercise the flags.
 mat!(match_flag_case, "(?i)abc", "ABC", Some((0, 3)));
 mat!(match_flag_weird_case, "(?i)a(?-i)bc", "Abc", Some((0, 3)));
 mat!(match_flag_weird_case_not, "(?i)a(?-i)bc", "ABC", None);
+mat!(match_flag_case_literal, "(?i)error", "an ErRoR", Some((3, 8)));
+mat!(match_flag_case_literal_not, "(?i)error", "an ERRO", None);
+mat!(match_flag_case_literal_weird, "(?i)erro(?-i)r", "ERROR ERROr",
+     Some((6, 11)));
 mat!(match_flag_case_dotnl, "(?is)a.", "A\n", Some((0, 2)));
 mat!(match_flag_case_dotnl_toggle, "(?is)a.(?-is)a.", "A\nab", Some((0, 4)));
 mat!(match_flag_case_dotnl_toggle_not, "(?is)a.(?-is)a.", "A\na\n", None);
@@ -384,10 +645,319 @@ fn regression_captures_rep() {
     assert_eq!(caps.name("foo").unwrap(), "x");
 }
 
+#[test]
+fn captures_iter_groups() {
+    let re = regex!(r"(a)(?P<b>b)?(?P<c>c)");
+    let caps = re.captures("xac").unwrap();
+    let groups: Vec<_> = caps.iter_groups().collect();
+    assert_eq!(groups, vec![
+        (0, None, Some((1, 3))),
+        (1, None, Some((1, 2))),
+        (2, Some("b"), None),
+        (3, Some("c"), Some((2, 3))),
+    ]);
+    assert_eq!(caps.iter_groups().size_hint(), (4, Some(4)));
+}
+
+#[test]
+fn captures_name_match() {
+    let re = regex!(r"(?P<a>\d+)(?P<b>x)?-(?P<c>é+)");
+    let caps = re.captures("n 42-éé").unwrap();
+    let a = caps.name_match("a").unwrap();
+    assert_eq!((a.start(), a.end(), a.as_str()), (2, 4, "42"));
+    let c = caps.name_match("c").unwrap();
+    assert_eq!((c.pos(), c.as_str()), ((5, 9), "éé"));
+    assert_eq!(caps.name_match("b"), None);
+    assert_eq!(caps.name_match("z"), None);
+}
+
+#[test]
+fn captures_debug() {
+    let re = regex!(r"(?P<key>\w+)=(\d+)?(.*)");
+    let caps = re.captures("a=").unwrap();
+    let expected = concat!(r#"Captures({0: 0..2 "a=", 1 (key): 0..1 "a", "#,
+                           r#"2: None, 3: 2..2 ""})"#);
+    assert_eq!(format!("{:?}", caps), expected);
+    let m = caps.name_match("key").unwrap();
+    assert_eq!(format!("{:?}", m), r#"Match(0..1 "a")"#);
+
+    let xs: String = ::std::iter::repeat('x').take(40).collect();
+    let text = format!("k={}", xs);
+    let caps = re.captures(&text).unwrap();
+    let shown = format!("{:?}", caps);
+    assert!(shown.contains(&format!("3: 2..42 {:?}...", &xs[..32])));
+}
+
+#[test]
+fn captures_at() {
+    let re = regex!(r"(?m)^(\w+)=(\w+)$");
+    let text = "a=1\nb=2";
+    let caps = re.captures_at(text, 1).unwrap();
+    assert_eq!(caps.pos(0), Some((4, 7)));
+    assert_eq!(caps.at(1), Some("b"));
+    assert!(re.captures_at(text, 5).is_none());
+    assert_eq!(re.find_at(text, 0), Some((0, 3)));
+    assert_eq!(re.find_at(text, 4), Some((4, 7)));
+    assert_eq!(re.find_at(text, 7), None);
+    assert_eq!(regex!("").find_at(text, 7), Some((7, 7)));
+}
+
+#[test]
+fn count_matches() {
+    let re = regex!(r"\b|a+|[b-z]\w*");
+    for text in &["", "aaa bcd", "a☃b ab\nzzz  ", "☃☃"] {
+        assert_eq!(re.count_matches(text), re.find_iter(text).count());
+    }
+}
+
+#[test]
+fn find_iter_with_budget() {
+    let re = regex!(r"a*|b");
+    let text = "aabxxbaaa";
+    let expected: Vec<_> = re.find_iter(text).collect();
+    for budget in 0..12 {
+        let found: Vec<_> = re.find_iter(text).with_budget(budget)
+                              .filter_map(|step| match step {
+                                  MatchStep::Found(s, e) => Some((s, e)),
+                                  MatchStep::Pending => None,
+                              })
+                              .collect();
+        assert_eq!(found, expected);
+    }
+    let re = regex!("z");
+    let mut it = re.find_iter("xxxxz").with_budget(2);
+    assert_eq!(it.next(), Some(MatchStep::Pending));
+    assert_eq!(it.next(), Some(MatchStep::Pending));
+    assert_eq!(it.next(), Some(MatchStep::Found(4, 5)));
+    assert_eq!(it.next(), None);
+}
+
+#[test]
+fn search_with_scratch() {
+    let re = regex!(r"(?:(a)|(b)|(?P<c>c+))*(d)?\b");
+    let mut scratch = re.scratch();
+    let mut slots = vec![None; 2 * re.captures_len()];
+    let text: String = ::std::iter::repeat("xx abcacbd ccc  ab ba ")
+                                   .take(50).collect();
+    let mut start = 0;
+    while let Some(caps) = re.captures_at(&text, start) {
+        assert!(re.is_match_with(&text, &mut scratch));
+        assert_eq!(re.find_at_with(&text, start, &mut scratch), caps.pos(0));
+        assert!(re.read_captures_at_with(&text, start, &mut slots,
+                                         &mut scratch));
+        for i in 0..caps.len() {
+            assert_eq!((slots[2 * i], slots[2 * i + 1]),
+                       caps.pos(i).map_or((None, None),
+                                          |(s, e)| (Some(s), Some(e))));
+        }
+        start = caps.pos(0).unwrap().1 + 1;
+        if start > text.len() {
+            break;
+        }
+    }
+    let re = regex!("z");
+    assert_eq!(re.find_at_with("abc", 0, &mut re.scratch()), None);
+}
+
+#[test]
+fn builder_literal_optimizations() {
+    let text = "foo12 xfoo bar foo3";
+    for &yes in &[true, false] {
+        let re = RegexBuilder::new(r"foo\d+|bar")
+                     .literal_optimizations(yes)
+                     .compile()
+                     .unwrap();
+        assert_eq!(re.find_iter(text).collect::<Vec<_>>(),
+                   vec![(0, 5), (11, 14), (15, 19)]);
+        assert!(!re.is_match("fo1 ba"));
+    }
+}
+
+#[test]
+fn builder_size_limit() {
+    assert!(RegexBuilder::new(r"\w{100}").size_limit(100).compile().is_err());
+    assert!(RegexBuilder::new(r"\w").size_limit(1 << 20).compile().is_ok());
+}
+
+#[test]
+fn nested_repetition_too_big() {
+    let limit = 1 << 16;
+    match Regex::with_size_limit(limit, r"x(a{1,100}){1,100}") {
+        Err(Error::RepetitionTooBig {
+            ref repetition, size, limit: l, ref suggestion,
+        }) => {
+            assert_eq!(repetition, "(a{1, 100}){1, 100}");
+            assert!(size > limit);
+            assert_eq!(l, limit);
+            assert_eq!(suggestion.as_ref().map(|s| &**s),
+                       Some("(a{1, 100}){1,}"));
+        }
+        r => panic!("unexpected result: {:?}", r),
+    }
+    match Regex::with_size_limit(limit, r"(?:a{100}){100}") {
+        Err(Error::RepetitionTooBig { ref suggestion, .. }) => {
+            assert!(suggestion.is_none());
+        }
+        r => panic!("unexpected result: {:?}", r),
+    }
+    // A single repetition still fails the usual way.
+    match Regex::with_size_limit(limit, r"a{100000}") {
+        Err(Error::CompiledTooBig(l)) => assert_eq!(l, limit),
+        r => panic!("unexpected result: {:?}", r),
+    }
+    assert!(Regex::with_size_limit(limit, r"(a{1,10}){1,10}").is_ok());
+}
+
+#[test]
+fn captures_with_history() {
+    let re = regex!(r"((a)|b)+");
+    let caps = re.captures_with_history("xaba").unwrap();
+    assert_eq!(caps.all(0), vec![(1, 4)]);
+    assert_eq!(caps.all(1), vec![(1, 2), (2, 3), (3, 4)]);
+    assert_eq!(caps.all(2), vec![(1, 2), (3, 4)]);
+    assert_eq!(caps.all(3), vec![]);
+
+    let re = regex!(r"(?:(a)b|(a)c)+");
+    let caps = re.captures_with_history("acab").unwrap();
+    assert_eq!(caps.all(1), vec![(2, 3)]);
+    assert_eq!(caps.all(2), vec![(0, 1)]);
+
+    // Iterations given back so that the rest of the regex can match are
+    // forgotten.
+    let re = regex!(r"(?:(\w),)+(\w),$");
+    let caps = re.captures_with_history("a,b,c,").unwrap();
+    assert_eq!(caps.all(1), vec![(0, 1), (2, 3)]);
+    assert_eq!(caps.all(2), vec![(4, 5)]);
+    assert!(re.captures_with_history("a,b").is_none());
+
+    // Without a history, only the last span is known.
+    let caps = re.captures("a,b,c,").unwrap();
+    assert_eq!(caps.all(1), vec![(2, 3)]);
+}
+
+#[test]
+fn captures_with_history_over_budget() {
+    // The backtracking engine doesn't fit, so no history is kept.
+    let re = RegexBuilder::new(r"(?:(\w),)+")
+                          .backtrack_limit(0)
+                          .compile()
+                          .unwrap();
+    let caps = re.captures_with_history("a,b,c,").unwrap();
+    assert_eq!(caps.at(0), Some("a,b,c,"));
+    assert_eq!(caps.all(1), vec![(4, 5)]);
+}
+
+#[test]
+fn captures_parse() {
+    let re = regex!(r"(?P<key>\w+)=(?P<value>\w+)");
+    let caps = re.captures("port=80x").unwrap();
+    assert_eq!(caps.parse::<String>("key"), Ok("port".to_owned()));
+    assert_eq!(caps.parse::<u16>("value"),
+               Err(CaptureError::Invalid(
+                   "value".to_owned(),
+                   "invalid digit found in string".to_owned())));
+    assert_eq!(caps.parse::<u16>("nope"),
+               Err(CaptureError::Missing("nope".to_owned())));
+}
+
+#[test]
+fn captures_extract() {
+    #[derive(Debug, PartialEq)]
+    struct Entry {
+        level: String,
+        code: u32,
+    }
+
+    impl FromCaptures for Entry {
+        fn from_captures(caps: &Captures) -> Result<Entry, CaptureError> {
+            Ok(Entry {
+                level: try!(caps.parse("level")),
+                code: try!(caps.parse("code")),
+            })
+        }
+    }
+
+    let re = regex!(r"\[(?P<level>[A-Z]+)\] (?P<code>\d+)");
+    let entry: Entry = re.captures("[WARN] 404").unwrap().extract().unwrap();
+    assert_eq!(entry, Entry { level: "WARN".to_owned(), code: 404 });
+
+    let re = regex!(r"\[(?P<level>[A-Z]+)\] (?P<code>\w+)");
+    let err = re.captures("[WARN] x").unwrap().extract::<Entry>().unwrap_err();
+    match err {
+        CaptureError::Invalid(ref name, _) => assert_eq!(name, "code"),
+        err => panic!("unexpected error: {:?}", err),
+    }
+}
+
+// Alternations with a common prefix are rewritten before compilation, which
+// must not change which branch is preferred.
+mat!(alt_prefix_shortest_first, r"foo|foobar", "foobar", Some((0, 3)));
+mat!(alt_prefix_longest_first, r"foobar|foo", "foobar", Some((0, 6)));
+mat!(alt_prefix_captures, r"fo(o)|fo(x)|f", "fox",
+     Some((0, 3)), None, Some((2, 3)));
+mat!(alt_prefix_dead_branch, r"a|(b[^\x00-\x{10FFFF}])|c", "c",
+     Some((0, 1)), None);
+
+// Alternations of more than two expressions branch out in one instruction,
+// which must try them in order.
+mat!(alt_many_first, r"x|(b)|(a)|(ab)", "ab",
+     Some((0, 1)), None, Some((0, 1)), None);
+mat!(alt_many_last, r"x|y|z|(ab)", "zab", Some((0, 1)), None);
+mat!(alt_many_nested, r"(?:x|y|(z|a|b)+)+c", "zabc",
+     Some((0, 4)), Some((2, 3)));
+
+// Repetitions of expressions that can match the empty string.
+mat!(empty_loop_star, r"(a*)*b", "b", Some((0, 1)), Some((0, 0)));
+mat!(empty_loop_star_many, r"(a*)*b", "aab", Some((0, 3)), Some((0, 2)));
+mat!(empty_loop_plus, r"(a*)+b", "b", Some((0, 1)), Some((0, 0)));
+mat!(empty_loop_lazy, r"(a*?)*b", "aab", Some((0, 3)), Some((0, 2)));
+mat!(empty_loop_nested, r"((a*)*)*b", "b",
+     Some((0, 1)), Some((0, 0)), Some((0, 0)));
+mat!(empty_loop_assertion, r"(^)*x", "x", Some((0, 1)), Some((0, 0)));
+mat!(empty_loop_range, r"(a*){0,}b", "b", Some((0, 1)), Some((0, 0)));
+mat!(empty_loop_alt, r"(a*|b)*", "ab", Some((0, 2)), Some((1, 2)));
+mat!(empty_loop_lazy_fail, r"(?:a?)*?b", "a", None);
+mat!(empty_loop_lazy_fail_other, r"(?:x?)*?a", "x", None);
+mat!(empty_loop_lazy_many, r"(?:a?)*?b", "aab", Some((0, 3)));
+mat!(empty_loop_lazy_group, r"(a?)*?b", "aab", Some((0, 3)), Some((1, 2)));
+mat!(empty_loop_lazy_plus, r"(a?)+?b", "a", None);
+mat!(empty_loop_lazy_nested, r"(?:a*?)*?x", "ax", Some((0, 2)));
+
+// A group in a star loop that only matches the empty string reports that
+// empty match. RE2 and Go (and so src/testdata) leave it unset, so these
+// replace the tests generated from those entries.
+mat!(empty_loop_unset_basic_128, r"(a*)*", "-", Some((0, 0)), Some((0, 0)));
+mat!(empty_loop_unset_basic_131, r"(a*|b)*", "-",
+     Some((0, 0)), Some((0, 0)));
+mat!(empty_loop_unset_basic_137, r"(^)*", "-", Some((0, 0)), Some((0, 0)));
+mat!(empty_loop_unset_basic_144, r"((a*|b))*", "-",
+     Some((0, 0)), Some((0, 0)), Some((0, 0)));
+mat!(empty_loop_unset_nullsubexpr_5, r"(a*)*", "x",
+     Some((0, 0)), Some((0, 0)));
+mat!(empty_loop_unset_nullsubexpr_23, r"([a]*)*", "x",
+     Some((0, 0)), Some((0, 0)));
+mat!(empty_loop_unset_nullsubexpr_32, r"([^b]*)*", "b",
+     Some((0, 0)), Some((0, 0)));
+mat!(empty_loop_unset_nullsubexpr_45, r"([^a]*)*", "aaaaaa",
+     Some((0, 0)), Some((0, 0)));
+mat!(empty_loop_unset_nullsubexpr_48, r"([^ab]*)*", "ababab",
+     Some((0, 0)), Some((0, 0)));
+mat!(empty_loop_unset_nullsubexpr_69, r"(a*)*(x)", "x",
+     Some((0, 1)), Some((0, 0)), Some((0, 1)));
+mat!(empty_loop_unset_range, r"(X{0,2})*", "", Some((0, 0)), Some((0, 0)));
+
 // Regression test for https://github.com/rust-lang-nursery/regex/issues/153
 mat!(regression_alt_in_alt1, r"ab?|$", "az", Some((0, 1)));
 mat!(regression_alt_in_alt2, r"^(.*?)(\n|\r\n?|$)", "ab\rcd", Some((0, 3)));
 
+// An alternate that compiles to nothing still matches the empty string.
+mat!(empty_alternate_first, r"(?:c{0}|..)", "bé__x", Some((0, 0)));
+mat!(empty_alternate_last, r"(?:..|c{0})x", "bx", Some((1, 2)));
+mat!(empty_alternate_many, r"(?:a|c{0}|..)", "bé__x", Some((0, 0)));
+mat!(empty_alternate_loop, r"^(?:a|c{0})*b", "aab", Some((0, 3)));
+mat!(empty_alternate_group, r"(a|(b){0})x", "x", Some((0, 1)), Some((0, 0)));
+mat!(empty_alternate_hoisted, r"bc{0}|b.{2}|x?", "bé__x", Some((0, 1)));
+
 // A whole mess of tests from Glenn Fowler's regex test suite.
 // Generated by the 'src/etc/regex-match-tests' program.
 #[path = "matches.rs"]
@@ -15,9 +15,19 @@ import argparse
 import datetime
 import os.path as path
 
+# Tests whose expected capture groups this crate deliberately differs from.
+# They expect a group in a star loop that only matched the empty string to
+# be unset (as RE2 and Go report it). This crate reports the empty match of
+# the loop's only iteration instead, which is tested in tests.rs.
+SKIP = set([
+    'basic_128', 'basic_131', 'basic_137', 'basic_144',
+    'nullsubexpr_5', 'nullsubexpr_23', 'nullsubexpr_32', 'nullsubexpr_45',
+    'nullsubexpr_48', 'nullsubexpr_69',
+])
+
 
 def print_tests(tests):
-    print('\n'.join([test_tostr(t) for t in tests]))
+    print('\n'.join([test_tostr(t) for t in tests if t[0] not in SKIP]))
 
 
 def read_tests(f):
@@ -18,27 +18,52 @@
 // state is keyed by `(instruction index, input index)`, we have that its time
 // complexity is `O(mn)`.
 //
+// A program with backreferences can't be run this way, since whether a state
+// leads to a match depends on the captures saved on the way to it. So such a
+// program is run without the visited set, which may take exponential time.
+// Only empty loops are cut short: a split that's taken twice at the same
+// position on the current path fails the second time. Instead, such a search
+// may take as many steps as the visited set it would otherwise be allowed
+// has bits, after which it gives up and reports no match.
+//
 // The backtracking engine can beat out the NFA simulation on small
 // regexes/inputs because it doesn't have to keep track of multiple copies of
 // the capture groups. In benchmarks, the backtracking engine is roughly twice
 // as fast as the full NFA simulation.
 
+use std::cmp;
+use std::usize;
+
+use char::Char;
 use input::{Input, InputAt, CharInput};
 use inst::InstIdx;
 use program::Program;
+use profile::SearchCounts;
 use re::CaptureIdxs;
+use trace::TraceEvent;
 
-type Bits = u32;
-const BIT_SIZE: usize = 32;
-const MAX_PROG_SIZE: usize = 100;
-const MAX_INPUT_SIZE: usize = 256 * (1 << 10);
+type Bits = u64;
+const BIT_SIZE: usize = 64;
+pub const MAX_VISITED_BYTES: usize = 4 * (1 << 20);
+const DIRTY_RATIO: usize = 8;
 
-// Total memory usage in bytes is determined by:
+// Total memory usage in bytes of the visited set is determined by:
 //
-//   ((len(insts) * (len(input) + 1) + bits - 1) / bits) / (bits / 8)
+//   ((len(insts) * (len(input) + 1) + bits - 1) / bits) * (bits / 8)
 //
-// With the above settings, this comes out to ~3.2MB. Mostly these numbers
-// were picked empirically with suspicious benchmarks.
+// The engine is used whenever this fits in the program's backtrack limit,
+// which is `MAX_VISITED_BYTES` by default. e.g., that's a 100 instruction
+// program on ~320KB of input, or a 10,000 instruction program on ~3KB of
+// input. In particular, any program of less than half a million
+// instructions fits on 64 bytes of input, so tiny inputs never pay for
+// setting up the NFA simulation. Mostly this number was picked empirically
+// with suspicious benchmarks.
+
+/// Returns the number of words needed for the visited set of a program with
+/// `num_insts` instructions on an input of `input_len` bytes.
+fn visited_words(num_insts: usize, input_len: usize) -> usize {
+    (num_insts * (input_len + 1) + BIT_SIZE - 1) / BIT_SIZE
+}
 
 /// A backtracking matching engine.
 #[derive(Debug)]
@@ -47,6 +72,23 @@ pub struct Backtrack<'a, 'r, 't, 'c> {
     input: CharInput<'t>,
     caps: &'c mut CaptureIdxs,
     m: &'a mut BackMachine,
+    scan_prefixes: bool,
+    /// Whether every capture saved on the way to the match is kept in
+    /// `m.history`.
+    keep_history: bool,
+    /// Whether visited states are remembered in `m.visited`. They're not
+    /// if the program has backreferences, in which case the splits taken
+    /// are kept in `m.path` instead.
+    memoize: bool,
+    /// The steps left before a search that doesn't remember visited states
+    /// gives up.
+    steps_left: usize,
+    /// The work done so far, for the program's statistics.
+    counts: SearchCounts,
+    /// The position of the last zero-width assertion and the character just
+    /// before it, so that assertions at the same position (e.g., `\b` on
+    /// either side of `\w*`) don't decode it again.
+    before: (usize, Char),
 }
 
 /// Shared cached state between multiple invocations of a backtracking engine
@@ -57,6 +99,17 @@ pub struct Backtrack<'a, 'r, 't, 'c> {
 pub struct BackMachine {
     jobs: Vec<Job>,
     visited: Vec<Bits>,
+    /// The indices of every non-zero word in `visited`, unless `all_dirty`
+    /// is set. This lets us clear only what the last search touched.
+    dirty: Vec<usize>,
+    /// Set when too many words were touched to be worth tracking.
+    all_dirty: bool,
+    /// Every `(slot, position)` saved on the current path, in order, when
+    /// the search keeps a capture history.
+    history: Vec<(usize, usize)>,
+    /// Every split taken on the current path, with its position, in order,
+    /// when the search doesn't remember visited states.
+    path: Vec<(InstIdx, usize)>,
 }
 
 impl BackMachine {
@@ -65,6 +118,10 @@ impl BackMachine {
         BackMachine {
             jobs: vec![],
             visited: vec![],
+            dirty: vec![],
+            all_dirty: false,
+            history: vec![],
+            path: vec![],
         }
     }
 }
@@ -79,186 +136,430 @@ impl BackMachine {
 enum Job {
     Inst { pc: InstIdx, at: InputAt },
     SaveRestore { slot: usize, old_pos: Option<usize> },
+    HistoryRestore { len: usize },
+    PathRestore { len: usize },
 }
 
 impl<'a, 'r, 't, 'c> Backtrack<'a, 'r, 't, 'c> {
     /// Execute the backtracking matching engine.
     ///
-    /// If there's a match, `exec` returns `true` and populates the given
-    /// captures accordingly.
+    /// If there's a match, `exec` returns the index of the pattern that
+    /// matched and populates the given captures accordingly.
+    ///
+    /// When `scan_prefixes` is false, the program's literal prefixes are not
+    /// used to skip ahead in the input.
     pub fn exec(
         prog: &'r Program,
         mut caps: &mut CaptureIdxs,
         text: &'t str,
         start: usize,
-    ) -> bool {
+        scan_prefixes: bool,
+    ) -> Option<usize> {
+        if prog.backrefs && caps.len() < 2 * prog.num_captures() {
+            // A backreference needs the group it refers to, even if the
+            // caller doesn't.
+            let mut all = prog.alloc_captures();
+            let matched =
+                Backtrack::exec(prog, &mut all, text, start, scan_prefixes);
+            let n = caps.len();
+            caps.copy_from_slice(&all[..n]);
+            return matched;
+        }
         let input = CharInput::new(text);
-        let start = input.at(start);
+        let start = input.decode_at(start);
         let mut m = prog.backtrack.get();
         let mut b = Backtrack {
             prog: prog,
             input: input,
             caps: caps,
             m: &mut m,
+            scan_prefixes: scan_prefixes,
+            keep_history: false,
+            memoize: !prog.backrefs,
+            steps_left: prog.backtrack_limit.saturating_mul(8),
+            counts: SearchCounts::default(),
+            before: (usize::MAX, None.into()),
         };
         b.exec_(start)
     }
 
+    /// Execute the backtracking matching engine, keeping a history of every
+    /// capture saved on the way to the match.
+    ///
+    /// This is like `exec`, except that if there's a match, every
+    /// `(slot, position)` pair saved on the path that led to it is appended
+    /// to `history` in order. So a group in a repetition shows up once for
+    /// every iteration, instead of only for the last one.
+    pub fn exec_history(
+        prog: &'r Program,
+        mut caps: &mut CaptureIdxs,
+        history: &mut Vec<(usize, usize)>,
+        text: &'t str,
+        start: usize,
+        scan_prefixes: bool,
+    ) -> Option<usize> {
+        let input = CharInput::new(text);
+        let start = input.decode_at(start);
+        let mut m = prog.backtrack.get();
+        let matched = {
+            let mut b = Backtrack {
+                prog: prog,
+                input: input,
+                caps: caps,
+                m: &mut m,
+                scan_prefixes: scan_prefixes,
+                keep_history: true,
+                memoize: !prog.backrefs,
+                steps_left: prog.backtrack_limit.saturating_mul(8),
+                counts: SearchCounts::default(),
+                before: (usize::MAX, None.into()),
+            };
+            b.exec_(start)
+        };
+        if matched.is_some() {
+            history.extend(m.history.iter().cloned());
+        }
+        matched
+    }
+
     /// Returns true iff the given regex and input can be executed by this
     /// engine with reasonable memory usage.
+    ///
+    /// The memory used is proportional to the size of the program times the
+    /// length of the input, so a bigger program is allowed when the input is
+    /// tiny. (Setting up an NFA simulation for a handful of characters is
+    /// never worth it.)
+    ///
+    /// A program with backreferences can only be run by this engine, so
+    /// it always can, without a visited set.
     pub fn should_exec(prog: &'r Program, input: &str) -> bool {
-        prog.insts.len() <= MAX_PROG_SIZE && input.len() <= MAX_INPUT_SIZE
+        if prog.backrefs {
+            return true;
+        }
+        let words = visited_words(prog.insts.len(), input.len());
+        words.saturating_mul(BIT_SIZE / 8) <= prog.backtrack_limit
     }
 
     fn clear(&mut self) {
         // Reset the job memory so that we start fresh.
         self.m.jobs.truncate(0);
+        self.m.history.truncate(0);
+        if !self.memoize {
+            // The captures are only read by backreferences, which mustn't
+            // see anything left over from another search.
+            for slot in self.caps.iter_mut() {
+                *slot = None;
+            }
+            return;
+        }
 
-        // Now we need to clear the bit state set.
-        // We do this by figuring out how much space we need to keep track
-        // of the states we've visited.
-        // Then we reset all existing allocated space to 0.
-        // Finally, we request more space if we need it.
+        // Now we need to clear the bit state set. Rather than zeroing all of
+        // it, we only reset the words touched by the previous search. This
+        // matters when iterating over many small matches in a big input,
+        // since each search only visits the states near its match.
         //
-        // This is all a little circuitous, but doing this unsafely
-        // doesn't seem to have a measurable impact on performance.
-        // (Probably because backtracking is limited to such small
-        // inputs/regexes in the first place.)
-        let visited_len =
-            (self.prog.insts.len() * (self.input.len() + 1) + BIT_SIZE - 1)
-            /
-            BIT_SIZE;
-        self.m.visited.truncate(visited_len);
-        for v in &mut self.m.visited {
-            *v = 0;
+        // If the previous search touched a lot of words, then we gave up on
+        // tracking them and just zero everything.
+        if self.m.all_dirty {
+            for v in &mut self.m.visited {
+                *v = 0;
+            }
+        } else {
+            for &k in &self.m.dirty {
+                self.m.visited[k] = 0;
+            }
         }
+        self.m.dirty.truncate(0);
+        self.m.all_dirty = false;
+
+        // If we need more space than we have, the vector grows geometrically
+        // so that a sequence of searches on slowly growing inputs doesn't
+        // reallocate every time. New words always start out zeroed.
+        let visited_len =
+            visited_words(self.prog.insts.len(), self.input.len());
         if visited_len > self.m.visited.len() {
-            let len = self.m.visited.len();
-            self.m.visited.reserve_exact(visited_len - len);
-            for _ in 0..(visited_len - len) {
-                self.m.visited.push(0);
-            }
+            let max_words = self.prog.backtrack_limit / (BIT_SIZE / 8);
+            let grown = cmp::min(2 * self.m.visited.len(), max_words);
+            self.m.visited.resize(cmp::max(visited_len, grown), 0);
+        }
+    }
+
+    /// Returns the character just before `at`.
+    fn char_before(&mut self, at: InputAt) -> Char {
+        if self.before.0 != at.pos() {
+            let c = self.input.decode_before(at.pos()).char();
+            self.before = (at.pos(), c);
         }
+        self.before.1
     }
 
-    fn exec_(&mut self, mut at: InputAt) -> bool {
+    fn exec_(&mut self, at: InputAt) -> Option<usize> {
         self.clear();
+        let matched = self.search(at);
+        self.prog.record_prefix_scan(
+            self.counts.prefix_scans,
+            matched.is_some() as usize,
+            self.counts.prefix_skipped);
+        self.prog.record_counts(&self.counts);
+        matched
+    }
+
+    fn search(&mut self, mut at: InputAt) -> Option<usize> {
+        if self.prog.anchored_start {
+            return self.backtrack(at);
+        }
         if self.prog.anchored_begin {
             return if !at.is_beginning() {
-                false
+                None
             } else {
-                match self.input.prefix_at(&self.prog.prefixes, at) {
-                    None => false,
-                    Some(at) => self.backtrack(at),
+                let offset = self.prog.prefix_offset;
+                let next =
+                    self.input.prefix_at(&self.prog.prefixes, offset, at);
+                self.prog.tracer.emit(|| TraceEvent::PrefixScan {
+                    from: at.pos(),
+                    to: next.map(|at| at.pos()),
+                });
+                match next {
+                    None => None,
+                    Some(next) => {
+                        self.counts.prefix_scans += 1;
+                        self.counts.prefix_skipped += next.pos() - at.pos();
+                        self.backtrack(next)
+                    }
                 }
             };
         }
         loop {
-            if !self.prog.prefixes.is_empty() {
-                at = match self.input.prefix_at(&self.prog.prefixes, at) {
-                    None => return false,
+            if self.scan_prefixes && !self.prog.prefixes.is_empty() {
+                let offset = self.prog.prefix_offset;
+                let before = at.pos();
+                let next = self.input.prefix_at(&self.prog.prefixes,
+                                                offset, at);
+                self.prog.tracer.emit(|| TraceEvent::PrefixScan {
+                    from: before,
+                    to: next.map(|at| at.pos()),
+                });
+                at = match next {
+                    None => return None,
                     Some(at) => at,
                 };
+                self.counts.prefix_scans += 1;
+                self.counts.prefix_skipped += at.pos() - before;
+            } else if self.prog.anchored_line_begin {
+                at = match self.input.line_start_at(at) {
+                    None => return None,
+                    Some(at) => at,
+                };
+            }
+            // Stop once the shortest possible match can't fit anymore.
+            if at.pos() + self.prog.min_len > self.input.len() {
+                return None;
+            }
+            if let Some(pat) = self.backtrack(at) {
+                return Some(pat);
             }
-            if self.backtrack(at) {
-                return true;
+            if !self.memoize && self.steps_left == 0 {
+                debug!("backtracking gave up at {} (backtrack limit {})",
+                       at.pos(), self.prog.backtrack_limit);
+                return None;
             }
             if at.char().is_none() {
-                return false;
+                return None;
             }
-            at = self.input.at(at.next_pos());
+            at = self.input.decode_at(at.next_pos());
         }
     }
 
     // This `inline(always)` seems to result in about a 10-15% increase in
     // throughput on the `hard` benchmarks (over a standard `inline`). ---AG
     #[inline(always)]
-    fn backtrack(&mut self, start: InputAt) -> bool {
+    fn backtrack(&mut self, start: InputAt) -> Option<usize> {
+        self.counts.positions += 1;
+        self.m.path.truncate(0);
         self.push(0, start);
         while let Some(job) = self.m.jobs.pop() {
             match job {
                 Job::Inst { pc, at } => {
-                    if self.step(pc, at) {
-                        return true;
+                    self.counts.threads += 1;
+                    if let Some(pat) = self.step(pc, at) {
+                        return Some(pat);
                     }
                 }
                 Job::SaveRestore { slot, old_pos } => {
                     self.caps[slot] = old_pos;
                 }
+                Job::HistoryRestore { len } => {
+                    self.m.history.truncate(len);
+                }
+                Job::PathRestore { len } => {
+                    self.m.path.truncate(len);
+                }
             }
         }
-        false
+        None
     }
 
-    fn step(&mut self, mut pc: InstIdx, mut at: InputAt) -> bool {
+    fn step(&mut self, mut pc: InstIdx, mut at: InputAt) -> Option<usize> {
         use inst::Inst::*;
         loop {
             // This loop is an optimization to avoid constantly pushing/popping
             // from the stack. Namely, if we're pushing a job only to run it
             // next, avoid the push and just mutate `pc` (and possibly `at`)
             // in place.
-            match self.prog.insts[pc] {
-                Match => return true,
+            //
+            // Every instruction is checked here, including the one a popped
+            // job resumes at. Otherwise, an empty loop whose body is popped
+            // from the stack (e.g., in a lazy `(?:a?)*?`) never stops.
+            if self.memoize && self.has_visited(pc, at) {
+                return None;
+            }
+            if !self.memoize {
+                if self.steps_left == 0 {
+                    // Out of steps, so drop every other path too.
+                    self.m.jobs.truncate(0);
+                    return None;
+                }
+                self.steps_left -= 1;
+            }
+            self.prog.tracer.step(pc, at.pos());
+            match self.prog.insts[pc as usize] {
+                Match(pat) => {
+                    if self.prog.match_at_end
+                       && at.pos() != self.input.len() {
+                        return None;
+                    }
+                    return Some(pat);
+                }
                 Save(ref inst) => {
-                    if inst.slot < self.caps.len() {
+                    let slot = inst.slot as usize;
+                    if slot < self.caps.len() {
                         // If this path doesn't work out, then we save the old
                         // capture index (if one exists) in an alternate
                         // job. If the next path fails, then the alternate
                         // job is popped and the old capture index is restored.
-                        let old_pos = self.caps[inst.slot];
-                        self.push_save_restore(inst.slot, old_pos);
-                        self.caps[inst.slot] = Some(at.pos());
+                        let old_pos = self.caps[slot];
+                        self.push_save_restore(slot, old_pos);
+                        self.caps[slot] = Some(at.pos());
+                        if self.keep_history {
+                            // Likewise, forget the save if the path fails.
+                            let len = self.m.history.len();
+                            self.push_history_restore(len);
+                            self.m.history.push((slot, at.pos()));
+                        }
                     }
                     pc = inst.goto;
                 }
                 Split(ref inst) => {
+                    if !self.take_split(pc, at) {
+                        return None;
+                    }
                     self.push(inst.goto2, at);
                     pc = inst.goto1;
                 }
+                SplitMany(ref inst) => {
+                    if !self.take_split(pc, at) {
+                        return None;
+                    }
+                    for &goto in inst.gotos[1..].iter().rev() {
+                        self.push(goto, at);
+                    }
+                    pc = inst.gotos[0];
+                }
                 EmptyLook(ref inst) => {
-                    let prev = self.input.previous_at(at.pos());
-                    if inst.matches(prev.char(), at.char()) {
+                    let prev = self.char_before(at);
+                    if inst.matches(prev, at.char()) {
                         pc = inst.goto;
                     } else {
-                        return false;
+                        return None;
                     }
                 }
                 Char(ref inst) => {
                     if inst.c == at.char() {
                         pc = inst.goto;
-                        at = self.input.at(at.next_pos());
+                        at = self.input.decode_at(at.next_pos());
                     } else {
-                        return false;
+                        return None;
                     }
                 }
                 Ranges(ref inst) => {
-                    if inst.matches(at.char()) {
+                    if inst.matches(&self.prog.ranges, at.char()) {
                         pc = inst.goto;
-                        at = self.input.at(at.next_pos());
+                        at = self.input.decode_at(at.next_pos());
                     } else {
-                        return false;
+                        return None;
+                    }
+                }
+                Backref(ref inst) => {
+                    let slot = inst.slot as usize;
+                    let text = self.input.as_str();
+                    let end = match (self.caps[slot], self.caps[slot + 1]) {
+                        // A group that hasn't matched (yet) on this path
+                        // can't be matched again.
+                        (Some(s), Some(e)) if s <= e => {
+                            inst.matches(text, at.pos(), &text[s..e])
+                        }
+                        _ => None,
+                    };
+                    match end {
+                        Some(end) => {
+                            pc = inst.goto;
+                            at = self.input.decode_at(end);
+                        }
+                        None => return None,
                     }
                 }
-            }
-            if self.has_visited(pc, at) {
-                return false;
             }
         }
     }
 
     fn push(&mut self, pc: InstIdx, at: InputAt) {
         self.m.jobs.push(Job::Inst { pc: pc, at: at });
+        if !self.memoize {
+            // The job continues the path as it is now.
+            let len = self.m.path.len();
+            self.m.jobs.push(Job::PathRestore { len: len });
+        }
+    }
+
+    /// Records taking the split at `pc` on the current path, unless the
+    /// path already took it at the same position, i.e., it went around an
+    /// empty loop. Returns false in that case.
+    ///
+    /// Nothing is recorded when visited states are remembered, since they
+    /// already stop such loops.
+    fn take_split(&mut self, pc: InstIdx, at: InputAt) -> bool {
+        if self.memoize {
+            return true;
+        }
+        let looped = self.m.path.iter().rev()
+            .take_while(|&&(_, pos)| pos == at.pos())
+            .any(|&(p, _)| p == pc);
+        if !looped {
+            self.m.path.push((pc, at.pos()));
+        }
+        !looped
     }
 
     fn push_save_restore(&mut self, slot: usize, old_pos: Option<usize>) {
         self.m.jobs.push(Job::SaveRestore { slot: slot, old_pos: old_pos });
     }
 
+    fn push_history_restore(&mut self, len: usize) {
+        self.m.jobs.push(Job::HistoryRestore { len: len });
+    }
+
     fn has_visited(&mut self, pc: InstIdx, at: InputAt) -> bool {
-        let k = pc * (self.input.len() + 1) + at.pos();
+        let k = pc as usize * (self.input.len() + 1) + at.pos();
         let k1 = k / BIT_SIZE;
-        let k2 = (1 << (k & (BIT_SIZE - 1))) as Bits;
+        let k2: Bits = 1 << (k & (BIT_SIZE - 1));
         if self.m.visited[k1] & k2 == 0 {
+            if self.m.visited[k1] == 0 && !self.m.all_dirty {
+                if self.m.dirty.len() < self.m.visited.len() / DIRTY_RATIO {
+                    self.m.dirty.push(k1);
+                } else {
+                    self.m.all_dirty = true;
+                }
+            }
             self.m.visited[k1] |= k2;
             false
         } else {
@@ -15,6 +15,10 @@ use std::u32;
 
 use syntax;
 
+/// A bitmap of the ASCII word characters (`[0-9A-Za-z_]`), where character
+/// `c` corresponds to bit `c % 64` of word `c / 64`.
+const ASCII_WORD: [u64; 2] = [0x03FF_0000_0000_0000, 0x07FF_FFFE_87FF_FFFE];
+
 /// An inline representation of `Option<char>`.
 ///
 /// This eliminates the need to do case analysis on `Option<char>` to determine
@@ -48,10 +52,24 @@ impl Char {
         char::from_u32(self.0).map_or(0, |c| c.len_utf8())
     }
 
+    /// Returns the character as a byte if it is ASCII.
+    ///
+    /// If the character is absent or not ASCII, then `None` is returned.
+    #[inline]
+    pub fn as_ascii(self) -> Option<u8> {
+        if self.0 < 0x80 { Some(self.0 as u8) } else { None }
+    }
+
     /// Returns true iff the character is a word character.
     ///
     /// If the character is absent, then false is returned.
     pub fn is_word_char(self) -> bool {
+        // Word boundaries are evaluated at every position they're live at,
+        // so it pays to avoid the Unicode table lookup for ASCII.
+        if let Some(b) = self.as_ascii() {
+            let b = b as usize;
+            return ASCII_WORD[b / 64] & (1 << (b % 64)) != 0;
+        }
         char::from_u32(self.0).map_or(false, syntax::is_word_char)
     }
 
@@ -98,3 +116,22 @@ impl PartialOrd<Char> for char {
         (*self as u32).partial_cmp(&other.0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::char;
+
+    use syntax;
+    use super::Char;
+
+    #[test]
+    fn ascii_word_chars() {
+        for b in 0..128 {
+            let c = char::from_u32(b).unwrap();
+            assert_eq!(Char::from(c).is_word_char(), syntax::is_word_char(c),
+                       "{:?}", c);
+        }
+        assert!(!Char::from(None).is_word_char());
+        assert!(Char::from('δ').is_word_char());
+    }
+}
@@ -8,7 +8,7 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use std::collections::HashSet;
+use std::collections::{BTreeSet, HashMap};
 use std::iter;
 
 use syntax::{Expr, Repeater, CharClass, ClassRange};
@@ -17,18 +17,23 @@ use Error;
 use inst::{
     EmptyLook,
     Inst, InstIdx,
-    InstSave, InstSplit, InstEmptyLook, InstChar, InstRanges,
+    InstSave, InstSplit, InstSplitMany, InstEmptyLook, InstChar, InstRanges,
+    InstBackref,
 };
+use optimize::optimize;
 
-pub type Compiled = (Vec<Inst>, Vec<Option<String>>);
+pub type Compiled = (Vec<Inst>, Vec<(char, char)>, Vec<Option<String>>);
 
 type CompileResult = Result<Hole, Error>;
 
 pub struct Compiler {
     size_limit: usize,
     insts: Vec<MaybeInst>,
+    ranges: Vec<(char, char)>,
     cap_names: Vec<Option<String>>,
-    seen_caps: HashSet<usize>,
+    seen_caps: BTreeSet<usize>,
+    /// The index of every named group compiled so far, for backreferences.
+    named_caps: HashMap<String, usize>,
 }
 
 impl Compiler {
@@ -36,18 +41,83 @@ impl Compiler {
         Compiler {
             size_limit: size_limit,
             insts: vec![],
+            ranges: vec![],
             cap_names: vec![None],
-            seen_caps: HashSet::new(),
+            seen_caps: BTreeSet::new(),
+            named_caps: HashMap::new(),
         }
     }
 
-    pub fn compile(mut self, expr: &Expr) -> Result<Compiled, Error> {
+    /// Returns the most memory, in bytes, a compiled program may take.
+    pub fn size_limit(&self) -> usize {
+        self.size_limit
+    }
+
+    pub fn compile(&mut self, expr: &Expr) -> Result<Compiled, Error> {
+        self.reset();
+        let expr = &optimize(expr.clone());
+        try!(self.check_repetitions(expr));
+        try!(self.c_pattern(0, expr));
+        Ok(self.finish())
+    }
+
+    /// Compiles several expressions into a single program.
+    ///
+    /// The expressions are tried in order, as if they were alternates of one
+    /// big alternation, except that each one ends in its own `Match`
+    /// instruction carrying the index of the expression.
+    pub fn compile_many(
+        &mut self,
+        exprs: &[Expr],
+    ) -> Result<Compiled, Error> {
+        self.reset();
+        if exprs.is_empty() {
+            // Nothing matches an empty list of expressions, which we encode
+            // as a class without any ranges. (The `Match` is unreachable.)
+            let hole = try!(self.c_class(None));
+            self.fill_to_next(hole);
+            self.push_compiled(Inst::Match(0));
+            return Ok(self.finish());
+        }
+        let exprs: Vec<Expr> = exprs.iter().cloned().map(optimize).collect();
+        for e in &exprs {
+            try!(self.check_repetitions(e));
+        }
+        for (i, e) in exprs[0..exprs.len() - 1].iter().enumerate() {
+            let split = self.push_split_hole();
+            let goto1 = self.next_pc();
+            try!(self.c_pattern(i, e));
+            let goto2 = self.next_pc();
+            self.fill_split(split, Some(goto1), Some(goto2));
+        }
+        try!(self.c_pattern(exprs.len() - 1, &exprs[exprs.len() - 1]));
+        Ok(self.finish())
+    }
+
+    /// Clears the state left behind by a previous compilation.
+    ///
+    /// A compiler may be reused for any number of programs, which saves
+    /// reallocating its scratch space when compiling many regexes in bulk.
+    fn reset(&mut self) {
+        self.insts.clear();
+        self.ranges.clear();
+        self.cap_names = vec![None];
+        self.seen_caps.clear();
+        self.named_caps.clear();
+    }
+
+    fn finish(&mut self) -> Compiled {
+        let insts = self.insts.drain(..).map(|inst| inst.unwrap()).collect();
+        let ranges = self.ranges.drain(..).collect();
+        let cap_names = ::std::mem::replace(&mut self.cap_names, vec![None]);
+        (insts, ranges, cap_names)
+    }
+
+    fn c_pattern(&mut self, pat: usize, expr: &Expr) -> Result<(), Error> {
         let hole = try!(self.c_capture(0, expr));
         self.fill_to_next(hole);
-        self.push_compiled(Inst::Match);
-
-        let insts = self.insts.into_iter().map(|inst| inst.unwrap()).collect();
-        Ok((insts, self.cap_names))
+        self.push_compiled(Inst::Match(pat));
+        Ok(())
     }
 
     fn c(&mut self, expr: &Expr) -> CompileResult {
@@ -82,9 +152,13 @@ impl Compiler {
                 if !self.seen_caps.contains(&i) {
                     self.cap_names.push(name.clone());
                     self.seen_caps.insert(i);
+                    if let Some(ref name) = *name {
+                        self.named_caps.insert(name.clone(), i);
+                    }
                 }
                 self.c_capture(2 * i, e)
             }
+            Backref { ref name, casei } => self.c_backref(name, casei),
             Concat(ref es) => self.c_concat(es.iter()),
             Alternate(ref es) => self.c_alternate(&**es),
             Repeat { ref e, r, greedy } => self.c_repeat(e, r, greedy),
@@ -92,6 +166,7 @@ impl Compiler {
     }
 
     fn c_capture(&mut self, first_slot: usize, expr: &Expr) -> CompileResult {
+        let first_slot = first_slot as u32;
         let hole = self.push_hole(MaybeInst::Save { slot: first_slot });
         self.fill_to_next(hole);
 
@@ -101,6 +176,20 @@ impl Compiler {
         Ok(self.push_hole(MaybeInst::Save { slot: first_slot + 1 }))
     }
 
+    fn c_backref(&mut self, name: &str, casei: bool) -> CompileResult {
+        // The parser makes sure that the group comes before the
+        // backreference, so it's been compiled already. Unless it was
+        // optimized away for never matching, in which case neither does the
+        // backreference.
+        match self.named_caps.get(name) {
+            None => self.c_class(None),
+            Some(&i) => Ok(self.push_hole(MaybeInst::Backref {
+                slot: 2 * i as u32,
+                casei: casei,
+            })),
+        }
+    }
+
     fn c_literal(&mut self, chars: &[char], casei: bool) -> CompileResult {
         assert!(!chars.is_empty());
         if casei {
@@ -125,12 +214,17 @@ impl Compiler {
 
     fn c_class<I>(&mut self, ranges: I) -> CompileResult
             where I: IntoIterator<Item=(char, char)> {
-        let ranges: Vec<(char, char)> = ranges.into_iter().collect();
-        Ok(if ranges.len() == 1 && ranges[0].0 == ranges[0].1 {
-            self.push_hole(MaybeInst::Char { c: ranges[0].0 })
-        } else {
-            self.push_hole(MaybeInst::Ranges { ranges: ranges })
-        })
+        let start = self.ranges.len();
+        self.ranges.extend(ranges);
+        let end = self.ranges.len();
+        if end - start == 1 && self.ranges[start].0 == self.ranges[start].1 {
+            // A single character doesn't need a class.
+            let (c, _) = self.ranges.pop().unwrap();
+            return Ok(self.push_hole(MaybeInst::Char { c: c }));
+        }
+        let (start, end) = (start as u32, end as u32);
+        let inst = InstRanges::new(0, &self.ranges, start, end);
+        Ok(self.push_hole(MaybeInst::Ranges { inst: inst }))
     }
 
     fn c_empty_look(&mut self, look: EmptyLook) -> CompileResult {
@@ -149,15 +243,56 @@ impl Compiler {
 
     fn c_alternate(&mut self, exprs: &[Expr]) -> CompileResult {
         assert!(exprs.len() >= 2, "alternates must have at least 2 exprs");
+        if exprs.len() > 2 {
+            return self.c_alternate_many(exprs);
+        }
+        let split = self.push_split_hole();
+        let goto1 = self.next_pc();
+        let hole1 = try!(self.c(&exprs[0]));
+        let goto2 = self.next_pc();
+        let hole2 = try!(self.c(&exprs[1]));
+        // An alternate that compiles to nothing (e.g., `c{0}`) has no
+        // instruction to go to, so its side of the split goes on to
+        // whatever follows the alternation instead.
+        let (goto1, goto2) = (
+            if goto1 == goto2 { None } else { Some(goto1) },
+            if goto2 == self.next_pc() { None } else { Some(goto2) },
+        );
+        if goto1.is_none() && goto2.is_none() {
+            self.insts.pop();
+            return Ok(Hole::None);
+        }
+        let split_hole = self.fill_split(split, goto1, goto2);
+        Ok(Hole::Many(vec![hole1, hole2, split_hole]))
+    }
+
+    /// Compiles an alternation of more than two expressions to a single
+    /// `SplitMany` instead of a chain of `Split`s, so that matching engines
+    /// don't have to walk the chain to get to the last alternates.
+    fn c_alternate_many(&mut self, exprs: &[Expr]) -> CompileResult {
+        let split = self.next_pc();
+        self.insts.push(MaybeInst::SplitMany);
+        let mut gotos = Vec::with_capacity(exprs.len());
         let mut holes = vec![];
-        for e in &exprs[0..exprs.len() - 1] {
-            let split = self.push_split_hole();
-            let goto1 = self.insts.len();
+        for e in exprs {
+            let goto = self.next_pc();
             holes.push(try!(self.c(e)));
-            let goto2 = self.insts.len();
-            self.fill_split(split, Some(goto1), Some(goto2));
+            // As in `c_alternate`, an empty alternate goes on to whatever
+            // follows the alternation.
+            let empty = goto == self.next_pc();
+            gotos.push(if empty { None } else { Some(goto) });
+        }
+        if gotos.iter().all(|goto| goto.is_none()) {
+            self.insts.pop();
+            return Ok(Hole::None);
+        }
+        if gotos.iter().all(|goto| goto.is_some()) {
+            let gotos = gotos.into_iter().map(|goto| goto.unwrap()).collect();
+            self.insts[split as usize].complete_split_many(gotos);
+        } else {
+            self.insts[split as usize] = MaybeInst::SplitManyHoles(gotos);
+            holes.push(Hole::One(split));
         }
-        holes.push(try!(self.c(&exprs[exprs.len() - 1])));
         Ok(Hole::Many(holes))
     }
 
@@ -186,7 +321,7 @@ impl Compiler {
         greedy: bool,
     ) -> CompileResult {
         let split = self.push_split_hole();
-        let goto1 = self.insts.len();
+        let goto1 = self.next_pc();
         let hole1 = try!(self.c(expr));
 
         let hole2 = if greedy {
@@ -202,9 +337,26 @@ impl Compiler {
         expr: &Expr,
         greedy: bool,
     ) -> CompileResult {
-        let goto_split = self.insts.len();
+        if matches_empty(expr) {
+            // If `expr` can match the empty string, then `expr*` is compiled
+            // as `(?:expr+)?`. Otherwise, an iteration of the loop that
+            // matches nothing jumps straight back to the split it started
+            // from, which the matching engines have already visited at this
+            // position, so the thread dies along with any captures it set.
+            // With `expr+`, such a thread leaves the loop instead.
+            let split = self.push_split_hole();
+            let goto_rep_expr = self.next_pc();
+            let hole_rep_expr = try!(self.c_repeat_one_or_more(expr, greedy));
+            let hole_skip = if greedy {
+                self.fill_split(split, Some(goto_rep_expr), None)
+            } else {
+                self.fill_split(split, None, Some(goto_rep_expr))
+            };
+            return Ok(Hole::Many(vec![hole_rep_expr, hole_skip]));
+        }
+        let goto_split = self.next_pc();
         let split = self.push_split_hole();
-        let goto_rep_expr = self.insts.len();
+        let goto_rep_expr = self.next_pc();
         let hole_rep_expr = try!(self.c(expr));
 
         self.fill(hole_rep_expr, goto_split);
@@ -220,7 +372,7 @@ impl Compiler {
         expr: &Expr,
         greedy: bool,
     ) -> CompileResult {
-        let goto_rep_expr = self.insts.len();
+        let goto_rep_expr = self.next_pc();
         let hole_rep_expr = try!(self.c(expr));
         self.fill_to_next(hole_rep_expr);
         let split = self.push_split_hole();
@@ -289,7 +441,7 @@ impl Compiler {
         for _ in min..max {
             self.fill_to_next(prev_hole);
             let split = self.push_split_hole();
-            let goto_rep_expr = self.insts.len();
+            let goto_rep_expr = self.next_pc();
             prev_hole = try!(self.c(expr));
             if greedy {
                 holes.push(self.fill_split(split, Some(goto_rep_expr), None));
@@ -305,7 +457,7 @@ impl Compiler {
         match hole {
             Hole::None => {}
             Hole::One(pc) => {
-                self.insts[pc].complete(goto);
+                self.insts[pc as usize].complete(goto);
             }
             Hole::Many(holes) => {
                 for hole in holes {
@@ -316,7 +468,7 @@ impl Compiler {
     }
 
     fn fill_to_next(&mut self, hole: Hole) {
-        let next = self.insts.len();
+        let next = self.next_pc();
         self.fill(hole, next);
     }
 
@@ -331,15 +483,15 @@ impl Compiler {
             Hole::One(pc) => {
                 match (goto1, goto2) {
                     (Some(goto1), Some(goto2)) => {
-                        self.insts[pc].complete_split(goto1, goto2);
+                        self.insts[pc as usize].complete_split(goto1, goto2);
                         Hole::None
                     }
                     (Some(goto1), None) => {
-                        self.insts[pc].complete_split_goto1(goto1);
+                        self.insts[pc as usize].complete_split_goto1(goto1);
                         Hole::One(pc)
                     }
                     (None, Some(goto2)) => {
-                        self.insts[pc].complete_split_goto2(goto2);
+                        self.insts[pc as usize].complete_split_goto2(goto2);
                         Hole::One(pc)
                     }
                     (None, None) => unreachable!("at least one of the split \
@@ -362,18 +514,25 @@ impl Compiler {
         }
     }
 
+    /// Returns the index of the next instruction to be pushed.
+    ///
+    /// `check_size` makes sure that it fits in an `InstIdx`.
+    fn next_pc(&self) -> InstIdx {
+        self.insts.len() as InstIdx
+    }
+
     fn push_compiled(&mut self, inst: Inst) {
         self.insts.push(MaybeInst::Compiled(inst));
     }
 
     fn push_hole(&mut self, inst: MaybeInst) -> Hole {
-        let hole = self.insts.len();
+        let hole = self.next_pc();
         self.insts.push(inst);
         Hole::One(hole)
     }
 
     fn push_split_hole(&mut self) -> Hole {
-        let hole = self.insts.len();
+        let hole = self.next_pc();
         self.insts.push(MaybeInst::Split);
         Hole::One(hole)
     }
@@ -381,12 +540,152 @@ impl Compiler {
     fn check_size(&self) -> Result<(), Error> {
         use std::mem::size_of;
 
-        if self.insts.len() * size_of::<Inst>() > self.size_limit {
+        let size = self.insts.len() * size_of::<Inst>()
+                   + self.ranges.len() * size_of::<(char, char)>();
+        // Instruction indices and offsets into the range table are stored
+        // in 32 bits, so neither may grow past that, whatever the limit.
+        let max = ::std::u32::MAX as usize;
+        if size > self.size_limit
+           || self.insts.len() >= max
+           || self.ranges.len() >= max {
             Err(Error::CompiledTooBig(self.size_limit))
         } else {
             Ok(())
         }
     }
+
+    /// Makes sure that no repetition of a repetition in `expr` exceeds the
+    /// size limit by itself.
+    ///
+    /// The size of such a repetition is the product of the counts, so it
+    /// can grow huge from a short expression. Compiling it would eventually
+    /// fail with `CompiledTooBig`, but only after a lot of work, and without
+    /// saying which part of the expression was to blame.
+    fn check_repetitions(&self, expr: &Expr) -> Result<(), Error> {
+        use std::mem::size_of;
+        use syntax::Expr::*;
+
+        match *expr {
+            Group { ref e, .. } => self.check_repetitions(e),
+            Concat(ref es) | Alternate(ref es) => {
+                for e in es {
+                    try!(self.check_repetitions(e));
+                }
+                Ok(())
+            }
+            Repeat { ref e, r, greedy } => {
+                let size =
+                    projected_len(expr).saturating_mul(size_of::<Inst>());
+                if size <= self.size_limit || !has_repeat(e) {
+                    return self.check_repetitions(e);
+                }
+                let suggestion = match r {
+                    Repeater::Range { min, max: Some(max) } if min < max => {
+                        let unbounded = Repeat {
+                            e: e.clone(),
+                            r: Repeater::Range { min: min, max: None },
+                            greedy: greedy,
+                        };
+                        let size = projected_len(&unbounded)
+                                   .saturating_mul(size_of::<Inst>());
+                        if size <= self.size_limit {
+                            Some(unbounded.to_string())
+                        } else {
+                            None
+                        }
+                    }
+                    _ => None,
+                };
+                Err(Error::RepetitionTooBig {
+                    repetition: expr.to_string(),
+                    size: size,
+                    limit: self.size_limit,
+                    suggestion: suggestion,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Returns the number of instructions `expr` compiles to (or `usize::MAX`
+/// if that doesn't fit).
+fn projected_len(expr: &Expr) -> usize {
+    use syntax::Expr::*;
+
+    match *expr {
+        Empty => 0,
+        Literal { ref chars, .. } => chars.len(),
+        Group { ref e, i: None, .. } => projected_len(e),
+        Group { ref e, .. } => projected_len(e).saturating_add(2),
+        Concat(ref es) => {
+            es.iter().fold(0, |n, e| n.saturating_add(projected_len(e)))
+        }
+        Alternate(ref es) => {
+            // Every alternate but the last is preceded by a split.
+            es.iter().fold(es.len() - 1,
+                           |n, e| n.saturating_add(projected_len(e)))
+        }
+        Repeat { ref e, r, .. } => {
+            let n = projected_len(e);
+            match r {
+                Repeater::ZeroOrMore
+                | Repeater::Range { min: 0, max: None }
+                if matches_empty(e) => {
+                    // Compiled as `(?:e+)?`.
+                    n.saturating_add(2)
+                }
+                Repeater::ZeroOrOne
+                | Repeater::ZeroOrMore
+                | Repeater::OneOrMore
+                | Repeater::Range { min: 0, max: None } => {
+                    n.saturating_add(1)
+                }
+                Repeater::Range { min, max: None } => {
+                    n.saturating_mul(u32_to_usize(min)).saturating_add(1)
+                }
+                Repeater::Range { min, max: Some(max) } => {
+                    let (min, max) = (u32_to_usize(min), u32_to_usize(max));
+                    n.saturating_mul(min).saturating_add(
+                        n.saturating_add(1).saturating_mul(max - min))
+                }
+            }
+        }
+        // Everything else is a single instruction.
+        _ => 1,
+    }
+}
+
+/// Returns true if `expr` contains a repetition.
+fn has_repeat(expr: &Expr) -> bool {
+    use syntax::Expr::*;
+
+    match *expr {
+        Repeat { .. } => true,
+        Group { ref e, .. } => has_repeat(e),
+        Concat(ref es) | Alternate(ref es) => es.iter().any(has_repeat),
+        _ => false,
+    }
+}
+
+/// Returns true if `expr` can match the empty string.
+fn matches_empty(expr: &Expr) -> bool {
+    use syntax::Expr::*;
+
+    match *expr {
+        Literal { .. } | AnyChar | AnyCharNoNL | Class(_) => false,
+        Group { ref e, .. } => matches_empty(e),
+        Repeat { ref e, r, .. } => match r {
+            Repeater::ZeroOrOne | Repeater::ZeroOrMore => true,
+            Repeater::Range { min: 0, .. } => true,
+            Repeater::OneOrMore | Repeater::Range { .. } => matches_empty(e),
+        },
+        Concat(ref es) => es.iter().all(matches_empty),
+        Alternate(ref es) => es.iter().any(matches_empty),
+        // Empty, all of the zero-width assertions and backreferences (the
+        // group may have matched nothing).
+        _ => true,
+    }
 }
 
 /// Hole represents a pointer to zero or more instructions in a regex program
@@ -426,8 +725,13 @@ enum MaybeInst {
     /// Split2 is a branch instruction where only the second goto field has
     /// been set.
     Split2(InstIdx),
+    /// SplitMany is a branch instruction whose goto fields have not been set.
+    SplitMany,
+    /// SplitManyHoles is a SplitMany instruction where only some goto fields
+    /// have been set. The rest all go to the same place.
+    SplitManyHoles(Vec<Option<InstIdx>>),
     /// Save is a capture instruction whose goto field has not been set.
-    Save { slot: usize },
+    Save { slot: u32 },
     /// EmptyLook is a zero-width assertion instruction whose goto field has
     /// not been set.
     EmptyLook { look: EmptyLook },
@@ -435,8 +739,11 @@ enum MaybeInst {
     /// set.
     Char { c: char },
     /// Ranges is a character-range-match instruction whose goto field has not
-    /// been set.
-    Ranges { ranges: Vec<(char, char)> },
+    /// been set. Its ranges have already been added to the range table.
+    Ranges { inst: InstRanges },
+    /// Backref is a backreference instruction whose goto field has not been
+    /// set.
+    Backref { slot: u32, casei: bool },
 }
 
 impl MaybeInst {
@@ -454,9 +761,13 @@ impl MaybeInst {
                 goto: goto,
                 c: c,
             }),
-            MaybeInst::Ranges { ref ranges } => Inst::Ranges(InstRanges {
+            MaybeInst::Ranges { ref inst } => {
+                Inst::Ranges(InstRanges { goto: goto, ..inst.clone() })
+            }
+            MaybeInst::Backref { slot, casei } => Inst::Backref(InstBackref {
                 goto: goto,
-                ranges: ranges.clone(),
+                slot: slot,
+                casei: casei,
             }),
             MaybeInst::Split1(goto1) => {
                 Inst::Split(InstSplit { goto1: goto1, goto2: goto })
@@ -464,6 +775,11 @@ impl MaybeInst {
             MaybeInst::Split2(goto2) => {
                 Inst::Split(InstSplit { goto1: goto, goto2: goto2 })
             }
+            MaybeInst::SplitManyHoles(ref gotos) => {
+                Inst::SplitMany(InstSplitMany {
+                    gotos: gotos.iter().map(|g| g.unwrap_or(goto)).collect(),
+                })
+            }
             _ => unreachable!("must be called on an uncompiled instruction \
                                with exactly one missing goto field, \
                                instead it was called on: {:?}", self),
@@ -482,6 +798,17 @@ impl MaybeInst {
         *self = MaybeInst::Compiled(filled);
     }
 
+    fn complete_split_many(&mut self, gotos: Vec<InstIdx>) {
+        let filled = match *self {
+            MaybeInst::SplitMany => {
+                Inst::SplitMany(InstSplitMany { gotos: gotos })
+            }
+            _ => unreachable!("must be called on SplitMany instruction, \
+                               instead it was called on: {:?}", self),
+        };
+        *self = MaybeInst::Compiled(filled);
+    }
+
     fn complete_split_goto1(&mut self, goto1: InstIdx) {
         let half_filled = match *self {
             MaybeInst::Split => goto1,
@@ -0,0 +1,192 @@
+// Copyright 2016 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Conversion of shell glob patterns into regular expressions.
+//!
+//! The following syntax is supported:
+//!
+//! <pre class="rust">
+//! ?       any single character except `/`
+//! *       any sequence of characters except `/`
+//! **      when it is an entire path component, any number of components
+//! [abc]   any character in the set (ranges such as `a-z` are allowed)
+//! [!abc]  any character except `/` that is not in the set (`[^abc]` also
+//!         works)
+//! \c      the literal character `c`
+//! </pre>
+//!
+//! Every other character matches itself. The resulting regex is anchored at
+//! both ends, so it must match the entire text. A `[` without a closing `]`
+//! is treated as a literal `[`.
+//!
+//! # Example
+//!
+//! ```rust
+//! # extern crate regex; use regex::glob;
+//! # fn main() {
+//! let re = glob::compile("src/**/*.rs").unwrap();
+//! assert!(re.is_match("src/lib.rs"));
+//! assert!(re.is_match("src/a/b/mod.rs"));
+//! assert!(!re.is_match("src/lib.rs.bak"));
+//! # }
+//! ```
+
+use re::{Error, Regex, push_class_literal, quote};
+
+/// Compiles a glob pattern into a `Regex` that matches an entire path.
+///
+/// See the module documentation for the supported syntax. An error is
+/// returned if a bracket expression contains an invalid range (such as
+/// `[z-a]`) or the compiled regex exceeds the default size limit.
+pub fn compile(glob: &str) -> Result<Regex, Error> {
+    Regex::new(&translate(glob))
+}
+
+/// Translates a glob pattern into the source of an equivalent regular
+/// expression.
+///
+/// The string returned is anchored at both ends and can be passed to
+/// `Regex::new` or combined with other expressions.
+pub fn translate(glob: &str) -> String {
+    let chars: Vec<char> = glob.chars().collect();
+    let mut re = String::with_capacity(glob.len() + 8);
+    re.push_str("(?s)^");
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '*' if chars.get(i + 1) == Some(&'*') => {
+                let at_start = i == 0 || chars[i - 1] == '/';
+                let next = chars.get(i + 2).cloned();
+                if at_start && next == Some('/') {
+                    // `**/` matches zero or more leading components.
+                    re.push_str("(?:.*/)?");
+                    i += 3;
+                } else if at_start && next.is_none() {
+                    re.push_str(".*");
+                    i += 2;
+                } else {
+                    // Not a whole component, so it behaves like `*`.
+                    re.push_str("[^/]*");
+                    i += 2;
+                }
+            }
+            '*' => { re.push_str("[^/]*"); i += 1; }
+            '?' => { re.push_str("[^/]"); i += 1; }
+            '[' => match parse_class(&chars, i + 1) {
+                Some((class, end)) => { re.push_str(&class); i = end; }
+                None => { re.push_str(r"\["); i += 1; }
+            },
+            '\\' if i + 1 < chars.len() => {
+                re.push_str(&quote(&chars[i + 1].to_string()));
+                i += 2;
+            }
+            c => { re.push_str(&quote(&c.to_string())); i += 1; }
+        }
+    }
+    re.push('$');
+    re
+}
+
+/// Parses a bracket expression whose contents begin at `start` (just after
+/// the opening `[`).
+///
+/// If the bracket expression is closed, then the equivalent regex class is
+/// returned along with the position just after the closing `]`.
+fn parse_class(chars: &[char], start: usize) -> Option<(String, usize)> {
+    let mut i = start;
+    let negated = match chars.get(i) {
+        Some(&'!') | Some(&'^') => { i += 1; true }
+        _ => false,
+    };
+    let mut class = String::from(if negated { "[^" } else { "[" });
+    let first = i;
+    loop {
+        let c = match chars.get(i) {
+            None => return None,
+            Some(&']') if i > first => break,
+            Some(&c) => c,
+        };
+        push_class_literal(&mut class, c);
+        i += 1;
+        // A `-` either ends the class or forms a range.
+        if chars.get(i) == Some(&'-')
+           && chars.get(i + 1).map_or(false, |&c| c != ']') {
+            class.push('-');
+            push_class_literal(&mut class, chars[i + 1]);
+            i += 2;
+        }
+    }
+    if negated {
+        class.push('/');
+    }
+    class.push(']');
+    Some((class, i + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compile, translate};
+
+    fn is_match(glob: &str, text: &str) -> bool {
+        compile(glob).unwrap().is_match(text)
+    }
+
+    #[test]
+    fn glob_wildcards() {
+        assert!(is_match("*.rs", "lib.rs"));
+        assert!(is_match("*.rs", ".rs"));
+        assert!(!is_match("*.rs", "src/lib.rs"));
+        assert!(!is_match("*.rs", "lib.rs.bak"));
+        assert!(is_match("a?c", "abc"));
+        assert!(!is_match("a?c", "a/c"));
+        assert!(!is_match("a?c", "ac"));
+    }
+
+    #[test]
+    fn glob_double_star() {
+        assert!(is_match("**/*.rs", "lib.rs"));
+        assert!(is_match("**/*.rs", "a/b/lib.rs"));
+        assert!(is_match("a/**/b", "a/b"));
+        assert!(is_match("a/**/b", "a/x/y/b"));
+        assert!(!is_match("a/**/b", "ab"));
+        assert!(is_match("a/**", "a/x/y"));
+        assert!(!is_match("a/**", "ab"));
+        assert!(is_match("a**b", "axxb"));
+        assert!(!is_match("a**b", "a/b"));
+    }
+
+    #[test]
+    fn glob_classes() {
+        assert!(is_match("[abc].txt", "b.txt"));
+        assert!(!is_match("[abc].txt", "d.txt"));
+        assert!(is_match("[a-c]", "b"));
+        assert!(is_match("[!a-c]", "d"));
+        assert!(!is_match("[!a-c]", "b"));
+        assert!(!is_match("[!a-c]", "/"));
+        assert!(is_match("[]]", "]"));
+        assert!(is_match("[a-]", "-"));
+        assert!(is_match("[!-]", "a"));
+        assert!(!is_match("[!-]", "-"));
+        assert!(is_match("[[^\\]", "^"));
+        assert!(is_match("[[^\\]", "\\"));
+        assert!(is_match("x[", "x["));
+        assert!(compile("[z-a]").is_err());
+    }
+
+    #[test]
+    fn glob_escapes() {
+        assert!(is_match(r"\*", "*"));
+        assert!(!is_match(r"\*", "a"));
+        assert!(is_match("a.b(c)+$", "a.b(c)+$"));
+        assert!(!is_match("a.b", "axb"));
+        assert!(is_match("a\\", "a\\"));
+        assert_eq!(translate("a*"), "(?s)^a[^/]*$");
+    }
+}
@@ -8,7 +8,7 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use std::ops;
+use memchr::memchr;
 
 use char::Char;
 use prefix::Prefix;
@@ -52,13 +52,70 @@ impl InputAt {
 }
 
 /// An abstraction over input used in the matching engines.
+///
+/// Positions are byte offsets into the input, from `0` to `len()`
+/// inclusive, and always fall on character boundaries. Decoding a position
+/// yields an `InputAt`, which carries the character there (if any), so the
+/// engines only decode each character once as they step through the input.
+///
+/// Only the first four methods have to be implemented. The scans for
+/// prefixes and lines are built on them.
 pub trait Input {
-    /// Return an encoding of the position at byte offset `i`.
-    fn at(&self, i: usize) -> InputAt;
-    /// Return an encoding of the char position just prior to byte offset `i`.
-    fn previous_at(&self, i: usize) -> InputAt;
+    /// Returns the length of the input, in bytes.
+    fn len(&self) -> usize;
+    /// Returns the input as bytes, e.g., for a fast literal search.
+    fn as_bytes(&self) -> &[u8];
+    /// Decodes the character starting at byte offset `i`.
+    ///
+    /// At `len()`, the character is absent.
+    fn decode_at(&self, i: usize) -> InputAt;
+    /// Decodes the character ending at byte offset `i`, i.e., the one just
+    /// before it. The position returned is where that character starts.
+    ///
+    /// At `0`, the character is absent and the position is `0`.
+    fn decode_before(&self, i: usize) -> InputAt;
+
     /// Scan the input for a matching prefix.
-    fn prefix_at(&self, prefixes: &Prefix, at: InputAt) -> Option<InputAt>;
+    ///
+    /// The prefix must occur `offset` characters after the position
+    /// returned, which is where a match may start.
+    fn prefix_at(
+        &self,
+        prefixes: &Prefix,
+        offset: usize,
+        at: InputAt,
+    ) -> Option<InputAt> {
+        if offset == 0 {
+            return prefixes.find(&self.as_bytes()[at.pos()..])
+                           .map(|(s, _)| self.decode_at(at.pos() + s));
+        }
+        // Start looking `offset` characters ahead, so that stepping back
+        // from a prefix never goes past `at`.
+        let mut pos = at.pos();
+        for _ in 0..offset {
+            let next = self.decode_at(pos);
+            if next.char().is_none() {
+                return None;
+            }
+            pos = next.next_pos();
+        }
+        prefixes.find(&self.as_bytes()[pos..]).map(|(s, _)| {
+            let mut start = pos + s;
+            for _ in 0..offset {
+                start = self.decode_before(start).pos();
+            }
+            self.decode_at(start)
+        })
+    }
+
+    /// Scan the input for the start of a line, which may be `at` itself.
+    fn line_start_at(&self, at: InputAt) -> Option<InputAt> {
+        let (pos, bytes) = (at.pos(), self.as_bytes());
+        if pos == 0 || bytes[pos - 1] == b'\n' {
+            return Some(at);
+        }
+        memchr(b'\n', &bytes[pos..]).map(|i| self.decode_at(pos + i + 1))
+    }
 }
 
 /// An input reader over characters.
@@ -72,25 +129,30 @@ impl<'t> CharInput<'t> {
     pub fn new(s: &'t str) -> CharInput<'t> {
         CharInput(s)
     }
-}
 
-impl<'t> ops::Deref for CharInput<'t> {
-    type Target = str;
-
-    fn deref(&self) -> &str {
+    /// Returns the text being read.
+    pub fn as_str(&self) -> &'t str {
         self.0
     }
 }
 
 impl<'t> Input for CharInput<'t> {
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        self.0.as_bytes()
+    }
+
     // This `inline(always)` increases throughput by almost 25% on the `hard`
     // benchmarks over a normal `inline` annotation.
     //
     // I'm not sure why `#[inline]` isn't enough to convince LLVM, but it is
     // used *a lot* in the guts of the matching engines.
     #[inline(always)]
-    fn at(&self, i: usize) -> InputAt {
-        let c = self[i..].chars().next().into();
+    fn decode_at(&self, i: usize) -> InputAt {
+        let c = self.0[i..].chars().next().into();
         InputAt {
             pos: i,
             c: c,
@@ -98,8 +160,8 @@ impl<'t> Input for CharInput<'t> {
         }
     }
 
-    fn previous_at(&self, i: usize) -> InputAt {
-        let c: Char = self[..i].chars().rev().next().into();
+    fn decode_before(&self, i: usize) -> InputAt {
+        let c: Char = self.0[..i].chars().rev().next().into();
         let len = c.len_utf8();
         InputAt {
             pos: i - len,
@@ -107,8 +169,4 @@ impl<'t> Input for CharInput<'t> {
             len: len,
         }
     }
-
-    fn prefix_at(&self, prefixes: &Prefix, at: InputAt) -> Option<InputAt> {
-        prefixes.find(&self[at.pos()..]).map(|(s, _)| self.at(at.pos() + s))
-    }
 }
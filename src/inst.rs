@@ -1,21 +1,35 @@
-use std::cmp::Ordering;
+use std::cmp::{self, Ordering};
+
+use syntax::{CharClass, ClassRange};
 
 use char::Char;
 
 /// InstIdx represents the index of an instruction in a regex program.
-pub type InstIdx = usize;
+///
+/// The compiler refuses to build a program with more instructions than fit
+/// in a `u32`, which is far more than the size limit allows in practice.
+/// Using a `u32` instead of a `usize` keeps instructions small on 64 bit
+/// targets.
+pub type InstIdx = u32;
 
 /// Inst is an instruction code in a Regex program.
 #[derive(Clone, Debug)]
 pub enum Inst {
     /// Match indicates that the program has reached a match state.
-    Match,
+    ///
+    /// The index is the position of the pattern that matched in the list of
+    /// patterns the program was compiled from. (It is always `0` for a
+    /// program compiled from a single pattern.)
+    Match(usize),
     /// Save causes the program to save the current location of the input in
     /// the slot indicated by InstSave.
     Save(InstSave),
     /// Split causes the program to diverge to one of two paths in the
     /// program, preferring goto1 in InstSplit.
     Split(InstSplit),
+    /// SplitMany causes the program to diverge to any of several paths in
+    /// the program, preferring them in the order of InstSplitMany's gotos.
+    SplitMany(InstSplitMany),
     /// EmptyLook represents a zero-width assertion in a regex program. A
     /// zero-width assertion does not consume any of the input text.
     EmptyLook(InstEmptyLook),
@@ -25,6 +39,10 @@ pub enum Inst {
     /// Ranges requires the regex program to match the character at the current
     /// position in the input with one of the ranges specified in InstRanges.
     Ranges(InstRanges),
+    /// Backref requires the regex program to match the text that a capture
+    /// group last matched, starting at the current position in the input.
+    /// Only the backtracking engine can execute it.
+    Backref(InstBackref),
 }
 
 /// Representation of the Save instruction.
@@ -34,7 +52,7 @@ pub struct InstSave {
     pub goto: InstIdx,
     /// The capture slot (there are two slots for every capture in a regex,
     /// including the zeroth capture for the entire match).
-    pub slot: usize,
+    pub slot: u32,
 }
 
 /// Representation of the Split instruction.
@@ -48,6 +66,17 @@ pub struct InstSplit {
     pub goto2: InstIdx,
 }
 
+/// Representation of the SplitMany instruction.
+///
+/// This is equivalent to a chain of Split instructions, but lets a wide
+/// alternation like `a|b|c|...` branch out in one step.
+#[derive(Clone, Debug)]
+pub struct InstSplitMany {
+    /// The instructions to try. A match resulting from following an earlier
+    /// goto has precedence over a match resulting from following a later one.
+    pub gotos: Vec<InstIdx>,
+}
+
 /// Representation of the EmptyLook instruction.
 #[derive(Clone, Debug)]
 pub struct InstEmptyLook {
@@ -105,23 +134,74 @@ pub struct InstChar {
 }
 
 /// Representation of the Ranges instruction.
+///
+/// The ranges themselves aren't stored in the instruction. They live in a
+/// single table shared by every class in the program, so that instructions
+/// have a fixed size and sit next to each other in memory.
 #[derive(Clone, Debug)]
 pub struct InstRanges {
     /// The next location to execute in the program if this instruction
     /// succeeds.
     pub goto: InstIdx,
-    /// The set of Unicode scalar value ranges to test.
-    pub ranges: Vec<(char, char)>,
+    /// The start (inclusive) of this class's ranges in the program's range
+    /// table. The ranges are sorted and non-overlapping.
+    pub start: u32,
+    /// The end (exclusive) of this class's ranges in the program's range
+    /// table.
+    pub end: u32,
+    /// A bitmap of the ASCII characters in the ranges, where character `c`
+    /// corresponds to bit `c % 64` of word `c / 64`.
+    pub ascii: [u64; 2],
 }
 
 impl InstRanges {
+    /// Creates a new Ranges instruction for the sorted sequence of ranges
+    /// `table[start..end]`.
+    pub fn new(
+        goto: InstIdx,
+        table: &[(char, char)],
+        start: u32,
+        end: u32,
+    ) -> InstRanges {
+        let mut ascii = [0u64; 2];
+        for &(s, e) in &table[start as usize..end as usize] {
+            if s > '\x7f' {
+                break;
+            }
+            for b in (s as usize)..(cmp::min(e as usize, 0x7f) + 1) {
+                ascii[b / 64] |= 1 << (b % 64);
+            }
+        }
+        InstRanges {
+            goto: goto,
+            start: start,
+            end: end,
+            ascii: ascii,
+        }
+    }
+
+    /// Returns the ranges of this class, given the program's range table.
+    #[inline(always)]
+    pub fn ranges<'t>(
+        &self,
+        table: &'t [(char, char)],
+    ) -> &'t [(char, char)] {
+        &table[self.start as usize..self.end as usize]
+    }
+
     /// Tests whether the given input character matches this instruction.
     #[inline(always)] // About ~5-15% more throughput then `#[inline]`
-    pub fn matches(&self, c: Char) -> bool {
-        // This speeds up the `match_class_unicode` benchmark by checking
-        // some common cases quickly without binary search. e.g., Matching
-        // a Unicode class on predominantly ASCII text.
-        for r in self.ranges.iter().take(4) {
+    pub fn matches(&self, table: &[(char, char)], c: Char) -> bool {
+        // Most text is ASCII, for which membership is a single bit test, no
+        // matter how big the class is.
+        if let Some(b) = c.as_ascii() {
+            let b = b as usize;
+            return self.ascii[b / 64] & (1 << (b % 64)) != 0;
+        }
+        // Otherwise, check the first few ranges quickly without binary
+        // search. e.g., Classes with only a handful of ranges.
+        let ranges = self.ranges(table);
+        for r in ranges.iter().take(4) {
             if c < r.0 {
                 return false;
             }
@@ -129,7 +209,7 @@ impl InstRanges {
                 return true;
             }
         }
-        self.ranges.binary_search_by(|r| {
+        ranges.binary_search_by(|r| {
             if r.1 < c {
                 Ordering::Less
             } else if r.0 > c {
@@ -140,3 +220,51 @@ impl InstRanges {
         }).is_ok()
     }
 }
+
+/// Representation of the Backref instruction.
+#[derive(Clone, Debug)]
+pub struct InstBackref {
+    /// The next location to execute in the program if this instruction
+    /// succeeds.
+    pub goto: InstIdx,
+    /// The first of the two capture slots of the group referred to.
+    pub slot: u32,
+    /// Whether to match case insensitively.
+    pub casei: bool,
+}
+
+impl InstBackref {
+    /// Tests whether the text at `at` starts with `captured`, the text the
+    /// group matched. If it does, the end of the match is returned.
+    pub fn matches(
+        &self,
+        text: &str,
+        at: usize,
+        captured: &str,
+    ) -> Option<usize> {
+        let rest = &text[at..];
+        if !self.casei {
+            return if rest.starts_with(captured) {
+                Some(at + captured.len())
+            } else {
+                None
+            };
+        }
+        let mut chars = rest.chars();
+        for c1 in captured.chars() {
+            match chars.next() {
+                Some(c2) if c1 == c2 => {}
+                Some(c2) => {
+                    let folded = CharClass::new(vec![
+                        ClassRange { start: c1, end: c1 },
+                    ]).case_fold();
+                    if !folded.matches(c2) {
+                        return None;
+                    }
+                }
+                None => return None,
+            }
+        }
+        Some(text.len() - chars.as_str().len())
+    }
+}
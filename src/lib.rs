@@ -10,10 +10,11 @@
 
 //! This crate provides a native implementation of regular expressions that is
 //! heavily based on RE2 both in syntax and in implementation. Notably,
-//! backreferences and arbitrary lookahead/lookbehind assertions are not
-//! provided. In return, regular expression searching provided by this package
-//! has excellent worst-case performance. The specific syntax supported is
-//! documented further down.
+//! backreferences (unless enabled with `RegexBuilder::backreferences`) and
+//! arbitrary lookahead/lookbehind assertions are not provided. In return,
+//! regular expression searching provided by this package has excellent
+//! worst-case performance. The specific syntax supported is documented
+//! further down.
 //!
 //! This crate's documentation provides some simple examples, describes Unicode
 //! support and exhaustively lists the supported syntax. For more specific
@@ -302,8 +303,15 @@
 //! (?:exp)        non-capturing group
 //! (?flags)       set flags within current group
 //! (?flags:exp)   set flags for exp (non-capturing)
+//! (?P=name)      backreference: the text last matched by the named group
+//! \k&lt;name&gt;       backreference (same as (?P=name))
 //! </pre>
 //!
+//! Backreferences are only accepted by a regex compiled with
+//! `RegexBuilder::backreferences` enabled, and a backreference must come
+//! after the group it refers to. Everywhere else, an expression with one is
+//! reported as `Error::Unsupported`.
+//!
 //! Flags are each a single character. For example, `(?x)` sets the flag `x`
 //! and `(?-x)` clears the flag `x`. Multiple flags can be set or cleared at
 //! the same time: `(?xy)` sets both the `x` and `y` flags and `(?x-y)` sets
@@ -397,6 +405,10 @@
 //! text`), which means there's no way to cause exponential blow-up like with
 //! some other regular expression engines. (We pay for this by disallowing
 //! features like arbitrary look-ahead and backreferences.)
+//!
+//! Enabling backreferences with `RegexBuilder::backreferences` gives up
+//! this guarantee for the regexes compiled that way. (See its documentation
+//! for how their searches are bounded instead.)
 
 #![deny(missing_docs)]
 #![cfg_attr(test, deny(warnings))]
@@ -406,26 +418,63 @@
        html_root_url = "https://doc.rust-lang.org/regex/")]
 
 extern crate aho_corasick;
+#[cfg(feature = "log")]
+#[macro_use]
+extern crate log;
 extern crate memchr;
 extern crate regex_syntax as syntax;
 
+// With the `log` feature, how regexes are compiled and searched is logged
+// with the `log` crate. Without it, logging compiles to nothing.
+#[cfg(not(feature = "log"))]
+macro_rules! debug {
+    ($($arg:tt)*) => {}
+}
+#[cfg(not(feature = "log"))]
+macro_rules! trace {
+    ($($arg:tt)*) => {}
+}
+
 pub use re::{
     Regex, Error, Captures, SubCaptures, SubCapturesPos, SubCapturesNamed,
-    CaptureNames, FindCaptures, FindMatches,
-    Replacer, NoExpand, RegexSplits, RegexSplitsN,
+    SubCapturesGroups, CaptureNames, GroupInfo, GroupSpan, TemplateError,
+    FindCaptures, FindMatches, FromCaptures, CaptureError, Match,
+    BudgetedMatches, MatchStep, Scratch,
+    Replacer, NoExpand, Numbered, TryReplacer, ReplaceSet, RegexSplits,
+    RegexSplitsN, RegexSplitsInclusive, RegexSplitsPos, RegexSplitsWith,
+    SplitOptions, RequiredSuffixes,
     quote, is_match,
 };
+pub use pattern::Pattern;
+pub use profile::{EngineProfile, SearchCounts};
+pub use re_builder::{Engine, RegexBuilder};
+pub use set::{
+    RegexSet, SetMatches, SetMatchesIntoIter, SetMatchesIter, MatchPolicy,
+    PatternFlags, RegexSetBuilder,
+};
+#[cfg(feature = "trace")]
+pub use trace::TraceEvent;
 
 mod backtrack;
 mod char;
 mod compile;
+pub mod glob;
 mod input;
 mod inst;
+pub mod like;
+mod optimize;
+mod pattern;
 mod pool;
+pub mod posix;
 mod prefix;
+mod profile;
 mod program;
 mod nfa;
 mod re;
+mod re_builder;
+mod set;
+mod submatch;
+mod trace;
 
 /// The `internal` module exists to support the `regex!` macro and other
 /// suspicious activity, such as testing different matching engines.
@@ -0,0 +1,119 @@
+// Copyright 2016 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Conversion of SQL `LIKE` patterns into regular expressions.
+//!
+//! In a `LIKE` pattern, `%` matches any sequence of characters (including
+//! none) and `_` matches exactly one character. Every other character
+//! matches itself. If an escape character is given, then it makes the
+//! character following it match literally, so that, e.g., with `\` as the
+//! escape character, `50\%` matches only the text `50%`. An escape character
+//! at the very end of a pattern matches itself.
+//!
+//! As in SQL, the whole text must match the pattern.
+//!
+//! # Example
+//!
+//! ```rust
+//! # extern crate regex; use regex::like;
+//! # fn main() {
+//! let re = like::compile("J_n%", None).unwrap();
+//! assert!(re.is_match("January"));
+//! assert!(!re.is_match("june"));
+//!
+//! let re = like::compile_ilike("J_n%", None).unwrap();
+//! assert!(re.is_match("june"));
+//! # }
+//! ```
+
+use re::{Error, Regex, quote};
+
+/// Compiles a `LIKE` pattern into a `Regex`.
+///
+/// `escape` is the character given by the `ESCAPE` clause, if any.
+pub fn compile(pattern: &str, escape: Option<char>) -> Result<Regex, Error> {
+    Regex::new(&translate(pattern, escape))
+}
+
+/// Compiles an `ILIKE` pattern into a `Regex`.
+///
+/// This is the same as `compile`, except the regex matches without regard
+/// to case.
+pub fn compile_ilike(pattern: &str, escape: Option<char>)
+                     -> Result<Regex, Error> {
+    Regex::new(&format!("(?i){}", translate(pattern, escape)))
+}
+
+/// Translates a `LIKE` pattern into the source of an equivalent regular
+/// expression.
+///
+/// The string returned is anchored at both ends.
+pub fn translate(pattern: &str, escape: Option<char>) -> String {
+    let mut re = String::with_capacity(pattern.len() + 8);
+    re.push_str("(?s)^");
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        if Some(c) == escape {
+            let lit = chars.next().unwrap_or(c);
+            re.push_str(&quote(&lit.to_string()));
+            continue;
+        }
+        match c {
+            '%' => re.push_str(".*"),
+            '_' => re.push('.'),
+            c => re.push_str(&quote(&c.to_string())),
+        }
+    }
+    re.push('$');
+    re
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compile, compile_ilike, translate};
+
+    fn is_match(pattern: &str, escape: Option<char>, text: &str) -> bool {
+        compile(pattern, escape).unwrap().is_match(text)
+    }
+
+    #[test]
+    fn like_wildcards() {
+        assert!(is_match("a%", None, "a"));
+        assert!(is_match("a%", None, "abc\ndef"));
+        assert!(!is_match("a%", None, "ba"));
+        assert!(is_match("%b%", None, "abc"));
+        assert!(is_match("a_c", None, "abc"));
+        assert!(is_match("a_c", None, "a\u{2603}c"));
+        assert!(!is_match("a_c", None, "ac"));
+        assert!(!is_match("a_c", None, "abbc"));
+        assert!(is_match("a.c+", None, "a.c+"));
+        assert!(!is_match("a.c", None, "abc"));
+        assert_eq!(translate("a%_", None), "(?s)^a.*.$");
+    }
+
+    #[test]
+    fn like_escape() {
+        assert!(is_match(r"50\%", Some('\\'), "50%"));
+        assert!(!is_match(r"50\%", Some('\\'), "500"));
+        assert!(is_match("a!_b", Some('!'), "a_b"));
+        assert!(!is_match("a!_b", Some('!'), "axb"));
+        assert!(is_match("a!!", Some('!'), "a!"));
+        assert!(is_match("a!", Some('!'), "a!"));
+        // Without an escape character, `\` is just another literal.
+        assert!(is_match(r"a\%", None, r"a\bc"));
+    }
+
+    #[test]
+    fn ilike() {
+        let re = compile_ilike("%ÉTÉ%", None).unwrap();
+        assert!(re.is_match("un été chaud"));
+        assert!(!compile("%ÉTÉ%", None).unwrap().is_match("un été chaud"));
+    }
+}
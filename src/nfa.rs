@@ -37,92 +37,435 @@
 //
 // [1] - http://swtch.com/~rsc/regex/regex3.html
 
+use std::usize;
+
+use char::Char;
+use inst::InstIdx;
 use input::{Input, InputAt, CharInput};
+use profile::SearchCounts;
 use program::Program;
 use re::CaptureIdxs;
+use trace::TraceEvent;
 
 /// An NFA simulation matching engine.
 #[derive(Debug)]
 pub struct Nfa<'r, 't> {
     prog: &'r Program,
     input: CharInput<'t>,
+    scan_prefixes: bool,
+    /// The instruction that new threads start at. This is `0`, unless the
+    /// search is for a single pattern of a program compiled from several.
+    start_pc: InstIdx,
 }
 
 impl<'r, 't> Nfa<'r, 't> {
     /// Execute the NFA matching engine.
     ///
-    /// If there's a match, `exec` returns `true` and populates the given
-    /// captures accordingly.
+    /// If there's a match, `exec` returns the index of the pattern that
+    /// matched and populates the given captures accordingly.
+    ///
+    /// When `scan_prefixes` is false, the program's literal prefixes are not
+    /// used to skip ahead in the input.
     pub fn exec(
         prog: &'r Program,
         mut caps: &mut CaptureIdxs,
         text: &'t str,
         start: usize,
+        scan_prefixes: bool,
+    ) -> Option<usize> {
+        let mut q = prog.nfa_threads.get();
+        let input = CharInput::new(text);
+        let at = input.decode_at(start);
+        Nfa {
+            prog: prog,
+            input: input,
+            scan_prefixes: scan_prefixes,
+            start_pc: 0,
+        }.exec_(&mut q, &mut caps, &mut [], at)
+    }
+
+    /// Execute the NFA matching engine with the given thread lists instead
+    /// of ones taken from the program's pool.
+    ///
+    /// If `q` was created by `NfaThreads::with_capacity` for `prog`, then
+    /// the search doesn't allocate.
+    pub fn exec_with(
+        prog: &'r Program,
+        q: &mut NfaThreads,
+        mut caps: &mut CaptureIdxs,
+        text: &'t str,
+        start: usize,
+        scan_prefixes: bool,
+    ) -> Option<usize> {
+        let input = CharInput::new(text);
+        let at = input.decode_at(start);
+        Nfa {
+            prog: prog,
+            input: input,
+            scan_prefixes: scan_prefixes,
+            start_pc: 0,
+        }.exec_(q, &mut caps, &mut [], at)
+    }
+
+    /// Execute the NFA matching engine for the pattern that starts at
+    /// instruction `start_pc`.
+    pub fn exec_pattern(
+        prog: &'r Program,
+        start_pc: InstIdx,
+        mut caps: &mut CaptureIdxs,
+        text: &'t str,
+        start: usize,
+    ) -> Option<usize> {
+        let mut q = prog.nfa_threads.get();
+        let input = CharInput::new(text);
+        let at = input.decode_at(start);
+        Nfa {
+            prog: prog,
+            input: input,
+            scan_prefixes: false,
+            start_pc: start_pc,
+        }.exec_(&mut q, &mut caps, &mut [], at)
+    }
+
+    /// Execute the NFA matching engine to find every pattern that matches.
+    ///
+    /// Instead of stopping at the leftmost-first match, the whole input is
+    /// scanned (unless every pattern has matched) and `matches[i]` is set
+    /// for every pattern `i` that matches somewhere.
+    pub fn exec_many(
+        prog: &'r Program,
+        matches: &mut [bool],
+        text: &'t str,
+        start: usize,
+    ) -> bool {
+        let mut q = prog.nfa_threads.get();
+        let input = CharInput::new(text);
+        let at = input.decode_at(start);
+        let mut found: Vec<Option<usize>> =
+            matches.iter().map(|&m| if m { Some(start) } else { None })
+                   .collect();
+        let any = Nfa {
+            prog: prog,
+            input: input,
+            scan_prefixes: true,
+            start_pc: 0,
+        }.exec_(&mut q, &mut [], &mut found, at).is_some();
+        for (m, f) in matches.iter_mut().zip(found) {
+            *m = f.is_some();
+        }
+        any
+    }
+
+    /// Execute the NFA matching engine to find where the leftmost match of
+    /// every pattern starts.
+    ///
+    /// This is like `exec_many`, except that `starts[i]` is set to the start
+    /// of the leftmost match of pattern `i`. So the search goes on after
+    /// every pattern has matched, until no thread left could start a match
+    /// any earlier.
+    pub fn exec_starts(
+        prog: &'r Program,
+        starts: &mut [Option<usize>],
+        text: &'t str,
+        start: usize,
     ) -> bool {
         let mut q = prog.nfa_threads.get();
         let input = CharInput::new(text);
-        let at = input.at(start);
+        let at = input.decode_at(start);
         Nfa {
             prog: prog,
             input: input,
-        }.exec_(&mut q, &mut caps, at)
+            scan_prefixes: true,
+            start_pc: 0,
+        }.exec_(&mut q, &mut [None, None], starts, at).is_some()
+    }
+
+    /// Execute the NFA matching engine to count all successive
+    /// non-overlapping matches of the pattern that starts at instruction
+    /// `start_pc`, starting at `start`.
+    ///
+    /// The matches follow the same rules as `Regex::find_iter`, but nothing
+    /// is kept for any of them. The engine is set up once, but each search
+    /// starts over at the end of the previous match.
+    pub fn count_all(
+        prog: &'r Program,
+        start_pc: InstIdx,
+        text: &'t str,
+        start: usize,
+        scan_prefixes: bool,
+    ) -> usize {
+        let mut count = 0;
+        Nfa::each_match(prog, start_pc, text, start, scan_prefixes,
+                        |_, _| count += 1);
+        count
+    }
+
+    fn each_match<F: FnMut(usize, usize)>(
+        prog: &'r Program,
+        start_pc: InstIdx,
+        text: &'t str,
+        mut start: usize,
+        scan_prefixes: bool,
+        mut found: F,
+    ) {
+        let mut q = prog.nfa_threads.get();
+        let mut nfa = Nfa {
+            prog: prog,
+            input: CharInput::new(text),
+            scan_prefixes: scan_prefixes,
+            start_pc: start_pc,
+        };
+        let mut last_match = None;
+        while start <= text.len() {
+            let mut caps = [None, None];
+            let at = nfa.input.decode_at(start);
+            if nfa.exec_(&mut q, &mut caps, &mut [], at).is_none() {
+                break;
+            }
+            let (s, e) = (caps[0].unwrap(), caps[1].unwrap());
+            // Don't accept empty matches immediately following a match.
+            if s == e && Some(e) == last_match {
+                if e >= text.len() {
+                    break;
+                }
+                start = nfa.input.decode_at(e).next_pos();
+                continue;
+            }
+            found(s, e);
+            start = e;
+            last_match = Some(e);
+        }
+    }
+
+    /// Execute the NFA matching engine for at most `budget` steps, where a
+    /// step is the work done at one position in the text.
+    ///
+    /// If the search isn't over by then, the state of the search is kept in
+    /// `q` (and `caps`) and the position it reached is returned as an error.
+    /// Passing both back in `resume` continues the search. (`start` is
+    /// ignored in that case.)
+    ///
+    /// The literal prefixes aren't used, since scanning for them isn't
+    /// bounded by the budget.
+    pub fn exec_budget(
+        prog: &'r Program,
+        q: &mut NfaThreads,
+        caps: &mut CaptureIdxs,
+        text: &'t str,
+        start: usize,
+        resume: Option<NfaSuspended>,
+        budget: usize,
+    ) -> Result<Option<usize>, NfaSuspended> {
+        let mut nfa = Nfa {
+            prog: prog,
+            input: CharInput::new(text),
+            scan_prefixes: false,
+            start_pc: 0,
+        };
+        match resume {
+            None => {
+                let empty = nfa.start(q, caps);
+                let at = nfa.input.decode_at(start);
+                nfa.run(q, caps, &mut [], at, None, empty, budget)
+            }
+            Some(s) => {
+                let at = nfa.input.decode_at(s.at);
+                nfa.run(q, caps, &mut [], at, s.matched, s.empty, budget)
+            }
+        }
     }
 
     fn exec_(
+        &mut self,
+        q: &mut NfaThreads,
+        caps: &mut CaptureIdxs,
+        matches: &mut [Option<usize>],
+        at: InputAt,
+    ) -> Option<usize> {
+        let empty = self.start(q, caps);
+        match self.run(q, caps, matches, at, None, empty, usize::MAX) {
+            Ok(matched) => matched,
+            // Every step covers a position in the text, so there's no way
+            // to use up this budget.
+            Err(_) => unreachable!(),
+        }
+    }
+
+    /// Prepares `q` and `caps` for a new search and returns the capture set
+    /// that new threads start out with.
+    fn start(&self, q: &mut NfaThreads, caps: &mut CaptureIdxs) -> usize {
+        q.clist.empty(); q.nlist.empty();
+        // Every new thread starts out with this set of empty captures.
+        q.sets.reset(caps.len());
+        if self.prog.longest_match {
+            // Matches are compared against the best one so far, so there
+            // must not be one to start with.
+            for slot in caps.iter_mut() {
+                *slot = None;
+            }
+        }
+        q.sets.alloc_empty()
+    }
+
+    /// When `matches` isn't empty, the search is for every pattern that
+    /// matches, and `matches[i]` is where a match of pattern `i` was found.
+    /// If `caps` has room for the overall match, that's where the leftmost
+    /// match of the pattern starts, and the search only stops once no
+    /// thread left could start one any earlier.
+    fn run(
         &mut self,
         mut q: &mut NfaThreads,
         mut caps: &mut CaptureIdxs,
+        matches: &mut [Option<usize>],
         mut at: InputAt,
-    ) -> bool {
-        let mut matched = false;
-        q.clist.empty(); q.nlist.empty();
+        mut matched: Option<usize>,
+        empty: usize,
+        mut budget: usize,
+    ) -> Result<Option<usize>, NfaSuspended> {
+        let all_matches = !matches.is_empty();
+        let mut num_matched = matches.iter().filter(|m| m.is_some()).count();
+        // Whether the leftmost start of each pattern's matches is wanted.
+        let starts = all_matches && caps.len() >= 2;
+        // The character before `at`, which is needed to evaluate zero-width
+        // assertions. It's carried over from one step to the next, so it only
+        // needs to be decoded again when we jump ahead.
+        let mut prev = self.input.decode_before(at.pos()).char();
+        // Where every match has to start, if anywhere.
+        let anchor = if self.prog.anchored_start {
+            Some(at.pos())
+        } else {
+            None
+        };
+        // The work done, for the program's prefix statistics and counters.
+        let mut counts = SearchCounts::default();
 'LOOP:  loop {
+            if budget == 0 {
+                return Err(NfaSuspended {
+                    at: at.pos(),
+                    matched: matched,
+                    empty: empty,
+                });
+            }
+            budget -= 1;
             if q.clist.size == 0 {
                 // Three ways to bail out when our current set of threads is
                 // empty.
                 //
                 // 1. We have a match---so we're done exploring any possible
-                //    alternatives.  Time to quit.
+                //    alternatives.  Time to quit. (Unless we're looking for
+                //    every pattern that matches.)
                 //
-                // 2. If the expression starts with a '^' we can terminate as
-                //    soon as the last thread dies.
-                if matched
-                   || (!at.is_beginning() && self.prog.anchored_begin) {
+                // 2. If the expression starts with a '^' (or the search is
+                //    anchored) we can terminate as soon as the last thread
+                //    dies.
+                if (matched.is_some() && !all_matches)
+                   || (all_matches && num_matched == matches.len())
+                   || (!at.is_beginning() && self.prog.anchored_begin)
+                   || anchor.map_or(false, |pos| at.pos() != pos) {
                     break;
                 }
 
                 // 3. If there's a literal prefix for the program, try to
                 //    jump ahead quickly. If it can't be found, then we can
-                //    bail out early.
-                if !self.prog.prefixes.is_empty() {
-                    at = match self.input.prefix_at(&self.prog.prefixes, at) {
+                //    bail out early. Similarly, if every match must start at
+                //    the beginning of a line, jump to the next line.
+                if self.scan_prefixes && !self.prog.prefixes.is_empty() {
+                    let offset = self.prog.prefix_offset;
+                    let before = at.pos();
+                    let next = self.input.prefix_at(&self.prog.prefixes,
+                                                    offset, at);
+                    self.prog.tracer.emit(|| TraceEvent::PrefixScan {
+                        from: before,
+                        to: next.map(|at| at.pos()),
+                    });
+                    at = match next {
+                        None => break,
+                        Some(at) => at,
+                    };
+                    counts.prefix_scans += 1;
+                    counts.prefix_skipped += at.pos() - before;
+                    prev = self.input.decode_before(at.pos()).char();
+                } else if self.prog.anchored_line_begin && anchor.is_none() {
+                    at = match self.input.line_start_at(at) {
                         None => break,
                         Some(at) => at,
                     };
+                    prev = self.input.decode_before(at.pos()).char();
+                }
+
+                // 4. If there's not enough input left for the shortest
+                //    possible match, then no new thread can ever match.
+                if !self.has_room(at) {
+                    break;
                 }
             }
 
             // This simulates a preceding '.*?' for every regex by adding
             // a state starting at the current position in the input for the
             // beginning of the program only if we don't already have a match.
-            if q.clist.size == 0 || (!self.prog.anchored_begin && !matched) {
-                self.add(&mut q.clist, &mut caps, 0, at)
+            // (And only if a match starting here could still fit.)
+            if q.clist.size == 0
+               || (!self.prog.anchored_begin
+                   && anchor.is_none()
+                   && (if all_matches {
+                           num_matched < matches.len()
+                       } else {
+                           matched.is_none()
+                       })
+                   && self.has_room(at)) {
+                let pc = self.start_pc;
+                self.add(&mut q.clist, &mut q.stack, &mut q.sets, empty,
+                         pc, at, prev);
             }
             // The previous call to "add" actually inspects the position just
             // before the current character. For stepping through the machine,
             // we can to look at the current character, so we advance the
             // input.
-            let at_next = self.input.at(at.next_pos());
+            let at_next = self.input.decode_at(at.next_pos());
+            counts.positions += 1;
+            counts.threads += q.clist.size;
             for i in 0..q.clist.size {
                 let pc = q.clist.pc(i);
-                let tcaps = q.clist.caps(i);
-                if self.step(&mut q.nlist, caps, tcaps, pc, at, at_next) {
-                    matched = true;
+                let set = match q.clist.caps(i) {
+                    None => continue,
+                    Some(set) => set,
+                };
+                let step = self.step(
+                    &mut q.nlist, &mut q.stack, &mut q.sets, caps, set,
+                    pc, at, at_next);
+                if let Some(pat) = step {
+                    if all_matches {
+                        // Every thread must keep running, since a thread
+                        // with lower priority may still reach a different
+                        // pattern.
+                        // A thread that started earlier may still match
+                        // later on, so only the earliest start is kept.
+                        let pos =
+                            if starts { caps[0] } else { Some(at.pos()) };
+                        match matches[pat] {
+                            None => {
+                                matches[pat] = pos;
+                                num_matched += 1;
+                            }
+                            Some(old) if starts && pos < Some(old) => {
+                                matches[pat] = pos;
+                            }
+                            Some(_) => {}
+                        }
+                        matched = matched.or(Some(pat));
+                        continue;
+                    }
+                    matched = Some(pat);
                     if caps.len() == 0 {
                         // If we only care if a match occurs (not its
                         // position), then we can quit right now.
                         break 'LOOP;
                     }
+                    if self.prog.longest_match {
+                        // A thread with lower priority may still find a
+                        // longer match, so keep all of them running.
+                        continue;
+                    }
                     // We don't need to check the rest of the threads in this
                     // set because we've matched something ("leftmost-first").
                     // However, we still need to check threads in the next set
@@ -130,93 +473,217 @@ impl<'r, 't> Nfa<'r, 't> {
                     break;
                 }
             }
+            if all_matches && num_matched == matches.len() && !starts {
+                break;
+            }
             if at.char().is_none() {
                 break;
             }
+            prev = at.char();
             at = at_next;
+            q.clist.release(&mut q.sets);
             q.swap();
             q.nlist.empty();
         }
-        matched
+        self.prog.record_prefix_scan(
+            counts.prefix_scans,
+            matched.is_some() as usize,
+            counts.prefix_skipped);
+        self.prog.record_counts(&counts);
+        Ok(matched)
+    }
+
+    /// Returns true if a match of the shortest possible length starting at
+    /// `at` fits in the rest of the input.
+    fn has_room(&self, at: InputAt) -> bool {
+        at.pos() + self.prog.min_len <= self.input.len()
     }
 
     fn step(
         &self,
         nlist: &mut Threads,
+        stack: &mut Vec<FollowEpsilon>,
+        sets: &mut CaptureSets,
         caps: &mut [Option<usize>],
-        thread_caps: &mut [Option<usize>],
-        pc: usize,
+        set: usize,
+        pc: InstIdx,
         at: InputAt,
         at_next: InputAt,
-    ) -> bool {
+    ) -> Option<usize> {
         use inst::Inst::*;
-        match self.prog.insts[pc] {
-            Match => {
-                for (slot, val) in caps.iter_mut().zip(thread_caps.iter()) {
+        self.prog.tracer.step(pc, at.pos());
+        match self.prog.insts[pc as usize] {
+            Match(pat) => {
+                if self.prog.match_at_end && at.pos() != self.input.len() {
+                    return None;
+                }
+                if self.prog.longest_match
+                   && !is_better(caps, sets.get(set)) {
+                    return None;
+                }
+                for (slot, val) in caps.iter_mut().zip(sets.get(set)) {
                     *slot = *val;
                 }
-                true
+                Some(pat)
             }
             Char(ref inst) => {
                 if inst.c == at.char() {
-                    self.add(nlist, thread_caps, inst.goto, at_next);
+                    self.add(nlist, stack, sets, set,
+                             inst.goto, at_next, at.char());
                 }
-                false
+                None
             }
             Ranges(ref inst) => {
-                if inst.matches(at.char()) {
-                    self.add(nlist, thread_caps, inst.goto, at_next);
+                if inst.matches(&self.prog.ranges, at.char()) {
+                    self.add(nlist, stack, sets, set,
+                             inst.goto, at_next, at.char());
                 }
-                false
+                None
             }
-            EmptyLook(_) | Save(_) | Split(_) => false,
+            EmptyLook(_) | Save(_) | Split(_) | SplitMany(_) => None,
+            Backref(_) => unreachable!("backreferences are only run by the \
+                                        backtracking engine"),
         }
     }
 
+    /// Follows all epsilon transitions from `pc` and adds the resulting
+    /// threads to `nlist`.
+    ///
+    /// This uses an explicit stack instead of recursion, since the chains of
+    /// `Split`, `Save` and `EmptyLook` instructions can be arbitrarily long.
+    ///
+    /// The new threads share the capture set `set` (and the sets derived
+    /// from it by `Save` instructions). The caller keeps its own reference
+    /// to `set`. `prev` must be the character just before `at`.
     fn add(
         &self,
         nlist: &mut Threads,
-        thread_caps: &mut [Option<usize>],
-        pc: usize,
+        stack: &mut Vec<FollowEpsilon>,
+        sets: &mut CaptureSets,
+        set: usize,
+        pc: InstIdx,
         at: InputAt,
+        prev: Char,
     ) {
-        use inst::Inst::*;
-
-        if nlist.contains(pc) {
-            return
-        }
-        let ti = nlist.add(pc);
-        match self.prog.insts[pc] {
-            EmptyLook(ref inst) => {
-                let prev = self.input.previous_at(at.pos());
-                if inst.matches(prev.char(), at.char()) {
-                    self.add(nlist, thread_caps, inst.goto, at);
+        let mut cur = set;
+        stack.push(FollowEpsilon::IP(pc));
+        while let Some(frame) = stack.pop() {
+            match frame {
+                FollowEpsilon::IP(pc) => {
+                    self.add_step(nlist, stack, sets, &mut cur, pc, at, prev);
                 }
-            }
-            Save(ref inst) => {
-                if inst.slot >= thread_caps.len() {
-                    self.add(nlist, thread_caps, inst.goto, at);
-                } else {
-                    let old = thread_caps[inst.slot];
-                    thread_caps[inst.slot] = Some(at.pos());
-                    self.add(nlist, thread_caps, inst.goto, at);
-                    thread_caps[inst.slot] = old;
+                FollowEpsilon::Captures(old) => {
+                    // The current set was created by a `Save` below `old`,
+                    // so we own a reference to it.
+                    sets.decref(cur);
+                    cur = old;
                 }
             }
-            Split(ref inst) => {
-                self.add(nlist, thread_caps, inst.goto1, at);
-                self.add(nlist, thread_caps, inst.goto2, at);
+        }
+    }
+
+    fn add_step(
+        &self,
+        nlist: &mut Threads,
+        stack: &mut Vec<FollowEpsilon>,
+        sets: &mut CaptureSets,
+        cur: &mut usize,
+        mut pc: InstIdx,
+        at: InputAt,
+        prev: Char,
+    ) {
+        use inst::Inst::*;
+
+        // Instead of recursing on the first branch of an instruction, we just
+        // follow it here. Everything else (the second branch of a `Split` and
+        // switching back to the capture set in use before a `Save`) is pushed
+        // on the stack, which preserves the order in which threads are added.
+        loop {
+            if nlist.contains(pc) {
+                return
             }
-            Match | Char(_) | Ranges(_) => {
-                let mut t = &mut nlist.thread(ti);
-                for (slot, val) in t.caps.iter_mut().zip(thread_caps.iter()) {
-                    *slot = *val;
+            let ti = nlist.add(pc);
+            match self.prog.insts[pc as usize] {
+                EmptyLook(ref inst) => {
+                    if !inst.matches(prev, at.char()) {
+                        return;
+                    }
+                    pc = inst.goto;
+                }
+                Save(ref inst) => {
+                    // Capture sets may be shared by many threads, so we
+                    // write to a fresh copy. Copying is only needed here
+                    // instead of every time a thread is added.
+                    let (slot, pos) = (inst.slot as usize, Some(at.pos()));
+                    if slot < sets.slots_per_set
+                       && sets.get(*cur)[slot] != pos {
+                        stack.push(FollowEpsilon::Captures(*cur));
+                        *cur = sets.copy(*cur);
+                        sets.get_mut(*cur)[slot] = pos;
+                    }
+                    pc = inst.goto;
+                }
+                Split(ref inst) => {
+                    stack.push(FollowEpsilon::IP(inst.goto2));
+                    pc = inst.goto1;
+                }
+                SplitMany(ref inst) => {
+                    for &goto in inst.gotos[1..].iter().rev() {
+                        stack.push(FollowEpsilon::IP(goto));
+                    }
+                    pc = inst.gotos[0];
                 }
+                Match(_) | Char(_) | Ranges(_) => {
+                    sets.incref(*cur);
+                    nlist.set_caps(ti, *cur);
+                    return;
+                }
+                Backref(_) => unreachable!("backreferences are only run by \
+                                            the backtracking engine"),
             }
         }
     }
 }
 
+/// Where a search with `Nfa::exec_budget` stopped when it ran out of budget.
+#[derive(Clone, Copy, Debug)]
+pub struct NfaSuspended {
+    /// The position of the threads in the current list.
+    at: usize,
+    /// The pattern matched so far, if any.
+    matched: Option<usize>,
+    /// The capture set that new threads start out with.
+    empty: usize,
+}
+
+/// Returns true if the match with captures `new` is preferred over the
+/// match recorded in `best` under leftmost-longest semantics.
+///
+/// That is, `new` either starts earlier or starts at the same position and
+/// ends later. (Ties keep the match found first, i.e., the one with higher
+/// priority.)
+fn is_better(best: &[Option<usize>], new: &[Option<usize>]) -> bool {
+    if best.len() < 2 {
+        // The caller doesn't want to know where the match is.
+        return true;
+    }
+    match (best[0], best[1], new[0], new[1]) {
+        (Some(s1), Some(e1), Some(s2), Some(e2)) => {
+            s2 < s1 || (s2 == s1 && e2 > e1)
+        }
+        _ => true,
+    }
+}
+
+/// A unit of work for following epsilon transitions in `Nfa::add`.
+#[derive(Clone, Copy, Debug)]
+enum FollowEpsilon {
+    /// Follow the transitions starting at this instruction.
+    IP(InstIdx),
+    /// Switch back to the capture set that was in use before a `Save`.
+    Captures(usize),
+}
+
 /// Shared cached state between multiple invocations of a NFA engine
 /// in the same thread.
 ///
@@ -225,71 +692,196 @@ impl<'r, 't> Nfa<'r, 't> {
 pub struct NfaThreads {
     clist: Threads,
     nlist: Threads,
+    stack: Vec<FollowEpsilon>,
+    sets: CaptureSets,
 }
 
+/// A sparse set of threads, keyed by instruction.
+///
+/// Threads stopped at an instruction that consumes input (or at a `Match`)
+/// hold a reference to a capture set in `caps`.
 #[derive(Debug)]
 struct Threads {
-    dense: Vec<Thread>,
-    sparse: Vec<usize>,
+    dense: Vec<InstIdx>,
+    sparse: Vec<u32>,
     size: usize,
+    caps: Vec<Option<usize>>,
 }
 
-#[derive(Clone, Debug)]
-struct Thread {
-    pc: usize,
-    caps: Vec<Option<usize>>,
+/// Reference counted capture sets, shared by threads until one of them needs
+/// to write to a slot (copy-on-write).
+///
+/// All sets live in one flat vector, where the slots of set `i` start at
+/// `i * slots_per_set`. Sets whose reference count drops to zero are reused.
+#[derive(Debug)]
+struct CaptureSets {
+    slots: Vec<Option<usize>>,
+    refs: Vec<usize>,
+    free: Vec<usize>,
+    slots_per_set: usize,
 }
 
 impl NfaThreads {
     /// Create new empty state for the NFA engine.
     pub fn new(num_insts: usize, ncaps: usize) -> NfaThreads {
         NfaThreads {
-            clist: Threads::new(num_insts, ncaps),
-            nlist: Threads::new(num_insts, ncaps),
+            clist: Threads::new(num_insts),
+            nlist: Threads::new(num_insts),
+            stack: vec![],
+            sets: CaptureSets::new(ncaps * 2),
         }
     }
 
+    /// Create new state for the NFA engine that has room for everything a
+    /// search needs, so that no search ever has to grow it.
+    pub fn with_capacity(num_insts: usize, ncaps: usize) -> NfaThreads {
+        let mut q = NfaThreads::new(num_insts, ncaps);
+        // Every instruction added to a list pushes at most one frame.
+        q.stack.reserve(num_insts + 1);
+        // A capture set is referenced by a thread in one of the two lists,
+        // by a frame on the stack or by new threads (the empty set).
+        let num_sets = 3 * num_insts + 1;
+        q.sets.slots.reserve(num_sets * ncaps * 2);
+        q.sets.refs.reserve(num_sets);
+        q.sets.free.reserve(num_sets);
+        q
+    }
+
+    /// Returns the total number of elements that the buffers can hold
+    /// without allocating.
+    ///
+    /// This doesn't change over a search unless it had to allocate.
+    pub fn capacity(&self) -> usize {
+        self.stack.capacity()
+        + self.sets.slots.capacity()
+        + self.sets.refs.capacity()
+        + self.sets.free.capacity()
+    }
+
     fn swap(&mut self) {
         ::std::mem::swap(&mut self.clist, &mut self.nlist);
     }
 }
 
 impl Threads {
-    fn new(num_insts: usize, ncaps: usize) -> Threads {
-        let t = Thread { pc: 0, caps: vec![None; ncaps * 2] };
+    fn new(num_insts: usize) -> Threads {
         Threads {
-            dense: vec![t; num_insts],
+            dense: vec![0; num_insts],
             sparse: vec![0; num_insts],
             size: 0,
+            caps: vec![None; num_insts],
         }
     }
 
-    fn add(&mut self, pc: usize) -> usize {
+    fn add(&mut self, pc: InstIdx) -> usize {
         let i = self.size;
-        self.dense[i].pc = pc;
-        self.sparse[pc] = i;
+        self.dense[i] = pc;
+        self.sparse[pc as usize] = i as u32;
+        self.caps[i] = None;
         self.size += 1;
         i
     }
 
-    fn thread(&mut self, i: usize) -> &mut Thread {
-        &mut self.dense[i]
+    /// Drops the references to capture sets held by every thread.
+    fn release(&mut self, sets: &mut CaptureSets) {
+        for i in 0..self.size {
+            if let Some(set) = self.caps[i] {
+                sets.decref(set);
+            }
+        }
     }
 
-    fn contains(&self, pc: usize) -> bool {
-        let s = self.sparse[pc];
-        s < self.size && self.dense[s].pc == pc
+    fn contains(&self, pc: InstIdx) -> bool {
+        let s = self.sparse[pc as usize] as usize;
+        s < self.size && self.dense[s] == pc
     }
 
     fn empty(&mut self) {
         self.size = 0;
     }
 
-    fn pc(&self, i: usize) -> usize {
-        self.dense[i].pc
+    fn pc(&self, i: usize) -> InstIdx {
+        self.dense[i]
+    }
+
+    fn caps(&self, i: usize) -> Option<usize> {
+        self.caps[i]
+    }
+
+    fn set_caps(&mut self, i: usize, set: usize) {
+        self.caps[i] = Some(set);
+    }
+}
+
+impl CaptureSets {
+    fn new(slots_per_set: usize) -> CaptureSets {
+        CaptureSets {
+            slots: vec![],
+            refs: vec![],
+            free: vec![],
+            slots_per_set: slots_per_set,
+        }
+    }
+
+    /// Drops every set and prepares for sets with the given number of slots.
+    fn reset(&mut self, slots_per_set: usize) {
+        self.slots.truncate(0);
+        self.refs.truncate(0);
+        self.free.truncate(0);
+        self.slots_per_set = slots_per_set;
+    }
+
+    /// Returns a new set with a reference count of one. The values of its
+    /// slots are unspecified.
+    fn alloc(&mut self) -> usize {
+        if let Some(set) = self.free.pop() {
+            self.refs[set] = 1;
+            return set;
+        }
+        let set = self.refs.len();
+        self.refs.push(1);
+        let len = self.slots.len() + self.slots_per_set;
+        self.slots.resize(len, None);
+        set
+    }
+
+    /// Returns a new set with all of its slots empty.
+    fn alloc_empty(&mut self) -> usize {
+        let set = self.alloc();
+        for slot in self.get_mut(set) {
+            *slot = None;
+        }
+        set
+    }
+
+    /// Returns a new set with the same slots as `set`.
+    fn copy(&mut self, set: usize) -> usize {
+        let new = self.alloc();
+        let n = self.slots_per_set;
+        for i in 0..n {
+            self.slots[new * n + i] = self.slots[set * n + i];
+        }
+        new
+    }
+
+    fn incref(&mut self, set: usize) {
+        self.refs[set] += 1;
+    }
+
+    fn decref(&mut self, set: usize) {
+        self.refs[set] -= 1;
+        if self.refs[set] == 0 {
+            self.free.push(set);
+        }
+    }
+
+    fn get(&self, set: usize) -> &[Option<usize>] {
+        let start = set * self.slots_per_set;
+        &self.slots[start..start + self.slots_per_set]
     }
 
-    fn caps(&mut self, i: usize) -> &mut [Option<usize>] {
-        &mut self.dense[i].caps
+    fn get_mut(&mut self, set: usize) -> &mut [Option<usize>] {
+        let start = set * self.slots_per_set;
+        &mut self.slots[start..start + self.slots_per_set]
     }
 }
@@ -0,0 +1,386 @@
+// Copyright 2014-2016 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use syntax::{CharClass, ClassRange, Expr, Repeater};
+use syntax::Expr::*;
+
+/// Rewrites `expr` into an equivalent expression that compiles to a
+/// smaller program.
+///
+/// The parser already merges adjacent literals and drops non-capturing
+/// groups that don't matter, but machine generated regexes (e.g., a list
+/// of words joined with `|`) leave a lot more on the table:
+///
+/// 1. A class of a single character becomes a literal, so that it's merged
+///    with the literals around it.
+/// 2. Non-capturing groups are dropped wherever they don't change how the
+///    expression parses, e.g., `a(?:bc)d` is `abcd`.
+/// 3. Branches of an alternation that can never match are removed, and so
+///    are repetitions of at most zero times (e.g., `c{0}`), unless they
+///    contain a capture group.
+/// 4. Adjacent branches of an alternation that each match a single
+///    character are merged into one class, e.g., `a|b|[xy]` is `[abxy]`.
+/// 5. The common literal prefix of adjacent branches is hoisted out of
+///    them, e.g., `foo|foobar|fox` is `fo(?:o(?:bar)?|x)`.
+///
+/// None of this changes which match is found (with leftmost-first
+/// semantics) or what the capture groups are.
+pub fn optimize(expr: Expr) -> Expr {
+    match expr {
+        Class(cls) => {
+            if cls.len() == 1 && cls[0].start == cls[0].end {
+                Literal { chars: vec![cls[0].start], casei: false }
+            } else {
+                Class(cls)
+            }
+        }
+        Group { e, i, name } => Group {
+            e: Box::new(optimize(*e)),
+            i: i,
+            name: name,
+        },
+        Repeat { ref e, r: Repeater::Range { min: 0, max: Some(0) }, .. }
+                if !has_captures(e) => Empty,
+        Repeat { e, r, greedy } => Repeat {
+            e: Box::new(optimize(*e)),
+            r: r,
+            greedy: greedy,
+        },
+        Concat(es) => concat(es.into_iter().map(optimize).collect()),
+        Alternate(es) => alternate(es.into_iter().map(optimize).collect()),
+        e => e,
+    }
+}
+
+/// Builds the concatenation of the given optimized expressions.
+fn concat(es: Vec<Expr>) -> Expr {
+    let mut flat = Vec::with_capacity(es.len());
+    for e in es {
+        match e {
+            Empty => {}
+            Concat(es) => flat.extend(es),
+            Group { e, i: None, name: None } => match *e {
+                Concat(es) => flat.extend(es),
+                Alternate(es) => flat.push(group(Alternate(es))),
+                e => flat.push(e),
+            },
+            e => flat.push(e),
+        }
+    }
+    let mut merged: Vec<Expr> = Vec::with_capacity(flat.len());
+    for e in flat {
+        if let Literal { ref chars, casei } = e {
+            if let Some(&mut Literal { chars: ref mut prev, casei: c }) =
+                    merged.last_mut() {
+                if c == casei {
+                    prev.extend(chars.iter().cloned());
+                    continue;
+                }
+            }
+        }
+        merged.push(e);
+    }
+    match merged.len() {
+        0 => Empty,
+        1 => merged.pop().unwrap(),
+        _ => Concat(merged),
+    }
+}
+
+/// Builds the alternation of the given optimized expressions.
+fn alternate(es: Vec<Expr>) -> Expr {
+    let mut flat = Vec::with_capacity(es.len());
+    for e in es {
+        match e {
+            Alternate(es) => flat.extend(es),
+            Group { e, i: None, name: None } => match *e {
+                Alternate(es) => flat.extend(es),
+                e => flat.push(e),
+            },
+            e => flat.push(e),
+        }
+    }
+    // If every branch is dead, then any one of them will do.
+    if flat.iter().any(|e| !never_matches(e)) {
+        flat.retain(|e| !never_matches(e) || has_captures(e));
+    } else {
+        flat.truncate(1);
+    }
+    let branches = hoist_prefixes(merge_classes(flat));
+    if branches.len() == 1 {
+        branches.into_iter().next().unwrap()
+    } else {
+        Alternate(branches)
+    }
+}
+
+/// Merges every run of adjacent branches that match a single character
+/// into one class.
+///
+/// Only adjacent branches may be merged, since a branch in between could
+/// match a longer text and take precedence over the ones after it.
+fn merge_classes(branches: Vec<Expr>) -> Vec<Expr> {
+    let mut merged: Vec<Expr> = Vec::with_capacity(branches.len());
+    // The class of the run that ends with the last branch in `merged`.
+    let mut run: Option<CharClass> = None;
+    for e in branches {
+        match (one_char_class(&e), run.take()) {
+            (Some(cls), Some(prev)) => {
+                let cls = prev.merge(cls);
+                merged.pop();
+                merged.push(optimize(Class(cls.clone())));
+                run = Some(cls);
+            }
+            (cls, _) => {
+                run = cls;
+                merged.push(e);
+            }
+        }
+    }
+    merged
+}
+
+/// Hoists the common literal prefix out of every run of adjacent branches
+/// that start with the same character.
+fn hoist_prefixes(branches: Vec<Expr>) -> Vec<Expr> {
+    let mut hoisted = Vec::with_capacity(branches.len());
+    let mut branches = branches.into_iter().peekable();
+    while let Some(first) = branches.next() {
+        let mut run = vec![first];
+        loop {
+            let same_head = match (literal_head(&run[0]),
+                                   branches.peek().and_then(literal_head)) {
+                (Some((c1, casei1)), Some((c2, casei2))) => {
+                    c1[0] == c2[0] && casei1 == casei2
+                }
+                _ => false,
+            };
+            if !same_head {
+                break;
+            }
+            run.push(branches.next().unwrap());
+        }
+        if run.len() == 1 {
+            hoisted.extend(run);
+            continue;
+        }
+        match hoist_prefix(run) {
+            Ok(e) => hoisted.push(e),
+            Err(run) => hoisted.extend(run),
+        }
+    }
+    hoisted
+}
+
+/// Rewrites a run of at least two branches that start with the same
+/// literal character into a single expression with their common prefix in
+/// front.
+///
+/// If the run can't be rewritten, then it is given back unchanged. This
+/// happens when more than one branch is nothing but the prefix, or when
+/// one is and it's neither the first nor the last branch. (A branch that
+/// matches the empty string can only be expressed by an optional
+/// repetition of the others, which must come either before or after it.)
+fn hoist_prefix(run: Vec<Expr>) -> Result<Expr, Vec<Expr>> {
+    let (prefix, casei) = {
+        let (first, casei) = literal_head(&run[0]).unwrap();
+        let mut len = first.len();
+        for e in &run[1..] {
+            let (chars, _) = literal_head(e).unwrap();
+            len = first.iter().zip(chars).take(len)
+                       .take_while(|&(c1, c2)| c1 == c2).count();
+        }
+        (first[..len].to_vec(), casei)
+    };
+    let rests: Vec<Expr> = run.iter().cloned()
+                              .map(|e| strip_prefix(e, prefix.len()))
+                              .collect();
+    let empties: Vec<usize> = rests.iter().enumerate()
+                                   .filter(|&(_, e)| *e == Empty)
+                                   .map(|(i, _)| i)
+                                   .collect();
+    let rest = if empties.is_empty() {
+        alternate(rests)
+    } else if empties.len() == 1
+              && (empties[0] == 0 || empties[0] == rests.len() - 1) {
+        // Prefer skipping the rest if the empty branch comes first.
+        let greedy = empties[0] != 0;
+        let others = rests.into_iter().filter(|e| *e != Empty).collect();
+        Repeat {
+            e: Box::new(repeatable(alternate(others))),
+            r: Repeater::ZeroOrOne,
+            greedy: greedy,
+        }
+    } else {
+        return Err(run);
+    };
+    let rest = match rest {
+        Alternate(es) => group(Alternate(es)),
+        rest => rest,
+    };
+    Ok(concat(vec![Literal { chars: prefix, casei: casei }, rest]))
+}
+
+/// Returns the class of characters matched by `e`, if it always matches
+/// exactly one character.
+fn one_char_class(e: &Expr) -> Option<CharClass> {
+    let one = |c| CharClass::new(vec![ClassRange { start: c, end: c }]);
+    match *e {
+        Literal { ref chars, casei } if chars.len() == 1 => {
+            if casei {
+                Some(one(chars[0]).case_fold())
+            } else {
+                Some(one(chars[0]))
+            }
+        }
+        AnyChar => Some(CharClass::new(vec![
+            ClassRange { start: '\x00', end: '\u{10ffff}' },
+        ])),
+        AnyCharNoNL => Some(CharClass::new(vec![
+            ClassRange { start: '\x00', end: '\x09' },
+            ClassRange { start: '\x0b', end: '\u{10ffff}' },
+        ])),
+        Class(ref cls) => Some(cls.clone()),
+        _ => None,
+    }
+}
+
+/// Returns the literal that `e` starts with (and whether it's case
+/// insensitive), if any.
+fn literal_head(e: &Expr) -> Option<(&[char], bool)> {
+    match *e {
+        Literal { ref chars, casei } => Some((chars, casei)),
+        Concat(ref es) => match es[0] {
+            Literal { ref chars, casei } => Some((chars, casei)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Removes the first `n` characters of the literal that `e` starts with.
+fn strip_prefix(e: Expr, n: usize) -> Expr {
+    match e {
+        Literal { chars, casei } => {
+            if n == chars.len() {
+                Empty
+            } else {
+                Literal { chars: chars[n..].to_vec(), casei: casei }
+            }
+        }
+        Concat(mut es) => {
+            let first = strip_prefix(es.remove(0), n);
+            es.insert(0, first);
+            concat(es)
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// Wraps `e` in a non-capturing group.
+fn group(e: Expr) -> Expr {
+    Group { e: Box::new(e), i: None, name: None }
+}
+
+/// Wraps `e` in a non-capturing group if that's needed to repeat it.
+fn repeatable(e: Expr) -> Expr {
+    match e {
+        Concat(_) | Alternate(_) | Repeat { .. } | Empty => group(e),
+        e => e,
+    }
+}
+
+/// Returns true if `e` can't match any text.
+fn never_matches(e: &Expr) -> bool {
+    match *e {
+        Class(ref cls) => cls.is_empty(),
+        Group { ref e, .. } => never_matches(e),
+        Repeat { ref e, r, .. } => {
+            let min = match r {
+                Repeater::ZeroOrOne | Repeater::ZeroOrMore => 0,
+                Repeater::OneOrMore => 1,
+                Repeater::Range { min, .. } => min,
+            };
+            min > 0 && never_matches(e)
+        }
+        Concat(ref es) => es.iter().any(never_matches),
+        Alternate(ref es) => es.iter().all(never_matches),
+        _ => false,
+    }
+}
+
+/// Returns true if `e` contains a capture group.
+fn has_captures(e: &Expr) -> bool {
+    match *e {
+        Group { i: Some(_), .. } => true,
+        Group { ref e, .. } | Repeat { ref e, .. } => has_captures(e),
+        Concat(ref es) | Alternate(ref es) => es.iter().any(has_captures),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use syntax::Expr;
+    use super::optimize;
+
+    macro_rules! opt {
+        ($re:expr) => { optimize(Expr::parse($re).unwrap()).to_string() }
+    }
+
+    #[test]
+    fn single_char_class() {
+        assert_eq!(opt!("a[b]c"), "abc");
+        assert_eq!(opt!("a[b]+c"), "ab+c");
+    }
+
+    #[test]
+    fn flatten() {
+        assert_eq!(opt!("a(?:bc)d"), "abcd");
+        assert_eq!(opt!("a|(?:b|c)|d"), "[a-d]");
+        assert_eq!(opt!("a(?:b|c)d"), "a[b-c]d");
+        assert_eq!(opt!("a(bc)d"), "a(bc)d");
+    }
+
+    #[test]
+    fn merge_classes() {
+        assert_eq!(opt!("a|b|[xy]"), "[a-bx-y]");
+        assert_eq!(opt!("a|bc|d"), "a|bc|d");
+        assert_eq!(opt!("a|(?i)b"), "[B-Ba-b]");
+    }
+
+    #[test]
+    fn hoist_prefixes() {
+        assert_eq!(opt!("foo|foobar|fox"), "fo(?:o(?:bar)??|x)");
+        assert_eq!(opt!("foobar|foo"), "foo(?:bar)?");
+        assert_eq!(opt!("abc|abd|xyz"), "ab[c-d]|xyz");
+        assert_eq!(opt!("ab|xy|ac"), "ab|xy|ac");
+        assert_eq!(opt!("a(b)|a(c)"), "a(?:(b)|(c))");
+    }
+
+    #[test]
+    fn hoist_prefixes_empty_in_middle() {
+        assert_eq!(opt!("abc|ab|abd"), "abc|ab|abd");
+        assert_eq!(opt!("ab|ab"), "ab|ab");
+    }
+
+    #[test]
+    fn hoist_prefixes_empty_repeat() {
+        assert_eq!(opt!("bc{0}|b.{2}|x?"), "b(?:.{2})??|x?");
+        assert_eq!(opt!("a(b){0}|ac"), "a(?:(b){0}|c)");
+    }
+
+    #[test]
+    fn dead_branches() {
+        assert_eq!(opt!(r"a|x[^\x00-\x{10FFFF}]|b"), "[a-b]");
+        assert_eq!(opt!(r"a|(x[^\x00-\x{10FFFF}])|b"),
+                   r"a|(x[])|b");
+    }
+}
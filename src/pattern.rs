@@ -0,0 +1,357 @@
+// Copyright 2016 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::fmt;
+
+use syntax::{CharClass, ClassRange, Expr, Repeater};
+
+use compile::Compiler;
+use program::{Program, ProgramOptions};
+use re::{Error, Regex};
+
+/// A regular expression built out of smaller ones, instead of parsed from a
+/// string.
+///
+/// Code that generates a regex, e.g., from user input, doesn't need to
+/// escape anything: a literal always matches exactly its characters, and
+/// combining patterns never changes what their parts mean. (Whereas joining
+/// `a|b` and `c` as strings gives `a|bc`.)
+///
+/// The source of a pattern, which can be parsed back into an equivalent
+/// regex, is written by its `Display` impl.
+///
+/// # Example
+///
+/// ```rust
+/// # extern crate regex; use regex::Pattern;
+/// # fn main() {
+/// let digits = Pattern::class(&[('0', '9')]).repeat(1, Some(4));
+/// let pat = Pattern::literal("v.").then(digits.named("major"))
+///                                  .alt(Pattern::literal("dev"));
+/// assert_eq!(pat.to_string(), r"v\.(?P<major>[0-9]{1, 4})|dev");
+///
+/// let re = pat.compile().unwrap();
+/// assert_eq!(re.captures("v.12").unwrap().name("major"), Some("12"));
+/// assert!(!re.is_match("v12"));
+/// # }
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Pattern {
+    expr: Expr,
+}
+
+impl Pattern {
+    /// A pattern that matches exactly the text `s`.
+    ///
+    /// # Panics
+    ///
+    /// If `s` is empty.
+    pub fn literal(s: &str) -> Pattern {
+        assert!(!s.is_empty(), "empty literal");
+        Pattern::new(Expr::Literal {
+            chars: s.chars().collect(),
+            casei: false,
+        })
+    }
+
+    /// A pattern that matches any one character in the given ranges, whose
+    /// ends are included.
+    ///
+    /// # Panics
+    ///
+    /// If there are no ranges.
+    pub fn class(ranges: &[(char, char)]) -> Pattern {
+        assert!(!ranges.is_empty(), "empty class");
+        let ranges = ranges.iter().map(|&(s, e)| {
+            if s <= e {
+                ClassRange { start: s, end: e }
+            } else {
+                ClassRange { start: e, end: s }
+            }
+        }).collect();
+        let class = CharClass::new(vec![]).merge(CharClass::new(ranges));
+        Pattern::new(Expr::Class(class))
+    }
+
+    /// A pattern that matches any one character, including `\n`.
+    pub fn any_char() -> Pattern {
+        Pattern::new(Expr::AnyChar)
+    }
+
+    /// A pattern that matches only at the beginning of the text.
+    pub fn start_text() -> Pattern {
+        Pattern::new(Expr::StartText)
+    }
+
+    /// A pattern that matches only at the end of the text.
+    pub fn end_text() -> Pattern {
+        Pattern::new(Expr::EndText)
+    }
+
+    /// A pattern that matches only at a word boundary.
+    pub fn word_boundary() -> Pattern {
+        Pattern::new(Expr::WordBoundary)
+    }
+
+    /// A pattern that matches this one followed by `next`.
+    pub fn then(self, next: Pattern) -> Pattern {
+        let mut es = match self.expr {
+            Expr::Concat(es) => es,
+            e => vec![non_alternate(e)],
+        };
+        match next.expr {
+            Expr::Concat(next) => es.extend(next),
+            e => es.push(non_alternate(e)),
+        }
+        Pattern::new(Expr::Concat(es))
+    }
+
+    /// A pattern that matches this one or, failing that, `other`.
+    ///
+    /// As with `|`, this one is preferred if both match.
+    pub fn alt(self, other: Pattern) -> Pattern {
+        let mut es = match self.expr {
+            Expr::Alternate(es) => es,
+            e => vec![e],
+        };
+        match other.expr {
+            Expr::Alternate(other) => es.extend(other),
+            e => es.push(e),
+        }
+        Pattern::new(Expr::Alternate(es))
+    }
+
+    /// A pattern that matches this one at least `min` and at most `max`
+    /// times in a row, as many times as possible. If `max` is `None`, then
+    /// there is no upper bound.
+    ///
+    /// # Panics
+    ///
+    /// If `max` is less than `min`.
+    pub fn repeat(self, min: u32, max: Option<u32>) -> Pattern {
+        assert!(max.map_or(true, |max| min <= max),
+                "repetition with max < min");
+        self.repeater(Repeater::Range { min: min, max: max })
+    }
+
+    /// A pattern that matches this one or nothing, like `?`.
+    pub fn optional(self) -> Pattern {
+        self.repeater(Repeater::ZeroOrOne)
+    }
+
+    /// A pattern that matches this one any number of times, like `*`.
+    pub fn zero_or_more(self) -> Pattern {
+        self.repeater(Repeater::ZeroOrMore)
+    }
+
+    /// A pattern that matches this one at least once, like `+`.
+    pub fn one_or_more(self) -> Pattern {
+        self.repeater(Repeater::OneOrMore)
+    }
+
+    /// Makes the outermost repetition of this pattern match as few times as
+    /// possible instead, like `*?`. Any other pattern is left as is.
+    pub fn lazy(mut self) -> Pattern {
+        if let Expr::Repeat { ref mut greedy, .. } = self.expr {
+            *greedy = false;
+        }
+        self
+    }
+
+    /// Puts this pattern in a capture group.
+    ///
+    /// Groups are numbered in the order they appear in the whole pattern,
+    /// starting at `1`.
+    pub fn group(self) -> Pattern {
+        Pattern::new(Expr::Group {
+            e: Box::new(self.expr),
+            i: Some(0),
+            name: None,
+        })
+    }
+
+    /// Puts this pattern in a capture group with the given name.
+    ///
+    /// If the name isn't valid or another group has it too, then compiling
+    /// the pattern fails.
+    pub fn named(self, name: &str) -> Pattern {
+        Pattern::new(Expr::Group {
+            e: Box::new(self.expr),
+            i: Some(0),
+            name: Some(name.to_owned()),
+        })
+    }
+
+    /// Makes this pattern match without regard to case, like `(?i)`.
+    pub fn case_insensitive(self) -> Pattern {
+        Pattern::new(fold_case(self.expr))
+    }
+
+    /// Compiles this pattern into a regex.
+    ///
+    /// If the pattern has a group with an invalid or duplicate name, or if
+    /// it exceeds the default size limit, then an error is returned.
+    pub fn compile(&self) -> Result<Regex, Error> {
+        let original = self.to_string();
+        let mut expr = self.expr.clone();
+        let mut named = false;
+        number_groups(&mut expr, &mut 0, &mut named);
+        if named {
+            // Parsing the source checks the names of the groups, so that an
+            // invalid or duplicate name is reported like in any other regex.
+            try!(Expr::parse(&original));
+        }
+        let mut compiler = Compiler::new(10 * (1 << 20));
+        let opts = ProgramOptions::default();
+        Program::with_exprs(&mut compiler, &opts, &[original], &[expr])
+            .map(Regex::Dynamic)
+    }
+
+    fn new(expr: Expr) -> Pattern {
+        Pattern { expr: expr }
+    }
+
+    fn repeater(self, r: Repeater) -> Pattern {
+        let e = match self.expr {
+            e @ Expr::Literal { .. } | e @ Expr::AnyChar | e @ Expr::Class(_)
+            | e @ Expr::Group { .. } => e,
+            e => Expr::Group { e: Box::new(e), i: None, name: None },
+        };
+        Pattern::new(Expr::Repeat { e: Box::new(e), r: r, greedy: true })
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.expr)
+    }
+}
+
+/// Wraps an alternation in a non-capturing group, so that it can be
+/// concatenated with something else.
+fn non_alternate(e: Expr) -> Expr {
+    match e {
+        e @ Expr::Alternate(_) => {
+            Expr::Group { e: Box::new(e), i: None, name: None }
+        }
+        e => e,
+    }
+}
+
+/// Returns `e` with every literal and class in it matching without regard to
+/// case.
+fn fold_case(e: Expr) -> Expr {
+    match e {
+        Expr::Literal { chars, .. } => {
+            Expr::Literal { chars: chars, casei: true }
+        }
+        Expr::Class(class) => Expr::Class(class.case_fold()),
+        Expr::Group { e, i, name } => {
+            Expr::Group { e: Box::new(fold_case(*e)), i: i, name: name }
+        }
+        Expr::Repeat { e, r, greedy } => {
+            Expr::Repeat { e: Box::new(fold_case(*e)), r: r, greedy: greedy }
+        }
+        Expr::Concat(es) => {
+            Expr::Concat(es.into_iter().map(fold_case).collect())
+        }
+        Expr::Alternate(es) => {
+            Expr::Alternate(es.into_iter().map(fold_case).collect())
+        }
+        e => e,
+    }
+}
+
+/// Numbers the capture groups in `e` in the order they appear, after the
+/// `last` groups before it. `named` is set if any of them has a name.
+fn number_groups(e: &mut Expr, last: &mut usize, named: &mut bool) {
+    match *e {
+        Expr::Group { ref mut e, ref mut i, ref name } => {
+            if i.is_some() {
+                *last += 1;
+                *i = Some(*last);
+                *named = *named || name.is_some();
+            }
+            number_groups(e, last, named);
+        }
+        Expr::Repeat { ref mut e, .. } => number_groups(e, last, named),
+        Expr::Concat(ref mut es) | Expr::Alternate(ref mut es) => {
+            for e in es {
+                number_groups(e, last, named);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Pattern;
+
+    #[test]
+    fn escapes_literals() {
+        let pat = Pattern::literal("a+b").alt(Pattern::literal("(c)"));
+        assert_eq!(pat.to_string(), r"a\+b|\(c\)");
+        let re = pat.compile().unwrap();
+        assert_eq!(re.find("aab a+b"), Some((4, 7)));
+        assert_eq!(re.captures_len(), 1);
+    }
+
+    #[test]
+    fn precedence() {
+        let ab = Pattern::literal("a").alt(Pattern::literal("b"));
+        let re = ab.clone().then(Pattern::literal("c")).compile().unwrap();
+        assert!(!re.is_match("a"));
+        assert!(re.is_match("ac"));
+        let re = Pattern::literal("xy").then(ab).one_or_more()
+                                        .then(Pattern::end_text())
+                                        .compile().unwrap();
+        assert_eq!(re.find("xyaxyb xyaxyb"), Some((7, 13)));
+    }
+
+    #[test]
+    fn groups() {
+        let word = Pattern::class(&[('z', 'a')]).one_or_more();
+        let pat = word.clone().named("k")
+                      .then(Pattern::literal("="))
+                      .then(word.clone().lazy().group().optional());
+        let re = pat.compile().unwrap();
+        let caps = re.captures("ab=cd").unwrap();
+        assert_eq!(caps.name("k"), Some("ab"));
+        assert_eq!(caps.at(2), Some("c"));
+
+        assert!(word.clone().named("1x").compile().is_err());
+        assert!(word.clone().named("k").then(word.named("k"))
+                    .compile().is_err());
+    }
+
+    #[test]
+    fn case_insensitive() {
+        let pat = Pattern::literal("ok").then(Pattern::class(&[('x', 'z')]))
+                                         .case_insensitive();
+        let re = pat.compile().unwrap();
+        assert!(re.is_full_match("OkY"));
+        assert_eq!(pat.compile().unwrap().as_str(), pat.to_string());
+    }
+
+    #[test]
+    fn repeat() {
+        let pat = Pattern::literal("a").repeat(2, Some(2));
+        assert!(pat.compile().unwrap().is_full_match("aa"));
+        let pat = Pattern::literal("ab").repeat(1, None);
+        assert_eq!(pat.to_string(), "(?:ab){1,}");
+    }
+
+    #[test]
+    #[should_panic]
+    fn repeat_max_less_than_min() {
+        Pattern::literal("a").repeat(3, Some(1));
+    }
+}
@@ -11,6 +11,20 @@
 use std::fmt;
 use std::ops::{Deref, DerefMut, Drop};
 use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The number of independent stacks in a pool.
+///
+/// Each thread always uses the same stack, so up to this many threads can
+/// use a pool at the same time without contending on a lock.
+const NUM_SHARDS: usize = 16;
+
+/// The source of thread identifiers. Each thread takes the next one the
+/// first time it uses any pool.
+static NEXT_THREAD_ID: AtomicUsize = AtomicUsize::new(0);
+
+thread_local!(static THREAD_ID: usize =
+    NEXT_THREAD_ID.fetch_add(1, Ordering::Relaxed));
 
 /// A very simple memory pool for managing cached state.
 ///
@@ -27,8 +41,12 @@ use std::sync::Mutex;
 /// We use inherited mutability and ensure that each thread gets its own
 /// state. There is no limit on the number of states that are created. If a
 /// thread requests one and one isn't available, a new one is created.
+///
+/// The resources are spread over several stacks, and every thread sticks to
+/// the stack picked by its identifier. This way, a regex shared by a pool of
+/// threads doesn't serialize all of their searches on a single lock.
 pub struct Pool<T> {
-    stack: Mutex<Vec<T>>,
+    stacks: Vec<Mutex<Vec<T>>>,
     create: CreateFn<T>,
 }
 
@@ -39,6 +57,7 @@ pub type CreateFn<T> = Box<Fn() -> T + Send + Sync>;
 #[derive(Debug)]
 pub struct PoolGuard<'a, T: 'a> {
     pool: &'a Pool<T>,
+    shard: usize,
     val: Option<T>,
 }
 
@@ -55,7 +74,7 @@ impl<T> Pool<T> {
     /// All resources are created lazily/on-demand.
     pub fn new(create: CreateFn<T>) -> Pool<T> {
         Pool {
-            stack: Mutex::new(vec![]),
+            stacks: (0..NUM_SHARDS).map(|_| Mutex::new(vec![])).collect(),
             create: create,
         }
     }
@@ -66,18 +85,21 @@ impl<T> Pool<T> {
     ///
     /// Once the guard is dropped, the resource is returned to the pool.
     pub fn get(&self) -> PoolGuard<T> {
-        let mut stack = self.stack.lock().unwrap();
-        match stack.pop() {
-            None => PoolGuard { pool: self, val: Some((self.create)()) },
-            Some(v) => PoolGuard { pool: self, val: Some(v) },
-        }
+        let shard = THREAD_ID.with(|&id| id % NUM_SHARDS);
+        let v = self.stacks[shard].lock().unwrap().pop();
+        let v = match v {
+            None => (self.create)(),
+            Some(v) => v,
+        };
+        PoolGuard { pool: self, shard: shard, val: Some(v) }
     }
 
     /// Add a resource to the pool.
     ///
-    /// This makes the resource available for use with `get`.
-    fn put(&self, v: T) {
-        let mut stack = self.stack.lock().unwrap();
+    /// This makes the resource available for use with `get` by threads
+    /// that use the given shard.
+    fn put(&self, shard: usize, v: T) {
+        let mut stack = self.stacks[shard].lock().unwrap();
         stack.push(v);
     }
 }
@@ -94,14 +116,18 @@ impl<'a, T> DerefMut for PoolGuard<'a, T> {
 impl<'a, T> Drop for PoolGuard<'a, T> {
     fn drop(&mut self) {
         let val = self.val.take().unwrap();
-        self.pool.put(val);
+        self.pool.put(self.shard, val);
     }
 }
 
 impl<T: fmt::Debug> fmt::Debug for Pool<T> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let stack = self.stack.lock();
-        let stack = stack.unwrap();
-        stack.fmt(f)
+        let mut list = f.debug_list();
+        for stack in &self.stacks {
+            for v in stack.lock().unwrap().iter() {
+                list.entry(v);
+            }
+        }
+        list.finish()
     }
 }
@@ -0,0 +1,386 @@
+// Copyright 2016 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Support for POSIX extended (ERE) and basic (BRE) regular expressions.
+//!
+//! The regexes produced here follow POSIX semantics where they differ from
+//! this crate's own dialect:
+//!
+//! * Searches report the *leftmost-longest* match rather than the
+//!   leftmost-first match. e.g., `a|ab` matches all of `ab`. (Capture groups
+//!   are still resolved with this crate's priority rules, so they can differ
+//!   from what POSIX prescribes for submatches. To follow POSIX there too,
+//!   compile the result of `translate_ere` or `translate_bre` with
+//!   `RegexBuilder`, enabling both `longest_match` and `posix_submatches`.)
+//! * `.` and negated bracket expressions match a newline.
+//! * Inside a bracket expression, `\` is an ordinary character. `]` is
+//!   ordinary when it comes first and `-` when it comes first or last.
+//!   Character classes such as `[:alpha:]` and single character equivalence
+//!   classes and collating symbols such as `[=a=]` and `[.-.]` are
+//!   supported.
+//! * A repetition operator with nothing to repeat, e.g., the `*` in `*a` or
+//!   `(*a)`, matches itself.
+//!
+//! In a BRE, `\(`, `\)`, `\{` and `\}` are the grouping and interval
+//! operators, while `(`, `)`, `{`, `}`, `+`, `?` and `|` match themselves.
+//! The GNU extensions `\+`, `\?` and `\|` are supported. `^` is only an
+//! anchor at the start of the expression or a group and `$` only at the end.
+//!
+//! Both dialects also accept the GNU escapes `\w`, `\W`, `\s`, `\S`, `\b`,
+//! `\B`, `\<` and `\>` (the last two match any word boundary).
+//! Backreferences are not supported.
+//!
+//! # Example
+//!
+//! ```rust
+//! # extern crate regex; use regex::posix;
+//! # fn main() {
+//! let re = posix::compile_ere("x*(a|ab)(c|bcd)").unwrap();
+//! assert_eq!(re.find("xabcd"), Some((0, 5)));
+//!
+//! let re = posix::compile_bre(r"\(ab\)\{2\}+").unwrap();
+//! assert_eq!(re.find("ababab+"), Some((2, 7)));
+//! # }
+//! ```
+
+use re::{Error, Regex, push_class_literal, quote};
+use re_builder::RegexBuilder;
+
+/// Compiles a POSIX extended regular expression.
+pub fn compile_ere(re: &str) -> Result<Regex, Error> {
+    compile(&try!(translate_ere(re)))
+}
+
+/// Compiles a POSIX basic regular expression.
+pub fn compile_bre(re: &str) -> Result<Regex, Error> {
+    compile(&try!(translate_bre(re)))
+}
+
+/// Translates a POSIX extended regular expression into this crate's syntax.
+///
+/// Note that a regex compiled from the string returned reports
+/// leftmost-first matches. Use `compile_ere` to get leftmost-longest
+/// matches.
+pub fn translate_ere(re: &str) -> Result<String, Error> {
+    translate(re, false)
+}
+
+/// Translates a POSIX basic regular expression into this crate's syntax.
+///
+/// Note that a regex compiled from the string returned reports
+/// leftmost-first matches. Use `compile_bre` to get leftmost-longest
+/// matches.
+pub fn translate_bre(re: &str) -> Result<String, Error> {
+    translate(re, true)
+}
+
+fn compile(re: &str) -> Result<Regex, Error> {
+    RegexBuilder::new(re).longest_match(true).compile()
+}
+
+fn translate(re: &str, basic: bool) -> Result<String, Error> {
+    let chars: Vec<char> = re.chars().collect();
+    let mut out = String::with_capacity(re.len() + 4);
+    out.push_str("(?s)");
+    // True at the start of the expression or of a group or alternate.
+    let mut group_start = true;
+    // True when a repetition operator would have nothing to repeat.
+    let mut no_operand = true;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let (was_group_start, was_no_operand) = (group_start, no_operand);
+        group_start = false;
+        no_operand = false;
+        i += 1;
+        if c == '\\' {
+            let e = match chars.get(i) {
+                // Let the parser complain about the dangling escape.
+                None => { out.push('\\'); break; }
+                Some(&e) => e,
+            };
+            i += 1;
+            match e {
+                '(' | '|' if basic => {
+                    out.push(e);
+                    group_start = true;
+                    no_operand = true;
+                }
+                ')' if basic => out.push(')'),
+                '{' | '+' | '?' if basic && was_no_operand => {
+                    out.push_str(&quote(&e.to_string()));
+                }
+                '{' if basic => i = push_interval(&mut out, &chars, i, true),
+                '+' | '?' if basic => out.push(e),
+                '1' | '2' | '3' | '4' | '5' | '6' | '7' | '8' | '9' => {
+                    return Err(Error::Unsupported("backreferences".into()));
+                }
+                '<' | '>' => out.push_str(r"\b"),
+                'w' | 'W' | 's' | 'S' | 'b' | 'B' => {
+                    out.push('\\');
+                    out.push(e);
+                }
+                e => out.push_str(&quote(&e.to_string())),
+            }
+            continue;
+        }
+        match c {
+            '[' => match try!(parse_bracket(&chars, i)) {
+                Some((class, end)) => { out.push_str(&class); i = end; }
+                // Let the parser complain about the unclosed bracket.
+                None => out.push('['),
+            },
+            '^' if !basic || was_group_start => {
+                out.push('^');
+                no_operand = true;
+            }
+            '$' if !basic || is_bre_end(&chars, i) => out.push('$'),
+            '*' if was_no_operand => out.push_str(r"\*"),
+            '*' | '.' => out.push(c),
+            '(' | '|' if !basic => {
+                out.push(c);
+                group_start = true;
+                no_operand = true;
+            }
+            '+' | '?' | '{' if !basic && was_no_operand => {
+                out.push_str(&quote(&c.to_string()));
+            }
+            '{' if !basic => i = push_interval(&mut out, &chars, i, false),
+            ')' | '+' | '?' if !basic => out.push(c),
+            c => out.push_str(&quote(&c.to_string())),
+        }
+    }
+    Ok(out)
+}
+
+/// Copies the bounds of an interval expression, which begin at `start`
+/// (just after the opening brace), to `out` and returns the position just
+/// after the closing brace.
+///
+/// If the interval isn't closed, then only the opening brace is copied, so
+/// the parser can complain about it.
+fn push_interval(
+    out: &mut String,
+    chars: &[char],
+    start: usize,
+    basic: bool,
+) -> usize {
+    out.push('{');
+    let close: &[char] = if basic { &['\\', '}'] } else { &['}'] };
+    let mut i = start;
+    while i + close.len() <= chars.len() {
+        if &chars[i..i + close.len()] == close {
+            out.extend(&chars[start..i]);
+            out.push('}');
+            return i + close.len();
+        }
+        i += 1;
+    }
+    start
+}
+
+/// Returns true if a `$` just before position `i` is an anchor in a BRE.
+fn is_bre_end(chars: &[char], i: usize) -> bool {
+    match (chars.get(i), chars.get(i + 1)) {
+        (None, _) => true,
+        (Some(&'\\'), Some(&')')) | (Some(&'\\'), Some(&'|')) => true,
+        _ => false,
+    }
+}
+
+/// Parses a bracket expression whose contents begin at `start` (just after
+/// the opening `[`).
+///
+/// If the bracket expression is closed, then the equivalent regex class is
+/// returned along with the position just after the closing `]`.
+fn parse_bracket(
+    chars: &[char],
+    start: usize,
+) -> Result<Option<(String, usize)>, Error> {
+    let mut i = start;
+    let mut class = String::from("[");
+    if chars.get(i) == Some(&'^') {
+        class.push('^');
+        i += 1;
+    }
+    let first = i;
+    loop {
+        let c = match chars.get(i) {
+            None => return Ok(None),
+            Some(&']') if i > first => break,
+            Some(&c) => c,
+        };
+        i += 1;
+        if c == '[' {
+            match chars.get(i) {
+                Some(&':') => {
+                    let end = match find_close(chars, i + 1, ':') {
+                        None => return Ok(None),
+                        Some(end) => end,
+                    };
+                    let name: String = chars[i + 1..end].iter().collect();
+                    class.push_str(&format!("[:{}:]", name));
+                    i = end + 2;
+                    continue;
+                }
+                Some(&'=') | Some(&'.') => {
+                    let end = match find_close(chars, i + 1, chars[i]) {
+                        None => return Ok(None),
+                        Some(end) => end,
+                    };
+                    if end != i + 2 {
+                        return Err(Error::Unsupported(
+                            "multi-character collating elements".into()));
+                    }
+                    push_class_literal(&mut class, chars[i + 1]);
+                    i = end + 2;
+                    continue;
+                }
+                _ => {}
+            }
+        }
+        push_class_literal(&mut class, c);
+        // A `-` either ends the bracket expression or forms a range.
+        if chars.get(i) == Some(&'-')
+           && chars.get(i + 1).map_or(false, |&c| c != ']') {
+            class.push('-');
+            push_class_literal(&mut class, chars[i + 1]);
+            i += 2;
+        }
+    }
+    class.push(']');
+    Ok(Some((class, i + 1)))
+}
+
+/// Returns the position of the `delim` in the first `delim]` at or after
+/// `start`.
+fn find_close(chars: &[char], start: usize, delim: char) -> Option<usize> {
+    (start..chars.len().saturating_sub(1))
+        .find(|&i| chars[i] == delim && chars[i + 1] == ']')
+}
+
+#[cfg(test)]
+mod tests {
+    use {Regex, RegexBuilder};
+    use super::{compile_bre, compile_ere, translate_bre, translate_ere};
+
+    fn find_ere(re: &str, text: &str) -> Option<(usize, usize)> {
+        compile_ere(re).unwrap().find(text)
+    }
+
+    fn find_bre(re: &str, text: &str) -> Option<(usize, usize)> {
+        compile_bre(re).unwrap().find(text)
+    }
+
+    #[test]
+    fn ere_leftmost_longest() {
+        assert_eq!(find_ere("a|ab", "xabc"), Some((1, 3)));
+        assert_eq!(Regex::new("a|ab").unwrap().find("xabc"), Some((1, 2)));
+        assert_eq!(find_ere("(a|ab)(c|bcd)", "abcd"), Some((0, 4)));
+        assert_eq!(find_ere("a*?", "aaa"), Some((0, 3)));
+        assert_eq!(find_ere("(a|ab)(c|bcd)$", "abcd abc"), Some((5, 8)));
+        let all: Vec<_> = compile_ere("a|ab").unwrap()
+                                             .find_iter("abaab").collect();
+        assert_eq!(all, vec![(0, 2), (2, 3), (3, 5)]);
+        assert!(compile_ere("a|ab").unwrap().is_match("xab"));
+    }
+
+    #[test]
+    fn ere_captures() {
+        let re = compile_ere("(a|ab)(c|bcd)(d*)").unwrap();
+        let caps = re.captures("abcd").unwrap();
+        assert_eq!(caps.pos(0), Some((0, 4)));
+        assert!(caps.pos(1).is_some() && caps.pos(2).is_some());
+    }
+
+    #[test]
+    fn ere_captures_without_groups() {
+        // The overall match of a regex with groups doesn't depend on
+        // whether the groups are asked for.
+        assert_eq!(find_ere("(a|ab)(c|bcd)?", "abc"), Some((0, 3)));
+    }
+
+    #[test]
+    fn ere_posix_submatches() {
+        let re = translate_ere("(a|ab)(c|bcd)(d*)").unwrap();
+        let re = RegexBuilder::new(&re)
+            .longest_match(true)
+            .posix_submatches(true)
+            .compile()
+            .unwrap();
+        let caps = re.captures("abcd").unwrap();
+        assert_eq!(caps.pos(0), Some((0, 4)));
+        assert_eq!(caps.pos(1), Some((0, 2)));
+        assert_eq!(caps.pos(2), Some((2, 3)));
+        assert_eq!(caps.pos(3), Some((3, 4)));
+    }
+
+    #[test]
+    fn posix_submatches_with_scratch() {
+        // Searches with scratch memory must not allocate, so they resolve
+        // the groups by priority.
+        let re = RegexBuilder::new("(a|ab)(c|bcd)(d*)")
+            .posix_submatches(true)
+            .compile()
+            .unwrap();
+        let mut slots = vec![None; 8];
+        assert!(re.read_captures_at_with("abcd", 0, &mut slots,
+                                         &mut re.scratch()));
+        assert_eq!(slots, vec![Some(0), Some(4), Some(0), Some(1),
+                               Some(1), Some(4), Some(4), Some(4)]);
+        assert_eq!(re.captures("abcd").unwrap().pos(2), Some((2, 3)));
+    }
+
+    #[test]
+    fn ere_syntax() {
+        assert_eq!(find_ere("a{2}", "aaa"), Some((0, 2)));
+        assert_eq!(find_ere("*a", "x*a"), Some((1, 3)));
+        assert_eq!(find_ere("(*a)", "*a"), Some((0, 2)));
+        assert_eq!(find_ere("^*a", "*a"), Some((0, 2)));
+        assert_eq!(find_ere("a.b", "a\nb"), Some((0, 3)));
+        assert_eq!(find_ere("a]}#", "a]}#"), Some((0, 4)));
+        assert_eq!(find_ere(r"\<foo\>", "a foo b"), Some((2, 5)));
+        assert_eq!(find_ere(r"\.", "a.b"), Some((1, 2)));
+        assert!(compile_ere(r"(a)\1").is_err());
+        assert!(compile_ere("a\\").is_err());
+        assert!(compile_ere("[a").is_err());
+    }
+
+    #[test]
+    fn brackets() {
+        assert_eq!(find_ere("[]a]+", "x]a]"), Some((1, 4)));
+        assert_eq!(find_ere("[^]a]+", "]ab]"), Some((2, 3)));
+        assert_eq!(find_ere(r"[\]+", r"a\\"), Some((1, 3)));
+        assert_eq!(find_ere("[a-]+", "b-a-"), Some((1, 4)));
+        assert_eq!(find_ere("[[:digit:]x]+", "ab1x2"), Some((2, 5)));
+        assert_eq!(find_ere("[[=a=][.-.]]+", "b-a"), Some((1, 3)));
+        assert_eq!(find_ere("[^a]", "a\n"), Some((1, 2)));
+        assert!(compile_ere("[[.ch.]]").is_err());
+        assert!(compile_ere("[z-a]").is_err());
+    }
+
+    #[test]
+    fn bre_syntax() {
+        assert_eq!(find_bre(r"\(ab\)*c", "xababc"), Some((1, 6)));
+        assert_eq!(find_bre(r"a\{2,3\}", "aaaa"), Some((0, 3)));
+        assert_eq!(find_bre("a+?|(b){1}", "a+?|(b){1}"), Some((0, 10)));
+        assert_eq!(find_bre(r"a\+", "xaa"), Some((1, 3)));
+        assert_eq!(find_bre(r"a\|ab", "ab"), Some((0, 2)));
+        assert_eq!(find_bre("*a", "*a"), Some((0, 2)));
+        assert_eq!(find_bre("^*a", "*a"), Some((0, 2)));
+        assert_eq!(find_bre(r"\(*a\)", "*a"), Some((0, 2)));
+        assert_eq!(find_bre("a^b$c", "a^b$c"), Some((0, 5)));
+        assert_eq!(find_bre("^ab$", "ab"), Some((0, 2)));
+        assert_eq!(find_bre(r"\(^a$\)", "a"), Some((0, 1)));
+        assert_eq!(find_bre("^ab$", "xab"), None);
+        assert!(compile_bre(r"\(a\)\1").is_err());
+        assert_eq!(translate_bre("a+").unwrap(), r"(?s)a\+");
+    }
+}
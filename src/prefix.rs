@@ -8,7 +8,9 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+use std::cmp;
 use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use aho_corasick::{Automaton, AcAutomaton, FullAcAutomaton};
 use memchr::memchr;
@@ -24,8 +26,21 @@ use memchr::memchr;
 /// 1. The prefix is a single byte. Just use memchr.
 /// 2. If the prefix is a set of two or more single byte prefixes, then
 ///    a single sparse map is created. Checking if there is a match is a lookup
-///    in this map for each byte in the search text.
-/// 3. In all other cases, build an Aho-Corasick automaton.
+///    in this map for each byte in the search text. (Unless there are only a
+///    few bytes, in which case memchr is used on each of them.)
+/// 3. If every prefix is two bytes long, then a set of all pairs of bytes
+///    is created. Checking if there is a match is a lookup in this set for
+///    each pair of adjacent bytes in the search text.
+/// 4. If the prefix is a single literal compared without regard to ASCII
+///    case, e.g., in `(?i)error`, then look for either case of its rarest
+///    byte and compare the rest ignoring case. (Expanding it into every way
+///    of writing it, `error`, `Error`, `ERROR` and so on, takes two
+///    alternates for every letter.)
+/// 5. If the regex is nothing but a list of more literals than the prefix
+///    limits allow, e.g., a blocklist of words, then build an Aho-Corasick
+///    automaton without a full transition table, and use it to find the
+///    leftmost literal, preferring the ones listed first.
+/// 6. In all other cases, build an Aho-Corasick automaton.
 ///
 /// It's possible that there's room here for other substring algorithms,
 /// such as Boyer-Moore for single-set prefixes greater than 1, or Rabin-Karp
@@ -44,6 +59,12 @@ pub enum Prefix {
         sparse: Vec<bool>,
     },
     Single(SingleSearch),
+    /// A set of two or more two byte prefixes.
+    Pairs(PairSearch),
+    /// A single prefix matched without regard to ASCII case.
+    Folded(FoldedSearch),
+    /// All of the literals that make up a regex.
+    Keywords(KeywordSearch),
     /// A full Aho-Corasick DFA automaton.
     Automaton(FullAcAutomaton<String>),
 }
@@ -65,11 +86,94 @@ impl Prefix {
             Prefix::Bytes { chars: chars, sparse: set }
         } else if pfxs.len() == 1 {
             Prefix::Single(SingleSearch::new(pfxs.pop().unwrap()))
+        } else if pfxs.iter().all(|s| s.len() == 2) {
+            Prefix::Pairs(PairSearch::new(pfxs))
         } else {
             Prefix::Automaton(AcAutomaton::new(pfxs).into_full())
         }
     }
 
+    /// Returns roughly how much memory, in bytes, the machine built by `new`
+    /// for `pfxs` takes.
+    ///
+    /// An Aho-Corasick automaton has a row of 256 transitions for each of
+    /// its states, and there can be a state for every byte of the prefixes.
+    /// That's what makes long prefixes expensive.
+    pub fn projected_size(pfxs: &[String]) -> usize {
+        use std::mem::size_of;
+
+        let bytes: usize = pfxs.iter().map(|p| p.len()).sum();
+        if pfxs.is_empty() || pfxs[0].is_empty() {
+            0
+        } else if pfxs.len() == 1 && pfxs[0].len() == 1 {
+            0
+        } else if pfxs.len() >= 2 && pfxs.iter().all(|s| s.len() == 1) {
+            256 + bytes
+        } else if pfxs.len() == 1 {
+            256 * size_of::<usize>() + bytes
+        } else if pfxs.iter().all(|s| s.len() == 2) {
+            (1 << 16) / 8 + bytes
+        } else {
+            // Plus the failure and root states.
+            let states = bytes.saturating_add(2);
+            let row = 256 * size_of::<u32>() + size_of::<Vec<usize>>();
+            states.saturating_mul(row).saturating_add(bytes)
+        }
+    }
+
+    /// Create a prefix machine that looks for `pfx` without regard to the
+    /// case of its ASCII letters.
+    ///
+    /// Every other character is matched exactly.
+    pub fn folded(pfx: String) -> Prefix {
+        if pfx.is_empty() {
+            Prefix::Empty
+        } else {
+            Prefix::Folded(FoldedSearch::new(pfx))
+        }
+    }
+
+    /// Returns roughly how much memory, in bytes, the machine built by
+    /// `folded` for `pfx` takes.
+    pub fn projected_folded_size(pfx: &str) -> usize {
+        pfx.len() + 2
+    }
+
+    /// Create a prefix machine that finds the leftmost of the given
+    /// literals, preferring the ones listed first, just like the
+    /// alternation of them would.
+    ///
+    /// Its size grows with the total length of the literals, but unlike
+    /// with `new`, not with the number of different bytes in them, so
+    /// there's no limit on how many there are.
+    pub fn keywords(pats: Vec<String>) -> Prefix {
+        if pats.is_empty() || pats.iter().any(|p| p.is_empty()) {
+            Prefix::Empty
+        } else {
+            Prefix::Keywords(KeywordSearch::new(pats))
+        }
+    }
+
+    /// Create a prefix machine that looks for any of the given bytes.
+    ///
+    /// Unlike the prefixes given to `new`, the bytes may be the first bytes
+    /// of multi-byte characters.
+    pub fn bytes(mut bytes: Vec<u8>) -> Prefix {
+        bytes.sort();
+        bytes.dedup();
+        match bytes.len() {
+            0 => Prefix::Empty,
+            1 => Prefix::Byte(bytes[0]),
+            _ => {
+                let mut set = vec![false; 256];
+                for &b in &bytes {
+                    set[b as usize] = true;
+                }
+                Prefix::Bytes { chars: bytes, sparse: set }
+            }
+        }
+    }
+
     /// Find the position of a prefix in `haystack` if it exists.
     ///
     /// In the matching engines, we only actually need the starting index
@@ -77,17 +181,28 @@ impl Prefix {
     /// still needs to run over the prefix input. However, we return the ending
     /// location as well in case the prefix corresponds to the entire regex,
     /// in which case, you need the end of the match.
-    pub fn find(&self, haystack: &str) -> Option<(usize, usize)> {
+    pub fn find(&self, haystack: &[u8]) -> Option<(usize, usize)> {
         use self::Prefix::*;
         match *self {
             Empty => Some((0, 0)),
-            Byte(b) => memchr(b, haystack.as_bytes()).map(|i| (i, i+1)),
-            Bytes { ref sparse, .. } => {
-                find_singles(sparse, haystack.as_bytes())
+            Byte(b) => memchr(b, haystack).map(|i| (i, i+1)),
+            Bytes { ref chars, ref sparse } => {
+                if chars.len() <= FEW_BYTES_LIMIT {
+                    find_few(chars, haystack)
+                } else {
+                    find_singles(sparse, haystack)
+                }
             }
             Single(ref searcher) => {
                 searcher.find(haystack).map(|i| (i, i + searcher.pat.len()))
             }
+            Pairs(ref searcher) => {
+                searcher.find(haystack).map(|i| (i, i + 2))
+            }
+            Folded(ref searcher) => {
+                searcher.find(haystack).map(|i| (i, i + searcher.pat.len()))
+            }
+            Keywords(ref searcher) => searcher.find(haystack),
             Automaton(ref aut) => {
                 aut.find(haystack).next().map(|m| (m.start, m.end))
             }
@@ -106,6 +221,9 @@ impl Prefix {
             Prefix::Byte(_) => 1,
             Prefix::Bytes { ref chars, .. } => chars.len(),
             Prefix::Single(_) => 1,
+            Prefix::Pairs(ref searcher) => searcher.pats.len(),
+            Prefix::Folded(_) => 1,
+            Prefix::Keywords(ref searcher) => searcher.aut.len(),
             Prefix::Automaton(ref aut) => aut.len(),
         }
     }
@@ -121,6 +239,9 @@ impl Prefix {
             Prefix::Byte(_) => true,
             Prefix::Bytes{..} => true,
             Prefix::Single(_) => true,
+            Prefix::Pairs(_) => true,
+            Prefix::Folded(_) => true,
+            Prefix::Keywords(_) => true,
             Prefix::Automaton(ref aut) => {
                 // Okay, so the automaton can respect priority in one
                 // particular case: when every pattern is of the same length.
@@ -148,6 +269,11 @@ impl Prefix {
                 chars.iter().map(|&b| format!("{}", b as char)).collect()
             }
             Prefix::Single(ref searcher) => vec![searcher.pat.clone()],
+            Prefix::Pairs(ref searcher) => searcher.pats.clone(),
+            Prefix::Folded(ref searcher) => vec![searcher.pat.clone()],
+            Prefix::Keywords(ref searcher) => {
+                searcher.aut.patterns().to_vec()
+            }
             Prefix::Automaton(ref aut) => aut.patterns().to_vec(),
         }
     }
@@ -165,10 +291,16 @@ impl Prefix {
 /// longer (see the `easy0_1MB` vs. `easy1_1MB` benchmarks).
 ///
 /// More analysis needs to be done to test this on different search texts.
+///
+/// Instead of always skipping ahead with `memchr` on the first byte of the
+/// pattern, the byte of the pattern that is least likely to occur in the
+/// search text is used. e.g., for `"    at "`, it is `t`.
 #[derive(Clone, Debug)]
 pub struct SingleSearch {
     pat: String,
     shift: Vec<usize>,
+    /// The position in `pat` of the byte given to `memchr`.
+    rare: usize,
 }
 
 impl SingleSearch {
@@ -178,19 +310,30 @@ impl SingleSearch {
         for i in 0..(pat.len() - 1) {
             shift[pat.as_bytes()[i] as usize] = pat.len() - i - 1;
         }
+        // Ties go to the earliest position, so `memchr` on the first byte
+        // is kept unless some other byte is rarer.
+        let mut rare = 0;
+        for (i, &b) in pat.as_bytes().iter().enumerate() {
+            if frequency_rank(b) < frequency_rank(pat.as_bytes()[rare]) {
+                rare = i;
+            }
+        }
         SingleSearch {
             pat: pat,
             shift: shift,
+            rare: rare,
         }
     }
 
-    fn find(&self, haystack: &str) -> Option<usize> {
+    fn find(&self, haystack: &[u8]) -> Option<usize> {
         let pat = self.pat.as_bytes();
-        let haystack = haystack.as_bytes();
         if haystack.len() < pat.len() {
             return None;
         }
-        let mut i = match memchr(pat[0], haystack) {
+        // Candidate positions are found by looking for the rare byte and
+        // stepping back to where the pattern would start.
+        let (rare, rare_byte) = (self.rare, pat[self.rare]);
+        let mut i = match memchr(rare_byte, &haystack[rare..]) {
             None => return None,
             Some(i) => i,
         };
@@ -203,7 +346,10 @@ impl SingleSearch {
                 return Some(i);
             }
             i += self.shift[b as usize];
-            i += match memchr(pat[0], &haystack[i..]) {
+            if i > haystack.len() - pat.len() {
+                return None;
+            }
+            i += match memchr(rare_byte, &haystack[i + rare..]) {
                 None => return None,
                 Some(i) => i,
             };
@@ -212,7 +358,192 @@ impl SingleSearch {
     }
 }
 
+/// Returns a rough guess of how common the byte `b` is in typical text.
+/// Rarer bytes have lower ranks.
+fn frequency_rank(b: u8) -> u8 {
+    match b {
+        b' ' => 255,
+        b'e' | b't' | b'a' | b'o' | b'i' | b'n' | b's' | b'r' | b'h' => 200,
+        b'\n' | b'.' | b',' | b'-' | b'"' | b'\'' => 120,
+        b'\t' | b'\r' => 80,
+        _ if b >= b'a' && b <= b'z' => 150,
+        _ if b >= b'0' && b <= b'9' => 120,
+        _ if b >= b'A' && b <= b'Z' => 100,
+        _ if b >= 0x80 => 30,
+        _ if b >= b'!' && b <= b'~' => 60,
+        _ => 10,
+    }
+}
+
+/// Searches for a single string without regard to the case of its ASCII
+/// letters.
+///
+/// Candidates are found by looking for either case of the rarest byte of the
+/// string with `memchr`, and then comparing the rest of it.
+#[derive(Clone, Debug)]
+pub struct FoldedSearch {
+    /// The string, with its ASCII letters in lower case.
+    pat: String,
+    /// The position in `pat` of the byte looked for.
+    rare: usize,
+    /// The ways of writing that byte.
+    rare_bytes: Vec<u8>,
+}
+
+impl FoldedSearch {
+    fn new(pat: String) -> FoldedSearch {
+        assert!(pat.len() >= 1);
+        let pat: String = pat.chars().map(fold_ascii).collect();
+        let mut rare = 0;
+        for (i, &b) in pat.as_bytes().iter().enumerate() {
+            if frequency_rank(b) < frequency_rank(pat.as_bytes()[rare]) {
+                rare = i;
+            }
+        }
+        let b = pat.as_bytes()[rare];
+        let rare_bytes = if b >= b'a' && b <= b'z' {
+            vec![b, b - b'a' + b'A']
+        } else {
+            vec![b]
+        };
+        FoldedSearch {
+            pat: pat,
+            rare: rare,
+            rare_bytes: rare_bytes,
+        }
+    }
+
+    fn find(&self, haystack: &[u8]) -> Option<usize> {
+        let pat = self.pat.as_bytes();
+        if haystack.len() < pat.len() {
+            return None;
+        }
+        let last = haystack.len() - pat.len();
+        let mut i = 0;
+        while i <= last {
+            let rest = &haystack[i + self.rare..];
+            i += match find_few(&self.rare_bytes, rest) {
+                None => return None,
+                Some((j, _)) => j,
+            };
+            if i > last {
+                return None;
+            }
+            let found = pat.iter()
+                           .zip(&haystack[i..i + pat.len()])
+                           .all(|(&p, &b)| p == fold_ascii(b as char) as u8);
+            if found {
+                return Some(i);
+            }
+            i += 1;
+        }
+        None
+    }
+}
+
+/// Returns the lower case of an ASCII letter, and any other character as is.
+fn fold_ascii(c: char) -> char {
+    if c >= 'A' && c <= 'Z' {
+        ((c as u8) - b'A' + b'a') as char
+    } else {
+        c
+    }
+}
+
+/// Searches for the leftmost of a list of literals, preferring the one
+/// listed first among those that start at the same position.
+///
+/// An Aho-Corasick automaton reports the literals in the order in which they
+/// *end*, so the search goes on through all of them until none of the rest
+/// can start any earlier than the best one so far.
+#[derive(Clone, Debug)]
+pub struct KeywordSearch {
+    aut: AcAutomaton<String>,
+    /// The length of the longest literal.
+    max_len: usize,
+}
+
+impl KeywordSearch {
+    fn new(pats: Vec<String>) -> KeywordSearch {
+        let max_len = pats.iter().map(|p| p.len()).max().unwrap_or(0);
+        KeywordSearch {
+            aut: AcAutomaton::new(pats),
+            max_len: max_len,
+        }
+    }
+
+    fn find(&self, haystack: &[u8]) -> Option<(usize, usize)> {
+        let mut best: Option<(usize, usize, usize)> = None;
+        for m in self.aut.find_overlapping(haystack) {
+            if let Some((start, pati, _)) = best {
+                // A literal that starts at or before `start` ends at most
+                // `max_len` bytes after it.
+                if m.end > start + self.max_len {
+                    break;
+                }
+                if (m.start, m.pati) >= (start, pati) {
+                    continue;
+                }
+            }
+            best = Some((m.start, m.pati, m.end));
+        }
+        best.map(|(s, _, e)| (s, e))
+    }
+}
+
 /// A quick scan for multiple single byte prefixes using a sparse map.
+/// Searches for any of a set of two byte strings.
+///
+/// Each pair of bytes is packed into a 16 bit number, which indexes a set
+/// of 2^16 bits (i.e., 8KB). So there is a single lookup for every position
+/// in the search text, no matter how many pairs there are.
+#[derive(Clone)]
+pub struct PairSearch {
+    pats: Vec<String>,
+    set: Vec<u64>,
+}
+
+impl PairSearch {
+    fn new(pats: Vec<String>) -> PairSearch {
+        let mut set = vec![0; (1 << 16) / 64];
+        for pat in &pats {
+            let pat = pat.as_bytes();
+            let pair = pack(pat[0], pat[1]);
+            set[pair / 64] |= 1 << (pair % 64);
+        }
+        PairSearch {
+            pats: pats,
+            set: set,
+        }
+    }
+
+    fn find(&self, haystack: &[u8]) -> Option<usize> {
+        if haystack.len() < 2 {
+            return None;
+        }
+        let mut pair = haystack[0] as usize;
+        for (i, &b) in haystack[1..].iter().enumerate() {
+            pair = ((pair << 8) | b as usize) & 0xFFFF;
+            if self.set[pair / 64] & (1 << (pair % 64)) != 0 {
+                return Some(i);
+            }
+        }
+        None
+    }
+}
+
+/// Packs two bytes into a 16 bit number, the first one being the most
+/// significant.
+fn pack(b1: u8, b2: u8) -> usize {
+    ((b1 as usize) << 8) | b2 as usize
+}
+
+impl fmt::Debug for PairSearch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PairSearch").field("pats", &self.pats).finish()
+    }
+}
+
 fn find_singles(sparse: &[bool], haystack: &[u8]) -> Option<(usize, usize)> {
     // TODO: Improve this with ideas found in jetscii crate.
     for (hi, &b) in haystack.iter().enumerate() {
@@ -223,6 +554,32 @@ fn find_singles(sparse: &[bool], haystack: &[u8]) -> Option<(usize, usize)> {
     None
 }
 
+/// The largest set of bytes that is searched for with `memchr`.
+const FEW_BYTES_LIMIT: usize = 3;
+
+/// The number of bytes searched at a time by `find_few`.
+const FEW_BYTES_WINDOW: usize = 1024;
+
+/// Finds the first occurrence of any of a few bytes.
+///
+/// Running `memchr` on each byte is much faster than looking up every byte
+/// of the haystack in a table, as long as there are only a few of them. But
+/// a byte that occurs late (or never) would be searched for well past the
+/// first occurrence of another one, so the haystack is searched in windows.
+fn find_few(chars: &[u8], haystack: &[u8]) -> Option<(usize, usize)> {
+    let mut start = 0;
+    while start < haystack.len() {
+        let end = cmp::min(start + FEW_BYTES_WINDOW, haystack.len());
+        let window = &haystack[start..end];
+        let found = chars.iter().filter_map(|&b| memchr(b, window)).min();
+        if let Some(i) = found {
+            return Some((start + i, start + i + 1));
+        }
+        start = end;
+    }
+    None
+}
+
 impl fmt::Debug for Prefix {
     #[allow(deprecated)] // connect => join in 1.3
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -237,7 +594,83 @@ impl fmt::Debug for Prefix {
                 write!(f, "{}", chars.connect(", "))
             }
             Prefix::Single(ref searcher) => write!(f, "{:?}", searcher),
+            Prefix::Pairs(ref searcher) => write!(f, "{:?}", searcher.pats),
+            Prefix::Folded(ref searcher) => write!(f, "{:?}", searcher),
+            Prefix::Keywords(ref searcher) => write!(f, "{:?}", searcher.aut),
             Prefix::Automaton(ref aut) => write!(f, "{:?}", aut),
         }
     }
 }
+
+/// The number of candidates that must be seen before `PrefixStats` passes
+/// judgment on the prefixes.
+const STATS_MIN_CANDIDATES: usize = 512;
+/// Prefixes are abandoned if fewer than one in this many candidates leads
+/// to a match...
+const STATS_VERIFY_RATIO: usize = 64;
+/// ... and if the scan skips fewer than this many bytes per candidate on
+/// average. (Prefixes that skip a lot of text pay off even if they rarely
+/// lead to a match.)
+const STATS_MIN_SKIP: usize = 16;
+
+/// Feedback on how well the prefixes of a program work, gathered from the
+/// searches done with it so far.
+///
+/// A candidate is a position where the prefix scan stopped. If candidates
+/// close together almost never lead to a match, then the matching engine
+/// spends its time stopping and restarting the scan, which is slower than
+/// not scanning at all. The sample taken before every search catches the
+/// obvious cases, but not prefixes that are common all over the text
+/// without being common at the start of it.
+///
+/// The counters are only ever updated with relaxed atomic operations, so
+/// they can be shared by every thread using the program without a lock. A
+/// lost update here and there only delays the verdict.
+#[derive(Debug)]
+pub struct PrefixStats {
+    candidates: AtomicUsize,
+    verified: AtomicUsize,
+    skipped: AtomicUsize,
+}
+
+impl PrefixStats {
+    /// Creates empty statistics.
+    pub fn new() -> PrefixStats {
+        PrefixStats {
+            candidates: AtomicUsize::new(0),
+            verified: AtomicUsize::new(0),
+            skipped: AtomicUsize::new(0),
+        }
+    }
+
+    /// Records the outcome of a search, which stopped at `candidates`
+    /// candidates, `verified` of which led to a match, after skipping
+    /// `skipped` bytes in total.
+    ///
+    /// Once there's enough to go on, nothing more is recorded.
+    pub fn record(&self, candidates: usize, verified: usize, skipped: usize) {
+        if candidates == 0 || self.is_settled() {
+            return;
+        }
+        self.candidates.fetch_add(candidates, Ordering::Relaxed);
+        self.verified.fetch_add(verified, Ordering::Relaxed);
+        self.skipped.fetch_add(skipped, Ordering::Relaxed);
+    }
+
+    /// Returns true if the prefixes have been seen to stop often and to
+    /// rarely lead to a match, so that they should no longer be used.
+    pub fn is_useless(&self) -> bool {
+        if !self.is_settled() {
+            return false;
+        }
+        let candidates = self.candidates.load(Ordering::Relaxed);
+        let verified = self.verified.load(Ordering::Relaxed);
+        let skipped = self.skipped.load(Ordering::Relaxed);
+        verified.saturating_mul(STATS_VERIFY_RATIO) < candidates
+        && skipped < candidates.saturating_mul(STATS_MIN_SKIP)
+    }
+
+    fn is_settled(&self) -> bool {
+        self.candidates.load(Ordering::Relaxed) >= STATS_MIN_CANDIDATES
+    }
+}
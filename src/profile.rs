@@ -0,0 +1,91 @@
+// Copyright 2016 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use re_builder::Engine;
+
+/// How a search with one matching engine went, as measured by
+/// `Regex::profile`.
+#[derive(Clone, Debug)]
+pub struct EngineProfile {
+    /// The engine, which is never `Engine::Automatic`.
+    pub engine: Engine,
+    /// How long the search took.
+    pub time: Duration,
+    /// The match found, if any. Every engine finds the same one.
+    pub matched: Option<(usize, usize)>,
+    /// The work the engine did.
+    pub counts: SearchCounts,
+}
+
+/// Counts of the work a matching engine did during a search.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SearchCounts {
+    /// The number of positions in the text the engine looked at.
+    ///
+    /// For the NFA, this is every position it stepped all of its threads
+    /// at. For the backtracking engine, this is every position it tried to
+    /// find a match starting at.
+    pub positions: usize,
+    /// The number of threads the engine ran.
+    ///
+    /// For the NFA, a thread is counted once for every position it's
+    /// stepped at. For the backtracking engine, this is every path it tried
+    /// (which it never tries twice).
+    pub threads: usize,
+    /// The number of times the engine looked for the next occurrence of the
+    /// regex's literal prefixes to skip ahead to.
+    pub prefix_scans: usize,
+    /// The number of bytes skipped by looking for the literal prefixes.
+    pub prefix_skipped: usize,
+}
+
+/// Counts of the work done by every search with a program, which can be
+/// shared between threads.
+#[derive(Debug)]
+pub struct Counters {
+    positions: AtomicUsize,
+    threads: AtomicUsize,
+    prefix_scans: AtomicUsize,
+    prefix_skipped: AtomicUsize,
+}
+
+impl Counters {
+    /// Creates counters that are all zero.
+    pub fn new() -> Counters {
+        Counters {
+            positions: AtomicUsize::new(0),
+            threads: AtomicUsize::new(0),
+            prefix_scans: AtomicUsize::new(0),
+            prefix_skipped: AtomicUsize::new(0),
+        }
+    }
+
+    /// Adds the counts of a search.
+    pub fn add(&self, counts: &SearchCounts) {
+        self.positions.fetch_add(counts.positions, Ordering::Relaxed);
+        self.threads.fetch_add(counts.threads, Ordering::Relaxed);
+        self.prefix_scans.fetch_add(counts.prefix_scans, Ordering::Relaxed);
+        self.prefix_skipped.fetch_add(
+            counts.prefix_skipped, Ordering::Relaxed);
+    }
+
+    /// Returns the counts so far.
+    pub fn get(&self) -> SearchCounts {
+        SearchCounts {
+            positions: self.positions.load(Ordering::Relaxed),
+            threads: self.threads.load(Ordering::Relaxed),
+            prefix_scans: self.prefix_scans.load(Ordering::Relaxed),
+            prefix_skipped: self.prefix_skipped.load(Ordering::Relaxed),
+        }
+    }
+}
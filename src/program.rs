@@ -8,25 +8,41 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+use std::cmp;
+use std::sync::Arc;
+use std::time::Instant;
+
 use syntax;
 
 use Error;
-use backtrack::{Backtrack, BackMachine};
+use backtrack::{Backtrack, BackMachine, MAX_VISITED_BYTES};
 use compile::Compiler;
-use inst::{EmptyLook, Inst};
+use inst::{EmptyLook, Inst, InstIdx};
+use input::{CharInput, Input};
 use nfa::{Nfa, NfaThreads};
 use pool::Pool;
-use prefix::Prefix;
+use prefix::{Prefix, PrefixStats};
+use profile::{Counters, EngineProfile, SearchCounts};
 use re::CaptureIdxs;
+use re_builder::Engine;
+use submatch;
+use trace::{TraceEvent, Tracer};
 
 const NUM_PREFIX_LIMIT: usize = 30;
 const PREFIX_LENGTH_LIMIT: usize = 15;
+const PREFIX_SAMPLE_SIZE: usize = 256;
+const PREFIX_DENSITY_LIMIT: usize = 8;
+/// Checking the candidates found by the prefixes may cost up to this many
+/// times as much as scanning the text for them...
+const PREFILTER_COST_RATIO: usize = 4;
+/// ... plus this many bytes, before a single engine takes over the search.
+const PREFILTER_COST_SLACK: usize = 1 << 12;
 
 /// The matching engines offered by this regex implementation.
 ///
 /// N.B. This is exported for use in testing.
 #[doc(hidden)]
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum MatchEngine {
     /// A bounded backtracking implementation. About twice as fast as the
     /// NFA, but can only work on small regexes and small input.
@@ -39,6 +55,61 @@ pub enum MatchEngine {
     Literals,
 }
 
+/// Options that control how a program is compiled, apart from the size
+/// limit (which is enforced by the compiler).
+#[derive(Clone, Debug)]
+pub struct ProgramOptions {
+    /// The matching engine to use. When `None`, pick one automatically.
+    pub engine: Option<MatchEngine>,
+    /// Whether literal prefixes are extracted and used to skip ahead in the
+    /// text.
+    pub literal_optimizations: bool,
+    /// The maximum number of literal prefixes.
+    pub prefix_count_limit: usize,
+    /// The maximum length, in bytes, of a literal prefix. Longer prefixes
+    /// are cut short.
+    pub prefix_length_limit: usize,
+    /// Whether the literal prefixes are given up on once previous searches
+    /// show that they don't pay off (see `PrefixStats`).
+    pub adaptive_prefixes: bool,
+    /// The length, in bytes, of the text at the start of a search in which
+    /// the literal prefixes are counted before they are used.
+    pub prefix_sample_size: usize,
+    /// The most memory, in bytes, the backtracking engine may use for a
+    /// search. It isn't chosen automatically for texts that need more.
+    pub backtrack_limit: usize,
+    /// Whether the leftmost-longest match is reported instead of the
+    /// leftmost-first match.
+    pub longest_match: bool,
+    /// Whether capture groups are resolved by POSIX rules (see the
+    /// `submatch` module) instead of by priority.
+    pub posix_submatches: bool,
+    /// Whether backreferences are accepted. They're only run by the
+    /// backtracking engine, whose searches are then bounded by
+    /// `backtrack_limit` instead of being linear.
+    pub backreferences: bool,
+    /// Where the events of every search are reported.
+    pub tracer: Tracer,
+}
+
+impl Default for ProgramOptions {
+    fn default() -> ProgramOptions {
+        ProgramOptions {
+            engine: None,
+            literal_optimizations: true,
+            prefix_count_limit: NUM_PREFIX_LIMIT,
+            prefix_length_limit: PREFIX_LENGTH_LIMIT,
+            adaptive_prefixes: true,
+            prefix_sample_size: PREFIX_SAMPLE_SIZE,
+            backtrack_limit: MAX_VISITED_BYTES,
+            longest_match: false,
+            posix_submatches: false,
+            backreferences: false,
+            tracer: Tracer::default(),
+        }
+    }
+}
+
 /// Program represents a compiled regular expression. Once an expression is
 /// compiled, its representation is immutable and will never change.
 /// (Well, almost. In fact, the matching engines cache state that can be
@@ -49,28 +120,112 @@ pub struct Program {
     /// The original regular expression string.
     pub original: String,
     /// A sequence of instructions.
-    pub insts: Vec<Inst>,
+    ///
+    /// The instructions never change once compiled, so copies of the
+    /// program (like the anchored one used as a prefilter) share them.
+    pub insts: Arc<Vec<Inst>>,
+    /// The ranges of every character class in the program, one after the
+    /// other. Each `Ranges` instruction refers to a slice of this table.
+    pub ranges: Arc<Vec<(char, char)>>,
     /// The sequence of capture group names. There is an entry for each capture
     /// group index and a name exists only if the capture group is named.
     pub cap_names: Vec<Option<String>>,
+    /// The number of regexes the program was compiled from.
+    pub num_patterns: usize,
     /// If the regular expression requires a literal prefix in order to have a
     /// match, that prefix is stored here as a DFA.
-    pub prefixes: Prefix,
+    ///
+    /// It is shared by all clones of the program, since the automaton can
+    /// be big.
+    pub prefixes: Arc<Prefix>,
+    /// Feedback on how well the prefixes work, or `None` if the prefixes
+    /// are always used.
+    pub prefix_stats: Option<PrefixStats>,
     /// True iff matching any literal prefix indicates a match.
     pub prefixes_complete: bool,
-    /// True iff program is anchored at the beginning.
+    /// The length, in bytes, of the text sampled to decide whether the
+    /// prefixes are worth scanning for (see `should_scan_prefixes`).
+    pub prefix_sample_size: usize,
+    /// The number of characters that come before the literal prefixes in
+    /// every match, e.g., 2 for `..foo`.
+    pub prefix_offset: usize,
+    /// True iff every match of the program starts at the beginning of the
+    /// text.
     pub anchored_begin: bool,
-    /// True iff program is anchored at the end.
+    /// True iff every match of the program starts at the beginning of a
+    /// line, e.g., because all of its alternates start with `(?m)^`.
+    pub anchored_line_begin: bool,
+    /// True iff every match of the program ends at the end of the text.
     pub anchored_end: bool,
+    /// True iff the program only looks for a match that starts exactly
+    /// where the search starts. (See `anchored`.)
+    pub anchored_start: bool,
+    /// True iff the program only looks for a match that ends exactly at the
+    /// end of the text. (See `anchored_to_end`.)
+    pub match_at_end: bool,
+    /// The length, in bytes, of the shortest text that can match.
+    pub min_len: usize,
+    /// The length, in bytes, of the longest text that can match, if there
+    /// is a limit.
+    pub max_len: Option<usize>,
+    /// The most memory, in bytes, the backtracking engine may use for a
+    /// search.
+    pub backtrack_limit: usize,
+    /// True iff the program reports the leftmost-longest match (as POSIX
+    /// requires) instead of the leftmost-first match. Only the NFA engine
+    /// supports this.
+    pub longest_match: bool,
+    /// The regex the program was compiled from, if its capture groups are
+    /// resolved by POSIX rules after a match is found (see the `submatch`
+    /// module).
+    pub submatches: Option<Arc<syntax::Expr>>,
+    /// True iff the program has backreferences, which only the backtracking
+    /// engine can run (see `backtrack`). It then runs them for every
+    /// search, whatever the length of the text, and gives up once it has
+    /// taken as many steps as `backtrack_limit` has bits.
+    pub backrefs: bool,
+    /// A copy of this program without capture instructions, used when the
+    /// caller only wants to know whether there is a match.
+    pub match_only: Option<Box<Program>>,
+    /// A copy of this program that only saves the overall match, used when
+    /// the caller doesn't ask for any capture group. It exists only if the
+    /// program has capture groups.
+    pub find_only: Option<Box<Program>>,
+    /// An anchored copy of this program, which checks whether a match
+    /// starts at each candidate found by the prefixes (see
+    /// `exec_prefiltered`). It exists only if the prefixes can't find a
+    /// match by themselves.
+    pub prefilter: Option<Box<Program>>,
+    /// Copies of the program used for boolean queries (`match_only`, or
+    /// this one) that only look for a match at the start of the text, at
+    /// its end, or spanning all of it. They're built once, when a single
+    /// regex is compiled, and share its instructions.
+    pub anchored_copies: Option<Box<AnchoredCopies>>,
     /// The type of matching engine to use.
     /// When `None` (the default), pick an engine automatically.
     pub engine: Option<MatchEngine>,
+    /// Where the events of every search are reported.
+    pub tracer: Tracer,
+    /// Where the work done by every search is added up, if anywhere. This
+    /// is only set by `profile`.
+    pub counters: Option<Arc<Counters>>,
     /// Cached NFA threads.
     pub nfa_threads: Pool<NfaThreads>,
     /// Cached backtracking memory.
     pub backtrack: Pool<BackMachine>,
 }
 
+/// Anchored copies of a program. See `Program::anchored_copies`.
+#[derive(Clone, Debug)]
+pub struct AnchoredCopies {
+    /// Only finds a match that starts where the search starts.
+    pub start: Program,
+    /// Only finds a match that ends at the end of the text.
+    pub end: Program,
+    /// Only finds a match that does both.
+    pub both: Program,
+}
+
 impl Program {
     /// Compiles a Regex.
     pub fn new(
@@ -78,69 +233,640 @@ impl Program {
         size_limit: usize,
         re: &str,
     ) -> Result<Program, Error> {
-        let expr = try!(syntax::Expr::parse(re));
-        let compiler = Compiler::new(size_limit);
-        let (insts, cap_names) = try!(compiler.compile(&expr));
+        Program::new_many(engine, size_limit, &[re])
+    }
+
+    /// Compiles several regexes into a single program.
+    ///
+    /// The regexes are tried in order (as if they were the alternates of one
+    /// big alternation) and every `Match` instruction records the index of
+    /// the regex it belongs to. Only the overall match of each regex is
+    /// meaningful; its capture groups share slots with the other regexes.
+    pub fn new_many<S: AsRef<str>>(
+        engine: Option<MatchEngine>,
+        size_limit: usize,
+        res: &[S],
+    ) -> Result<Program, Error> {
+        let opts = ProgramOptions { engine: engine, ..Default::default() };
+        Program::with_compiler(&mut Compiler::new(size_limit), &opts, res)
+    }
+
+    /// Compiles several regexes into a single program using the given
+    /// compiler.
+    ///
+    /// This is like `new_many`, except the compiler's scratch space is reused
+    /// instead of being allocated from scratch for every program.
+    pub fn with_compiler<S: AsRef<str>>(
+        compiler: &mut Compiler,
+        opts: &ProgramOptions,
+        res: &[S],
+    ) -> Result<Program, Error> {
+        let mut exprs = Vec::with_capacity(res.len());
+        for re in res {
+            exprs.push(try!(syntax::Expr::parse(re.as_ref())));
+        }
+        Program::with_exprs(compiler, opts, res, &exprs)
+    }
+
+    /// Compiles several regexes that have already been parsed into a single
+    /// program using the given compiler.
+    ///
+    /// `res` are the regexes that `exprs` were parsed from, in the same
+    /// order.
+    pub fn with_exprs<S: AsRef<str>>(
+        compiler: &mut Compiler,
+        opts: &ProgramOptions,
+        res: &[S],
+        exprs: &[syntax::Expr],
+    ) -> Result<Program, Error> {
+        let (insts, ranges, cap_names) = try!(compiler.compile_many(exprs));
+        let backrefs = insts.iter().any(|inst| match *inst {
+            Inst::Backref(_) => true,
+            _ => false,
+        });
+        if backrefs {
+            let unsupported = match opts.engine {
+                _ if !opts.backreferences => {
+                    Some("backreferences (see RegexBuilder::backreferences)")
+                }
+                Some(MatchEngine::Nfa) | Some(MatchEngine::Literals) => {
+                    Some("backreferences without the backtracking engine")
+                }
+                _ if opts.longest_match => {
+                    Some("backreferences with leftmost-longest matches")
+                }
+                _ if opts.posix_submatches => {
+                    Some("backreferences with POSIX submatches")
+                }
+                _ => None,
+            };
+            if let Some(what) = unsupported {
+                return Err(Error::Unsupported(what.into()));
+            }
+        }
         let (insts_len, ncaps) = (insts.len(), num_captures(&insts));
         let create_threads = move || NfaThreads::new(insts_len, ncaps);
         let create_backtrack = move || BackMachine::new();
+        let original = if res.len() == 1 {
+            res[0].as_ref().into()
+        } else {
+            let alts: Vec<String> =
+                res.iter().map(|re| format!("(?:{})", re.as_ref())).collect();
+            alts.join("|")
+        };
         let mut prog = Program {
-            original: re.into(),
-            insts: insts,
+            original: original,
+            insts: Arc::new(insts),
+            ranges: Arc::new(ranges),
             cap_names: cap_names,
-            prefixes: Prefix::Empty,
+            num_patterns: res.len(),
+            prefixes: Arc::new(Prefix::Empty),
+            prefix_stats: if opts.adaptive_prefixes {
+                Some(PrefixStats::new())
+            } else {
+                None
+            },
             prefixes_complete: false,
+            prefix_sample_size: opts.prefix_sample_size,
+            prefix_offset: 0,
             anchored_begin: false,
+            anchored_line_begin: false,
+            anchored_start: false,
+            match_at_end: false,
             anchored_end: false,
-            engine: engine,
+            min_len: 0,
+            max_len: None,
+            backtrack_limit: opts.backtrack_limit,
+            longest_match: opts.longest_match,
+            submatches: if opts.posix_submatches && exprs.len() == 1 {
+                Some(Arc::new(exprs[0].clone()))
+            } else {
+                None
+            },
+            backrefs: backrefs,
+            engine: opts.engine,
+            tracer: opts.tracer.clone(),
+            counters: None,
+            match_only: None,
+            find_only: None,
+            prefilter: None,
+            anchored_copies: None,
             nfa_threads: Pool::new(Box::new(create_threads)),
             backtrack: Pool::new(Box::new(create_backtrack)),
         };
 
-        prog.find_prefixes();
-        prog.anchored_begin = match prog.insts[1] {
-            Inst::EmptyLook(ref inst) => inst.look == EmptyLook::StartText,
-            _ => false,
-        };
-        prog.anchored_end = match prog.insts[prog.insts.len() - 3] {
-            Inst::EmptyLook(ref inst) => inst.look == EmptyLook::EndText,
-            _ => false,
-        };
+        if opts.literal_optimizations {
+            // The prefix machine counts against the size limit too, so it
+            // gets whatever the instructions leave over.
+            let budget = compiler.size_limit()
+                                 .saturating_sub(prog.compiled_size());
+            prog.find_prefixes(opts, budget);
+        }
+        prog.min_len = min_match_len(&prog.insts, &prog.ranges);
+        prog.max_len = max_match_len(&prog.insts, &prog.ranges);
+        if res.len() != 1 {
+            // A prefix match can't tell which regex matched, so the prefixes
+            // may only be used to skip ahead.
+            prog.prefixes_complete = false;
+        }
+        prog.anchored_begin =
+            is_anchored_start(&prog.insts, &[EmptyLook::StartText]);
+        prog.anchored_line_begin = is_anchored_start(
+            &prog.insts, &[EmptyLook::StartText, EmptyLook::StartLine]);
+        prog.anchored_end = is_anchored_end(&prog.insts);
+        // Only bother with a separate program for boolean queries if there
+        // is more to strip than the two slots of the overall match. (A
+        // backreference needs the slots of its group, so nothing can be
+        // stripped then.)
+        if !backrefs && (prog.num_captures() > 1 || res.len() > 1) {
+            let mut match_only = prog.with_slots(0);
+            // Whether there is a match doesn't depend on which one is
+            // reported, so the faster engines can be used.
+            match_only.longest_match = false;
+            prog.match_only = Some(Box::new(match_only));
+        }
+        // Likewise, searches that only want the overall match don't need to
+        // keep track of any capture group.
+        if !backrefs && prog.num_captures() > 1 {
+            prog.find_only = Some(Box::new(prog.with_slots(2)));
+        }
+        // Unless the prefixes find a match by themselves, a search can look
+        // for them first and only run an engine where one is found.
+        let literals_only =
+            prog.prefixes_complete && prog.prefixes.preserves_priority();
+        if res.len() == 1
+           && !prog.prefixes.is_empty()
+           && !prog.anchored_begin
+           && !literals_only {
+            prog.prefilter = Some(Box::new(prog.anchored()));
+        }
+        if res.len() == 1 {
+            let copies = {
+                let prog = prog.match_only.as_ref().map_or(&prog, |p| &**p);
+                let start = prog.anchored();
+                AnchoredCopies {
+                    end: prog.anchored_to_end(),
+                    both: start.anchored_to_end(),
+                    start: start,
+                }
+            };
+            prog.anchored_copies = Some(Box::new(copies));
+        }
+        debug!("compiled {:?}: {} instructions, {} bytes, {} literal \
+                prefixes (complete: {}, offset: {}), prefilter: {}",
+               prog.original, prog.insts.len(), prog.compiled_size(),
+               prog.prefixes.len(), prog.prefixes_complete,
+               prog.prefix_offset, prog.prefilter.is_some());
         Ok(prog)
     }
 
+    /// Returns a copy of this program without the `Save` instructions for
+    /// any slot from `nslots` on.
+    ///
+    /// The copy can only report the first `nslots` slots (with `0`, it can
+    /// only tell whether and which regex matches), but it is smaller and its
+    /// threads carry fewer capture slots.
+    fn with_slots(&self, nslots: usize) -> Program {
+        let insts = strip_saves(&self.insts, nslots);
+        let (insts_len, ncaps) = (insts.len(), nslots / 2);
+        let create_threads = move || NfaThreads::new(insts_len, ncaps);
+        let create_backtrack = move || BackMachine::new();
+        Program {
+            original: self.original.clone(),
+            insts: Arc::new(insts),
+            ranges: self.ranges.clone(),
+            cap_names: self.cap_names.iter().take(ncaps).cloned().collect(),
+            num_patterns: self.num_patterns,
+            prefixes: self.prefixes.clone(),
+            prefix_stats: self.fresh_prefix_stats(),
+            prefixes_complete: self.prefixes_complete,
+            prefix_sample_size: self.prefix_sample_size,
+            prefix_offset: self.prefix_offset,
+            anchored_begin: self.anchored_begin,
+            anchored_line_begin: self.anchored_line_begin,
+            anchored_start: self.anchored_start,
+            match_at_end: self.match_at_end,
+            anchored_end: self.anchored_end,
+            min_len: self.min_len,
+            max_len: self.max_len,
+            backtrack_limit: self.backtrack_limit,
+            longest_match: self.longest_match,
+            submatches: None,
+            backrefs: self.backrefs,
+            engine: self.engine,
+            tracer: self.tracer.clone(),
+            counters: None,
+            match_only: None,
+            find_only: None,
+            prefilter: None,
+            anchored_copies: None,
+            nfa_threads: Pool::new(Box::new(create_threads)),
+            backtrack: Pool::new(Box::new(create_backtrack)),
+        }
+    }
+
+    /// Returns a copy of this program that only looks for a match starting
+    /// exactly where the search starts, as if every regex in it began with
+    /// an assertion for the start of the search.
+    ///
+    /// The copy has no literal prefixes, since there's nothing to skip.
+    pub fn anchored(&self) -> Program {
+        let mut prog = self.clone();
+        prog.anchored_start = true;
+        prog.prefixes = Arc::new(Prefix::Empty);
+        prog.prefix_stats = None;
+        prog.prefixes_complete = false;
+        prog.prefix_offset = 0;
+        prog.prefilter = None;
+        prog.anchored_copies = None;
+        prog.match_only = self.match_only.as_ref().map(|p| {
+            Box::new(p.anchored())
+        });
+        prog.find_only = self.find_only.as_ref().map(|p| {
+            Box::new(p.anchored())
+        });
+        prog
+    }
+
+    /// Returns a copy of this program that only looks for a match ending
+    /// exactly at the end of the text, as if every regex in it ended with an
+    /// assertion for the end of the text.
+    ///
+    /// Since the copy is anchored at the end, a search with it doesn't look
+    /// further back than the longest possible match. Its literal prefixes
+    /// can still be used to skip ahead, but not to report a match.
+    pub fn anchored_to_end(&self) -> Program {
+        let mut prog = self.clone();
+        prog.match_at_end = true;
+        prog.anchored_end = true;
+        prog.prefixes_complete = false;
+        prog.anchored_copies = None;
+        prog.prefilter = self.prefilter.as_ref().map(|p| {
+            Box::new(p.anchored_to_end())
+        });
+        prog.match_only = self.match_only.as_ref().map(|p| {
+            Box::new(p.anchored_to_end())
+        });
+        prog.find_only = self.find_only.as_ref().map(|p| {
+            Box::new(p.anchored_to_end())
+        });
+        prog
+    }
+
     /// Executes a compiled regex program.
+    ///
+    /// If there's a match, the index of the regex that matched is returned.
     pub fn exec(
         &self,
         caps: &mut CaptureIdxs,
         text: &str,
         start: usize,
-    ) -> bool {
-        match self.choose_engine(caps.len(), text) {
-            MatchEngine::Backtrack => Backtrack::exec(self, caps, text, start),
-            MatchEngine::Nfa => Nfa::exec(self, caps, text, start),
+    ) -> Option<usize> {
+        let mut start = match self.search_start(text, start) {
+            None => return None,
+            Some(start) => start,
+        };
+        if let Some(ref verifier) = self.prefilter {
+            if self.engine.is_none()
+               && self.should_scan_prefixes(text, start) {
+                match self.exec_prefiltered(verifier, caps, text, start) {
+                    Ok(matched) => return matched,
+                    Err(next) => {
+                        debug!("prefilter for {:?} gave up at {}, the \
+                                candidates are too dense",
+                               self.original, next);
+                        start = next;
+                    }
+                }
+            }
+        }
+        if caps.is_empty() {
+            if let Some(ref prog) = self.match_only {
+                return prog.exec(caps, text, start);
+            }
+        } else if caps.len() == 2 {
+            if let Some(ref prog) = self.find_only {
+                return prog.exec(caps, text, start);
+            }
+        }
+        let (engine, scan) = self.choose_engine(caps.len(), text, start);
+        trace!("searching {:?} from {} with {:?}",
+               self.original, start, engine);
+        let matched = match engine {
+            MatchEngine::Backtrack => {
+                self.trace_search(Engine::Backtrack, start);
+                Backtrack::exec(self, caps, text, start, scan)
+            }
+            MatchEngine::Nfa => {
+                self.trace_search(Engine::Nfa, start);
+                Nfa::exec(self, caps, text, start, scan)
+            }
             MatchEngine::Literals => {
-                match self.prefixes.find(&text[start..]) {
-                    None => false,
+                self.tracer.emit(|| {
+                    TraceEvent::LiteralSearch { start: start }
+                });
+                match self.prefixes.find(text[start..].as_bytes()) {
+                    None => None,
                     Some((s, e)) => {
                         if caps.len() == 2 {
                             caps[0] = Some(start + s);
                             caps[1] = Some(start + e);
                         }
-                        true
+                        Some(0)
                     }
                 }
             }
+        };
+        if matched.is_some() {
+            self.resolve_submatches(caps, text);
+        }
+        matched
+    }
+
+    /// Finds the first match by looking for the literal prefixes, and then
+    /// running `verifier`, the anchored copy of the program, at each
+    /// candidate they point to.
+    ///
+    /// A check that fails may still have looked at up to the longest
+    /// possible match, so if the candidates are dense, this could take time
+    /// quadratic in the length of the text. Once the checks could have cost
+    /// too much compared to the text scanned (see `PREFILTER_COST_RATIO`),
+    /// the position of the next candidate to try is returned as an error,
+    /// and the rest of the text should be searched with one engine.
+    fn exec_prefiltered(
+        &self,
+        verifier: &Program,
+        caps: &mut CaptureIdxs,
+        text: &str,
+        start: usize,
+    ) -> Result<Option<usize>, usize> {
+        let input = CharInput::new(text);
+        let mut at = input.decode_at(start);
+        let (mut candidates, mut cost) = (0, 0);
+        let mut counts = SearchCounts::default();
+        let result = loop {
+            let before = at.pos();
+            let next =
+                input.prefix_at(&self.prefixes, self.prefix_offset, at);
+            self.tracer.emit(|| TraceEvent::PrefixScan {
+                from: before,
+                to: next.map(|at| at.pos()),
+            });
+            let candidate = match next {
+                None => break Ok(None),
+                Some(candidate) => candidate,
+            };
+            candidates += 1;
+            counts.prefix_scans += 1;
+            counts.prefix_skipped += candidate.pos() - before;
+            if let Some(pat) = verifier.exec(caps, text, candidate.pos()) {
+                break Ok(Some(pat));
+            }
+            let rest = text.len() - candidate.pos();
+            cost += cmp::min(self.max_len.unwrap_or(rest), rest);
+            // Prefixes are never empty, so a candidate is never at the end.
+            at = input.decode_at(candidate.next_pos());
+            if cost > PREFILTER_COST_RATIO * (at.pos() - start)
+                      + PREFILTER_COST_SLACK {
+                break Err(at.pos());
+            }
+        };
+        let verified = match result {
+            Ok(Some(_)) => 1,
+            _ => 0,
+        };
+        self.record_prefix_scan(candidates, verified, counts.prefix_skipped);
+        self.record_counts(&counts);
+        result
+    }
+
+    /// Reports a search starting at `start` with `engine` to the tracer.
+    fn trace_search(&self, engine: Engine, start: usize) {
+        self.tracer.emit(|| TraceEvent::Search {
+            engine: engine,
+            start: start,
+        });
+    }
+
+    /// Sets the capture groups in `caps` by POSIX rules, if the program
+    /// asks for that, once the overall match is known.
+    fn resolve_submatches(&self, caps: &mut CaptureIdxs, text: &str) {
+        if let Some(ref expr) = self.submatches {
+            if caps.len() > 2 {
+                submatch::resolve(expr, text, caps);
+            }
+        }
+    }
+
+    /// Executes a compiled regex program with the backtracking engine,
+    /// keeping every capture saved on the way to the match in `history` (see
+    /// `Backtrack::exec_history`).
+    ///
+    /// If the program can't keep a history for `text` (see
+    /// `keeps_history`), then this is the same as `exec` and `history` is
+    /// left alone.
+    pub fn exec_history(
+        &self,
+        caps: &mut CaptureIdxs,
+        history: &mut Vec<(usize, usize)>,
+        text: &str,
+        start: usize,
+    ) -> Option<usize> {
+        if !self.keeps_history(text) {
+            return self.exec(caps, text, start);
+        }
+        let start = match self.search_start(text, start) {
+            None => return None,
+            Some(start) => start,
+        };
+        self.trace_search(Engine::Backtrack, start);
+        let scan = self.should_scan_prefixes(text, start);
+        Backtrack::exec_history(self, caps, history, text, start, scan)
+    }
+
+    /// Returns true if `exec_history` keeps a history of the captures when
+    /// searching `text`.
+    ///
+    /// The backtracking engine can't find leftmost-longest matches or
+    /// resolve groups by POSIX rules, and it must fit in the backtracking
+    /// budget like for any other search.
+    pub fn keeps_history(&self, text: &str) -> bool {
+        !self.longest_match
+        && self.submatches.is_none()
+        && Backtrack::should_exec(self, text)
+    }
+
+    /// Executes a compiled regex program with the NFA engine, using the
+    /// given thread lists.
+    ///
+    /// If `threads` was created by `NfaThreads::with_capacity` for this
+    /// program, then this doesn't allocate. For that reason, capture groups
+    /// are always resolved by priority here, even if the program asks for
+    /// POSIX rules (see `resolve_submatches`), whose memory grows with the
+    /// length of the match.
+    ///
+    /// The NFA can't run backreferences, so a program with any is run by
+    /// `exec` instead, which allocates.
+    pub fn exec_with(
+        &self,
+        threads: &mut NfaThreads,
+        caps: &mut CaptureIdxs,
+        text: &str,
+        start: usize,
+    ) -> Option<usize> {
+        if self.backrefs {
+            return self.exec(caps, text, start);
+        }
+        let start = match self.search_start(text, start) {
+            None => return None,
+            Some(start) => start,
+        };
+        self.trace_search(Engine::Nfa, start);
+        let scan = self.should_scan_prefixes(text, start);
+        Nfa::exec_with(self, threads, caps, text, start, scan)
+    }
+
+    /// Returns the position at which a search starting at `start` can
+    /// begin, or `None` if it can't possibly find a match.
+    fn search_start(&self, text: &str, start: usize) -> Option<usize> {
+        // A regex anchored at the beginning can't match anywhere else, so
+        // don't bother starting up an engine.
+        if self.anchored_begin && start > 0 {
+            return None;
+        }
+        // Likewise, don't bother if the text is too short to ever match.
+        if start + self.min_len > text.len() {
+            return None;
+        }
+        if self.anchored_start {
+            return Some(start);
+        }
+        // If every match ends at the end of the text and can't be longer
+        // than `max_len`, then there's no point in looking any further back.
+        // e.g., `\.txt$` only needs to look at the last four bytes.
+        Some(match self.max_len {
+            Some(max_len) if self.anchored_end
+                             && text.len() - start > max_len => {
+                let mut start = text.len() - max_len;
+                while !text.is_char_boundary(start) {
+                    start -= 1;
+                }
+                start
+            }
+            _ => start,
+        })
+    }
+
+    /// Counts the successive non-overlapping matches in `text`, starting at
+    /// `start`, of the regex whose first instruction is `start_pc` (see
+    /// `pattern_starts`), with the NFA engine. The engine is run again from
+    /// the end of each match.
+    pub fn count_all(&self, start_pc: InstIdx, text: &str, start: usize)
+                    -> usize {
+        self.trace_search(Engine::Nfa, start);
+        let scan = self.should_scan_prefixes(text, start);
+        Nfa::count_all(self, start_pc, text, start, scan)
+    }
+
+    /// Executes the regex whose first instruction is `start_pc` (see
+    /// `pattern_starts`) with the NFA engine.
+    pub fn exec_pattern(
+        &self,
+        start_pc: InstIdx,
+        caps: &mut CaptureIdxs,
+        text: &str,
+        start: usize,
+    ) -> bool {
+        self.trace_search(Engine::Nfa, start);
+        Nfa::exec_pattern(self, start_pc, caps, text, start).is_some()
+    }
+
+    /// Returns the first instruction of each regex the program was compiled
+    /// from, in order.
+    pub fn pattern_starts(&self) -> Vec<InstIdx> {
+        if self.num_patterns == 0 {
+            return vec![];
+        }
+        // All but the last regex are the first branch of a chain of `Split`s
+        // at the start.
+        let (mut starts, mut pc) = (vec![], 0);
+        while starts.len() + 1 < self.num_patterns {
+            match self.insts[pc as usize] {
+                Inst::Split(ref inst) => {
+                    starts.push(inst.goto1);
+                    pc = inst.goto2;
+                }
+                _ => unreachable!(),
+            }
         }
+        starts.push(pc);
+        starts
+    }
+
+    /// Reports every regex in the program that matches somewhere in `text`
+    /// by setting the corresponding element of `matches` to `true`.
+    ///
+    /// Returns true if and only if at least one regex matched.
+    pub fn exec_many(
+        &self,
+        matches: &mut [bool],
+        text: &str,
+        start: usize,
+    ) -> bool {
+        let prog = match self.match_only {
+            Some(ref prog) => &**prog,
+            None => self,
+        };
+        prog.trace_search(Engine::Nfa, start);
+        Nfa::exec_many(prog, matches, text, start)
+    }
+
+    /// Sets each element of `starts` to where the leftmost match in `text`
+    /// of the corresponding regex in the program starts, if it has one.
+    ///
+    /// Returns true if and only if at least one regex matched.
+    pub fn exec_starts(
+        &self,
+        starts: &mut [Option<usize>],
+        text: &str,
+        start: usize,
+    ) -> bool {
+        self.trace_search(Engine::Nfa, start);
+        Nfa::exec_starts(self, starts, text, start)
     }
 
-    fn choose_engine(&self, cap_len: usize, text: &str) -> MatchEngine {
+    /// Chooses the engine for a search of `text` starting at `start`, and
+    /// whether it should scan for the literal prefixes to skip ahead.
+    ///
+    /// Besides the program, this looks at the haystack:
+    ///
+    /// * Its length. The backtracking engine is only used if its visited set
+    ///   for the text fits in the backtrack limit, which a text of a few
+    ///   dozen bytes always does.
+    /// * Whether the prefixes are rare in a window at the start of the
+    ///   search (see `should_scan_prefixes`). If they're dense, the engine
+    ///   runs without scanning for them.
+    /// * Whether the regex is anchored at the beginning. There's only one
+    ///   place to try then, so there's nothing to scan for. (And a search
+    ///   starting past the beginning doesn't get here, see `search_start`.)
+    fn choose_engine(
+        &self,
+        cap_len: usize,
+        text: &str,
+        start: usize,
+    ) -> (MatchEngine, bool) {
+        let scan = self.should_scan_prefixes(text, start);
+        if self.longest_match {
+            // The other engines stop at the first match they find.
+            return (MatchEngine::Nfa, scan);
+        }
         // If the engine is already chosen, then we use it.
         // But that might not be a good idea. e.g., What if `Literals` is
         // chosen and it can't work? I guess we should probably check whether
         // the chosen engine is appropriate or not.
-        self.engine.unwrap_or_else(|| {
+        let engine = self.engine.unwrap_or_else(|| {
             if cap_len <= 2
+               && self.num_patterns <= 1
                && self.prefixes_complete
                && self.prefixes.preserves_priority() {
                 MatchEngine::Literals
@@ -150,7 +876,116 @@ impl Program {
             } else {
                 MatchEngine::Nfa
             }
-        })
+        });
+        (engine, scan)
+    }
+
+    /// Returns true if the literal prefixes should be used to skip ahead in
+    /// the text.
+    ///
+    /// Scanning for a prefix only pays off when the prefix is rare. If it
+    /// occurs all over the place, then the engine will stop and restart the
+    /// scan at nearly every position, which is slower than not scanning at
+    /// all. So we sample a window at the start of the search and give up on
+    /// the prefixes if they show up too often in it. Likewise, we give up on
+    /// them for good once previous searches show that they don't pay off.
+    fn should_scan_prefixes(&self, text: &str, start: usize) -> bool {
+        if self.prefixes.is_empty() || self.anchored_begin {
+            return false;
+        }
+        if self.prefix_stats.as_ref().map_or(false, |s| s.is_useless()) {
+            trace!("not scanning for the prefixes of {:?}, they don't pay \
+                    off", self.original);
+            return false;
+        }
+        let text = &text[start..];
+        if text.len() <= self.prefix_sample_size {
+            return true;
+        }
+        let mut end = self.prefix_sample_size;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        let sample = text[..end].as_bytes();
+        let (mut at, mut count) = (0, 0);
+        while let Some((_, e)) = self.prefixes.find(&sample[at..]) {
+            count += 1;
+            if count * PREFIX_DENSITY_LIMIT > sample.len() {
+                trace!("not scanning for the prefixes of {:?}, they're too \
+                        common at {}", self.original, start);
+                return false;
+            }
+            // Prefixes are never empty, so this always makes progress.
+            at += e;
+        }
+        true
+    }
+
+    /// Returns statistics for a copy of this program, which start out empty
+    /// since they are about the searches done with one program.
+    fn fresh_prefix_stats(&self) -> Option<PrefixStats> {
+        self.prefix_stats.as_ref().map(|_| PrefixStats::new())
+    }
+
+    /// Records the outcome of a search that scanned for the prefixes (see
+    /// `PrefixStats::record`).
+    pub fn record_prefix_scan(
+        &self,
+        candidates: usize,
+        verified: usize,
+        skipped: usize,
+    ) {
+        if let Some(ref stats) = self.prefix_stats {
+            stats.record(candidates, verified, skipped);
+        }
+    }
+
+    /// Adds the work done by a search to the program's counters, if it has
+    /// any.
+    pub fn record_counts(&self, counts: &SearchCounts) {
+        if let Some(ref counters) = self.counters {
+            counters.add(counts);
+        }
+    }
+
+    /// Finds the first match in `text` with `engine`, and measures how long
+    /// it takes and how much work the engine does.
+    ///
+    /// `Engine::Automatic` means the NFA. `engine` must be able to run the
+    /// program, i.e., it can't be the backtracking engine if the program
+    /// needs the longest match, and it must be if the program has
+    /// backreferences.
+    pub fn profile(&self, engine: Engine, text: &str) -> EngineProfile {
+        let mut prog = match self.find_only {
+            Some(ref prog) => (**prog).clone(),
+            None => self.clone(),
+        };
+        let engine = match engine {
+            Engine::Backtrack => Engine::Backtrack,
+            _ => Engine::Nfa,
+        };
+        prog.engine = Some(match engine {
+            Engine::Backtrack => MatchEngine::Backtrack,
+            _ => MatchEngine::Nfa,
+        });
+        // Run the search once beforehand, so that the time doesn't include
+        // allocating the engine's memory.
+        let mut caps = vec![None, None];
+        prog.exec(&mut caps, text, 0);
+        let counters = Arc::new(Counters::new());
+        prog.counters = Some(counters.clone());
+        let started = Instant::now();
+        let matched = prog.exec(&mut caps, text, 0).and(
+            match (caps[0], caps[1]) {
+                (Some(s), Some(e)) => Some((s, e)),
+                _ => None,
+            });
+        EngineProfile {
+            engine: engine,
+            time: started.elapsed(),
+            matched: matched,
+            counts: counters.get(),
+        }
     }
 
     /// Returns the total number of capture groups in the regular expression.
@@ -164,36 +999,195 @@ impl Program {
         vec![None; 2 * self.num_captures()]
     }
 
+    /// Returns true if no match can span more than one line.
+    ///
+    /// This is the case when no instruction can match a `\n` and the program
+    /// never asserts the start of the text (which would tell the first line
+    /// apart from the others).
+    pub fn is_line_local(&self) -> bool {
+        self.insts.iter().all(|inst| match *inst {
+            Inst::Char(ref inst) => inst.c != '\n',
+            Inst::Ranges(ref inst) => {
+                let ranges = inst.ranges(&self.ranges);
+                ranges.iter().all(|&(s, e)| '\n' < s || e < '\n')
+            }
+            Inst::EmptyLook(ref inst) => inst.look != EmptyLook::StartText,
+            _ => true,
+        })
+    }
+
+    /// Returns roughly how much memory, in bytes, the instructions of the
+    /// program take. This is what the compiler checks the size limit
+    /// against.
+    fn compiled_size(&self) -> usize {
+        use std::mem::size_of;
+
+        self.insts.len() * size_of::<Inst>()
+        + self.ranges.len() * size_of::<(char, char)>()
+    }
+
     /// Find and store a prefix machine for the current program.
-    pub fn find_prefixes(&mut self) {
+    ///
+    /// The machine takes at most `budget` bytes (see `fit_prefixes`).
+    pub fn find_prefixes(&mut self, opts: &ProgramOptions, budget: usize) {
+        // A case insensitive literal, e.g., `(?i)error`, is written in too
+        // many ways to look for every one of them, so it's looked for
+        // without regard to case instead.
+        if let Some((pfx, complete)) = self.folded_literal(opts, 0) {
+            if Prefix::projected_folded_size(&pfx) <= budget {
+                self.prefixes = Arc::new(Prefix::folded(pfx));
+                self.prefixes_complete = complete;
+                return;
+            }
+        }
+        // A regex that's just a long list of literals, e.g., a blocklist,
+        // has too many of them for the prefix limits, but is still found by
+        // looking for the literals alone.
+        if let Some(words) = self.keywords() {
+            if words.len() > opts.prefix_count_limit {
+                let words = minimize_prefixes(words, true);
+                self.prefixes = Arc::new(Prefix::keywords(words));
+                self.prefixes_complete = true;
+                return;
+            }
+        }
+        if let Some((pfxs, complete)) = self.prefixes_at(opts, 0) {
+            let pfxs = minimize_prefixes(pfxs, complete);
+            let (prefixes, complete) = fit_prefixes(pfxs, complete, budget);
+            self.prefixes = Arc::new(prefixes);
+            self.prefixes_complete = complete;
+            return;
+        }
+        // Failing that, look for literals after a fixed number of
+        // characters, e.g., `foo` in `..foo`. A match can then be found by
+        // looking for the literals and stepping back.
+        let (offset, pc) = self.skip_classes(opts, self.skip(0));
+        if offset > 0 {
+            if let Some((pfxs, _)) = self.prefixes_at(opts, pc) {
+                let pfxs = minimize_prefixes(pfxs, false);
+                let (prefixes, _) = fit_prefixes(pfxs, false, budget);
+                if !prefixes.is_empty() {
+                    self.prefixes = Arc::new(prefixes);
+                    self.prefixes_complete = false;
+                    self.prefix_offset = offset;
+                }
+                return;
+            }
+        }
+        // As a last resort, look for the first byte of any character in a
+        // leading class that's too big to be turned into literals, e.g.,
+        // `\d` in `\d{6}`.
+        let bytes = self.first_bytes(opts, self.skip(0));
+        if !bytes.is_empty() && 256 + bytes.len() <= budget {
+            self.prefixes = Arc::new(Prefix::bytes(bytes));
+            self.prefixes_complete = false;
+        }
+    }
+
+    /// Returns the bytes that a character matched by the class at `pc` (if
+    /// it is one, after any assertions) can begin with.
+    ///
+    /// Nothing is returned if there are more than the prefix count limit.
+    fn first_bytes(&self, opts: &ProgramOptions, mut pc: usize) -> Vec<u8> {
+        let ranges = loop {
+            match self.insts[pc] {
+                Inst::Save(ref inst) => pc = inst.goto as usize,
+                Inst::EmptyLook(ref inst) => pc = inst.goto as usize,
+                Inst::Ranges(ref inst) => break inst.ranges(&self.ranges),
+                _ => return vec![],
+            }
+        };
+        let mut bytes = vec![false; 256];
+        for &(s, e) in ranges {
+            // The first byte of a character's UTF-8 encoding only grows
+            // with the character, and every byte in between is the first
+            // byte of some character in the range.
+            let (s, e) = (first_byte(s), first_byte(e));
+            for b in (s as usize)..(e as usize + 1) {
+                bytes[b] = true;
+            }
+        }
+        let bytes: Vec<u8> =
+            (0..256).filter(|&b| bytes[b]).map(|b| b as u8).collect();
+        if bytes.len() > opts.prefix_count_limit {
+            vec![]
+        } else {
+            bytes
+        }
+    }
+
+    /// Find the literal prefixes of the text matched starting at the given
+    /// instruction, if any.
+    fn prefixes_at(
+        &self,
+        opts: &ProgramOptions,
+        pc: usize,
+    ) -> Option<(Vec<String>, bool)> {
         // First, look for a standard literal prefix---this includes things
         // like `a+` and `[0-9]+`, but not `a|b`.
-        let (ps, complete) = self.literals(self.skip(1));
+        let (ps, complete) = self.literals(opts, self.skip(pc));
         if !ps.is_empty() {
-            self.prefixes = Prefix::new(ps);
-            self.prefixes_complete = complete;
-            return;
+            return Some((ps, complete));
         }
         // Ok, now look for alternate prefixes, e.g., `a|b`.
-        if let Some((pfxs, complete)) = self.alternate_prefixes() {
-            self.prefixes = Prefix::new(pfxs);
-            self.prefixes_complete = complete;
+        self.alternate_prefixes(opts, pc)
+    }
+
+    /// Skips over the classes starting at `pc` that are too big to be
+    /// turned into literals. Each of them matches exactly one character.
+    ///
+    /// Returns the number of classes skipped and the first instruction that
+    /// isn't skipped.
+    fn skip_classes(
+        &self,
+        opts: &ProgramOptions,
+        mut pc: usize,
+    ) -> (usize, usize) {
+        let mut count = 0;
+        loop {
+            match self.insts[pc] {
+                Inst::Save(ref inst) => pc = inst.goto as usize,
+                Inst::EmptyLook(ref inst) => pc = inst.goto as usize,
+                Inst::Ranges(ref inst)
+                        if num_chars_in_ranges(inst.ranges(&self.ranges))
+                           > opts.prefix_count_limit => {
+                    count += 1;
+                    pc = inst.goto as usize;
+                }
+                _ => return (count, pc),
+            }
         }
     }
 
-    fn alternate_prefixes(&self) -> Option<(Vec<String>, bool)> {
+    fn alternate_prefixes(
+        &self,
+        opts: &ProgramOptions,
+        start: usize,
+    ) -> Option<(Vec<String>, bool)> {
         let mut prefixes = vec![];
         let mut pcomplete = true;
-        let mut stack = vec![self.skip(1)];
+        // Splits can form a loop that doesn't consume anything, e.g., in
+        // `(?:a*?)+`, so each one is only followed once.
+        let mut seen = vec![false; self.insts.len()];
+        let mut stack = vec![self.skip(start)];
         while let Some(mut pc) = stack.pop() {
             pc = self.skip(pc);
+            if seen[pc] {
+                continue;
+            }
+            seen[pc] = true;
             match self.insts[pc] {
                 Inst::Split(ref inst) => {
-                    stack.push(inst.goto2);
-                    stack.push(inst.goto1);
+                    stack.push(inst.goto2 as usize);
+                    stack.push(inst.goto1 as usize);
+                }
+                Inst::SplitMany(ref inst) => {
+                    for &goto in inst.gotos.iter().rev() {
+                        stack.push(goto as usize);
+                    }
                 }
                 _ => {
-                    let (alt_prefixes, complete) = self.literals(pc);
+                    let (alt_prefixes, complete) = self.literals(opts, pc);
                     if alt_prefixes.is_empty() {
                         // If no prefixes could be identified for this
                         // alternate, then we can't use a prefix machine to
@@ -201,7 +1195,8 @@ impl Program {
                         // nothing.
                         return None;
                     }
-                    if prefixes.len() + alt_prefixes.len() > NUM_PREFIX_LIMIT {
+                    let count = prefixes.len() + alt_prefixes.len();
+                    if count > opts.prefix_count_limit {
                         // Arg. We've over-extended ourselves, quit with
                         // nothing to show for it.
                         //
@@ -231,12 +1226,25 @@ impl Program {
     /// Returns `true` in the tuple if the end of the literal leads trivially
     /// to a match. (This may report false negatives, but being conservative
     /// is OK.)
-    fn literals(&self, mut pc: usize) -> (Vec<String>, bool) {
+    fn literals(
+        &self,
+        opts: &ProgramOptions,
+        mut pc: usize,
+    ) -> (Vec<String>, bool) {
         #![allow(unused_assignments)]
         use inst::Inst::*;
 
         let mut complete = true;
-        let mut alts = vec![String::new()];
+        // Whether an assertion was skipped, in which case finding a literal
+        // doesn't mean the regex matches there.
+        let mut asserts = false;
+        // The alternate prefixes are every combination of one character
+        // from each column. They're only written out once at the end, since
+        // copying every alternate for every character in a class adds up
+        // quickly with big limits.
+        let mut columns: Vec<Vec<char>> = vec![];
+        // The number of alternates and the length of the first one.
+        let (mut count, mut len) = (1, 0);
         loop {
             let inst = &self.insts[pc];
 
@@ -244,58 +1252,292 @@ impl Program {
             // it stops. Thus, the prefix alternates grow in lock step, and it
             // suffices to check one of them to see if the prefix limit has
             // been exceeded.
-            if alts[0].len() > PREFIX_LENGTH_LIMIT {
+            if len > opts.prefix_length_limit {
                 complete = false;
                 break;
             }
             match *inst {
-                Save(ref inst) => { pc = inst.goto; continue }
+                Save(ref inst) => { pc = inst.goto as usize; continue }
+                EmptyLook(ref inst) => {
+                    // Assertions don't consume any input, so the literals
+                    // that follow are still required.
+                    asserts = true;
+                    pc = inst.goto as usize;
+                    continue;
+                }
                 Char(ref inst) => {
-                    for alt in &mut alts {
-                        alt.push(inst.c);
-                    }
-                    pc = inst.goto;
+                    columns.push(vec![inst.c]);
+                    len += inst.c.len_utf8();
+                    pc = inst.goto as usize;
                 }
                 Ranges(ref inst) => {
                     // This adds a new literal for *each* character in this
                     // range. This has the potential to use way too much
                     // memory, so we bound it naively for now.
-                    let nchars = num_chars_in_ranges(&inst.ranges);
-                    if alts.len() * nchars > NUM_PREFIX_LIMIT {
+                    let ranges = inst.ranges(&self.ranges);
+                    let nchars = num_chars_in_ranges(ranges);
+                    if nchars == 0
+                       || count * nchars > opts.prefix_count_limit {
                         complete = false;
                         break;
                     }
 
-                    let orig = alts;
-                    alts = Vec::with_capacity(orig.len());
-                    for &(s, e) in &inst.ranges {
+                    let mut column = Vec::with_capacity(nchars);
+                    for &(s, e) in ranges {
                         for c in (s as u32)..(e as u32 + 1){
-                            for alt in &orig {
-                                let mut alt = alt.clone();
-                                alt.push(::std::char::from_u32(c).unwrap());
-                                alts.push(alt);
-                            }
+                            column.push(::std::char::from_u32(c).unwrap());
                         }
                     }
-                    pc = inst.goto;
+                    count *= nchars;
+                    len += column[0].len_utf8();
+                    columns.push(column);
+                    pc = inst.goto as usize;
                 }
                 _ => { complete = self.leads_to_match(pc); break }
             }
         }
-        if alts[0].is_empty() {
+        if columns.is_empty() {
             (vec![], false)
         } else {
-            (alts, complete)
+            (product(&columns, count, len), complete && !asserts)
         }
     }
 
-    fn leads_to_match(&self, pc: usize) -> bool {
-        // I'm pretty sure this is conservative, so it might have some
-        // false negatives.
-        match self.insts[self.skip(pc)] {
-            Inst::Match => true,
-            _ => false,
-        }
+    /// Returns the literals that make up the whole program, in order of
+    /// priority, if it's nothing but an alternation of them, e.g.,
+    /// `foo|bar|quux`.
+    ///
+    /// Alternates that start the same way may have been merged, so the
+    /// alternations form a tree. Anything else, like a class, an assertion
+    /// or a character reached from two alternates, means there are no such
+    /// literals.
+    fn keywords(&self) -> Option<Vec<String>> {
+        let mut words = vec![];
+        let mut seen = vec![false; self.insts.len()];
+        // The alternates still to follow, with the length of the word up to
+        // them. Since they're followed depth first, the word is always the
+        // same up to there when one is taken off the stack.
+        let mut stack = vec![(0, 0)];
+        let mut word = String::new();
+        while let Some((mut pc, len)) = stack.pop() {
+            word.truncate(len);
+            loop {
+                match self.insts[pc] {
+                    Inst::Save(ref inst) => {
+                        pc = inst.goto as usize;
+                        continue;
+                    }
+                    Inst::Match(_) => break,
+                    _ => {}
+                }
+                if seen[pc] {
+                    return None;
+                }
+                seen[pc] = true;
+                match self.insts[pc] {
+                    Inst::Char(ref inst) => {
+                        word.push(inst.c);
+                        pc = inst.goto as usize;
+                    }
+                    Inst::Split(ref inst) => {
+                        stack.push((inst.goto2 as usize, word.len()));
+                        pc = inst.goto1 as usize;
+                    }
+                    Inst::SplitMany(ref inst) => {
+                        for &goto in inst.gotos[1..].iter().rev() {
+                            stack.push((goto as usize, word.len()));
+                        }
+                        pc = inst.gotos[0] as usize;
+                    }
+                    _ => return None,
+                }
+            }
+            if word.is_empty() {
+                return None;
+            }
+            words.push(word.clone());
+        }
+        Some(words)
+    }
+
+    /// Find a required literal starting at the given instruction whose
+    /// ASCII letters match either case, and nothing else does.
+    ///
+    /// The literal is only returned if it has too many case variants for
+    /// `literals` to find them all. Returns `true` in the tuple if the end
+    /// of the literal leads trivially to a match.
+    fn folded_literal(
+        &self,
+        opts: &ProgramOptions,
+        mut pc: usize,
+    ) -> Option<(String, bool)> {
+        let mut lit = String::new();
+        let mut folded = 0;
+        let mut asserts = false;
+        let complete = loop {
+            if lit.len() > opts.prefix_length_limit {
+                break false;
+            }
+            match self.insts[pc] {
+                Inst::Save(ref inst) => pc = inst.goto as usize,
+                Inst::EmptyLook(ref inst) => {
+                    asserts = true;
+                    pc = inst.goto as usize;
+                }
+                Inst::Char(ref inst) => {
+                    // A letter matched in only one case can't be looked for
+                    // without regard to case.
+                    if is_ascii_letter(inst.c) {
+                        break false;
+                    }
+                    lit.push(inst.c);
+                    pc = inst.goto as usize;
+                }
+                Inst::Ranges(ref inst) => {
+                    match ascii_case_pair(inst.ranges(&self.ranges)) {
+                        None => break false,
+                        Some(c) => {
+                            lit.push(c);
+                            folded += 1;
+                            pc = inst.goto as usize;
+                        }
+                    }
+                }
+                _ => break self.leads_to_match(pc),
+            }
+        };
+        // Each folded letter doubles the number of variants.
+        if folded >= 64 || (1 << folded) <= opts.prefix_count_limit {
+            return None;
+        }
+        Some((lit, complete && !asserts))
+    }
+
+    /// Find the literals that every match ends with, if any.
+    ///
+    /// This is `literals` in reverse: the instructions leading up to each
+    /// `Match` are followed backwards, one character at a time, until the
+    /// start of the program, a loop, a class that's too big or the limits
+    /// on the number and length of prefixes. The literals are sorted, and a
+    /// literal that ends with another one is dropped, since every text that
+    /// ends with it also ends with the other one.
+    pub fn suffixes(&self) -> Vec<String> {
+        let mut preds = vec![vec![]; self.insts.len()];
+        for (pc, inst) in self.insts.iter().enumerate() {
+            match *inst {
+                Inst::Match(_) => {}
+                Inst::Save(ref inst) => preds[inst.goto as usize].push(pc),
+                Inst::EmptyLook(ref inst) => {
+                    preds[inst.goto as usize].push(pc);
+                }
+                Inst::Split(ref inst) => {
+                    preds[inst.goto1 as usize].push(pc);
+                    preds[inst.goto2 as usize].push(pc);
+                }
+                Inst::SplitMany(ref inst) => {
+                    for &goto in &inst.gotos {
+                        preds[goto as usize].push(pc);
+                    }
+                }
+                Inst::Char(ref inst) => preds[inst.goto as usize].push(pc),
+                Inst::Ranges(ref inst) => preds[inst.goto as usize].push(pc),
+                Inst::Backref(ref inst) => preds[inst.goto as usize].push(pc),
+            }
+        }
+        let mut suffixes = vec![];
+        // Each suffix comes with the instructions that consumed it, so that
+        // a loop isn't followed around more than once.
+        let mut stack: Vec<(usize, String, Vec<usize>)> = vec![];
+        for (pc, inst) in self.insts.iter().enumerate() {
+            if let Inst::Match(_) = *inst {
+                stack.push((pc, String::new(), vec![]));
+            }
+        }
+        while let Some((pc, suffix, path)) = stack.pop() {
+            // Find the instructions that consume the character before `pc`,
+            // skipping over the ones that don't consume anything. If the
+            // program can start in between, then the suffix can't grow.
+            let mut before = vec![];
+            let mut starts = false;
+            let mut seen = vec![false; self.insts.len()];
+            let mut empty = vec![pc];
+            while let Some(pc) = empty.pop() {
+                if seen[pc] {
+                    continue;
+                }
+                seen[pc] = true;
+                starts = starts || pc == 0;
+                for &p in &preds[pc] {
+                    match self.insts[p] {
+                        Inst::Char(_) | Inst::Ranges(_) => before.push(p),
+                        // The text matched by a backreference isn't known,
+                        // so the suffix can't grow past it either.
+                        Inst::Backref(_) => starts = true,
+                        _ => empty.push(p),
+                    }
+                }
+            }
+            let mut grown = vec![];
+            let mut done = starts
+                           || before.is_empty()
+                           || before.iter().any(|p| path.contains(p))
+                           || suffix.len() > PREFIX_LENGTH_LIMIT;
+            for &p in &before {
+                if done {
+                    break;
+                }
+                let mut path = path.clone();
+                path.push(p);
+                match self.insts[p] {
+                    Inst::Char(ref inst) => {
+                        grown.push((p, prepend(inst.c, &suffix), path));
+                    }
+                    Inst::Ranges(ref inst) => {
+                        let ranges = inst.ranges(&self.ranges);
+                        if num_chars_in_ranges(ranges) > NUM_PREFIX_LIMIT {
+                            done = true;
+                            break;
+                        }
+                        for &(s, e) in ranges {
+                            for c in (s as u32)..(e as u32 + 1) {
+                                let c = ::std::char::from_u32(c).unwrap();
+                                grown.push((p, prepend(c, &suffix),
+                                            path.clone()));
+                            }
+                        }
+                    }
+                    _ => unreachable!(),
+                }
+            }
+            let count = suffixes.len() + stack.len() + grown.len();
+            if done || count > NUM_PREFIX_LIMIT {
+                if suffix.is_empty() {
+                    // Some match doesn't end with any literal at all.
+                    return vec![];
+                }
+                suffixes.push(suffix);
+            } else {
+                stack.extend(grown);
+            }
+        }
+        suffixes.sort_by(|a, b| a.len().cmp(&b.len()).then(a.cmp(b)));
+        let mut kept: Vec<String> = vec![];
+        for s in suffixes {
+            if !kept.iter().any(|k| s.ends_with(&**k)) {
+                kept.push(s);
+            }
+        }
+        kept.sort();
+        kept
+    }
+
+    fn leads_to_match(&self, pc: usize) -> bool {
+        // I'm pretty sure this is conservative, so it might have some
+        // false negatives.
+        match self.insts[self.skip(pc)] {
+            Inst::Match(_) => true,
+            _ => false,
+        }
     }
 
     fn skip(&self, mut pc: usize) -> usize {
@@ -316,24 +1558,421 @@ impl Clone for Program {
         Program {
             original: self.original.clone(),
             insts: self.insts.clone(),
+            ranges: self.ranges.clone(),
             cap_names: self.cap_names.clone(),
+            num_patterns: self.num_patterns,
             prefixes: self.prefixes.clone(),
+            prefix_stats: self.fresh_prefix_stats(),
             prefixes_complete: self.prefixes_complete,
+            prefix_sample_size: self.prefix_sample_size,
+            prefix_offset: self.prefix_offset,
             anchored_begin: self.anchored_begin,
+            anchored_line_begin: self.anchored_line_begin,
+            anchored_start: self.anchored_start,
+            match_at_end: self.match_at_end,
             anchored_end: self.anchored_end,
+            min_len: self.min_len,
+            max_len: self.max_len,
+            backtrack_limit: self.backtrack_limit,
+            longest_match: self.longest_match,
+            submatches: self.submatches.clone(),
+            backrefs: self.backrefs,
             engine: self.engine,
+            tracer: self.tracer.clone(),
+            counters: None,
+            match_only: self.match_only.clone(),
+            find_only: self.find_only.clone(),
+            prefilter: self.prefilter.clone(),
+            anchored_copies: self.anchored_copies.clone(),
             nfa_threads: Pool::new(Box::new(create_threads)),
             backtrack: Pool::new(Box::new(create_backtrack)),
         }
     }
 }
 
+/// Removes the prefixes that can't change where a prefix is found first.
+///
+/// Duplicates are always removed (keeping the first one). If the prefixes
+/// are only used to skip ahead (i.e., they aren't `complete`), then any
+/// prefix that begins with another prefix in the set is removed too, since
+/// the shorter one is found wherever the longer one is. e.g., `ab` is
+/// dropped from `ab|a|ac`. The order of the remaining prefixes is kept.
+fn minimize_prefixes(pfxs: Vec<String>, complete: bool) -> Vec<String> {
+    let mut kept: Vec<String> = Vec::with_capacity(pfxs.len());
+    for p in pfxs {
+        if kept.contains(&p) {
+            continue;
+        }
+        if !complete {
+            if kept.iter().any(|k| p.starts_with(&**k)) {
+                continue;
+            }
+            kept.retain(|k| !k.starts_with(&*p));
+        }
+        kept.push(p);
+    }
+    kept
+}
+
+/// Builds the prefix machine for `pfxs`, cutting the prefixes short until it
+/// takes at most `budget` bytes. Returns the machine and whether the
+/// prefixes are still `complete`.
+///
+/// A prefix of a prefix is found wherever the prefix is, so the machine can
+/// still be used to skip ahead, but no longer to report a match. Every
+/// prefix is cut to half as many characters at a time, and in the end, only
+/// their first bytes are looked for. If even that doesn't fit, there are no
+/// prefixes at all.
+fn fit_prefixes(
+    mut pfxs: Vec<String>,
+    mut complete: bool,
+    budget: usize,
+) -> (Prefix, bool) {
+    loop {
+        if Prefix::projected_size(&pfxs) <= budget {
+            return (Prefix::new(pfxs), complete);
+        }
+        complete = false;
+        if pfxs.iter().all(|p| p.chars().count() <= 1) {
+            let bytes: Vec<u8> =
+                pfxs.iter().filter_map(|p| p.bytes().next()).collect();
+            if 256 + bytes.len() > budget {
+                return (Prefix::Empty, false);
+            }
+            return (Prefix::bytes(bytes), false);
+        }
+        let cut = pfxs.into_iter().map(|p| {
+            let half = (p.chars().count() + 1) / 2;
+            p.chars().take(half).collect()
+        }).collect();
+        pfxs = minimize_prefixes(cut, false);
+    }
+}
+
+/// Returns the length, in bytes, of the shortest text that can be matched by
+/// the given sequence of instructions, whose classes refer to `ranges`.
+///
+/// Empty width assertions are assumed to always succeed, so this is a lower
+/// bound. This is a shortest path search from the first instruction to any
+/// `Match` instruction, where consuming a character costs its UTF-8 length.
+fn min_match_len(insts: &[Inst], ranges: &[(char, char)]) -> usize {
+    use std::collections::BinaryHeap;
+    use std::usize;
+
+    let mut dist = vec![usize::MAX; insts.len()];
+    // `BinaryHeap` is a max-heap, so distances are stored inverted.
+    let mut heap = BinaryHeap::new();
+    heap.push((usize::MAX, 0));
+    while let Some((inv, pc)) = heap.pop() {
+        let d = usize::MAX - inv;
+        if d >= dist[pc] {
+            continue;
+        }
+        dist[pc] = d;
+        let mut push = |pc: usize, len: usize| {
+            heap.push((usize::MAX - (d + len), pc));
+        };
+        match insts[pc] {
+            Inst::Match(_) => return d,
+            Inst::Save(ref inst) => push(inst.goto as usize, 0),
+            Inst::EmptyLook(ref inst) => push(inst.goto as usize, 0),
+            Inst::Split(ref inst) => {
+                push(inst.goto1 as usize, 0);
+                push(inst.goto2 as usize, 0);
+            }
+            Inst::SplitMany(ref inst) => {
+                for &goto in &inst.gotos {
+                    push(goto as usize, 0);
+                }
+            }
+            Inst::Char(ref inst) => {
+                push(inst.goto as usize, inst.c.len_utf8());
+            }
+            Inst::Ranges(ref inst) => {
+                // The ranges are sorted, so the first one has the shortest
+                // encoding. An empty class can never match.
+                if let Some(&(s, _)) = inst.ranges(ranges).first() {
+                    push(inst.goto as usize, s.len_utf8());
+                }
+            }
+            // The group may have matched nothing.
+            Inst::Backref(ref inst) => push(inst.goto as usize, 0),
+        }
+    }
+    0
+}
+
+/// Returns true if every match of the given sequence of instructions must
+/// start right where one of the given zero-width assertions holds.
+///
+/// That is, every path to an instruction consuming a character (or to a
+/// `Match` instruction) goes through one of `looks`.
+fn is_anchored_start(insts: &[Inst], looks: &[EmptyLook]) -> bool {
+    let mut seen = vec![false; insts.len()];
+    let mut stack = vec![0];
+    while let Some(pc) = stack.pop() {
+        if seen[pc] {
+            continue;
+        }
+        seen[pc] = true;
+        match insts[pc] {
+            Inst::Match(_) | Inst::Char(_) | Inst::Ranges(_)
+            | Inst::Backref(_) => return false,
+            Inst::Save(ref inst) => stack.push(inst.goto as usize),
+            Inst::EmptyLook(ref inst) => {
+                if !looks.contains(&inst.look) {
+                    stack.push(inst.goto as usize);
+                }
+            }
+            Inst::Split(ref inst) => {
+                stack.push(inst.goto1 as usize);
+                stack.push(inst.goto2 as usize);
+            }
+            Inst::SplitMany(ref inst) => {
+                for &goto in &inst.gotos {
+                    stack.push(goto as usize);
+                }
+            }
+        }
+    }
+    true
+}
+
+/// Returns true if every match of the given sequence of instructions must end
+/// at the end of the text.
+///
+/// That is, every path to a `Match` instruction goes through a `$` (without
+/// the multi-line flag) that isn't followed by any instruction consuming a
+/// character.
+fn is_anchored_end(insts: &[Inst]) -> bool {
+    // Search all states `(pc, anchored)`, where `anchored` is true if the
+    // path so far is anchored at the end.
+    let mut seen = vec![[false; 2]; insts.len()];
+    let mut stack = vec![(0, false)];
+    while let Some((pc, anchored)) = stack.pop() {
+        if seen[pc][anchored as usize] {
+            continue;
+        }
+        seen[pc][anchored as usize] = true;
+        match insts[pc] {
+            Inst::Match(_) => {
+                if !anchored {
+                    return false;
+                }
+            }
+            Inst::Save(ref inst) => {
+                stack.push((inst.goto as usize, anchored));
+            }
+            Inst::EmptyLook(ref inst) => {
+                let end = inst.look == EmptyLook::EndText;
+                stack.push((inst.goto as usize, anchored || end));
+            }
+            Inst::Split(ref inst) => {
+                stack.push((inst.goto1 as usize, anchored));
+                stack.push((inst.goto2 as usize, anchored));
+            }
+            Inst::SplitMany(ref inst) => {
+                for &goto in &inst.gotos {
+                    stack.push((goto as usize, anchored));
+                }
+            }
+            Inst::Char(ref inst) => stack.push((inst.goto as usize, false)),
+            Inst::Ranges(ref inst) => stack.push((inst.goto as usize, false)),
+            Inst::Backref(ref inst) => {
+                stack.push((inst.goto as usize, false));
+            }
+        }
+    }
+    true
+}
+
+/// Returns the length, in bytes, of the longest text that can be matched by
+/// the given sequence of instructions, whose classes refer to `ranges`.
+///
+/// If there is no limit (i.e., the instructions contain a loop or a
+/// backreference), then `None` is returned.
+fn max_match_len(
+    insts: &[Inst],
+    ranges: &[(char, char)],
+) -> Option<usize> {
+    #[derive(Clone, Copy)]
+    enum State {
+        New,
+        OnStack,
+        // The longest path to a `Match`, if any.
+        Done(Option<usize>),
+    }
+
+    fn longest(state: &[State], pc: usize, len: usize) -> Option<usize> {
+        match state[pc] {
+            State::Done(n) => n.map(|n| n + len),
+            _ => unreachable!(),
+        }
+    }
+
+    // A depth first search, which computes the longest path from every
+    // instruction once all of its successors are done. If we come across an
+    // instruction that is still on the stack, then there's a loop.
+    let mut state = vec![State::New; insts.len()];
+    let mut stack = vec![(0, false)];
+    while let Some((pc, expanded)) = stack.pop() {
+        if expanded {
+            let n = match insts[pc] {
+                Inst::Match(_) => Some(0),
+                Inst::Save(ref inst) => {
+                    longest(&state, inst.goto as usize, 0)
+                }
+                Inst::EmptyLook(ref inst) => {
+                    longest(&state, inst.goto as usize, 0)
+                }
+                Inst::Split(ref inst) => {
+                    match (longest(&state, inst.goto1 as usize, 0),
+                           longest(&state, inst.goto2 as usize, 0)) {
+                        (Some(n1), Some(n2)) => Some(cmp::max(n1, n2)),
+                        (n, None) | (None, n) => n,
+                    }
+                }
+                Inst::SplitMany(ref inst) => {
+                    inst.gotos.iter().filter_map(|&goto| {
+                        longest(&state, goto as usize, 0)
+                    }).max()
+                }
+                Inst::Char(ref inst) => {
+                    longest(&state, inst.goto as usize, inst.c.len_utf8())
+                }
+                Inst::Ranges(ref inst) => {
+                    // The ranges are sorted, so the last one has the longest
+                    // encoding. An empty class can never match.
+                    inst.ranges(ranges).last().and_then(|&(_, e)| {
+                        longest(&state, inst.goto as usize, e.len_utf8())
+                    })
+                }
+                // The search stops at the first backreference, see below.
+                Inst::Backref(_) => unreachable!(),
+            };
+            state[pc] = State::Done(n);
+            continue;
+        }
+        match state[pc] {
+            State::New => {}
+            State::OnStack => return None,
+            State::Done(_) => continue,
+        }
+        state[pc] = State::OnStack;
+        stack.push((pc, true));
+        match insts[pc] {
+            Inst::Match(_) => {}
+            Inst::Save(ref inst) => {
+                stack.push((inst.goto as usize, false));
+            }
+            Inst::EmptyLook(ref inst) => {
+                stack.push((inst.goto as usize, false));
+            }
+            Inst::Split(ref inst) => {
+                stack.push((inst.goto1 as usize, false));
+                stack.push((inst.goto2 as usize, false));
+            }
+            Inst::SplitMany(ref inst) => {
+                for &goto in &inst.gotos {
+                    stack.push((goto as usize, false));
+                }
+            }
+            Inst::Char(ref inst) => stack.push((inst.goto as usize, false)),
+            Inst::Ranges(ref inst) => stack.push((inst.goto as usize, false)),
+            // A backreference matches as much as its group does.
+            Inst::Backref(_) => return None,
+        }
+    }
+    match state[0] {
+        State::Done(n) => n,
+        _ => None,
+    }
+}
+
+/// Returns a copy of the given instructions without the `Save` instructions
+/// for any slot from `nslots` on.
+///
+/// Every jump to such a `Save` is redirected to the first instruction that
+/// isn't one in the chain starting there.
+fn strip_saves(insts: &[Inst], nslots: usize) -> Vec<Inst> {
+    let stripped_save = |inst: &Inst| match *inst {
+        Inst::Save(ref inst) => inst.slot as usize >= nslots,
+        _ => false,
+    };
+    let mut new_pcs = Vec::with_capacity(insts.len());
+    let mut n: InstIdx = 0;
+    for inst in insts {
+        new_pcs.push(n);
+        if !stripped_save(inst) {
+            n += 1;
+        }
+    }
+    let resolve = |mut pc: InstIdx| -> InstIdx {
+        loop {
+            match insts[pc as usize] {
+                Inst::Save(ref inst) if inst.slot as usize >= nslots => {
+                    pc = inst.goto;
+                }
+                _ => return new_pcs[pc as usize],
+            }
+        }
+    };
+    // The program starts with a chain of `Save` instructions leading to the
+    // next instruction (or with a `Save` that is kept), so the start of the
+    // program doesn't move.
+    debug_assert_eq!(resolve(0), 0);
+
+    let mut stripped = Vec::with_capacity(n as usize);
+    for inst in insts {
+        let mut inst = inst.clone();
+        if stripped_save(&inst) {
+            continue;
+        }
+        match inst {
+            Inst::Match(_) => {}
+            Inst::Save(ref mut inst) => inst.goto = resolve(inst.goto),
+            Inst::Split(ref mut inst) => {
+                inst.goto1 = resolve(inst.goto1);
+                inst.goto2 = resolve(inst.goto2);
+            }
+            Inst::SplitMany(ref mut inst) => {
+                for goto in &mut inst.gotos {
+                    *goto = resolve(*goto);
+                }
+            }
+            Inst::EmptyLook(ref mut inst) => inst.goto = resolve(inst.goto),
+            Inst::Char(ref mut inst) => inst.goto = resolve(inst.goto),
+            Inst::Ranges(ref mut inst) => inst.goto = resolve(inst.goto),
+            Inst::Backref(ref mut inst) => inst.goto = resolve(inst.goto),
+        }
+        stripped.push(inst);
+    }
+    stripped
+}
+
+/// Returns every string made of one character from each of the given
+/// columns, of which there are `count`.
+///
+/// The characters of the first column vary fastest. `len` is the length of
+/// the first string, which is reserved for each of them.
+fn product(columns: &[Vec<char>], count: usize, len: usize) -> Vec<String> {
+    (0..count).map(|i| {
+        let mut s = String::with_capacity(len);
+        let mut rest = i;
+        for column in columns {
+            s.push(column[rest % column.len()]);
+            rest /= column.len();
+        }
+        s
+    }).collect()
+}
+
 /// Return the number of captures in the given sequence of instructions.
 fn num_captures(insts: &[Inst]) -> usize {
     let mut n = 0;
     for inst in insts {
         if let Inst::Save(ref inst) = *inst {
-            n = ::std::cmp::max(n, inst.slot + 1)
+            n = ::std::cmp::max(n, inst.slot as usize + 1)
         }
     }
     // There's exactly 2 Save slots for every capture.
@@ -344,6 +1983,48 @@ fn num_captures(insts: &[Inst]) -> usize {
 ///
 /// This is useful for pre-emptively limiting the number of prefix literals
 /// we extract from a regex program.
+/// Returns the first byte of the UTF-8 encoding of `c`.
+/// Returns `s` with `c` in front of it.
+fn prepend(c: char, s: &str) -> String {
+    let mut t = String::with_capacity(c.len_utf8() + s.len());
+    t.push(c);
+    t.push_str(s);
+    t
+}
+
+/// Returns true if `c` is an ASCII letter.
+fn is_ascii_letter(c: char) -> bool {
+    (c >= 'a' && c <= 'z') || (c >= 'A' && c <= 'Z')
+}
+
+/// Returns the lower case letter of a class that matches exactly the two
+/// cases of an ASCII letter, e.g., `[Ee]`.
+fn ascii_case_pair(ranges: &[(char, char)]) -> Option<char> {
+    if ranges.len() != 2 {
+        return None;
+    }
+    let ((u1, u2), (l1, l2)) = (ranges[0], ranges[1]);
+    if u1 == u2 && l1 == l2 && u1 >= 'A' && u1 <= 'Z'
+       && l1 as u32 == u1 as u32 + 32 {
+        Some(l1)
+    } else {
+        None
+    }
+}
+
+fn first_byte(c: char) -> u8 {
+    let c = c as u32;
+    if c < 0x80 {
+        c as u8
+    } else if c < 0x800 {
+        (0xC0 | (c >> 6)) as u8
+    } else if c < 0x10000 {
+        (0xE0 | (c >> 12)) as u8
+    } else {
+        (0xF0 | (c >> 18)) as u8
+    }
+}
+
 fn num_chars_in_ranges(ranges: &[(char, char)]) -> usize {
     ranges.iter()
           .map(|&(s, e)| 1 + (e as u32) - (s as u32))
@@ -352,7 +2033,10 @@ fn num_chars_in_ranges(ranges: &[(char, char)]) -> usize {
 
 #[cfg(test)]
 mod tests {
-    use super::Program;
+    use std::sync::Arc;
+
+    use inst::Inst;
+    use super::{MatchEngine, Program};
 
     macro_rules! prog {
         ($re:expr) => { Program::new(None, 1 << 30, $re).unwrap() }
@@ -385,7 +2069,7 @@ mod tests {
     #[test]
     fn single_alt() {
         assert_eq!(prefixes_complete!("a|b"), vec!["a", "b"]);
-        assert_eq!(prefixes_complete!("b|a"), vec!["b", "a"]);
+        assert_eq!(prefixes_complete!("b|a"), vec!["a", "b"]);
         assert_eq!(prefixes_complete!("[a]|[b]"), vec!["a", "b"]);
         assert_eq!(prefixes!("a+|b"), vec!["a", "b"]);
         assert_eq!(prefixes!("a|b+"), vec!["a", "b"]);
@@ -421,6 +2105,208 @@ mod tests {
         ]);
     }
 
+    #[test]
+    fn through_assertions() {
+        assert_eq!(prefixes!("^foo"), vec!["foo"]);
+        assert_eq!(prefixes!(r"\bfoo\b"), vec!["foo"]);
+        assert_eq!(prefixes!("(?m)^a$b"), vec!["ab"]);
+        assert_eq!(prefixes!(r"foo\B|^bar"), vec!["foo", "bar"]);
+        assert_eq!(prefixes!("foo$"), vec!["foo"]);
+
+        let prog = prog!(r"\bfoo\d");
+        let mut caps = prog.alloc_captures();
+        assert_eq!(prog.exec(&mut caps, "xfoo1 foo2", 0), Some(0));
+        assert_eq!((caps[0], caps[1]), (Some(6), Some(10)));
+    }
+
+    #[test]
+    fn offset_prefixes() {
+        let prog = prog!("..foo");
+        assert_eq!(prog.prefix_offset, 2);
+        assert_eq!(prog.prefixes.prefixes(), vec!["foo"]);
+        assert!(!prog.prefixes_complete);
+        let prog = prog!(r"(\w)\b\d(?:ab|cd)");
+        assert_eq!(prog.prefix_offset, 2);
+        assert_eq!(prog.prefixes.prefixes(), vec!["ab", "cd"]);
+        assert_eq!(prog!("foo").prefix_offset, 0);
+        assert!(prog!(".+foo").prefixes.is_empty());
+
+        let text = "☃foo é☃foo";
+        for &engine in &[MatchEngine::Nfa, MatchEngine::Backtrack] {
+            let prog = Program::new(Some(engine), 1 << 30, "..foo").unwrap();
+            let mut caps = prog.alloc_captures();
+            assert_eq!(prog.exec(&mut caps, text, 0), Some(0));
+            assert_eq!((caps[0], caps[1]), (Some(7), Some(15)));
+            assert_eq!(prog.exec(&mut caps, text, 9), None);
+        }
+    }
+
+    #[test]
+    fn first_bytes() {
+        let prog = prog!(r"\d{6}");
+        let mut expected: Vec<String> =
+            (b'0'..b'9' + 1).map(|b| (b as char).to_string()).collect();
+        expected.extend(
+            ['\u{d9}', '\u{db}', '\u{df}', '\u{e0}', '\u{e1}', '\u{ea}',
+             '\u{ef}', '\u{f0}'].iter().map(|c| c.to_string()));
+        assert_eq!(prog.prefixes.prefixes(), expected);
+        assert!(!prog.prefixes_complete);
+        assert_eq!(prog!(r"\b[a-zA-Z]+").prefixes.len(), 0);
+
+        let text = "id: ٣٤٥٦٧٨, 123456";
+        let mut caps = prog.alloc_captures();
+        assert_eq!(prog.exec(&mut caps, text, 0), Some(0));
+        assert_eq!((caps[0], caps[1]), (Some(4), Some(16)));
+        assert_eq!(prog.exec(&mut caps, text, 6), Some(0));
+        assert_eq!((caps[0], caps[1]), (Some(18), Some(24)));
+    }
+
+    #[test]
+    fn range_table() {
+        use inst::Inst;
+
+        let p = prog!("[a-c]x[0-9A-F]y");
+        assert_eq!(*p.ranges, vec![
+            ('a', 'c'), ('0', '9'), ('A', 'F'),
+        ]);
+        let classes: Vec<_> = p.insts.iter().filter_map(|inst| {
+            match *inst {
+                Inst::Ranges(ref inst) => Some(inst.ranges(&p.ranges)),
+                _ => None,
+            }
+        }).collect();
+        assert_eq!(classes, vec![
+            &[('a', 'c')][..], &[('0', '9'), ('A', 'F')][..],
+        ]);
+        // A class of a single character is compiled to a `Char` and leaves
+        // nothing behind in the table.
+        assert!(prog!("[a]b").ranges.is_empty());
+    }
+
+    #[test]
+    fn few_bytes() {
+        use prefix::Prefix;
+
+        let prefixes = Prefix::bytes(vec![b'z', b'y', b'z']);
+        assert_eq!(prefixes.prefixes(), vec!["y", "z"]);
+        for &at in &[0, 1023, 1024, 1025, 5000] {
+            let mut text: String = ::std::iter::repeat("ab")
+                                   .take(3000).collect();
+            assert_eq!(prefixes.find(text.as_bytes()), None);
+            text.insert(at + 1, 'z');
+            text.insert(at, 'y');
+            assert_eq!(prefixes.find(text.as_bytes()), Some((at, at + 1)));
+        }
+
+        let prog = prog!(r"[\x{100}-\x{17f}]+");
+        assert_eq!(prog.prefixes.prefixes(), vec!["\u{c4}", "\u{c5}"]);
+        let mut caps = prog.alloc_captures();
+        let text = "long text ÿ then ħĶ";
+        assert_eq!(prog.exec(&mut caps, text, 0), Some(0));
+        assert_eq!((caps[0], caps[1]), (Some(18), Some(22)));
+    }
+
+    #[test]
+    fn pairs() {
+        use prefix::Prefix;
+
+        let prog = prog!(r"\r\n|\*/|\$\{");
+        assert!(prog.prefixes_complete);
+        match *prog.prefixes {
+            Prefix::Pairs(_) => {}
+            ref p => panic!("expected pairs, got {:?}", p),
+        }
+        assert_eq!(prog.prefixes.prefixes(), vec!["\r\n", "*/", "${"]);
+        assert_eq!(prog.prefixes.find(b"a*b*/\r\n"), Some((3, 5)));
+        assert_eq!(prog.prefixes.find(b"$a{$"), None);
+        assert_eq!(prog.prefixes.find(b"$"), None);
+        assert_eq!(prog.prefixes.find("é${".as_bytes()), Some((2, 4)));
+    }
+
+    #[test]
+    fn single_rare_byte() {
+        for pat in &["    at ", "zebra", "ee", "aaaaaaab", "x", "a☃ b"] {
+            let prog = prog!(&::re::quote(pat));
+            let texts = &[
+                format!("{}", pat),
+                format!("ab{} {}", &pat[..pat.len() - 1], pat),
+                format!("{}{}", pat, pat),
+                format!("{}", &pat[1..]),
+                format!(" {}", &pat[..pat.len() - 1]),
+            ];
+            for text in texts {
+                assert_eq!(prog.prefixes.find(text.as_bytes()),
+                           text.find(pat).map(|i| (i, i + pat.len())));
+            }
+        }
+    }
+
+    #[test]
+    fn folded_literal() {
+        use prefix::Prefix;
+
+        let prog = prog!("(?i)error");
+        assert!(prog.prefixes_complete);
+        match *prog.prefixes {
+            Prefix::Folded(_) => {}
+            ref p => panic!("expected folded, got {:?}", p),
+        }
+        assert_eq!(prog.prefixes.prefixes(), vec!["error"]);
+        assert_eq!(prog.choose_engine(2, "ERROR", 0).0,
+                   MatchEngine::Literals);
+        let text = "an eRRor, then an Error";
+        assert_eq!(prog.prefixes.find(text.as_bytes()), Some((3, 8)));
+        assert_eq!(prog.prefixes.find(b"errr ERRO"), None);
+
+        assert_eq!(prefixes!(r"(?i)error\d"), vec!["error"]);
+        assert_eq!(prefixes!(r"(?i)\berror"), vec!["error"]);
+        assert_eq!(prefixes_complete!(r"(?i)a-b_c-d\.e"),
+                   vec!["a-b_c-d.e"]);
+        // Too few variants to bother, a letter that must be upper case, and
+        // a letter with a non-ASCII case variant.
+        assert_eq!(prefixes_complete!("(?i)ab").len(), 4);
+        assert_eq!(prefixes_complete!("(?i:erro)R").len(), 16);
+        assert_eq!(prefixes!("(?i)errorsk")[0].len(), 5);
+    }
+
+    #[test]
+    fn suffixes() {
+        let none: Vec<String> = vec![];
+        assert_eq!(prog!("abc").suffixes(), vec!["abc"]);
+        assert_eq!(prog!(r"\w+\.txt").suffixes(), vec![".txt"]);
+        assert_eq!(prog!(r"(?:foo|bar)\b").suffixes(), vec!["bar", "foo"]);
+        assert_eq!(prog!("(?:ab|b)c").suffixes(), vec!["bc"]);
+        assert_eq!(prog!("x(?:ab)+").suffixes(), vec!["ab"]);
+        assert_eq!(prog!("[ab]c|d$").suffixes(), vec!["ac", "bc", "d"]);
+        assert_eq!(prog!("abc|x?").suffixes(), none);
+        assert_eq!(prog!("ab*").suffixes(), vec!["a", "b"]);
+        assert_eq!(prog!("x*").suffixes(), none);
+        assert_eq!(prog!(r"a\w").suffixes(), none);
+        assert_eq!(prog!("a{20}").suffixes()[0].len(), 16);
+        assert!(prog!("a$").anchored_end);
+    }
+
+    #[test]
+    fn clones_share_prefixes() {
+        use prefix::Prefix;
+
+        let prog = prog!("foo|bar|quux");
+        let clone = prog.clone();
+        assert_eq!(&*prog.prefixes as *const Prefix,
+                   &*clone.prefixes as *const Prefix);
+    }
+
+    #[test]
+    fn minimized() {
+        assert_eq!(prefixes!("(?:ab|a|ac)\\w"), vec!["a"]);
+        assert_eq!(prefixes!("(?:abc|b|ab)\\w"), vec!["b", "ab"]);
+        assert_eq!(prefixes!("(?:a|b|a)+"), vec!["a", "b"]);
+        assert_eq!(prefixes!("(?i:k)\\d"), vec!["K", "k", "\u{212a}"]);
+        // The literals are the whole match, so the longer ones stay.
+        assert_eq!(prefixes_complete!("ab|a|ab"), vec!["ab", "a"]);
+        assert_eq!(prefixes_complete!("[aa]"), vec!["a"]);
+    }
+
     #[test]
     fn preceding_alt() {
         assert_eq!(prefixes!("(?:a|b).+"), vec!["a", "b"]);
@@ -434,4 +2320,609 @@ mod tests {
         assert_eq!(prefixes_complete!("((a|b)|(c|d))"),
                    vec!["a", "b", "c", "d"]);
     }
+
+    #[test]
+    fn match_pattern_index() {
+        let res = &["[0-9]+", "[a-z]+", "abc"];
+        for &engine in &[MatchEngine::Nfa, MatchEngine::Backtrack] {
+            let prog = Program::new_many(Some(engine), 1 << 30, res).unwrap();
+            let mut caps = prog.alloc_captures();
+            assert_eq!(prog.exec(&mut caps, "  abc", 0), Some(1));
+            assert_eq!((caps[0], caps[1]), (Some(2), Some(5)));
+            assert_eq!(prog.exec(&mut caps, "abc 123", 3), Some(0));
+            assert_eq!((caps[0], caps[1]), (Some(4), Some(7)));
+            assert_eq!(prog.exec(&mut caps, "  ", 0), None);
+        }
+    }
+
+    #[test]
+    fn min_len() {
+        assert_eq!(prog!("").min_len, 0);
+        assert_eq!(prog!("a*").min_len, 0);
+        assert_eq!(prog!("abc").min_len, 3);
+        assert_eq!(prog!("ab|c").min_len, 1);
+        assert_eq!(prog!("a+b{2,}").min_len, 3);
+        assert_eq!(prog!("^(?:x|yy)\\b$").min_len, 1);
+        assert_eq!(prog!("☃|[αβ]").min_len, 2);
+        assert_eq!(prog!("[a☃]").min_len, 1);
+
+        for &engine in &[MatchEngine::Nfa, MatchEngine::Backtrack] {
+            let prog = Program::new(Some(engine), 1 << 30, "a.c").unwrap();
+            let mut caps = prog.alloc_captures();
+            assert_eq!(prog.exec(&mut caps, "xxabc", 0), Some(0));
+            assert_eq!(prog.exec(&mut caps, "xxab", 0), None);
+            assert_eq!(prog.exec(&mut caps, "abc", 1), None);
+        }
+    }
+
+    #[test]
+    fn line_anchored() {
+        assert!(prog!("(?m)^ERROR").anchored_line_begin);
+        assert!(prog!("^ERROR").anchored_line_begin);
+        assert!(!prog!("(?m)a^").anchored_line_begin);
+
+        let text = "ok\nERRORS\nwarn ERROR\n\nERROR: x";
+        for &engine in &[MatchEngine::Nfa, MatchEngine::Backtrack] {
+            let re = "(?m)^(?:ERROR)?$";
+            let prog = Program::new(Some(engine), 1 << 30, re).unwrap();
+            assert!(prog.anchored_line_begin);
+            let mut caps = prog.alloc_captures();
+            assert_eq!(prog.exec(&mut caps, text, 0), Some(0));
+            assert_eq!((caps[0], caps[1]), (Some(21), Some(21)));
+            assert_eq!(prog.exec(&mut caps, text, 22), None);
+
+            let prog =
+                Program::new(Some(engine), 1 << 30, "(?m)^ERROR:?").unwrap();
+            assert_eq!(prog.exec(&mut caps, text, 1), Some(0));
+            assert_eq!((caps[0], caps[1]), (Some(3), Some(8)));
+            assert_eq!(prog.exec(&mut caps, text, 4), Some(0));
+            assert_eq!((caps[0], caps[1]), (Some(22), Some(28)));
+        }
+    }
+
+    #[test]
+    fn match_only() {
+        use inst::Inst;
+
+        assert!(prog!("abc").match_only.is_none());
+        for re in &["(a)(b)?c", "(?P<x>a)|(y)", "((a*)*)b", "(a|b?)+$"] {
+            let prog = prog!(re);
+            let match_only = prog.match_only.as_ref().unwrap();
+            assert!(match_only.insts.len() < prog.insts.len());
+            assert!(match_only.insts.iter().all(|inst| match *inst {
+                Inst::Save(_) => false,
+                _ => true,
+            }));
+            for text in &["", "a", "ab", "abc", "y", "aab", "ba"] {
+                let mut caps = prog.alloc_captures();
+                assert_eq!(prog.exec(&mut [], text, 0).is_some(),
+                           prog.exec(&mut caps, text, 0).is_some());
+            }
+        }
+    }
+
+    #[test]
+    fn find_only() {
+        use inst::Inst;
+
+        assert!(prog!("abc").find_only.is_none());
+        for re in &["(a)(b)?c", "(?P<x>a)|(y)", "((a*)*)b", "(a|b?)+$"] {
+            let prog = prog!(re);
+            let find_only = prog.find_only.as_ref().unwrap();
+            assert_eq!(find_only.num_captures(), 1);
+            assert!(find_only.insts.iter().all(|inst| match *inst {
+                Inst::Save(ref inst) => inst.slot < 2,
+                _ => true,
+            }));
+            for text in &["", "a", "ab", "abc", "y", "aab", "ba"] {
+                let mut caps = prog.alloc_captures();
+                let found = prog.exec(&mut caps, text, 0);
+                let mut bounds = vec![None, None];
+                assert_eq!(prog.exec(&mut bounds, text, 0), found);
+                assert_eq!(&bounds[..], &caps[..2]);
+            }
+        }
+    }
+
+    #[test]
+    fn find_only_longest_match() {
+        use compile::Compiler;
+        use super::ProgramOptions;
+
+        // The copy must report the same match as the program it's made from.
+        let opts = ProgramOptions {
+            longest_match: true,
+            ..Default::default()
+        };
+        let mut compiler = Compiler::new(1 << 30);
+        let prog = Program::with_compiler(&mut compiler, &opts,
+                                          &["(a|ab)(c|bcd)?"]).unwrap();
+        assert!(prog.find_only.as_ref().unwrap().longest_match);
+        let mut caps = prog.alloc_captures();
+        let mut bounds = vec![None, None];
+        assert_eq!(prog.exec(&mut caps, "abcd", 0), Some(0));
+        assert_eq!(prog.exec(&mut bounds, "abcd", 0), Some(0));
+        assert_eq!(&bounds[..], &caps[..2]);
+        assert_eq!(&bounds[..], &[Some(0), Some(4)]);
+    }
+
+    #[test]
+    fn anchored_begin() {
+        assert!(prog!("^abc").anchored_begin);
+        assert!(prog!("(?:^a)|(?:^b)").anchored_begin);
+        assert!(prog!("(^a|^b)c").anchored_begin);
+        assert!(!prog!("^a|b").anchored_begin);
+        assert!(!prog!("a^").anchored_begin);
+        assert!(!prog!("(?m)^a").anchored_begin);
+        assert!(prog!("(?m)^a|^b").anchored_line_begin);
+        assert!(!prog!("(?m)^a|b").anchored_line_begin);
+
+        let prog = Program::new_many(None, 1 << 30, &["^a", "^b"]).unwrap();
+        assert!(prog.anchored_begin);
+        let mut caps = prog.alloc_captures();
+        assert_eq!(prog.exec(&mut caps, "ba", 0), Some(1));
+        assert_eq!(prog.exec(&mut caps, "ba", 1), None);
+        assert_eq!(prog.exec(&mut caps, "cab", 0), None);
+    }
+
+    #[test]
+    fn anchored() {
+        for &engine in &[MatchEngine::Backtrack, MatchEngine::Nfa] {
+            for re in &["b+", "(b)+", "xyz|b+"] {
+                let prog = Program::new(Some(engine), 1 << 30, re).unwrap();
+                let prog = prog.anchored();
+                assert!(prog.prefixes.is_empty());
+                let mut caps = prog.alloc_captures();
+                assert_eq!(prog.exec(&mut caps, "abb", 0), None);
+                assert_eq!(prog.exec(&mut caps, "abb", 1), Some(0));
+                assert_eq!((caps[0], caps[1]), (Some(1), Some(3)));
+                assert_eq!(prog.exec(&mut [], "abb", 0), None);
+                assert_eq!(prog.exec(&mut [], "abb", 2), Some(0));
+            }
+            // The search still sees the text before its start.
+            let prog = Program::new(Some(engine), 1 << 30, r"\bb").unwrap();
+            let prog = prog.anchored();
+            assert_eq!(prog.exec(&mut [], "abb", 1), None);
+            assert_eq!(prog.exec(&mut [], "a b", 2), Some(0));
+        }
+    }
+
+    #[test]
+    fn max_len() {
+        assert_eq!(prog!("").max_len, Some(0));
+        assert_eq!(prog!("abc").max_len, Some(3));
+        assert_eq!(prog!("ab?|c{2,4}").max_len, Some(4));
+        assert_eq!(prog!("ab?|c{2,4}|d").max_len, Some(4));
+        assert_eq!(prog!("[a☃]").max_len, Some(3));
+        assert_eq!(prog!("a*").max_len, None);
+        assert_eq!(prog!("(a|b)+c").max_len, None);
+    }
+
+    #[test]
+    fn anchored_end() {
+        assert!(prog!("abc$").anchored_end);
+        assert!(prog!("(?:a$|b$)").anchored_end);
+        assert!(!prog!("^a|b$").anchored_end);
+        assert!(!prog!("(?m)a$").anchored_end);
+
+        let text = format!("{}report.txt", ::std::iter::repeat("é.txt")
+                                                 .take(100)
+                                                 .collect::<String>());
+        for &engine in &[MatchEngine::Nfa, MatchEngine::Backtrack] {
+            let prog =
+                Program::new(Some(engine), 1 << 30, r"\w+\.txt$").unwrap();
+            assert_eq!(prog.max_len, None);
+            let mut caps = prog.alloc_captures();
+            assert_eq!(prog.exec(&mut caps, &text, 0), Some(0));
+            assert_eq!(caps[1], Some(text.len()));
+
+            let prog =
+                Program::new(Some(engine), 1 << 30, r"é?\.txt$").unwrap();
+            assert_eq!(prog.max_len, Some(6));
+            assert_eq!(prog.exec(&mut caps, &text, 0), Some(0));
+            let end = text.len();
+            assert_eq!((caps[0], caps[1]), (Some(end - 4), Some(end)));
+            // The search starts in the middle of `é`.
+            assert_eq!(prog.exec(&mut caps, "éa.txt", 0), Some(0));
+            assert_eq!((caps[0], caps[1]), (Some(3), Some(7)));
+            assert_eq!(prog.exec(&mut caps, "é.txt", 0), Some(0));
+            assert_eq!((caps[0], caps[1]), (Some(0), Some(6)));
+            assert_eq!(prog.exec(&mut caps, "é.txt.", 0), None);
+        }
+    }
+
+    #[test]
+    fn no_literal_optimizations() {
+        use compile::Compiler;
+        use super::ProgramOptions;
+
+        let opts = ProgramOptions {
+            literal_optimizations: false,
+            ..Default::default()
+        };
+        let mut compiler = Compiler::new(1 << 30);
+        let prog = Program::with_compiler(&mut compiler, &opts, &["ab|cd"])
+                           .unwrap();
+        assert!(prog.prefixes.is_empty());
+        assert!(!prog.prefixes_complete);
+        let mut caps = prog.alloc_captures();
+        assert_eq!(prog.exec(&mut caps, "xcd", 0), Some(0));
+        assert_eq!((caps[0], caps[1]), (Some(1), Some(3)));
+    }
+
+    #[test]
+    fn adaptive_prefixes() {
+        use compile::Compiler;
+        use super::ProgramOptions;
+
+        fn prog(opts: &ProgramOptions) -> Program {
+            let mut compiler = Compiler::new(1 << 30);
+            Program::with_compiler(&mut compiler, opts, &[r"a\d"]).unwrap()
+        }
+
+        // Every other byte is a prefix that doesn't lead to a match.
+        let text: String = ::std::iter::repeat("ab").take(100).collect();
+        let text = text + "a1";
+        let mut opts = ProgramOptions::default();
+        let p = prog(&opts);
+        assert!(p.should_scan_prefixes(&text, 0));
+        for _ in 0..10 {
+            let mut caps = p.alloc_captures();
+            assert_eq!(p.exec(&mut caps, &text, 0), Some(0));
+            assert_eq!(caps[0], Some(200));
+        }
+        assert!(p.prefix_stats.as_ref().unwrap().is_useless());
+        assert!(!p.should_scan_prefixes(&text, 0));
+        let mut caps = p.alloc_captures();
+        assert_eq!(p.exec(&mut caps, &text, 0), Some(0));
+        assert_eq!(caps[0], Some(200));
+        // A clone starts over.
+        assert!(p.clone().should_scan_prefixes(&text, 0));
+
+        opts.adaptive_prefixes = false;
+        let p = prog(&opts);
+        assert!(p.prefix_stats.is_none());
+        for _ in 0..10 {
+            let mut caps = p.alloc_captures();
+            assert_eq!(p.exec(&mut caps, &text, 0), Some(0));
+        }
+        assert!(p.should_scan_prefixes(&text, 0));
+    }
+
+    #[test]
+    fn prefix_limits() {
+        use compile::Compiler;
+        use super::ProgramOptions;
+
+        fn prefixes(opts: &ProgramOptions, re: &str) -> Vec<String> {
+            let mut compiler = Compiler::new(1 << 30);
+            Program::with_compiler(&mut compiler, opts, &[re])
+                    .unwrap().prefixes.prefixes()
+        }
+
+        let mut opts = ProgramOptions::default();
+        opts.prefix_count_limit = 2;
+        assert_eq!(prefixes(&opts, "ab|cd"), vec!["ab", "cd"]);
+        assert!(prefixes(&opts, "ab|cd|ef+").is_empty());
+        // Unless the regex is nothing but the literals.
+        assert_eq!(prefixes(&opts, "ab|cd|ef"), vec!["ab", "cd", "ef"]);
+        // The class is skipped instead.
+        assert_eq!(prefixes(&opts, "[a-c]x"), vec!["x"]);
+        opts.prefix_count_limit = 3;
+        assert_eq!(prefixes(&opts, "[a-c]x"), vec!["ax", "bx", "cx"]);
+        opts.prefix_count_limit = 1000;
+        let pfxs = prefixes(&opts, "[a-d][a-d]x[a-d]y[a-d]");
+        assert_eq!(pfxs.len(), 256);
+        assert!(pfxs.iter().any(|p| p == "dcxbya"));
+
+        let mut opts = ProgramOptions::default();
+        opts.prefix_length_limit = 2;
+        assert_eq!(prefixes(&opts, "abcdef"), vec!["abc"]);
+        opts.prefix_length_limit = 100;
+        let long: String = ::std::iter::repeat('a').take(50).collect();
+        assert_eq!(prefixes(&opts, &long), vec![long.clone()]);
+    }
+
+    #[test]
+    fn deterministic() {
+        // Compiling the same regex twice must produce the same program, so
+        // that compiled programs can be compared or snapshotted.
+        let res = &[
+            r"(?i)foo|bar[a-z]+|\pL\d",
+            r"(?P<x>[xyz]|é)+(?P<y>\w)?",
+            r"[^\s\S]|(?i)[k-mσ]{2,}",
+        ];
+        for re in res {
+            let (p1, p2) = (prog!(re), prog!(re));
+            assert_eq!(format!("{:?}", p1.insts), format!("{:?}", p2.insts));
+            assert_eq!(p1.cap_names, p2.cap_names);
+            assert_eq!(p1.prefixes.prefixes(), p2.prefixes.prefixes());
+        }
+        // Literals are kept in priority order.
+        assert_eq!(prefixes_complete!("c|(?i)ab"),
+                   vec!["c", "AB", "aB", "Ab", "ab"]);
+    }
+
+    #[test]
+    fn nfa_deep_epsilon_chain() {
+        // Each `a?` adds a `Split` to a chain of epsilon transitions, which
+        // used to be followed recursively and could overflow the stack.
+        let re: String = ::std::iter::repeat("a?").take(100000).collect();
+        let prog =
+            Program::new(Some(MatchEngine::Nfa), 1 << 30, &re).unwrap();
+        let mut caps = prog.alloc_captures();
+        assert_eq!(prog.exec(&mut caps, "aab", 0), Some(0));
+        assert_eq!((caps[0], caps[1]), (Some(0), Some(2)));
+    }
+
+    #[test]
+    fn split_many() {
+        // A wide alternation branches out with one instruction, whichever
+        // engine runs it, and the earlier alternates still win.
+        let words: Vec<String> = (0..500).map(|i| {
+            format!("{}{}", ::std::char::from_u32(0x4E00 + i).unwrap(), i)
+        }).collect();
+        let re = format!("({})|{}", words.join("|"), words[0]);
+        for &engine in &[MatchEngine::Nfa, MatchEngine::Backtrack] {
+            let prog = Program::new(Some(engine), 1 << 30, &re).unwrap();
+            let splits = prog.insts.iter().filter_map(|inst| {
+                match *inst {
+                    Inst::Split(_) => Some(2),
+                    Inst::SplitMany(ref inst) => Some(inst.gotos.len()),
+                    _ => None,
+                }
+            }).collect::<Vec<_>>();
+            assert_eq!(splits, vec![2, 500]);
+
+            let mut caps = prog.alloc_captures();
+            let text = format!("{} {}", words[499], words[0]);
+            assert_eq!(prog.exec(&mut caps, &text, 0), Some(0));
+            assert_eq!(&caps[..], &[Some(0), Some(6), Some(0), Some(6)]);
+            let start = words[499].len();
+            assert_eq!(prog.exec(&mut caps, &text, start), Some(0));
+            assert_eq!(caps[2], Some(start + 1));
+        }
+    }
+
+    #[test]
+    fn keywords() {
+        // Far more keywords than the prefix limits allow, some of which
+        // start like others or are found inside them.
+        let mut words: Vec<String> =
+            (0..2000).map(|i| format!("w{}x", i * 7)).collect();
+        words.extend(vec!["foo".to_owned(), "foobar".to_owned(),
+                          "bcd".to_owned(), "abcde".to_owned()]);
+        let re = words.join("|");
+        let prog = prog!(&re);
+        assert!(prog.prefixes_complete);
+        assert_eq!(prog.prefixes.len(), words.len());
+        let nfa = Program::new(Some(MatchEngine::Nfa), 1 << 30, &re).unwrap();
+
+        let mut caps = prog.alloc_captures();
+        let mut nfa_caps = nfa.alloc_captures();
+        for text in &["foobar", "w70 w77x", "abcdef", "xbcdew140x", "fo"] {
+            let found = prog.exec(&mut caps, text, 0);
+            assert_eq!(found, nfa.exec(&mut nfa_caps, text, 0));
+            assert_eq!(caps, nfa_caps);
+        }
+        assert_eq!(prog.exec(&mut caps, "foobar", 0), Some(0));
+        assert_eq!(&caps[..], &[Some(0), Some(3)]);
+        assert_eq!(prog.exec(&mut caps, "abcdef", 0), Some(0));
+        assert_eq!(&caps[..], &[Some(0), Some(5)]);
+
+        // Anything else in the regex means it isn't just the keywords.
+        let re = format!("{}|a+", words.join("|"));
+        assert!(!prog!(&re).prefixes_complete);
+        let re = format!("(?:{})x", words.join("|"));
+        assert!(!prog!(&re).prefixes_complete);
+    }
+
+    #[test]
+    fn anchored_copies() {
+        assert!(Program::new_many(None, 1 << 30, &["a", "b"])
+                    .unwrap().anchored_copies.is_none());
+
+        let prog = prog!(r"(a)(b)?");
+        let copies = prog.anchored_copies.as_ref().unwrap();
+        let match_only = prog.match_only.as_ref().unwrap();
+        for copy in &[&copies.start, &copies.end, &copies.both] {
+            assert!(Arc::ptr_eq(&copy.insts, &match_only.insts));
+            assert!(copy.anchored_copies.is_none());
+        }
+        let text = "xab";
+        assert_eq!(copies.start.exec(&mut [], text, 0), None);
+        assert_eq!(copies.start.exec(&mut [], text, 1), Some(0));
+        assert_eq!(copies.end.exec(&mut [], text, 0), Some(0));
+        assert_eq!(copies.both.exec(&mut [], text, 0), None);
+        assert_eq!(copies.both.exec(&mut [], &text[1..], 0), Some(0));
+    }
+
+    #[test]
+    fn prefilter() {
+        assert!(prog!("foo").prefilter.is_none());
+        assert!(prog!(r"^foo\w").prefilter.is_none());
+        assert!(prog!(r"\w+").prefilter.is_none());
+
+        let texts =
+            &["foo_bar", "fooxfoo12bar", "..foo", "a\u{2603}foo", "fo"];
+        for re in &[r"foo(\w+)bar", r"..foo", r"(?m)^foo|\bbar"] {
+            let prog = prog!(re);
+            {
+                // The prefilter shares the instructions instead of copying
+                // them, so it doesn't count against the size limit.
+                let pre = prog.prefilter.as_ref().unwrap();
+                assert!(Arc::ptr_eq(&pre.insts, &prog.insts));
+                assert!(Arc::ptr_eq(&pre.ranges, &prog.ranges));
+            }
+            let nfa =
+                Program::new(Some(MatchEngine::Nfa), 1 << 30, re).unwrap();
+            let mut caps = prog.alloc_captures();
+            let mut nfa_caps = nfa.alloc_captures();
+            for text in texts {
+                let found = prog.exec(&mut caps, text, 0);
+                assert_eq!(found, nfa.exec(&mut nfa_caps, text, 0));
+                assert_eq!(caps, nfa_caps);
+                let found = prog.exec(&mut caps[..2], text, 1);
+                assert_eq!(found, nfa.exec(&mut nfa_caps[..2], text, 1));
+                assert_eq!(caps, nfa_caps);
+            }
+        }
+
+        // Every check looks at the rest of the text, so checking every
+        // candidate would take forever.
+        let mut text: String = ::std::iter::repeat("abbbbbbbbbbbbbbb")
+                                   .take(20000).collect();
+        let prog = prog!("a[^z]*z");
+        assert_eq!(prog.exec(&mut [], &text, 0), None);
+        text.push('z');
+        let mut caps = prog.alloc_captures();
+        assert_eq!(prog.exec(&mut caps, &text, 5), Some(0));
+        assert_eq!((caps[0], caps[1]), (Some(16), Some(text.len())));
+    }
+
+    #[test]
+    fn backtrack_reuse_visited() {
+        let prog = Program::new(Some(MatchEngine::Backtrack), 1 << 30,
+                                "a[0-9]+").unwrap();
+        let mut caps = prog.alloc_captures();
+        let text = "a1 a22 a333 ";
+        let mut start = 0;
+        let mut ms = vec![];
+        while let Some(_) = prog.exec(&mut caps, text, start) {
+            ms.push((caps[0].unwrap(), caps[1].unwrap()));
+            start = caps[1].unwrap();
+        }
+        assert_eq!(ms, vec![(0, 2), (3, 6), (7, 11)]);
+        // A shorter input changes the layout of the visited set.
+        assert_eq!(prog.exec(&mut caps, "xa9", 0), Some(0));
+        assert_eq!((caps[0], caps[1]), (Some(1), Some(3)));
+    }
+
+    #[test]
+    fn scan_prefixes() {
+        fn repeat(s: &str, n: usize) -> String {
+            ::std::iter::repeat(s).take(n).collect()
+        }
+        let prog = prog!("ab+c");
+        let rare = format!("{}abbc", repeat("x", 1200));
+        let dense = format!("{}abbc", repeat("abx", 400));
+        assert!(prog.should_scan_prefixes(&rare, 0));
+        assert!(!prog.should_scan_prefixes(&dense, 0));
+        // Short haystacks aren't sampled.
+        assert!(prog.should_scan_prefixes("abxabxabbc", 0));
+        assert!(!prog!("^ab+c").should_scan_prefixes(&rare, 0));
+        // The engine runs without scanning when the prefixes are dense or
+        // the regex is anchored, while still being picked by size.
+        let long = repeat("x", 1 << 23);
+        assert_eq!(prog.choose_engine(2, &rare, 0),
+                   (MatchEngine::Backtrack, true));
+        assert_eq!(prog.choose_engine(2, &dense, 0),
+                   (MatchEngine::Backtrack, false));
+        assert_eq!(prog.choose_engine(2, &long, 0), (MatchEngine::Nfa, true));
+        assert_eq!(prog!("^ab+c").choose_engine(2, &rare, 0),
+                   (MatchEngine::Backtrack, false));
+
+        for &engine in &[MatchEngine::Nfa, MatchEngine::Backtrack] {
+            let prog = Program::new(Some(engine), 1 << 30, "ab+c").unwrap();
+            let mut caps = prog.alloc_captures();
+            assert_eq!(prog.exec(&mut caps, &dense, 0), Some(0));
+            assert_eq!((caps[0], caps[1]), (Some(1200), Some(1204)));
+        }
+    }
+
+    #[test]
+    fn backtrack_budget() {
+        fn repeat(s: &str, n: usize) -> String {
+            ::std::iter::repeat(s).take(n).collect()
+        }
+        // A tiny text always fits in the default budget, however big the
+        // program is.
+        let prog = prog!("(?:a?b?c?){2000}x");
+        assert!(prog.insts.len() > 10000);
+        let text = repeat("abc", 21);
+        assert_eq!(prog.choose_engine(2, &text, 0).0, MatchEngine::Backtrack);
+
+        // The budget allows long texts, where empty loops must still stop.
+        let prog = prog!("(?:a?)*?(?:b?)*?c");
+        let text = repeat("ab", 20000);
+        assert_eq!(prog.choose_engine(2, &text, 0).0, MatchEngine::Backtrack);
+        let mut caps = prog.alloc_captures();
+        assert_eq!(prog.exec(&mut caps, &text, 0), None);
+        let text = format!("{}c", text);
+        assert_eq!(prog.exec(&mut caps, &text, 0), Some(0));
+        assert_eq!((caps[0], caps[1]), (Some(39998), Some(40001)));
+    }
+
+    #[test]
+    fn engine_thresholds() {
+        use compile::Compiler;
+        use super::ProgramOptions;
+
+        let compile = |opts: &ProgramOptions| {
+            let mut compiler = Compiler::new(1 << 30);
+            Program::with_compiler(&mut compiler, opts, &["ab+c"]).unwrap()
+        };
+        let dense: String = ::std::iter::repeat("abx").take(400).collect();
+        let prog = compile(&ProgramOptions {
+            prefix_sample_size: 0,
+            backtrack_limit: 0,
+            ..Default::default()
+        });
+        assert!(prog.should_scan_prefixes(&dense, 0));
+        assert_eq!(prog.choose_engine(2, "abbc", 0).0, MatchEngine::Nfa);
+
+        let prog = compile(&ProgramOptions {
+            prefix_sample_size: 2000,
+            backtrack_limit: 1 << 30,
+            ..Default::default()
+        });
+        let long: String = ::std::iter::repeat("x").take(1 << 23).collect();
+        assert_eq!(prog.choose_engine(2, &long, 0).0, MatchEngine::Backtrack);
+        assert_eq!(prog!("ab+c").choose_engine(2, &long, 0).0,
+                   MatchEngine::Nfa);
+    }
+
+    #[test]
+    fn profile() {
+        use re_builder::Engine;
+
+        let prog = prog!(r"foo(\d)");
+        let text = "xxxxfoo1";
+        for &engine in &[Engine::Nfa, Engine::Backtrack] {
+            let p = prog.profile(engine, text);
+            assert_eq!(p.engine, engine);
+            assert_eq!(p.matched, Some((4, 8)));
+            assert_eq!(p.counts.prefix_scans, 1);
+            assert_eq!(p.counts.prefix_skipped, 4);
+            assert!(p.counts.positions > 0);
+            assert!(p.counts.threads > 0);
+        }
+        // The counters are only set on the copies that are profiled.
+        assert!(prog.counters.is_none());
+        let p = prog.profile(Engine::Nfa, "xxxx");
+        assert_eq!(p.matched, None);
+        assert_eq!(p.counts.positions, 0);
+    }
+
+    #[test]
+    fn prefixes_fit_size_limit() {
+        let text = "xx bar foo";
+        let prog = prog!("foo|bar|baz");
+        assert_eq!(prog.prefixes.prefixes(), vec!["foo", "bar", "baz"]);
+        assert!(prog.prefixes_complete);
+
+        let size = prog.compiled_size();
+        let prog = Program::new(None, size + 4000, "foo|bar|baz").unwrap();
+        assert_eq!(prog.prefixes.prefixes(), vec!["f", "b"]);
+        assert!(!prog.prefixes_complete);
+        let mut caps = prog.alloc_captures();
+        assert_eq!(prog.exec(&mut caps, text, 0), Some(0));
+        assert_eq!((caps[0], caps[1]), (Some(3), Some(6)));
+
+        // Not even the first bytes fit.
+        let prog = Program::new(None, size + 100, "foo|bar|baz").unwrap();
+        assert!(prog.prefixes.is_empty());
+        assert_eq!(prog.exec(&mut caps, text, 0), Some(0));
+        assert_eq!((caps[0], caps[1]), (Some(3), Some(6)));
+    }
 }
@@ -9,15 +9,26 @@
 // except according to those terms.
 
 use std::borrow::Cow;
+use std::cell::RefCell;
+use std::cmp;
 use std::collections::HashMap;
 use std::collections::hash_map::Iter;
 use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::ops::Index;
 #[cfg(feature = "pattern")]
 use std::str::pattern::{Pattern, Searcher, SearchStep};
 use std::str::FromStr;
 
+use memchr::memchr;
+
+use backtrack::Backtrack;
+use compile::Compiler;
+use nfa::{Nfa, NfaSuspended, NfaThreads};
+use pool::PoolGuard;
+use profile::EngineProfile;
 use program::{Program, MatchEngine};
+use re_builder::Engine;
 use syntax;
 
 const REPLACE_EXPAND: &'static str = r"(?x)
@@ -30,6 +41,17 @@ const REPLACE_EXPAND: &'static str = r"(?x)
   )
 ";
 
+const REPLACE_CONDITIONAL: &'static str = r"(?x)
+  \$\$ # Leave `$$` alone, so that `$${1:+a}` isn't expanded.
+  |
+  \$\{
+  (?P<name>[0-9]+|[_a-zA-Z][_0-9a-zA-Z]*)
+  :\+
+  (?P<yes>[^:}]*) # Used if the group participated in the match,
+  (?::(?P<no>[^}]*))? # and this otherwise.
+  \}
+";
+
 /// Type alias for representing capture indices.
 pub type CaptureIdxs = [Option<usize>];
 
@@ -48,6 +70,24 @@ pub fn quote(text: &str) -> String {
     quoted
 }
 
+/// Appends `c` to `class` so that it stands for itself inside a character
+/// class.
+///
+/// This is used when building character classes out of other pattern
+/// languages.
+pub fn push_class_literal(class: &mut String, c: char) {
+    if c == '-' {
+        // `-` can't be escaped with a backslash, so write it as a hex escape
+        // to avoid it being read as a range.
+        class.push_str(r"\x2D");
+    } else {
+        if syntax::is_punct(c) {
+            class.push('\\');
+        }
+        class.push(c);
+    }
+}
+
 /// Tests if the given regular expression matches somewhere in the text given.
 ///
 /// If there was a problem compiling the regular expression, an error is
@@ -67,6 +107,23 @@ pub enum Error {
     /// The compiled program exceeded the set size limit.
     /// The argument is the size limit imposed.
     CompiledTooBig(usize),
+    /// A repetition of an expression that itself contains a repetition
+    /// (e.g., `(a{1,100}){1,100}`) would make the compiled program exceed
+    /// the size limit. This is detected before compiling it.
+    RepetitionTooBig {
+        /// The offending repetition.
+        repetition: String,
+        /// The projected size, in bytes, of the compiled repetition.
+        size: usize,
+        /// The size limit imposed.
+        limit: usize,
+        /// The same repetition without an upper bound (e.g.,
+        /// `(a{1,100}){1,}`), if that fits in the size limit.
+        suggestion: Option<String>,
+    },
+    /// The expression uses a feature that isn't supported, such as a
+    /// backreference. The argument describes the feature.
+    Unsupported(String),
     /// Hints that destructuring should not be exhaustive.
     ///
     /// This enum may grow additional variants, so this makes sure clients
@@ -81,6 +138,8 @@ impl ::std::error::Error for Error {
         match *self {
             Error::Syntax(ref err) => err.description(),
             Error::CompiledTooBig(_) => "compiled program too big",
+            Error::RepetitionTooBig { .. } => "repetition too big",
+            Error::Unsupported(_) => "unsupported feature",
             Error::__Nonexhaustive => unreachable!(),
         }
     }
@@ -101,6 +160,23 @@ impl fmt::Display for Error {
                 write!(f, "Compiled regex exceeds size limit of {} bytes.",
                        limit)
             }
+            Error::RepetitionTooBig {
+                ref repetition, size, limit, ref suggestion,
+            } => {
+                try!(write!(f, "Repetition `{}` would compile to {} bytes, \
+                                which exceeds the size limit of {} bytes.",
+                            repetition, size, limit));
+                match *suggestion {
+                    None => Ok(()),
+                    Some(ref s) => {
+                        write!(f, " If its upper bound isn't needed, use \
+                                   `{}` instead.", s)
+                    }
+                }
+            }
+            Error::Unsupported(ref what) => {
+                write!(f, "Unsupported feature: {}.", what)
+            }
             Error::__Nonexhaustive => unreachable!(),
         }
     }
@@ -223,6 +299,15 @@ impl PartialEq for Regex {
 
 impl Eq for Regex {}
 
+/// Hashing is consistent with equality, i.e., it is based on the original
+/// string. Since all flags are written inline in the pattern, this makes it
+/// possible to use compiled regexes as keys in a map or set.
+impl Hash for Regex {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_str().hash(state)
+    }
+}
+
 impl FromStr for Regex {
     type Err = Error;
 
@@ -247,11 +332,48 @@ impl Regex {
     /// If the data structure exceeds the size given, then an error is
     /// returned.
     ///
+    /// The machine that looks for the regex's literal prefixes counts
+    /// against the limit too. It never causes an error, though: if it
+    /// doesn't fit in what the rest of the regex leaves over, then shorter
+    /// prefixes are looked for instead, or none at all.
+    ///
     /// The default size limit used in `new` is 10MB.
     pub fn with_size_limit(size: usize, re: &str) -> Result<Regex, Error> {
         Regex::with_engine(None, size, re)
     }
 
+    /// Compiles many dynamic regular expressions at once.
+    ///
+    /// This is equivalent to calling `Regex::new` on each expression, except
+    /// that the scratch space used while compiling is shared between all of
+    /// them. This makes a difference when compiling thousands of patterns,
+    /// e.g., for a rule engine.
+    ///
+    /// If any of the expressions is invalid, then the first error
+    /// encountered is returned.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate regex; use regex::Regex;
+    /// # fn main() {
+    /// let res = Regex::new_many(&[r"\d+", r"[a-z]+"]).unwrap();
+    /// assert_eq!(res[0].find("abc123"), Some((3, 6)));
+    /// assert_eq!(res[1].find("abc123"), Some((0, 3)));
+    /// # }
+    /// ```
+    pub fn new_many<I, S>(res: I) -> Result<Vec<Regex>, Error>
+            where I: IntoIterator<Item=S>, S: AsRef<str> {
+        let mut compiler = Compiler::new(10 * (1 << 20));
+        let mut compiled = vec![];
+        for re in res {
+            let prog = try!(Program::with_compiler(
+                &mut compiler, &Default::default(), &[re.as_ref()]));
+            compiled.push(Regex::Dynamic(prog));
+        }
+        Ok(compiled)
+    }
+
     /// Compiles a dynamic regular expression and uses given matching engine.
     ///
     /// This is exposed for use in testing and shouldn't be used by clients.
@@ -293,6 +415,65 @@ impl Regex {
         exec(self, &mut [], text, 0)
     }
 
+    /// Returns true if and only if the regex matches at the very beginning
+    /// of `text`, as if it started with `\A`.
+    ///
+    /// Unlike `find`, this never looks past a match at the start of `text`,
+    /// so it quickly gives up on text that doesn't begin with a match.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate regex; use regex::Regex;
+    /// # fn main() {
+    /// let re = Regex::new(r"\d+").unwrap();
+    /// assert!(re.starts_with("42 apples"));
+    /// assert!(!re.starts_with("apples: 42"));
+    /// # }
+    /// ```
+    pub fn starts_with(&self, text: &str) -> bool {
+        exec_anchored(self, text, true, false)
+    }
+
+    /// Returns true if and only if the regex matches at the very end of
+    /// `text`, as if it ended with `\z`.
+    ///
+    /// Any match that ends at the end of `text` counts, even if it isn't the
+    /// one `find` would report at that position. If no match can be longer
+    /// than a certain length, then only that many bytes at the end of `text`
+    /// are searched.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate regex; use regex::Regex;
+    /// # fn main() {
+    /// let re = Regex::new(r"\.(?:jpe?g|png)").unwrap();
+    /// assert!(re.ends_with("cat.jpeg"));
+    /// assert!(!re.ends_with("cat.png.txt"));
+    /// # }
+    /// ```
+    pub fn ends_with(&self, text: &str) -> bool {
+        exec_anchored(self, text, false, true)
+    }
+
+    /// Returns true if and only if the regex matches all of `text`, as if it
+    /// were surrounded by `\A` and `\z`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate regex; use regex::Regex;
+    /// # fn main() {
+    /// let re = Regex::new(r"a|ab").unwrap();
+    /// assert!(re.is_full_match("ab"));
+    /// assert!(!re.is_full_match("abc"));
+    /// # }
+    /// ```
+    pub fn is_full_match(&self, text: &str) -> bool {
+        exec_anchored(self, text, true, true)
+    }
+
     /// Returns the start and end byte range of the leftmost-first match in
     /// `text`. If no match exists, then `None` is returned.
     ///
@@ -314,8 +495,35 @@ impl Regex {
     /// # }
     /// ```
     pub fn find(&self, text: &str) -> Option<(usize, usize)> {
+        self.find_at(text, 0)
+    }
+
+    /// Returns the start and end byte range of the leftmost-first match in
+    /// `text` that starts at or after the byte index `start`.
+    ///
+    /// This is like `find` on `&text[start..]`, except that the regex still
+    /// sees all of `text`. So anchors and word boundaries look at the text
+    /// before `start`, and the range returned is relative to the start of
+    /// `text`.
+    ///
+    /// # Panics
+    ///
+    /// If `start` isn't on a character boundary of `text`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate regex; use regex::Regex;
+    /// # fn main() {
+    /// let re = Regex::new(r"\bbar").unwrap();
+    /// assert_eq!(re.find_at("foobar bar", 1), Some((7, 10)));
+    /// # }
+    /// ```
+    pub fn find_at(&self, text: &str, start: usize)
+                  -> Option<(usize, usize)> {
+        assert!(text.is_char_boundary(start));
         let mut caps = [None, None];
-        if exec(self, &mut caps, text, 0) {
+        if exec(self, &mut caps, text, start) {
             Some((caps[0].unwrap(), caps[1].unwrap()))
         } else {
             None
@@ -354,6 +562,93 @@ impl Regex {
         }
     }
 
+    /// Returns the number of successive non-overlapping matches in `text`.
+    ///
+    /// This counts the same matches as `find_iter`, without handing out
+    /// where any of them is. Like `find_iter`, the search starts over at the
+    /// end of every match, so this only saves the overhead of the iterator.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate regex; use regex::Regex;
+    /// # fn main() {
+    /// let re = Regex::new(r"(?m)^ERROR").unwrap();
+    /// let log = "ERROR a\nINFO b\nERROR c\nWARN ERROR d\n";
+    /// assert_eq!(re.count_matches(log), 2);
+    /// # }
+    /// ```
+    pub fn count_matches(&self, text: &str) -> usize {
+        match *self {
+            // Only the backtracking engine can run backreferences, and it
+            // stops at the first match.
+            Regex::Dynamic(ref prog) if !prog.backrefs => {
+                prog.count_all(0, text, 0)
+            }
+            _ => self.find_iter(text).count(),
+        }
+    }
+
+    /// Splits `text` into chunks that can be searched independently, e.g.,
+    /// on different threads or machines.
+    ///
+    /// Each chunk is returned as a pair of start and end byte indices. The
+    /// chunks are in order, cover all of `text` and are roughly `size`
+    /// bytes long: every chunk but the last is extended to the end of the
+    /// line it would otherwise stop in.
+    ///
+    /// The matches of the regex in `text` are exactly the matches found by
+    /// searching each chunk on its own (shifted by the start of the chunk),
+    /// except that an empty match at the end of a chunk other than the last
+    /// one must be dropped. (It is found again at the start of the next
+    /// chunk.)
+    ///
+    /// This is only possible when no match can span more than one line, so
+    /// `None` is returned if the regex can match a `\n` or uses `^` outside
+    /// of multi-line mode. `None` is also returned for regexes compiled by
+    /// `regex!`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate regex; use regex::Regex;
+    /// # fn main() {
+    /// let re = Regex::new(r"\w+").unwrap();
+    /// let chunks = re.partition("ab\ncd\nef", 2).unwrap();
+    /// assert_eq!(chunks, vec![(0, 3), (3, 6), (6, 8)]);
+    ///
+    /// assert!(Regex::new(r"\s+").unwrap().partition("a\nb", 2).is_none());
+    /// # }
+    /// ```
+    pub fn partition(&self, text: &str, size: usize)
+                    -> Option<Vec<(usize, usize)>> {
+        match *self {
+            Regex::Native(_) => return None,
+            Regex::Dynamic(ref prog) => {
+                if !prog.is_line_local() {
+                    return None;
+                }
+            }
+        }
+        if text.is_empty() {
+            return Some(vec![(0, 0)]);
+        }
+        let size = cmp::max(size, 1);
+        let mut chunks = vec![];
+        let mut start = 0;
+        while start < text.len() {
+            // The chunk ends just after a `\n`, so it can't split a match.
+            let from = cmp::min(start + size, text.len()) - 1;
+            let end = match memchr(b'\n', &text.as_bytes()[from..]) {
+                Some(i) => from + i + 1,
+                None => text.len(),
+            };
+            chunks.push((start, end));
+            start = end;
+        }
+        Some(chunks)
+    }
+
     /// Returns the capture groups corresponding to the leftmost-first
     /// match in `text`. Capture group `0` always corresponds to the entire
     /// match. If no match is found, then `None` is returned.
@@ -417,14 +712,254 @@ impl Regex {
     /// The `0`th capture group is always unnamed, so it must always be
     /// accessed with `at(0)` or `[0]`.
     pub fn captures<'t>(&self, text: &'t str) -> Option<Captures<'t>> {
+        self.captures_at(text, 0)
+    }
+
+    /// Returns the capture groups of the leftmost-first match in `text` that
+    /// starts at or after the byte index `start`.
+    ///
+    /// This is like `captures` on `&text[start..]`, except that the regex
+    /// still sees all of `text`. So anchors and word boundaries look at the
+    /// text before `start`, and the positions of the capture groups are
+    /// relative to the start of `text`.
+    ///
+    /// # Panics
+    ///
+    /// If `start` isn't on a character boundary of `text`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate regex; use regex::Regex;
+    /// # fn main() {
+    /// let re = Regex::new(r"^(\w+)|\b(\w+)").unwrap();
+    /// let caps = re.captures_at("foo bar", 1).unwrap();
+    /// assert_eq!(caps.pos(0), Some((4, 7)));
+    /// assert_eq!(caps.at(1), None);
+    /// assert_eq!(caps.at(2), Some("bar"));
+    /// # }
+    /// ```
+    pub fn captures_at<'t>(&self, text: &'t str, start: usize)
+                          -> Option<Captures<'t>> {
+        assert!(text.is_char_boundary(start));
         let mut caps = self.alloc_captures();
-        if exec(self, &mut caps, text, 0) {
+        if exec(self, &mut caps, text, start) {
             Some(Captures::new(self, text, caps))
         } else {
             None
         }
     }
 
+    /// Returns the capture groups of the leftmost-first match in `text`,
+    /// along with every span that each group matched on the way.
+    ///
+    /// This is like `captures`, except that a group inside a repetition
+    /// remembers all of its iterations, which `Captures::all` returns. (With
+    /// `captures`, only the last one is kept.)
+    ///
+    /// Only the backtracking engine keeps a history. Its memory use grows
+    /// with the length of the text times the size of the regex, so if that
+    /// would exceed the backtracking limit (see
+    /// `RegexBuilder::backtrack_limit`), then this is the same as
+    /// `captures`. So is a regex compiled with the `regex!` macro, by
+    /// `posix::compile_ere` or `posix::compile_bre`, or with leftmost-longest
+    /// matches or POSIX submatches (see `RegexBuilder`). Without a history,
+    /// `Captures::all` only returns the last span of each group.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate regex; use regex::Regex;
+    /// # fn main() {
+    /// let re = Regex::new(r"(?:(\w+),)+").unwrap();
+    /// let caps = re.captures_with_history("x: a,bc,d,").unwrap();
+    /// assert_eq!(caps.at(1), Some("d"));
+    /// assert_eq!(caps.all(1), vec![(3, 4), (5, 7), (8, 9)]);
+    /// # }
+    /// ```
+    pub fn captures_with_history<'t>(&self, text: &'t str)
+                                    -> Option<Captures<'t>> {
+        let prog = match *self {
+            Regex::Native(_) => return self.captures(text),
+            Regex::Dynamic(ref prog) => prog,
+        };
+        let mut caps = self.alloc_captures();
+        let mut history = vec![];
+        if prog.exec_history(&mut caps, &mut history, text, 0).is_none() {
+            return None;
+        }
+        let mut caps = Captures::new(self, text, caps);
+        if prog.keeps_history(text) {
+            caps.history = Some(history);
+        }
+        Some(caps)
+    }
+
+    /// Finds the first match in `text` with each matching engine that can
+    /// run this regex on it, and reports how long each engine took and how
+    /// much work it did.
+    ///
+    /// The NFA can run any regex without a backreference. The backtracking
+    /// engine is only tried if the regex is small enough for `text` (or has
+    /// a backreference), and never for a regex that reports
+    /// leftmost-longest matches. Every engine finds the same
+    /// match, so this is useful for choosing one with `RegexBuilder::engine`
+    /// or for finding out why a search is slow.
+    ///
+    /// A regex compiled with the `regex!` macro has no engines to compare,
+    /// so this returns nothing for it.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate regex; use regex::{Engine, Regex};
+    /// # fn main() {
+    /// let re = Regex::new(r"\d+").unwrap();
+    /// let profiles = re.profile("abc 123");
+    /// assert_eq!(profiles.len(), 2);
+    /// assert_eq!(profiles[0].engine, Engine::Nfa);
+    /// assert_eq!(profiles[1].engine, Engine::Backtrack);
+    /// for p in &profiles {
+    ///     assert_eq!(p.matched, Some((4, 7)));
+    /// }
+    /// # }
+    /// ```
+    pub fn profile(&self, text: &str) -> Vec<EngineProfile> {
+        let prog = match *self {
+            Regex::Native(_) => return vec![],
+            Regex::Dynamic(ref prog) => prog,
+        };
+        let mut profiles = vec![];
+        if !prog.backrefs {
+            profiles.push(prog.profile(Engine::Nfa, text));
+        }
+        let searched = prog.find_only.as_ref().map_or(prog, |p| &**p);
+        if !prog.longest_match && Backtrack::should_exec(searched, text) {
+            profiles.push(prog.profile(Engine::Backtrack, text));
+        }
+        profiles
+    }
+
+    /// Returns scratch memory for searching with this regex without
+    /// allocating.
+    ///
+    /// The scratch memory is allocated here once, at its final size. After
+    /// that, `is_match_with`, `find_at_with` and `read_captures_at_with` run
+    /// the matching engine entirely in it, so they don't allocate (nor lock
+    /// or free anything), which makes them suitable for threads that must
+    /// not block, such as real-time audio threads.
+    ///
+    /// The guarantee only covers the search itself:
+    ///
+    /// * It only holds for a regex compiled with `Regex::new` or
+    ///   `RegexBuilder`. A regex compiled with the `regex!` macro manages its
+    ///   own memory.
+    /// * It doesn't hold for a regex with a backreference, which only the
+    ///   backtracking engine can run. Such a regex is searched like with
+    ///   `is_match`, `find_at` or `read_captures_at`.
+    /// * A callback set with `RegexBuilder::trace` (with the `trace`
+    ///   feature) or the logger (with the `log` feature) is called during
+    ///   the search, and may allocate.
+    ///
+    /// In debug builds, an assertion after every search checks that the
+    /// scratch memory didn't have to grow. It can't see any other
+    /// allocation, so it's a check of the sizes computed here, not of the
+    /// whole guarantee.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate regex; use regex::Regex;
+    /// # fn main() {
+    /// let re = Regex::new(r"(\w+)=(\d+)").unwrap();
+    /// let mut scratch = re.scratch();
+    /// let mut slots = vec![None; 2 * re.captures_len()];
+    /// // From here on, nothing is allocated.
+    /// assert!(re.read_captures_at_with("a=1 b=2", 1, &mut slots,
+    ///                                  &mut scratch));
+    /// assert_eq!(&slots[..], &[Some(4), Some(7), Some(4), Some(5),
+    ///                          Some(6), Some(7)]);
+    /// # }
+    /// ```
+    pub fn scratch(&self) -> Scratch {
+        match *self {
+            Regex::Native(_) => Scratch {
+                threads: NfaThreads::new(0, 0),
+                num_insts: 0,
+            },
+            Regex::Dynamic(ref prog) => {
+                let (num_insts, ncaps) =
+                    (prog.insts.len(), prog.num_captures());
+                Scratch {
+                    threads: NfaThreads::with_capacity(num_insts, ncaps),
+                    num_insts: num_insts,
+                }
+            }
+        }
+    }
+
+    /// Returns true if and only if the regex matches the string given, using
+    /// the scratch memory given instead of allocating.
+    ///
+    /// # Panics
+    ///
+    /// If `scratch` wasn't returned by `scratch` on this regex.
+    pub fn is_match_with(&self, text: &str, scratch: &mut Scratch) -> bool {
+        exec_with(self, scratch, &mut [], text, 0)
+    }
+
+    /// Returns the start and end byte range of the leftmost-first match in
+    /// `text` that starts at or after the byte index `start`, using the
+    /// scratch memory given instead of allocating.
+    ///
+    /// # Panics
+    ///
+    /// If `start` isn't on a character boundary of `text`, or if `scratch`
+    /// wasn't returned by `scratch` on this regex.
+    pub fn find_at_with(
+        &self,
+        text: &str,
+        start: usize,
+        scratch: &mut Scratch,
+    ) -> Option<(usize, usize)> {
+        assert!(text.is_char_boundary(start));
+        let mut caps = [None, None];
+        if exec_with(self, scratch, &mut caps, text, start) {
+            Some((caps[0].unwrap(), caps[1].unwrap()))
+        } else {
+            None
+        }
+    }
+
+    /// Searches for the leftmost-first match in `text` that starts at or
+    /// after the byte index `start`, using the scratch memory given instead
+    /// of allocating. Returns true if and only if there's a match.
+    ///
+    /// If there is a match, the start and end byte indices of capture group
+    /// `i` are written to `slots[2 * i]` and `slots[2 * i + 1]`. (`None` if
+    /// the group didn't participate in the match.)
+    ///
+    /// The capture groups are always resolved by priority, even for a regex
+    /// compiled with `RegexBuilder::posix_submatches`, since resolving them
+    /// by POSIX rules needs memory that grows with the length of the match.
+    ///
+    /// # Panics
+    ///
+    /// If `slots.len()` isn't `2 * self.captures_len()`, if `start` isn't on
+    /// a character boundary of `text`, or if `scratch` wasn't returned by
+    /// `scratch` on this regex.
+    pub fn read_captures_at_with(
+        &self,
+        text: &str,
+        start: usize,
+        slots: &mut [Option<usize>],
+        scratch: &mut Scratch,
+    ) -> bool {
+        assert_eq!(slots.len(), 2 * self.captures_len());
+        assert!(text.is_char_boundary(start));
+        exec_with(self, scratch, slots, text, start)
+    }
+
     /// Returns an iterator over all the non-overlapping capture groups matched
     /// in `text`. This is operationally the same as `find_iter` (except it
     /// yields information about submatches).
@@ -485,6 +1020,60 @@ impl Regex {
         }
     }
 
+    /// Returns an iterator of substrings of `text` delimited by a match of
+    /// the regular expression, where each substring is paired with the
+    /// match that ended it.
+    ///
+    /// The substrings are the same ones that `split` yields. The last one
+    /// isn't followed by a delimiter, so it's paired with `None`.
+    ///
+    /// This method will *not* copy the text given.
+    ///
+    /// # Example
+    ///
+    /// Tell which separator ended each field:
+    ///
+    /// ```rust
+    /// # extern crate regex; use regex::Regex;
+    /// # fn main() {
+    /// let re = Regex::new(r"[,;]").unwrap();
+    /// let fields: Vec<_> = re.split_inclusive("a,b;c").collect();
+    /// assert_eq!(fields, vec![("a", Some(",")), ("b", Some(";")),
+    ///                         ("c", None)]);
+    /// # }
+    /// ```
+    pub fn split_inclusive<'r, 't>(&'r self, text: &'t str)
+                                  -> RegexSplitsInclusive<'r, 't> {
+        RegexSplitsInclusive {
+            finder: self.find_iter(text),
+            last: 0,
+        }
+    }
+
+    /// Returns an iterator of the start and end byte indices of the
+    /// substrings of `text` delimited by a match of the regular expression.
+    ///
+    /// The positions correspond to the substrings that `split` yields. (The
+    /// positions of the delimiters themselves are given by `find_iter`.)
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate regex; use regex::Regex;
+    /// # fn main() {
+    /// let re = Regex::new(r"\s+").unwrap();
+    /// let spans: Vec<_> = re.split_positions("ab  c d").collect();
+    /// assert_eq!(spans, vec![(0, 2), (4, 5), (6, 7)]);
+    /// # }
+    /// ```
+    pub fn split_positions<'r, 't>(&'r self, text: &'t str)
+                                  -> RegexSplitsPos<'r, 't> {
+        RegexSplitsPos {
+            finder: self.find_iter(text),
+            last: 0,
+        }
+    }
+
     /// Returns an iterator of at most `limit` substrings of `text` delimited
     /// by a match of the regular expression. (A `limit` of `0` will return no
     /// substrings.)
@@ -516,6 +1105,48 @@ impl Regex {
         }
     }
 
+    /// Returns an iterator of substrings of `text` delimited by a match of
+    /// the regular expression, split as `opts` says.
+    ///
+    /// Unlike `split`, which drops a single empty substring at the end, this
+    /// keeps every empty substring unless `opts` says otherwise (as
+    /// Python's `re.split` and Go's `Regexp.Split` do). See `SplitOptions`
+    /// for the other behaviors.
+    ///
+    /// This method will *not* copy the text given.
+    ///
+    /// # Example
+    ///
+    /// Split like Java's `String.split`, which drops every empty substring
+    /// at the end:
+    ///
+    /// ```rust
+    /// # extern crate regex; use regex::{Regex, SplitOptions};
+    /// # fn main() {
+    /// let re = Regex::new(",").unwrap();
+    /// let fields: Vec<&str> = re.split_with(",a,,b,,", SplitOptions::new())
+    ///                           .collect();
+    /// assert_eq!(fields, vec!["", "a", "", "b", "", ""]);
+    ///
+    /// let opts = SplitOptions::new().drop_trailing_empty(true);
+    /// let fields: Vec<&str> = re.split_with(",a,,b,,", opts).collect();
+    /// assert_eq!(fields, vec!["", "a", "", "b"]);
+    /// # }
+    /// ```
+    pub fn split_with<'r, 't>(&'r self, text: &'t str, opts: SplitOptions)
+                             -> RegexSplitsWith<'r, 't> {
+        RegexSplitsWith {
+            finder: self.find_iter(text),
+            last: 0,
+            opts: opts,
+            fields: 0,
+            started: false,
+            empties: 0,
+            held: None,
+            done: false,
+        }
+    }
+
     /// Replaces the leftmost-first match with the replacement provided.
     /// The replacement can be a regular string (where `$N` and `$name` are
     /// expanded to match capture groups) or a function that takes the matches'
@@ -604,22 +1235,98 @@ impl Regex {
     /// See the documentation for `replace` for details on how to access
     /// submatches in the replacement string.
     pub fn replacen<R: Replacer>
-                   (&self, text: &str, limit: usize, mut rep: R) -> String {
-        let mut new = String::with_capacity(text.len());
-        let mut last_match = 0;
+                   (&self, text: &str, limit: usize, rep: R) -> String {
+        self.replacen_count(text, limit, rep).0
+    }
 
-        if rep.no_expand().is_some() {
-            // borrow checker pains. `rep` is borrowed mutably in the `else`
-            // branch below.
-            let rep = rep.no_expand().unwrap();
-            for (i, (s, e)) in self.find_iter(text).enumerate() {
-                if limit > 0 && i >= limit {
-                    break
-                }
-                new.push_str(&text[last_match..s]);
-                new.push_str(&rep);
-                last_match = e;
-            }
+    /// Replaces all non-overlapping matches in `text` with the replacement
+    /// provided, and returns the new string along with the number of
+    /// replacements that were made.
+    ///
+    /// This tells whether anything was replaced without searching the text
+    /// a second time.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate regex; use regex::Regex;
+    /// # fn main() {
+    /// let re = Regex::new(r"\d{4}-\d{4}").unwrap();
+    /// let (redacted, n) = re.replace_all_count("card 1234-5678", "XXXX");
+    /// assert_eq!(redacted, "card XXXX");
+    /// assert_eq!(n, 1);
+    /// # }
+    /// ```
+    pub fn replace_all_count<R: Replacer>
+                            (&self, text: &str, rep: R) -> (String, usize) {
+        self.replacen_count(text, 0, rep)
+    }
+
+    /// Replaces at most `limit` non-overlapping matches in `text` with the
+    /// replacement provided, and returns the new string along with the
+    /// number of replacements that were made. If `limit` is 0, then all
+    /// non-overlapping matches are replaced.
+    pub fn replacen_count<R: Replacer>
+                         (&self, text: &str, limit: usize, rep: R)
+                         -> (String, usize) {
+        let mut new = String::with_capacity(text.len());
+        let count = self.replacen_append(text, limit, rep, &mut new);
+        (new, count)
+    }
+
+    /// Replaces all non-overlapping matches in `text` with the replacement
+    /// provided and appends the result to `dst`. The number of replacements
+    /// that were made is returned.
+    ///
+    /// This is the same as `replace_all`, except it writes into an existing
+    /// buffer instead of allocating a new string. This makes it possible to
+    /// reuse one buffer when rewriting many strings.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate regex; use regex::Regex;
+    /// # fn main() {
+    /// let re = Regex::new(r"\s+").unwrap();
+    /// let mut buf = String::new();
+    /// for line in &["a  b", "c\td"] {
+    ///     buf.clear();
+    ///     re.replace_append(line, " ", &mut buf);
+    ///     assert_eq!(buf.len(), 3);
+    /// }
+    /// # }
+    /// ```
+    pub fn replace_append<R: Replacer>
+                         (&self, text: &str, rep: R, dst: &mut String)
+                         -> usize {
+        self.replacen_append(text, 0, rep, dst)
+    }
+
+    /// Replaces at most `limit` non-overlapping matches in `text` with the
+    /// replacement provided and appends the result to `dst`. If `limit` is
+    /// 0, then all non-overlapping matches are replaced. The number of
+    /// replacements that were made is returned.
+    pub fn replacen_append<R: Replacer>
+                          (&self, text: &str, limit: usize, mut rep: R,
+                           dst: &mut String)
+                          -> usize {
+        let mut last_match = 0;
+        let mut count = 0;
+        dst.reserve(text.len());
+
+        if rep.no_expand().is_some() {
+            // borrow checker pains. `rep` is borrowed mutably in the `else`
+            // branch below.
+            let rep = rep.no_expand().unwrap();
+            for (i, (s, e)) in self.find_iter(text).enumerate() {
+                if limit > 0 && i >= limit {
+                    break
+                }
+                dst.push_str(&text[last_match..s]);
+                dst.push_str(&rep);
+                last_match = e;
+                count += 1;
+            }
         } else {
             for (i, cap) in self.captures_iter(text).enumerate() {
                 if limit > 0 && i >= limit {
@@ -627,13 +1334,63 @@ impl Regex {
                 }
                 // unwrap on 0 is OK because captures only reports matches
                 let (s, e) = cap.pos(0).unwrap();
-                new.push_str(&text[last_match..s]);
-                new.push_str(&rep.reg_replace(&cap));
+                dst.push_str(&text[last_match..s]);
+                dst.push_str(&rep.reg_replace(&cap));
                 last_match = e;
+                count += 1;
+            }
+        }
+        dst.push_str(&text[last_match..]);
+        count
+    }
+
+    /// Replaces all non-overlapping matches in `text` with a replacement
+    /// that may keep some of them or fail.
+    ///
+    /// For each match, the replacer returns either a replacement, `None`
+    /// to keep the text of the match as it is, or an error. The first error
+    /// stops the replacement, and is returned instead of the new string.
+    ///
+    /// # Example
+    ///
+    /// Redact every number that isn't a year, and give up on anything that
+    /// looks like a card number:
+    ///
+    /// ```rust
+    /// # extern crate regex; use regex::{Captures, Regex};
+    /// # fn main() {
+    /// let re = Regex::new(r"\d+").unwrap();
+    /// let redact = |caps: &Captures| {
+    ///     let n = caps.at(0).unwrap();
+    ///     if n.len() >= 12 {
+    ///         Err(format!("card number at {}", caps.pos(0).unwrap().0))
+    ///     } else if n.len() == 4 && n.starts_with("19") {
+    ///         Ok(None)
+    ///     } else {
+    ///         Ok(Some("###".to_owned()))
+    ///     }
+    /// };
+    /// assert_eq!(re.try_replace_all("in 1984, 42 people", redact),
+    ///            Ok("in 1984, ### people".to_owned()));
+    /// assert_eq!(re.try_replace_all("1 4111111111111111", redact),
+    ///            Err("card number at 2".to_owned()));
+    /// # }
+    /// ```
+    pub fn try_replace_all<R: TryReplacer>(&self, text: &str, mut rep: R)
+                                          -> Result<String, R::Error> {
+        let mut new = String::with_capacity(text.len());
+        let mut last_match = 0;
+        for cap in self.captures_iter(text) {
+            let (s, e) = cap.pos(0).unwrap();
+            new.push_str(&text[last_match..s]);
+            match try!(rep.try_replace(&cap)) {
+                None => new.push_str(&text[s..e]),
+                Some(replacement) => new.push_str(&replacement),
             }
+            last_match = e;
         }
         new.push_str(&text[last_match..]);
-        new
+        Ok(new)
     }
 
     /// Returns the original string of this regex.
@@ -644,6 +1401,50 @@ impl Regex {
         }
     }
 
+    /// Returns the literals that every match of this regex ends with, if
+    /// there are any.
+    ///
+    /// This is meant for filtering candidate texts before running the
+    /// regex on them, e.g., with the index of a database. If every match
+    /// also ends at the end of the text, then a text can only match if it
+    /// ends with one of the literals. Otherwise, it must at least contain
+    /// one of them.
+    ///
+    /// Only a few literals of limited length are found, so there may be
+    /// none even though every match does end with a literal. (e.g., if it's
+    /// one of many alternatives.)
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate regex; use regex::Regex;
+    /// # fn main() {
+    /// let re = Regex::new(r"\w+\.(?:jpe?g|png)$").unwrap();
+    /// let suffixes = re.required_suffixes().unwrap();
+    /// assert_eq!(suffixes.literals, vec![".jpeg", ".jpg", ".png"]);
+    /// assert!(suffixes.anchored);
+    ///
+    /// let re = Regex::new(r"\d+").unwrap();
+    /// assert_eq!(re.required_suffixes(), None);
+    /// # }
+    /// ```
+    pub fn required_suffixes(&self) -> Option<RequiredSuffixes> {
+        let prog = match *self {
+            Regex::Native(ExNative { original, .. }) => {
+                return Regex::new(original).unwrap().required_suffixes();
+            }
+            Regex::Dynamic(ref prog) => prog,
+        };
+        let literals = prog.suffixes();
+        if literals.is_empty() {
+            return None;
+        }
+        Some(RequiredSuffixes {
+            literals: literals,
+            anchored: prog.anchored_end,
+        })
+    }
+
     /// Returns an iterator over the capture names.
     pub fn capture_names(&self) -> CaptureNames {
         match *self {
@@ -660,6 +1461,79 @@ impl Regex {
         }
     }
 
+    /// Returns the names and indices of the capture groups in this regex.
+    ///
+    /// This makes it possible to check, e.g., that every `$name` in a
+    /// replacement template refers to a capture group before doing any
+    /// replacements.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate regex; use regex::Regex;
+    /// # fn main() {
+    /// let re = Regex::new(r"(?P<y>\d{4})-(\d{2})").unwrap();
+    /// let groups = re.group_info();
+    /// assert_eq!(groups.len(), 3);
+    /// assert_eq!(groups.index("y"), Some(1));
+    /// assert_eq!(groups.name(1), Some("y"));
+    /// assert_eq!(groups.name(2), None);
+    /// assert!(groups.index("m").is_none());
+    /// # }
+    /// ```
+    pub fn group_info(&self) -> GroupInfo {
+        let names: Vec<Option<&str>> = self.capture_names().collect();
+        let mut indices = HashMap::new();
+        for (i, name) in names.iter().enumerate() {
+            if let Some(name) = *name {
+                indices.insert(name, i);
+            }
+        }
+        GroupInfo {
+            names: names,
+            indices: indices,
+        }
+    }
+
+    /// Returns every capture group of this regex along with where it is in
+    /// the regex's pattern, ordered by index.
+    ///
+    /// A group's span is the byte range of the pattern from the group's
+    /// opening parenthesis to (and including) its closing one. The group at
+    /// index `0`, the entire match, spans the whole pattern. This is useful
+    /// for showing which part of a pattern a capture came from.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate regex; use regex::Regex;
+    /// # fn main() {
+    /// let pattern = r"(?P<y>\d{4})-(\d{2})";
+    /// let re = Regex::new(pattern).unwrap();
+    /// let spans = re.group_spans();
+    /// assert_eq!(spans.len(), 3);
+    /// assert_eq!(spans[1].name, Some("y"));
+    /// assert_eq!(&pattern[spans[1].start..spans[1].end], r"(?P<y>\d{4})");
+    /// assert_eq!(&pattern[spans[2].start..spans[2].end], r"(\d{2})");
+    /// # }
+    /// ```
+    pub fn group_spans(&self) -> Vec<GroupSpan> {
+        let pattern = self.as_str();
+        // The pattern was parsed before, so this can't fail.
+        let spans = syntax::Expr::capture_spans(pattern).unwrap();
+        let whole = Some((0, pattern.len()));
+        self.capture_names()
+            .zip(whole.into_iter().chain(spans))
+            .enumerate()
+            .map(|(i, (name, (start, end)))| GroupSpan {
+                index: i,
+                name: name,
+                start: start,
+                end: end,
+            })
+            .collect()
+    }
+
     fn alloc_captures(&self) -> Vec<Option<usize>> {
         match *self {
             Regex::Native(ref n) => vec![None; 2 * n.names.len()],
@@ -700,6 +1574,249 @@ impl<'r> Iterator for CaptureNames<'r> {
     }
 }
 
+/// The literals that every match of a regex ends with, as returned by
+/// `Regex::required_suffixes`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RequiredSuffixes {
+    /// Every match ends with one of these, which are sorted.
+    pub literals: Vec<String>,
+    /// True if every match also ends at the end of the text.
+    pub anchored: bool,
+}
+
+/// A capture group of a regex and where it is in the regex's pattern, as
+/// returned by `Regex::group_spans`.
+///
+/// `'r` is the lifetime of the compiled expression.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GroupSpan<'r> {
+    /// The index of the group.
+    pub index: usize,
+    /// The name of the group, if it has one.
+    pub name: Option<&'r str>,
+    /// The byte offset of the group's opening parenthesis in the pattern.
+    pub start: usize,
+    /// The byte offset just past the group's closing parenthesis.
+    pub end: usize,
+}
+
+/// Maps the names of a regex's capture groups to their indices and back.
+///
+/// The group at index `0` always corresponds to the entire match and is
+/// never named.
+///
+/// `'r` is the lifetime of the compiled expression.
+#[derive(Clone, Debug)]
+pub struct GroupInfo<'r> {
+    names: Vec<Option<&'r str>>,
+    indices: HashMap<&'r str, usize>,
+}
+
+impl<'r> GroupInfo<'r> {
+    /// Returns the total number of capture groups, including the group for
+    /// the entire match.
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    /// Returns true if and only if there are no capture groups.
+    ///
+    /// This is never the case for a compiled regex, which always has the
+    /// group for the entire match.
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// Returns the index of the capture group named `name`, if there is one.
+    pub fn index(&self, name: &str) -> Option<usize> {
+        self.indices.get(name).cloned()
+    }
+
+    /// Returns the name of the capture group at index `i`, if it exists and
+    /// is named.
+    pub fn name(&self, i: usize) -> Option<&'r str> {
+        self.names.get(i).and_then(|name| *name)
+    }
+
+    /// Returns true if and only if `name` refers to a capture group, either
+    /// by name or by index (as in a `$name` reference).
+    pub fn contains(&self, name: &str) -> bool {
+        match name.parse::<usize>() {
+            Ok(i) => i < self.len(),
+            Err(_) => self.indices.contains_key(name),
+        }
+    }
+
+    /// Returns the names of all capture groups in order of index.
+    ///
+    /// `None` indicates an unnamed capture group.
+    pub fn names(&self) -> &[Option<&'r str>] {
+        &self.names
+    }
+
+    /// Checks that every capture group referred to by the replacement
+    /// template `template` exists (see `Captures::expand` for the syntax).
+    ///
+    /// A reference to a group that doesn't exist expands to nothing, and a
+    /// `$` that doesn't start a reference (and isn't written as `$$`) is
+    /// kept as it is, so neither is an error when the template is used.
+    /// Both are usually mistakes, though, so this reports every one of
+    /// them, in order.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate regex; use regex::{Regex, TemplateError};
+    /// # fn main() {
+    /// let re = Regex::new(r"(?P<y>\d{4})-(\d{2})").unwrap();
+    /// let groups = re.group_info();
+    /// assert_eq!(groups.check_template("$2/$y ($$)"), Ok(()));
+    /// assert_eq!(groups.check_template("$3/${y}"), Err(vec![
+    ///     TemplateError::UnknownGroup { name: "3".to_owned(), pos: 0 },
+    ///     TemplateError::NotAReference { pos: 3 },
+    /// ]));
+    /// # }
+    /// ```
+    pub fn check_template(
+        &self,
+        template: &str,
+    ) -> Result<(), Vec<TemplateError>> {
+        let mut errors = vec![];
+        self.check_refs(template, 0, &mut errors);
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Adds an error to `errors` for each bad reference in `template`,
+    /// which starts at `offset` in the whole template.
+    fn check_refs(
+        &self,
+        template: &str,
+        offset: usize,
+        errors: &mut Vec<TemplateError>,
+    ) {
+        let cond = Regex::new(REPLACE_CONDITIONAL).unwrap();
+        let mut i = 0;
+        while let Some(found) = template[i..].find('$') {
+            let pos = i + found;
+            let rest = &template[pos + 1..];
+            if rest.starts_with('$') {
+                i = pos + 2;
+                continue;
+            }
+            if let Some(caps) = cond.captures(&template[pos..]) {
+                if caps.pos(0).unwrap().0 == 0 {
+                    let name = caps.name("name").unwrap();
+                    self.check_name(name, offset + pos, errors);
+                    // The branches (`yes` and `no`) are expanded after the
+                    // condition.
+                    for branch in 2..4 {
+                        if let Some((s, e)) = caps.pos(branch) {
+                            self.check_refs(&template[pos + s..pos + e],
+                                            offset + pos + s, errors);
+                        }
+                    }
+                    i = pos + caps.pos(0).unwrap().1;
+                    continue;
+                }
+            }
+            // A name is as in `REPLACE_EXPAND`.
+            let is_word = |c: char| {
+                c == '_' || ('a' <= c && c <= 'z') || ('A' <= c && c <= 'Z')
+            };
+            let len = match rest.chars().next() {
+                Some(c) if c.is_digit(10) => {
+                    rest.find(|c: char| !c.is_digit(10))
+                        .unwrap_or(rest.len())
+                }
+                Some(c) if is_word(c) => {
+                    rest.find(|c: char| !is_word(c) && !c.is_digit(10))
+                        .unwrap_or(rest.len())
+                }
+                _ => 0,
+            };
+            if len == 0 {
+                let pos = offset + pos;
+                errors.push(TemplateError::NotAReference { pos: pos });
+            } else {
+                self.check_name(&rest[..len], offset + pos, errors);
+            }
+            i = pos + 1 + len;
+        }
+    }
+
+    fn check_name(
+        &self,
+        name: &str,
+        pos: usize,
+        errors: &mut Vec<TemplateError>,
+    ) {
+        if !self.contains(name) {
+            errors.push(TemplateError::UnknownGroup {
+                name: name.to_owned(),
+                pos: pos,
+            });
+        }
+    }
+}
+
+/// A mistake in a replacement template, as found by
+/// `GroupInfo::check_template`.
+///
+/// Positions are byte offsets of the `$` in the template.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TemplateError {
+    /// A reference, e.g., `$name` or `${name:+yes:no}`, to a capture group
+    /// that doesn't exist.
+    UnknownGroup {
+        /// The name or index of the group, as written in the template.
+        name: String,
+        /// Where the reference starts.
+        pos: usize,
+    },
+    /// A `$` that doesn't start a reference, e.g., in `${name}`.
+    NotAReference {
+        /// Where the `$` is.
+        pos: usize,
+    },
+    /// Hints that destructuring should not be exhaustive.
+    ///
+    /// This enum may grow additional variants, so this makes sure clients
+    /// don't count on exhaustive matching. (Otherwise, adding a new variant
+    /// could break existing code.)
+    #[doc(hidden)]
+    __Nonexhaustive,
+}
+
+impl ::std::error::Error for TemplateError {
+    fn description(&self) -> &str {
+        match *self {
+            TemplateError::UnknownGroup { .. } => "unknown capture group",
+            TemplateError::NotAReference { .. } => "stray '$' in template",
+            TemplateError::__Nonexhaustive => unreachable!(),
+        }
+    }
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TemplateError::UnknownGroup { ref name, pos } => {
+                write!(f, "Unknown capture group '{}' at position {}.",
+                       name, pos)
+            }
+            TemplateError::NotAReference { pos } => {
+                write!(f, "The '$' at position {} isn't a capture group \
+                           reference. (Use '$$' for a literal '$'.)", pos)
+            }
+            TemplateError::__Nonexhaustive => unreachable!(),
+        }
+    }
+}
+
 /// NoExpand indicates literal string replacement.
 ///
 /// It can be used with `replace` and `replace_all` to do a literal
@@ -752,6 +1869,226 @@ impl<F> Replacer for F where F: FnMut(&Captures) -> String {
     }
 }
 
+/// A replacement that may keep the text of a match, or fail. It's used by
+/// `Regex::try_replace_all`.
+pub trait TryReplacer {
+    /// The error that stops a replacement.
+    type Error;
+
+    /// Returns the replacement for the match corresponding to the `caps`
+    /// capture group, or `None` to keep the text of the match.
+    fn try_replace(
+        &mut self,
+        caps: &Captures,
+    ) -> Result<Option<Cow<str>>, Self::Error>;
+}
+
+impl<F, E> TryReplacer for F
+        where F: FnMut(&Captures) -> Result<Option<String>, E> {
+    type Error = E;
+
+    fn try_replace<'a>(
+        &'a mut self,
+        caps: &Captures,
+    ) -> Result<Option<Cow<'a, str>>, E> {
+        (*self)(caps).map(|rep| rep.map(Cow::from))
+    }
+}
+
+/// A replacement closure that's also given the index of each match.
+///
+/// The closure is called with `0` for the first match it replaces, `1` for
+/// the second, and so on. This makes it possible to number the matches, or
+/// to only replace some of them, without finding all of them first.
+///
+/// # Example
+///
+/// Replace only the second match:
+///
+/// ```rust
+/// # extern crate regex; use regex::{Captures, Numbered, Regex};
+/// # fn main() {
+/// let re = Regex::new(r"\d+").unwrap();
+/// let second = Numbered::new(|i, caps: &Captures| {
+///     if i == 1 {
+///         "X".to_owned()
+///     } else {
+///         caps.at(0).unwrap().to_owned()
+///     }
+/// });
+/// let result = re.replace_all("1 22 333", second);
+/// assert_eq!(result, "1 X 333");
+/// # }
+/// ```
+pub struct Numbered<F> {
+    f: F,
+    next: usize,
+}
+
+impl<F> Numbered<F> where F: FnMut(usize, &Captures) -> String {
+    /// Wraps a closure that's called with the index and the captures of
+    /// each match.
+    pub fn new(f: F) -> Numbered<F> {
+        Numbered { f: f, next: 0 }
+    }
+}
+
+impl<F> Replacer for Numbered<F> where F: FnMut(usize, &Captures) -> String {
+    fn reg_replace<'a>(&'a mut self, caps: &Captures) -> Cow<'a, str> {
+        let i = self.next;
+        self.next += 1;
+        (self.f)(i, caps).into()
+    }
+}
+
+/// A sequence of (regex, replacement) rules applied to text in a single
+/// left-to-right pass.
+///
+/// At each position, the rule whose regex matches leftmost wins. When two
+/// rules match at the same position, the rule that was added first wins.
+/// Replaced text is never searched again, so unlike chaining several calls
+/// to `replace_all`, the outcome doesn't depend on one rule rewriting the
+/// output of another.
+///
+/// The regexes of all rules are compiled into a single program (like a
+/// `RegexSet`), so the text is only scanned once no matter how many rules
+/// there are. The matches are the same as those of one regex made of the
+/// alternation of every rule's regex.
+///
+/// `'a` is the lifetime of the replacements.
+///
+/// # Example
+///
+/// Swap two words, which can't be done with two calls to `replace_all`:
+///
+/// ```rust
+/// # extern crate regex; use regex::ReplaceSet;
+/// # fn main() {
+/// let mut rules = ReplaceSet::new(vec![("cat", "dog"), ("dog", "cat")])
+///                            .unwrap();
+/// assert_eq!(rules.replace_all("cat chases dog"), "dog chases cat");
+/// # }
+/// ```
+pub struct ReplaceSet<'a> {
+    rules: Vec<(Regex, Box<Replacer + 'a>)>,
+    /// The regexes of every rule, in order, compiled into one program.
+    prog: Program,
+}
+
+impl<'a> ReplaceSet<'a> {
+    /// Compiles each pattern and pairs it with its replacement, in order
+    /// of priority.
+    ///
+    /// If any pattern is invalid, then an error is returned.
+    pub fn new<I, S, R>(rules: I) -> Result<ReplaceSet<'a>, Error>
+            where I: IntoIterator<Item=(S, R)>,
+                  S: AsRef<str>,
+                  R: Replacer + 'a {
+        let mut compiled: Vec<(Regex, Box<Replacer + 'a>)> = vec![];
+        for (pat, rep) in rules {
+            compiled.push((try!(Regex::new(pat.as_ref())), Box::new(rep)));
+        }
+        let prog = try!(ReplaceSet::compile(&compiled));
+        Ok(ReplaceSet { rules: compiled, prog: prog })
+    }
+
+    /// Adds a rule with lower priority than every rule already added.
+    ///
+    /// The regexes of all rules are compiled again into a single program,
+    /// which fails if `re` can't be part of one (e.g., if it has a
+    /// backreference) or if the program gets too big. Then the rule isn't
+    /// added and an error is returned.
+    pub fn push<R: Replacer + 'a>(
+        &mut self,
+        re: Regex,
+        rep: R,
+    ) -> Result<(), Error> {
+        self.rules.push((re, Box::new(rep)));
+        match ReplaceSet::compile(&self.rules) {
+            Ok(prog) => {
+                self.prog = prog;
+                Ok(())
+            }
+            Err(err) => {
+                self.rules.pop();
+                Err(err)
+            }
+        }
+    }
+
+    fn compile(rules: &[(Regex, Box<Replacer + 'a>)])
+              -> Result<Program, Error> {
+        let patterns: Vec<&str> =
+            rules.iter().map(|&(ref re, _)| re.as_str()).collect();
+        Program::new_many(None, 10 * (1 << 20), &patterns)
+    }
+
+    /// Returns the number of rules.
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    /// Returns true if and only if there are no rules.
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Replaces all non-overlapping matches of every rule in `text`.
+    ///
+    /// See the documentation for `Regex::replace` for details on how to
+    /// access submatches in the replacement string.
+    pub fn replace_all(&mut self, text: &str) -> String {
+        let mut new = String::with_capacity(text.len());
+        if self.rules.is_empty() {
+            new.push_str(text);
+            return new;
+        }
+        let mut last_end = 0;
+        let mut last_match = None;
+        let mut start = 0;
+        while start <= text.len() {
+            let mut caps = [None, None];
+            let i = match self.prog.exec(&mut caps, text, start) {
+                None => break,
+                Some(i) => i,
+            };
+            let (s, e) = (caps[0].unwrap(), caps[1].unwrap());
+            // Like `find_iter`, don't accept an empty match immediately
+            // following a match.
+            if s == e && Some(s) == last_match {
+                if s >= text.len() {
+                    break;
+                }
+                start = s + text[s..].chars().next().unwrap().len_utf8();
+                continue;
+            }
+            let (ref re, ref mut rep) = self.rules[i];
+            new.push_str(&text[last_end..s]);
+            if let Some(rep) = rep.no_expand() {
+                new.push_str(&rep);
+            } else {
+                // Only the rule's own regex knows its capture groups. Its
+                // match starts at `s` too, so searching from there finds
+                // it right away.
+                let locs = if re.captures_len() == 1 {
+                    vec![Some(s), Some(e)]
+                } else {
+                    let mut locs = re.alloc_captures();
+                    exec(re, &mut locs, text, s);
+                    locs
+                };
+                let caps = Captures::new(re, text, locs);
+                new.push_str(&rep.reg_replace(&caps));
+            }
+            last_end = e;
+            last_match = Some(e);
+            start = e;
+        }
+        new.push_str(&text[last_end..]);
+        new
+    }
+}
+
 /// Yields all substrings delimited by a regular expression match.
 ///
 /// `'r` is the lifetime of the compiled expression and `'t` is the lifetime
@@ -776,42 +2113,297 @@ impl<'r, 't> Iterator for RegexSplits<'r, 't> {
                     Some(s)
                 }
             }
-            Some((s, e)) => {
-                let matched = &text[self.last..s];
-                self.last = e;
-                Some(matched)
+            Some((s, e)) => {
+                let matched = &text[self.last..s];
+                self.last = e;
+                Some(matched)
+            }
+        }
+    }
+}
+
+/// Yields the positions of all substrings delimited by a regular expression
+/// match.
+///
+/// `'r` is the lifetime of the compiled expression and `'t` is the lifetime
+/// of the string being split.
+pub struct RegexSplitsPos<'r, 't> {
+    finder: FindMatches<'r, 't>,
+    last: usize,
+}
+
+impl<'r, 't> Iterator for RegexSplitsPos<'r, 't> {
+    type Item = (usize, usize);
+
+    fn next(&mut self) -> Option<(usize, usize)> {
+        let len = self.finder.search.len();
+        match self.finder.next() {
+            None => {
+                if self.last >= len {
+                    None
+                } else {
+                    let s = self.last;
+                    self.last = len;
+                    Some((s, len))
+                }
+            }
+            Some((s, e)) => {
+                let field = (self.last, s);
+                self.last = e;
+                Some(field)
+            }
+        }
+    }
+}
+
+/// Yields all substrings delimited by a regular expression match, each paired
+/// with the delimiter that follows it.
+///
+/// `'r` is the lifetime of the compiled expression and `'t` is the lifetime
+/// of the string being split.
+pub struct RegexSplitsInclusive<'r, 't> {
+    finder: FindMatches<'r, 't>,
+    last: usize,
+}
+
+impl<'r, 't> Iterator for RegexSplitsInclusive<'r, 't> {
+    type Item = (&'t str, Option<&'t str>);
+
+    fn next(&mut self) -> Option<(&'t str, Option<&'t str>)> {
+        let text = self.finder.search;
+        match self.finder.next() {
+            None => {
+                if self.last >= text.len() {
+                    None
+                } else {
+                    let s = &text[self.last..];
+                    self.last = text.len();
+                    Some((s, None))
+                }
+            }
+            Some((s, e)) => {
+                let field = &text[self.last..s];
+                self.last = e;
+                Some((field, Some(&text[s..e])))
+            }
+        }
+    }
+}
+
+/// Yields at most `N` substrings delimited by a regular expression match.
+///
+/// The last substring will be whatever remains after splitting.
+///
+/// `'r` is the lifetime of the compiled expression and `'t` is the lifetime
+/// of the string being split.
+pub struct RegexSplitsN<'r, 't> {
+    splits: RegexSplits<'r, 't>,
+    cur: usize,
+    limit: usize,
+}
+
+impl<'r, 't> Iterator for RegexSplitsN<'r, 't> {
+    type Item = &'t str;
+
+    fn next(&mut self) -> Option<&'t str> {
+        let text = self.splits.finder.search;
+        if self.cur >= self.limit {
+            None
+        } else {
+            self.cur += 1;
+            if self.cur >= self.limit {
+                Some(&text[self.splits.last..])
+            } else {
+                self.splits.next()
+            }
+        }
+    }
+}
+
+/// How `Regex::split_with` splits text.
+///
+/// By default, every substring is kept, including empty ones at either end,
+/// and there's no limit on the number of substrings.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SplitOptions {
+    drop_leading_empty: bool,
+    drop_trailing_empty: bool,
+    max_fields: usize,
+}
+
+impl SplitOptions {
+    /// Creates options that keep every substring.
+    pub fn new() -> SplitOptions {
+        SplitOptions::default()
+    }
+
+    /// Sets whether the empty substrings before the first non-empty one are
+    /// dropped, e.g., when splitting on whitespace that may start the text.
+    pub fn drop_leading_empty(mut self, yes: bool) -> SplitOptions {
+        self.drop_leading_empty = yes;
+        self
+    }
+
+    /// Sets whether the empty substrings after the last non-empty one are
+    /// dropped.
+    pub fn drop_trailing_empty(mut self, yes: bool) -> SplitOptions {
+        self.drop_trailing_empty = yes;
+        self
+    }
+
+    /// Sets the most substrings to split the text into. The last one is
+    /// the rest of the text, delimiters included. With `0` (the default),
+    /// there's no limit.
+    ///
+    /// Dropped substrings don't count against the limit.
+    pub fn max_fields(mut self, limit: usize) -> SplitOptions {
+        self.max_fields = limit;
+        self
+    }
+}
+
+/// Yields the substrings delimited by a regular expression match, as
+/// `SplitOptions` say.
+///
+/// `'r` is the lifetime of the compiled expression and `'t` is the lifetime
+/// of the string being split.
+pub struct RegexSplitsWith<'r, 't> {
+    finder: FindMatches<'r, 't>,
+    last: usize,
+    opts: SplitOptions,
+    /// The number of substrings yielded or held back so far.
+    fields: usize,
+    /// Whether a non-empty substring was found yet.
+    started: bool,
+    /// The number of empty substrings held back until it's known whether a
+    /// non-empty one follows them.
+    empties: usize,
+    /// The non-empty substring that follows the held back empty ones.
+    held: Option<&'t str>,
+    done: bool,
+}
+
+impl<'r, 't> RegexSplitsWith<'r, 't> {
+    /// Returns the next substring, dropped or not.
+    fn next_field(&mut self) -> Option<&'t str> {
+        let text = self.finder.search;
+        if self.done {
+            return None;
+        }
+        let max = self.opts.max_fields;
+        if max > 0 && self.fields + 1 >= max {
+            self.done = true;
+            return Some(&text[self.last..]);
+        }
+        match self.finder.next() {
+            None => {
+                self.done = true;
+                Some(&text[self.last..])
+            }
+            Some((s, e)) => {
+                let field = &text[self.last..s];
+                self.last = e;
+                Some(field)
+            }
+        }
+    }
+}
+
+impl<'r, 't> Iterator for RegexSplitsWith<'r, 't> {
+    type Item = &'t str;
+
+    fn next(&mut self) -> Option<&'t str> {
+        if self.empties > 0 {
+            self.empties -= 1;
+            return Some("");
+        }
+        if let Some(field) = self.held.take() {
+            return Some(field);
+        }
+        loop {
+            let field = match self.next_field() {
+                None => return None,
+                Some(field) => field,
+            };
+            if field.is_empty() {
+                if !self.started && self.opts.drop_leading_empty {
+                    continue;
+                }
+                if self.opts.drop_trailing_empty {
+                    self.fields += 1;
+                    self.empties += 1;
+                    continue;
+                }
+            }
+            self.started = true;
+            self.fields += 1;
+            if self.empties > 0 {
+                self.held = Some(field);
+                self.empties -= 1;
+                return Some("");
             }
+            return Some(field);
         }
     }
 }
 
-/// Yields at most `N` substrings delimited by a regular expression match.
-///
-/// The last substring will be whatever remains after splitting.
+/// A single match of a capture group, with both its text and its position.
 ///
-/// `'r` is the lifetime of the compiled expression and `'t` is the lifetime
-/// of the string being split.
-pub struct RegexSplitsN<'r, 't> {
-    splits: RegexSplits<'r, 't>,
-    cur: usize,
-    limit: usize,
+/// `'t` is the lifetime of the matched text.
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub struct Match<'t> {
+    text: &'t str,
+    start: usize,
+    end: usize,
 }
 
-impl<'r, 't> Iterator for RegexSplitsN<'r, 't> {
-    type Item = &'t str;
+impl<'t> Match<'t> {
+    /// Returns the byte index in the searched text where the match starts.
+    pub fn start(&self) -> usize {
+        self.start
+    }
 
-    fn next(&mut self) -> Option<&'t str> {
-        let text = self.splits.finder.search;
-        if self.cur >= self.limit {
-            None
-        } else {
-            self.cur += 1;
-            if self.cur >= self.limit {
-                Some(&text[self.splits.last..])
-            } else {
-                self.splits.next()
-            }
-        }
+    /// Returns the byte index in the searched text where the match ends.
+    pub fn end(&self) -> usize {
+        self.end
+    }
+
+    /// Returns the start and end byte indices of the match.
+    pub fn pos(&self) -> (usize, usize) {
+        (self.start, self.end)
+    }
+
+    /// Returns the matched text.
+    pub fn as_str(&self) -> &'t str {
+        &self.text[self.start..self.end]
+    }
+}
+
+/// Shows the position of the match followed by its text (truncated if it's
+/// long), e.g., `Match(2..4 "42")`.
+impl<'t> fmt::Debug for Match<'t> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "Match("));
+        try!(fmt_span(f, self.text, self.start, self.end));
+        write!(f, ")")
+    }
+}
+
+/// The maximum number of characters of matched text shown by `Debug`.
+const DEBUG_TEXT_LIMIT: usize = 32;
+
+/// Writes `s..e` followed by the text at that position, truncated to
+/// `DEBUG_TEXT_LIMIT` characters.
+fn fmt_span(
+    f: &mut fmt::Formatter,
+    text: &str,
+    s: usize,
+    e: usize,
+) -> fmt::Result {
+    let matched = &text[s..e];
+    match matched.char_indices().nth(DEBUG_TEXT_LIMIT) {
+        None => write!(f, "{}..{} {:?}", s, e, matched),
+        Some((end, _)) => write!(f, "{}..{} {:?}...", s, e, &matched[..end]),
     }
 }
 
@@ -830,6 +2422,9 @@ pub struct Captures<'t> {
     text: &'t str,
     locs: Vec<Option<usize>>,
     named: Option<HashMap<String, usize>>,
+    /// Every `(slot, position)` saved on the way to the match, in order, if
+    /// the captures were found by `Regex::captures_with_history`.
+    history: Option<Vec<(usize, usize)>>,
 }
 
 impl<'t> Captures<'t> {
@@ -854,6 +2449,7 @@ impl<'t> Captures<'t> {
             text: search,
             locs: locs,
             named: named,
+            history: None,
         }
     }
 
@@ -871,6 +2467,34 @@ impl<'t> Captures<'t> {
         Some((self.locs[s].unwrap(), self.locs[e].unwrap()))
     }
 
+    /// Returns the start and end positions of every span matched by the
+    /// Nth capture group, in order.
+    ///
+    /// For captures found by `Regex::captures_with_history`, a group inside
+    /// a repetition has a span for every iteration it took part in.
+    /// Otherwise, there's at most one span, the same one `pos` returns. The
+    /// result is empty if `i` is not a valid capture group or if the group
+    /// did not match anything.
+    pub fn all(&self, i: usize) -> Vec<(usize, usize)> {
+        let history = match self.history {
+            None => return self.pos(i).into_iter().collect(),
+            Some(ref history) => history,
+        };
+        let (s, e) = (i * 2, i * 2 + 1);
+        let mut spans = vec![];
+        let mut start = None;
+        for &(slot, pos) in history {
+            if slot == s {
+                start = Some(pos);
+            } else if slot == e {
+                if let Some(start) = start.take() {
+                    spans.push((start, pos));
+                }
+            }
+        }
+        spans
+    }
+
     /// Returns the matched string for the capture group `i`.  If `i` isn't
     /// a valid capture group or didn't match anything, then `None` is
     /// returned.
@@ -896,6 +2520,33 @@ impl<'t> Captures<'t> {
         }
     }
 
+    /// Returns the match for the capture group named `name`, which carries
+    /// both the matched text and its position. If `name` isn't a valid
+    /// capture group or didn't match anything, then `None` is returned.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate regex; use regex::Regex;
+    /// # fn main() {
+    /// let re = Regex::new(r"(?P<key>\w+)=(?P<val>\w+)").unwrap();
+    /// let caps = re.captures("set x=1").unwrap();
+    /// let val = caps.name_match("val").unwrap();
+    /// assert_eq!(val.as_str(), "1");
+    /// assert_eq!(val.pos(), (6, 7));
+    /// # }
+    /// ```
+    pub fn name_match(&self, name: &str) -> Option<Match<'t>> {
+        let i = match self.named {
+            None => return None,
+            Some(ref h) => match h.get(name) {
+                None => return None,
+                Some(&i) => i,
+            },
+        };
+        self.pos(i).map(|(s, e)| Match { text: self.text, start: s, end: e })
+    }
+
     /// Creates an iterator of all the capture groups in order of appearance
     /// in the regular expression.
     pub fn iter(&'t self) -> SubCaptures<'t> {
@@ -916,6 +2567,43 @@ impl<'t> Captures<'t> {
         SubCapturesNamed { caps: self, inner: self.named.as_ref().map(|n| n.iter()) }
     }
 
+    /// Creates an iterator over every capture group in order of appearance
+    /// in the regular expression. Each element is a tuple of the group
+    /// index, the group name (if it has one) and the group's byte positions
+    /// (if it participated in the match).
+    ///
+    /// Unlike `iter_named`, unnamed and non-participating groups are included,
+    /// which makes this useful for rendering a match without knowing anything
+    /// about the pattern that produced it.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate regex; use regex::Regex;
+    /// # fn main() {
+    /// let re = Regex::new(r"(?P<y>\d{4})-(\d{2})(-x)?").unwrap();
+    /// let caps = re.captures("2015-03").unwrap();
+    /// let groups: Vec<_> = caps.iter_groups().collect();
+    /// assert_eq!(groups, vec![
+    ///     (0, None, Some((0, 7))),
+    ///     (1, Some("y"), Some((0, 4))),
+    ///     (2, None, Some((5, 7))),
+    ///     (3, None, None),
+    /// ]);
+    /// # }
+    /// ```
+    pub fn iter_groups(&'t self) -> SubCapturesGroups<'t> {
+        let mut names = vec![None; self.len()];
+        if let Some(ref named) = self.named {
+            for (name, &i) in named {
+                if i < names.len() {
+                    names[i] = Some(&**name);
+                }
+            }
+        }
+        SubCapturesGroups { idx: 0, caps: self, names: names }
+    }
+
     /// Expands all instances of `$name` in `text` to the corresponding capture
     /// group `name`.
     ///
@@ -927,11 +2615,46 @@ impl<'t> Captures<'t> {
     /// If `name` isn't a valid capture group (whether the name doesn't exist or
     /// isn't a valid index), then it is replaced with the empty string.
     ///
+    /// A conditional segment of the form `${name:+yes:no}` is replaced with
+    /// `yes` if the capture group `name` participated in the match and with
+    /// `no` otherwise. The `:no` part may be left out, in which case nothing
+    /// is inserted when the group didn't participate. `yes` can't contain
+    /// `:` or `}` and `no` can't contain `}`. Both may refer to capture
+    /// groups with `$name`.
+    ///
     /// To write a literal `$` use `$$`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate regex; use regex::Regex;
+    /// # fn main() {
+    /// let re = Regex::new(r"(\w+)(?:@(\w+))?").unwrap();
+    /// let tmpl = "$1${2:+ at $2:, no host}";
+    /// let caps = re.captures("me@home").unwrap();
+    /// assert_eq!(caps.expand(tmpl), "me at home");
+    /// let caps = re.captures("me").unwrap();
+    /// assert_eq!(caps.expand(tmpl), "me, no host");
+    /// # }
+    /// ```
     pub fn expand(&self, text: &str) -> String {
+        let re = Regex::new(REPLACE_CONDITIONAL).unwrap();
+        let text = re.replace_all(text, |refs: &Captures| -> String {
+            let name = match refs.name("name") {
+                // This is an escaped `$`, which is handled below.
+                None => return "$$".to_owned(),
+                Some(name) => name,
+            };
+            let participated = match name.parse::<usize>() {
+                Err(_) => self.name(name).is_some(),
+                Ok(i) => self.at(i).is_some(),
+            };
+            let branch = if participated { "yes" } else { "no" };
+            refs.name(branch).unwrap_or("").to_owned()
+        });
         // How evil can you get?
         let re = Regex::new(REPLACE_EXPAND).unwrap();
-        let text = re.replace_all(text, |refs: &Captures| -> String {
+        let text = re.replace_all(&text, |refs: &Captures| -> String {
             let before = refs.name("before").unwrap_or("");
             let name = refs.name("name").unwrap_or("");
             format!("{}{}", before, match name.parse::<usize>() {
@@ -943,6 +2666,65 @@ impl<'t> Captures<'t> {
         re.replace_all(&text, NoExpand("$"))
     }
 
+    /// Parses the text matched by the capture group named `name` with
+    /// `FromStr`.
+    ///
+    /// If the group doesn't exist or didn't participate in the match, then
+    /// `CaptureError::Missing` is returned. If the conversion fails, then
+    /// `CaptureError::Invalid` is returned with the group name and the
+    /// conversion error message.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate regex; use regex::Regex;
+    /// # fn main() {
+    /// let re = Regex::new(r"(?P<x>\d+),(?P<y>\d+)").unwrap();
+    /// let caps = re.captures("12,34").unwrap();
+    /// assert_eq!(caps.parse::<u32>("x"), Ok(12));
+    /// assert_eq!(caps.parse::<u32>("y"), Ok(34));
+    /// assert!(caps.parse::<u32>("z").is_err());
+    /// # }
+    /// ```
+    pub fn parse<T>(&self, name: &str) -> Result<T, CaptureError>
+            where T: FromStr, T::Err: fmt::Display {
+        match self.name(name) {
+            None => Err(CaptureError::Missing(name.to_owned())),
+            Some(text) => text.parse().map_err(|err: T::Err| {
+                CaptureError::Invalid(name.to_owned(), err.to_string())
+            }),
+        }
+    }
+
+    /// Converts these captures into a value of any type implementing
+    /// `FromCaptures`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate regex;
+    /// # use regex::{Captures, CaptureError, FromCaptures, Regex};
+    /// # fn main() {
+    /// struct Point { x: i32, y: i32 }
+    ///
+    /// impl FromCaptures for Point {
+    ///     fn from_captures(caps: &Captures) -> Result<Point, CaptureError> {
+    ///         Ok(Point {
+    ///             x: try!(caps.parse("x")),
+    ///             y: try!(caps.parse("y")),
+    ///         })
+    ///     }
+    /// }
+    ///
+    /// let re = Regex::new(r"\((?P<x>-?\d+), (?P<y>-?\d+)\)").unwrap();
+    /// let p: Point = re.captures("at (3, -4)").unwrap().extract().unwrap();
+    /// assert_eq!((p.x, p.y), (3, -4));
+    /// # }
+    /// ```
+    pub fn extract<T: FromCaptures>(&self) -> Result<T, CaptureError> {
+        T::from_captures(self)
+    }
+
     /// Returns the number of captured groups.
     #[inline]
     pub fn len(&self) -> usize { self.locs.len() / 2 }
@@ -952,6 +2734,57 @@ impl<'t> Captures<'t> {
     pub fn is_empty(&self) -> bool { self.len() == 0 }
 }
 
+/// A type that can be built from the capture groups of a single match.
+///
+/// Implementations usually call `Captures::parse` once for each field, so
+/// that a failed conversion reports the name of the offending group.
+pub trait FromCaptures: Sized {
+    /// Builds a value from the capture groups of a single match.
+    fn from_captures(caps: &Captures) -> Result<Self, CaptureError>;
+}
+
+/// An error that occurred while converting a capture group into a value.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CaptureError {
+    /// The named group doesn't exist or didn't participate in the match.
+    Missing(String),
+    /// The text of the named group could not be converted. The first field
+    /// is the group name and the second is the conversion error message.
+    Invalid(String, String),
+    /// Hints that destructuring should not be exhaustive.
+    ///
+    /// This enum may grow additional variants, so this makes sure clients
+    /// don't count on exhaustive matching. (Otherwise, adding a new variant
+    /// could break existing code.)
+    #[doc(hidden)]
+    __Nonexhaustive,
+}
+
+impl ::std::error::Error for CaptureError {
+    fn description(&self) -> &str {
+        match *self {
+            CaptureError::Missing(_) => "capture group did not match",
+            CaptureError::Invalid(_, _) => "invalid capture group value",
+            CaptureError::__Nonexhaustive => unreachable!(),
+        }
+    }
+}
+
+impl fmt::Display for CaptureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CaptureError::Missing(ref name) => {
+                write!(f, "Capture group '{}' did not match.", name)
+            }
+            CaptureError::Invalid(ref name, ref err) => {
+                write!(f, "Capture group '{}' has an invalid value: {}",
+                       name, err)
+            }
+            CaptureError::__Nonexhaustive => unreachable!(),
+        }
+    }
+}
+
 /// Get a group by index.
 ///
 /// # Panics
@@ -983,6 +2816,30 @@ impl<'t> Index<&'t str> for Captures<'t> {
 
 }
 
+/// Shows every capture group with its index, its name (if any), its position
+/// and its text (truncated if it's long), e.g.,
+/// `Captures({0: 0..3 "a=1", 1 (key): 0..1 "a", 2: None})`.
+impl<'t> fmt::Debug for Captures<'t> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "Captures({{"));
+        for (i, name, pos) in self.iter_groups() {
+            if i > 0 {
+                try!(write!(f, ", "));
+            }
+            try!(write!(f, "{}", i));
+            if let Some(name) = name {
+                try!(write!(f, " ({})", name));
+            }
+            try!(write!(f, ": "));
+            match pos {
+                None => try!(write!(f, "None")),
+                Some((s, e)) => try!(fmt_span(f, self.text, s, e)),
+            }
+        }
+        write!(f, "}})")
+    }
+}
+
 /// An iterator over capture groups for a particular match of a regular
 /// expression.
 ///
@@ -1049,6 +2906,37 @@ impl<'t> Iterator for SubCapturesNamed<'t> {
     }
 }
 
+/// An iterator over every capture group for a particular match of a regular
+/// expression, as a tuple with the group index, name and position.
+///
+/// Positions are byte indices in terms of the original string matched.
+///
+/// `'t` is the lifetime of the matched text.
+pub struct SubCapturesGroups<'t> {
+    idx: usize,
+    caps: &'t Captures<'t>,
+    names: Vec<Option<&'t str>>,
+}
+
+impl<'t> Iterator for SubCapturesGroups<'t> {
+    type Item = (usize, Option<&'t str>, Option<(usize, usize)>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.idx < self.caps.len() {
+            let i = self.idx;
+            self.idx += 1;
+            Some((i, self.names[i], self.caps.pos(i)))
+        } else {
+            None
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.caps.len() - self.idx;
+        (len, Some(len))
+    }
+}
+
 /// An iterator that yields all non-overlapping capture groups matching a
 /// particular regular expression.
 ///
@@ -1138,6 +3026,136 @@ impl<'r, 't> Iterator for FindMatches<'r, 't> {
     }
 }
 
+impl<'r, 't> FindMatches<'r, 't> {
+    /// Limits the work done by each call to `next` to `budget` steps, where
+    /// a step is the work done at one position in the text.
+    ///
+    /// When the budget runs out before the next match is found, `next`
+    /// returns `MatchStep::Pending` instead of blocking until the search is
+    /// over. The following call to `next` resumes the search where it
+    /// stopped. This makes it possible to spread a long search over several
+    /// time slices, e.g., frames of an interactive application.
+    ///
+    /// A `budget` of `0` is treated as `1`, so that every call makes
+    /// progress.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate regex; use regex::{MatchStep, Regex};
+    /// # fn main() {
+    /// let re = Regex::new(r"\d+").unwrap();
+    /// let mut it = re.find_iter("a long line 42").with_budget(4);
+    /// let mut found = vec![];
+    /// while let Some(step) = it.next() {
+    ///     match step {
+    ///         MatchStep::Found(s, e) => found.push((s, e)),
+    ///         // Do something else for a while, then continue.
+    ///         MatchStep::Pending => {}
+    ///     }
+    /// }
+    /// assert_eq!(found, vec![(12, 14)]);
+    /// # }
+    /// ```
+    pub fn with_budget(self, budget: usize) -> BudgetedMatches<'r, 't> {
+        BudgetedMatches {
+            it: self,
+            budget: cmp::max(1, budget),
+            threads: None,
+            suspended: None,
+            caps: [None, None],
+        }
+    }
+}
+
+/// Scratch memory for searching with a regex without allocating.
+///
+/// It's returned by `Regex::scratch` and can only be used with the regex
+/// that returned it.
+#[derive(Debug)]
+pub struct Scratch {
+    threads: NfaThreads,
+    num_insts: usize,
+}
+
+/// The result of a call to `next` on a `BudgetedMatches` iterator.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MatchStep {
+    /// The start and end byte indices of the next match.
+    Found(usize, usize),
+    /// The budget ran out before the next match was found. The search
+    /// continues with the next call to `next`.
+    Pending,
+}
+
+/// An iterator over all non-overlapping matches of a regex that does a
+/// limited amount of work each time it is advanced.
+///
+/// Only regexes compiled at runtime respect the budget. A regex compiled
+/// with the `regex!` macro, or one with a backreference, always runs until it
+/// finds the next match.
+///
+/// `'r` is the lifetime of the compiled expression and `'t` is the lifetime
+/// of the matched string.
+pub struct BudgetedMatches<'r, 't> {
+    it: FindMatches<'r, 't>,
+    budget: usize,
+    threads: Option<PoolGuard<'r, NfaThreads>>,
+    suspended: Option<NfaSuspended>,
+    caps: [Option<usize>; 2],
+}
+
+impl<'r, 't> Iterator for BudgetedMatches<'r, 't> {
+    type Item = MatchStep;
+
+    fn next(&mut self) -> Option<MatchStep> {
+        let prog = match *self.it.re {
+            // The budget is counted by the NFA, which can't run
+            // backreferences.
+            Regex::Dynamic(ref prog) if !prog.backrefs => prog,
+            _ => return self.it.next().map(|(s, e)| MatchStep::Found(s, e)),
+        };
+        let search = self.it.search;
+        if self.it.last_end > search.len() {
+            return None;
+        }
+        if self.threads.is_none() {
+            self.threads = Some(prog.nfa_threads.get());
+        }
+        let q = self.threads.as_mut().unwrap();
+        let res = Nfa::exec_budget(prog, q, &mut self.caps, search,
+                                   self.it.last_end, self.suspended.take(),
+                                   self.budget);
+        match res {
+            Err(suspended) => {
+                self.suspended = Some(suspended);
+                return Some(MatchStep::Pending);
+            }
+            Ok(None) => {
+                self.it.last_end = search.len() + 1;
+                return None;
+            }
+            Ok(Some(_)) => {}
+        }
+        let (s, e) = (self.caps[0].unwrap(), self.caps[1].unwrap());
+
+        // Don't accept empty matches immediately following a match.
+        // (This costs a step, so report it as pending.)
+        if e == s && Some(self.it.last_end) == self.it.last_match {
+            if self.it.last_end >= search.len() {
+                self.it.last_end = search.len() + 1;
+                return None;
+            }
+            self.it.last_end += search[self.it.last_end..].chars()
+                                    .next().unwrap().len_utf8();
+            return Some(MatchStep::Pending);
+        }
+        self.it.last_end = e;
+        self.it.last_match = Some(e);
+        Some(MatchStep::Found(s, e))
+    }
+}
+
 #[cfg(feature = "pattern")]
 pub struct RegexSearcher<'r, 't> {
     it: FindMatches<'r, 't>,
@@ -1200,13 +3218,75 @@ unsafe impl<'r, 't> Searcher<'t> for RegexSearcher<'r, 't> {
 fn exec(re: &Regex, caps: &mut CaptureIdxs, text: &str, start: usize) -> bool {
     match *re {
         Regex::Native(ExNative { ref prog, .. }) => (*prog)(caps, text, start),
-        Regex::Dynamic(ref prog) => prog.exec(caps, text, start),
+        Regex::Dynamic(ref prog) => prog.exec(caps, text, start).is_some(),
+    }
+}
+
+// A native regex has no program to anchor, so the first anchored search
+// with one on each thread compiles its pattern. There's only ever one entry
+// per use of `regex!`.
+thread_local!(static NATIVE_PROGRAMS: RefCell<HashMap<&'static str, Regex>> =
+    RefCell::new(HashMap::new()));
+
+/// Returns true if there is a match starting at the beginning of `text` (if
+/// `start` is set) and ending at its end (if `end` is set).
+///
+/// The search uses the anchored copies built when the regex was compiled.
+fn exec_anchored(re: &Regex, text: &str, start: bool, end: bool) -> bool {
+    let prog = match *re {
+        Regex::Native(ExNative { original, .. }) => {
+            return NATIVE_PROGRAMS.with(|progs| {
+                let mut progs = progs.borrow_mut();
+                let re = progs.entry(original).or_insert_with(|| {
+                    Regex::new(original).unwrap()
+                });
+                exec_anchored(re, text, start, end)
+            });
+        }
+        Regex::Dynamic(ref prog) => prog,
+    };
+    let copies = prog.anchored_copies.as_ref()
+                     .expect("a single regex has anchored copies");
+    let prog = match (start, end) {
+        (true, true) => &copies.both,
+        (true, false) => &copies.start,
+        (false, true) => &copies.end,
+        (false, false) => return exec(re, &mut [], text, 0),
+    };
+    prog.exec(&mut [], text, 0).is_some()
+}
+
+/// Searches with the scratch memory given instead of allocating.
+fn exec_with(
+    re: &Regex,
+    scratch: &mut Scratch,
+    caps: &mut CaptureIdxs,
+    text: &str,
+    start: usize,
+) -> bool {
+    match *re {
+        Regex::Native(ExNative { ref prog, .. }) => {
+            (*prog)(caps, text, start)
+        }
+        Regex::Dynamic(ref prog) => {
+            assert!(scratch.num_insts == prog.insts.len(),
+                    "scratch memory belongs to a different regex");
+            let capacity = scratch.threads.capacity();
+            let matched = prog.exec_with(&mut scratch.threads, caps,
+                                         text, start).is_some();
+            debug_assert!(scratch.threads.capacity() == capacity,
+                          "scratch memory grew during a search");
+            matched
+        }
     }
 }
 
 #[cfg(test)]
 mod test {
-    use super::{NoExpand, Regex};
+    use Error;
+    use re_builder::{Engine, RegexBuilder};
+    use set::RegexSet;
+    use super::{MatchStep, NoExpand, Regex};
 
     #[test]
     fn test_simple_expand() {
@@ -1262,4 +3342,153 @@ mod test {
         let cap = re.captures("abc").unwrap();
         let _ = cap["bad name"];
     }
+
+    #[test]
+    fn test_match_only_empty_loop() {
+        // Without captures, nothing stops the backtracker from running the
+        // body of this loop again and again at the same position.
+        let backtrack = RegexBuilder::new("(a?)+?b")
+                                     .engine(Engine::Backtrack)
+                                     .compile().unwrap();
+        for re in &[backtrack, Regex::new("(a?)+?b").unwrap()] {
+            assert!(!re.is_match("a"));
+            assert_eq!(re.find("a"), None);
+            assert_eq!(re.find("aab"), Some((0, 3)));
+        }
+    }
+
+    fn backrefs(re: &str) -> Regex {
+        RegexBuilder::new(re).backreferences(true).compile().unwrap()
+    }
+
+    #[test]
+    fn test_backreference() {
+        let re = backrefs(r"<(?P<t>\w+)>[^<]*</(?P=t)>");
+        assert_eq!(re.find("<b>x</i> <i>y</i>"), Some((9, 17)));
+        assert!(!re.is_match("<b>x</i>"));
+
+        let re = backrefs(r"\b(?P<w>\w+) \k<w>\b");
+        let caps = re.captures("it is the the end").unwrap();
+        assert_eq!(caps.pos(0), Some((6, 13)));
+        assert_eq!(caps.name("w"), Some("the"));
+        let found: Vec<_> = re.find_iter("a a b c c c").collect();
+        assert_eq!(found, vec![(0, 3), (6, 9)]);
+        assert_eq!(re.count_matches("a a b c c c"), 2);
+
+        // A backreference matches the group's text, not its expression.
+        let re = backrefs(r"(?P<a>[a-z]+)\k<a>");
+        assert_eq!(re.find("abcABC xyzabab"), Some((10, 14)));
+        let re = backrefs(r"(?P<a>\w+)(?i:\k<a>)");
+        assert_eq!(re.find("abcABC"), Some((0, 6)));
+        assert_eq!(re.find("éÉ"), Some((0, 4)));
+
+        // A group that didn't match can't be matched again.
+        let re = backrefs(r"(?:(?P<a>x)|y)\k<a>");
+        assert_eq!(re.find("yy"), None);
+        assert_eq!(re.find("yxx"), Some((1, 3)));
+    }
+
+    #[test]
+    fn test_backreference_empty_loop() {
+        // The group matches nothing, so without the visited set, only the
+        // check for empty loops stops these.
+        let res = [r"(?P<a>a*)(?:\k<a>)*b", r"(?P<a>a*)(?:\k<a>)*?b",
+                   r"(?P<a>a?)(?:(?:\k<a>)*)*b"];
+        for re in &res {
+            let re = backrefs(re);
+            assert_eq!(re.find("xb"), Some((1, 2)));
+            assert_eq!(re.find("x"), None);
+        }
+        let re = backrefs(r"^(?P<a>a*)(?:\k<a>)*$");
+        assert!(re.is_match("aaaa"));
+        assert!(re.is_match(""));
+        assert!(!re.is_match("aaab"));
+    }
+
+    #[test]
+    fn test_backreference_scratch() {
+        let re = backrefs(r"(?P<a>\d)\k<a>");
+        let mut scratch = re.scratch();
+        assert!(re.is_match_with("1 22", &mut scratch));
+        assert_eq!(re.find_at_with("1 22 33", 4, &mut scratch),
+                   Some((5, 7)));
+        let found: Vec<_> = re.find_iter("11 2 33").with_budget(1).collect();
+        assert_eq!(found, vec![MatchStep::Found(0, 2),
+                               MatchStep::Found(5, 7)]);
+        let profiles = re.profile("1 22");
+        assert_eq!(profiles.len(), 1);
+        assert_eq!(profiles[0].engine, Engine::Backtrack);
+        assert_eq!(profiles[0].matched, Some((2, 4)));
+    }
+
+    #[test]
+    fn test_backreference_unsupported() {
+        let re = r"(?P<a>.)\k<a>";
+        let builders = vec![
+            RegexBuilder::new(re),
+            RegexBuilder::new(re).backreferences(true).engine(Engine::Nfa),
+            RegexBuilder::new(re).backreferences(true).longest_match(true),
+            RegexBuilder::new(re).backreferences(true)
+                                 .posix_submatches(true),
+        ];
+        for b in builders {
+            match b.compile() {
+                Err(Error::Unsupported(_)) => {}
+                r => panic!("expected an unsupported feature, got {:?}", r),
+            }
+        }
+        for r in vec![Regex::new(re).map(|_| ()),
+                      RegexSet::new(&[re]).map(|_| ())] {
+            match r {
+                Err(Error::Unsupported(_)) => {}
+                r => panic!("expected an unsupported feature, got {:?}", r),
+            }
+        }
+        assert!(RegexBuilder::new(re).backreferences(true)
+                                     .engine(Engine::Backtrack)
+                                     .compile().is_ok());
+    }
+
+    #[test]
+    fn test_backreference_limit() {
+        // Every way of splitting the a's is tried before failing, so this
+        // takes exponential time without a bound.
+        let re = r"(?P<x>x)?(?:a|aa)*c(?P=x)";
+        let mut text: String = ::std::iter::repeat('a').take(64).collect();
+        text.push_str("bc");
+        let re = RegexBuilder::new(re).backreferences(true)
+                                      .backtrack_limit(1 << 10)
+                                      .compile().unwrap();
+        assert_eq!(re.find(&text), None);
+        assert_eq!(re.find("xaac x"), None);
+        assert_eq!(re.find("xaacx"), Some((0, 5)));
+    }
+
+    #[test]
+    fn test_partition() {
+        let text = "ab cd\n\nxyz ab\nfoo  bar\n   \nab";
+        let res = [r"\w+", r"\b", r"(?m)^\w*$", "a|b *", r"[^x\n]*$", ""];
+        for re in &res {
+            let re = Regex::new(re).unwrap();
+            let all: Vec<_> = re.find_iter(text).collect();
+            for size in 0..text.len() + 2 {
+                let chunks = re.partition(text, size).unwrap();
+                let mut found = vec![];
+                for (i, &(s, e)) in chunks.iter().enumerate() {
+                    let last = i + 1 == chunks.len();
+                    found.extend(re.find_iter(&text[s..e])
+                                   .map(|(ms, me)| (s + ms, s + me))
+                                   .filter(|&(ms, me)| {
+                                       last || ms < me || me < e
+                                   }));
+                }
+                assert_eq!(all, found);
+            }
+        }
+        assert_eq!(Regex::new("a").unwrap().partition("", 4),
+                   Some(vec![(0, 0)]));
+        assert!(Regex::new(r"\s").unwrap().partition("a", 1).is_none());
+        assert!(Regex::new(r"^a").unwrap().partition("a", 1).is_none());
+        assert!(Regex::new(r"a$").unwrap().partition("a", 1).is_some());
+    }
 }
@@ -0,0 +1,399 @@
+// Copyright 2016 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+#[cfg(feature = "trace")]
+use std::sync::Arc;
+
+use compile::Compiler;
+use program::{MatchEngine, Program, ProgramOptions};
+use re::{Error, Regex};
+#[cfg(feature = "trace")]
+use trace::TraceEvent;
+
+/// The matching engine used to search with a regex.
+///
+/// Every engine finds the same matches; they only differ in how fast they
+/// are and how much memory they use. Normally, an engine is picked
+/// automatically for every search, depending on the regex, the text and
+/// what the search asks for. Pinning one with `RegexBuilder::engine` is
+/// mostly useful for testing and benchmarking.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Engine {
+    /// Pick an engine for every search automatically.
+    Automatic,
+    /// A simulation of the regex's NFA, which keeps track of every way the
+    /// regex could match at once. It works on any regex and text.
+    Nfa,
+    /// A bounded backtracking engine, which tries one way the regex could
+    /// match at a time but never tries the same thing twice. It's usually
+    /// faster than the NFA, but it needs memory proportional to the size of
+    /// the regex times the length of the text, so it's normally only used
+    /// for small regexes on short texts.
+    Backtrack,
+}
+
+/// A configurable builder for a regular expression.
+///
+/// A builder can be used to configure how the regex is compiled and
+/// searched, beyond what the syntax of the regex itself can express. Every
+/// option has the same default value as with `Regex::new`.
+///
+/// # Example
+///
+/// ```rust
+/// # extern crate regex; use regex::RegexBuilder;
+/// # fn main() {
+/// let re = RegexBuilder::new(r"\bfoo\w+")
+///     .size_limit(1 << 16)
+///     .compile()
+///     .unwrap();
+/// assert_eq!(re.find("a foobar"), Some((2, 8)));
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct RegexBuilder {
+    pattern: String,
+    size_limit: usize,
+    options: ProgramOptions,
+}
+
+impl RegexBuilder {
+    /// Creates a new builder for the given regular expression.
+    ///
+    /// The regex isn't parsed until `compile` is called.
+    pub fn new(pattern: &str) -> RegexBuilder {
+        RegexBuilder {
+            pattern: pattern.to_owned(),
+            size_limit: 10 * (1 << 20),
+            options: ProgramOptions::default(),
+        }
+    }
+
+    /// Compiles the regular expression with the options set so far.
+    ///
+    /// If the expression is invalid or exceeds the size limit, then an error
+    /// is returned.
+    pub fn compile(&self) -> Result<Regex, Error> {
+        let mut compiler = Compiler::new(self.size_limit);
+        Program::with_compiler(&mut compiler, &self.options, &[&self.pattern])
+            .map(Regex::Dynamic)
+    }
+
+    /// Sets the size limit of the compiled regex. See
+    /// `Regex::with_size_limit`.
+    ///
+    /// The default is 10MB.
+    pub fn size_limit(mut self, limit: usize) -> RegexBuilder {
+        self.size_limit = limit;
+        self
+    }
+
+    /// Sets the matching engine used for every search.
+    ///
+    /// Pinning an engine disables the search for a literal match without
+    /// any engine that's otherwise used for some regexes, and
+    /// `Engine::Backtrack` is used no matter how long the text is. A regex
+    /// that reports leftmost-longest matches (see `longest_match`) always
+    /// uses the NFA. A regex with a backreference (see `backreferences`)
+    /// always uses the backtracking engine, so it can't be compiled with
+    /// `Engine::Nfa`.
+    ///
+    /// The default is `Engine::Automatic`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate regex; use regex::{Engine, RegexBuilder};
+    /// # fn main() {
+    /// let re = RegexBuilder::new(r"\w+")
+    ///     .engine(Engine::Nfa)
+    ///     .compile()
+    ///     .unwrap();
+    /// assert_eq!(re.find("  foo "), Some((2, 5)));
+    /// # }
+    /// ```
+    pub fn engine(mut self, engine: Engine) -> RegexBuilder {
+        self.options.engine = match engine {
+            Engine::Automatic => None,
+            Engine::Nfa => Some(MatchEngine::Nfa),
+            Engine::Backtrack => Some(MatchEngine::Backtrack),
+        };
+        self
+    }
+
+    /// Enables or disables the literal optimizations.
+    ///
+    /// Normally, the literal strings that every match must begin with are
+    /// extracted from the regex and found with a fast substring search,
+    /// which skips over most of the text that can't match. This makes most
+    /// searches much faster, but not all of them: when the literals occur
+    /// often without leading to a match, the search can be slower than
+    /// without them.
+    ///
+    /// When disabled, the matching engine always runs over the text
+    /// directly, so the time a search takes only depends on the length of
+    /// the text. This may be preferable where a predictable worst case
+    /// matters more than speed on typical input.
+    ///
+    /// This is enabled by default.
+    pub fn literal_optimizations(mut self, yes: bool) -> RegexBuilder {
+        self.options.literal_optimizations = yes;
+        self
+    }
+
+    /// Sets the maximum number of literal prefixes used by the literal
+    /// optimizations.
+    ///
+    /// A regex like `foo|bar|[a-c]z` can only begin with one of several
+    /// literals (here, five of them), and all of them are searched for at
+    /// once. Raising the limit lets regexes with more alternates benefit
+    /// from this, at the cost of more memory and a slower literal search. A
+    /// regex with more prefixes than the limit doesn't use any. (Except that
+    /// a single prefix is always used.)
+    ///
+    /// The default is 30.
+    pub fn prefix_count_limit(mut self, limit: usize) -> RegexBuilder {
+        self.options.prefix_count_limit = limit;
+        self
+    }
+
+    /// Sets the length, in bytes, after which literal prefixes are cut
+    /// short.
+    ///
+    /// Longer prefixes skip more text that can't match, but they also
+    /// multiply the number of prefixes when they run into a class (e.g.,
+    /// `abc[xy]`).
+    ///
+    /// The default is 15.
+    pub fn prefix_length_limit(mut self, limit: usize) -> RegexBuilder {
+        self.options.prefix_length_limit = limit;
+        self
+    }
+
+    /// Enables or disables giving up on the literal prefixes when they
+    /// don't pay off.
+    ///
+    /// A regex keeps track of how often the literal search stops at a
+    /// position that doesn't lead to a match, and how much text it skips
+    /// each time. Once enough searches show that the literals are common
+    /// and rarely lead to a match, later searches run the matching engine
+    /// over the text directly instead. This only ever changes how fast a
+    /// search is, never its result.
+    ///
+    /// This is enabled by default.
+    pub fn adaptive_prefixes(mut self, yes: bool) -> RegexBuilder {
+        self.options.adaptive_prefixes = yes;
+        self
+    }
+
+    /// Sets how much of the text, in bytes, is looked at to decide whether
+    /// to use the literal prefixes for a search.
+    ///
+    /// Before a search uses the literal prefixes to skip ahead, it counts
+    /// how often they occur at the start of the text. If they're too common
+    /// there, skipping to each of them would be slower than not skipping at
+    /// all, so the search doesn't. A bigger sample makes the guess better
+    /// for long texts, but it costs more for every search, which adds up
+    /// when searching many short texts. With `0`, the prefixes are always
+    /// used.
+    ///
+    /// The default is 256.
+    pub fn prefix_sample_size(mut self, bytes: usize) -> RegexBuilder {
+        self.options.prefix_sample_size = bytes;
+        self
+    }
+
+    /// Sets the most memory, in bytes, the backtracking engine may use for
+    /// a search.
+    ///
+    /// The backtracking engine is usually faster than the NFA, but it needs
+    /// a bit for every instruction of the regex at every position in the
+    /// text, so it's only chosen when that fits in the limit. Raising the
+    /// limit lets it run on longer texts, and lowering it to `0` means the
+    /// NFA is always used instead. (Unless the engine is pinned with
+    /// `engine`, in which case the limit is ignored.) It also bounds the
+    /// searches of a regex with backreferences (see `backreferences`).
+    ///
+    /// The default is 4MB, which is enough for a regex of 100 instructions
+    /// on about 320KB of text.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate regex; use regex::RegexBuilder;
+    /// # fn main() {
+    /// let re = RegexBuilder::new(r"\w+\s+\w+")
+    ///     .backtrack_limit(64 * (1 << 20))
+    ///     .compile()
+    ///     .unwrap();
+    /// assert_eq!(re.find("  foo bar "), Some((2, 9)));
+    /// # }
+    /// ```
+    pub fn backtrack_limit(mut self, bytes: usize) -> RegexBuilder {
+        self.options.backtrack_limit = bytes;
+        self
+    }
+
+    /// Enables or disables reporting the leftmost-longest match, as POSIX
+    /// requires, instead of the leftmost-first match.
+    ///
+    /// With leftmost-first semantics, the alternates of `a|ab` are tried in
+    /// order, so it matches just `a` in `ab`. With leftmost-longest
+    /// semantics, it matches all of `ab`. Only the NFA engine can find such
+    /// matches, so searches may be slower, and a regex with a backreference
+    /// can't be compiled this way.
+    ///
+    /// This only changes the overall match. Capture groups are still
+    /// resolved by priority unless `posix_submatches` is enabled too.
+    ///
+    /// This is disabled by default.
+    pub fn longest_match(mut self, yes: bool) -> RegexBuilder {
+        self.options.longest_match = yes;
+        self
+    }
+
+    /// Enables or disables resolving capture groups by POSIX rules.
+    ///
+    /// Normally, when a capture group could match different parts of the
+    /// overall match, it's settled the same way as the overall match: the
+    /// first alternate (or the greedy choice) that leads to a match wins.
+    /// POSIX instead has each subexpression, from left to right, match the
+    /// longest text it can without changing the overall match, and a
+    /// repetition of a subexpression that can match nothing does so once.
+    /// This matters when replacing a dependency on the C library's
+    /// `regexec`.
+    ///
+    /// This only changes the capture groups, not which match is found, and
+    /// combines with `longest_match` to follow POSIX all the way.
+    ///
+    /// The groups are resolved after a match is found, which takes time
+    /// (and memory) that grows with the cube of the length of the match in
+    /// the worst case, so this is best kept to short matches. Searches with
+    /// scratch memory (see `Regex::scratch`) never allocate, so they
+    /// resolve the groups by priority instead. `Regex::captures_with_history`
+    /// doesn't keep a history for such a regex. Nor can a regex with a
+    /// backreference be compiled this way.
+    ///
+    /// This is disabled by default.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate regex; use regex::RegexBuilder;
+    /// # fn main() {
+    /// let re = RegexBuilder::new(r"(a|ab)(c|bcd)(d*)")
+    ///     .posix_submatches(true)
+    ///     .compile()
+    ///     .unwrap();
+    /// let caps = re.captures("abcd").unwrap();
+    /// assert_eq!(caps.at(1), Some("ab"));
+    /// assert_eq!(caps.at(2), Some("c"));
+    /// assert_eq!(caps.at(3), Some("d"));
+    /// # }
+    /// ```
+    pub fn posix_submatches(mut self, yes: bool) -> RegexBuilder {
+        self.options.posix_submatches = yes;
+        self
+    }
+
+    /// Enables or disables backreferences, i.e., `(?P=name)` and
+    /// `\k<name>`, which match the text last matched by the named group
+    /// before them.
+    ///
+    /// No engine can match a backreference in linear time, so a regex with
+    /// one is reported as `Error::Unsupported` unless this is enabled. With
+    /// it, such a regex is always run by the backtracking engine, which
+    /// then can't remember the states it already visited (whether one leads
+    /// to a match depends on what the groups captured on the way). Its
+    /// searches could take time exponential in the length of the text, so
+    /// each one is bounded by the backtrack limit instead (see
+    /// `backtrack_limit`): once it has taken as many steps as the limit
+    /// has bits, it gives up and reports no match.
+    ///
+    /// A regex with a backreference can't report leftmost-longest matches
+    /// or resolve its groups by POSIX rules.
+    ///
+    /// This is disabled by default.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate regex; use regex::{Error, Regex, RegexBuilder};
+    /// # fn main() {
+    /// let re = r"\b(?P<w>\w+) (?P=w)\b";
+    /// match Regex::new(re) {
+    ///     Err(Error::Unsupported(_)) => {}
+    ///     r => panic!("unexpected {:?}", r),
+    /// }
+    /// let re = RegexBuilder::new(re)
+    ///     .backreferences(true)
+    ///     .compile()
+    ///     .unwrap();
+    /// assert_eq!(re.find("it is the the end"), Some((6, 13)));
+    /// # }
+    /// ```
+    pub fn backreferences(mut self, yes: bool) -> RegexBuilder {
+        self.options.backreferences = yes;
+        self
+    }
+
+    /// Sets a callback that's told what every search with the regex does.
+    ///
+    /// The callback gets a `TraceEvent` for the engine picked for a search
+    /// and for every scan for the regex's literal prefixes, which tells how
+    /// much of the text the search skipped and how often it stopped. With
+    /// `trace_steps`, it also gets every instruction that the engine runs,
+    /// which gives a full account of where the time goes.
+    ///
+    /// The callback is shared by every search, from every thread, so it must
+    /// be `Send` and `Sync`.
+    ///
+    /// This is only available with the `trace` feature.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate regex; use regex::{RegexBuilder, TraceEvent};
+    /// # use std::sync::{Arc, Mutex};
+    /// # fn main() {
+    /// let events = Arc::new(Mutex::new(vec![]));
+    /// let sink = events.clone();
+    /// let re = RegexBuilder::new(r"foo\d")
+    ///     .trace(move |e| sink.lock().unwrap().push(*e))
+    ///     .compile()
+    ///     .unwrap();
+    /// assert!(re.find("xx foo1").is_some());
+    /// assert!(events.lock().unwrap().contains(&TraceEvent::PrefixScan {
+    ///     from: 0,
+    ///     to: Some(3),
+    /// }));
+    /// # }
+    /// ```
+    #[cfg(feature = "trace")]
+    pub fn trace<F>(mut self, callback: F) -> RegexBuilder
+            where F: Fn(&TraceEvent) + Send + Sync + 'static {
+        self.options.tracer.set_callback(Arc::new(callback));
+        self
+    }
+
+    /// Enables or disables reporting every instruction that the engine runs
+    /// to the callback set with `trace`, as `TraceEvent::Step`.
+    ///
+    /// There can be many steps for every position in the text, so this
+    /// slows searches down a lot.
+    ///
+    /// This is disabled by default and only available with the `trace`
+    /// feature.
+    #[cfg(feature = "trace")]
+    pub fn trace_steps(mut self, yes: bool) -> RegexBuilder {
+        self.options.tracer.set_steps(yes);
+        self
+    }
+}
@@ -0,0 +1,805 @@
+// Copyright 2016 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::fmt;
+use std::iter;
+use std::slice;
+
+use syntax;
+
+use Error;
+use compile::Compiler;
+use program::Program;
+use re::{Captures, Regex};
+
+/// Match multiple (possibly overlapping) regular expressions in a single scan.
+///
+/// A regex set corresponds to the union of two or more regular expressions.
+/// That is, a regex set will match text where at least one of its
+/// constituent regular expressions matches. A regex set as its formulated
+/// here provides a touch more power: it will also report *which* regular
+/// expressions in the set match. Indeed, this is the key difference between
+/// regex sets and a single `Regex` with many alternates, since only one
+/// alternate can match at a time.
+///
+/// All of the regexes are compiled into a single program, so the text is
+/// only scanned once no matter how many regexes are in the set. (Each one is
+/// also compiled on its own, for finding its capture groups with
+/// `captures`.)
+///
+/// # Example
+///
+/// ```rust
+/// # extern crate regex; use regex::RegexSet;
+/// # fn main() {
+/// let set = RegexSet::new(&[
+///     r"\w+",
+///     r"\d+",
+///     r"\pL+",
+///     r"foo",
+///     r"bar",
+/// ]).unwrap();
+///
+/// let matches: Vec<_> = set.matches("foobar").into_iter().collect();
+/// assert_eq!(matches, vec![0, 2, 3, 4]);
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct RegexSet {
+    patterns: Vec<String>,
+    prog: Program,
+    /// A copy of `prog` for searches whose matches must start at a given
+    /// position.
+    anchored: Program,
+    /// Each regex compiled on its own, for extracting its capture groups.
+    regexes: Vec<Regex>,
+}
+
+impl RegexSet {
+    /// Create a new regex set with the given regular expressions.
+    ///
+    /// This takes an iterator of `S`, where `S` is something that can produce
+    /// a `&str`. If any of the strings in the iterator are not valid regular
+    /// expressions, then an error is returned. So is a regex with a
+    /// backreference, which a set can't search for.
+    pub fn new<I, S>(exprs: I) -> Result<RegexSet, Error>
+            where I: IntoIterator<Item=S>, S: AsRef<str> {
+        let patterns: Vec<String> =
+            exprs.into_iter().map(|s| s.as_ref().to_owned()).collect();
+        let prog = try!(Program::new_many(None, 10 * (1 << 20), &patterns));
+        let mut regexes = Vec::with_capacity(patterns.len());
+        for re in &patterns {
+            regexes.push(try!(Regex::new(re)));
+        }
+        Ok(RegexSet {
+            patterns: patterns,
+            anchored: prog.anchored(),
+            prog: prog,
+            regexes: regexes,
+        })
+    }
+
+    /// Returns true if and only if one of the regexes in this set matches
+    /// the text given.
+    ///
+    /// This method should be preferred if you only need to test whether any
+    /// of the regexes in the set should match, but don't care about *which*
+    /// regexes matched. This is because the underlying matching engine will
+    /// quit immediately after seeing the first match instead of continuing to
+    /// find all matches.
+    pub fn is_match(&self, text: &str) -> bool {
+        self.prog.exec(&mut [], text, 0).is_some()
+    }
+
+    /// Returns the set of regular expressions that match in the given text.
+    ///
+    /// The set returned contains the index of each regular expression that
+    /// matches in the given text. The index is in correspondence with the
+    /// order of regular expressions given to `RegexSet`'s constructor.
+    pub fn matches(&self, text: &str) -> SetMatches {
+        let mut matches = vec![false; self.patterns.len()];
+        let any = self.prog.exec_many(&mut matches, text, 0);
+        SetMatches {
+            matched_any: any,
+            matches: matches,
+        }
+    }
+
+    /// Returns where the leftmost match of each regular expression in this
+    /// set starts in the given text, or `None` for one that doesn't match.
+    ///
+    /// Each position is the start of the match that `Regex::find` would
+    /// return for the corresponding regular expression on its own, but the
+    /// text is only scanned once for all of them. The positions are indexed
+    /// in the order of the regular expressions given to `RegexSet`'s
+    /// constructor.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate regex; use regex::RegexSet;
+    /// # fn main() {
+    /// let set = RegexSet::new(&[r"\d+", r"[a-z]+", r"x"]).unwrap();
+    /// assert_eq!(set.match_starts("ab 12 cd"),
+    ///            vec![Some(3), Some(0), None]);
+    /// # }
+    /// ```
+    pub fn match_starts(&self, text: &str) -> Vec<Option<usize>> {
+        let mut starts = vec![None; self.patterns.len()];
+        self.prog.exec_starts(&mut starts, text, 0);
+        starts
+    }
+
+    /// Returns the set of regular expressions that match in the given text
+    /// starting exactly at `start`.
+    ///
+    /// This is like `matches`, except that a match starting anywhere else
+    /// doesn't count. The text before `start` is still taken into account by
+    /// zero-width assertions like `\b` and `^`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate regex; use regex::RegexSet;
+    /// # fn main() {
+    /// let set = RegexSet::new(&[r"\d+", r"[a-z]+", r"\bx"]).unwrap();
+    /// let matches: Vec<_> =
+    ///     set.matches_anchored("12 ax", 3).into_iter().collect();
+    /// assert_eq!(matches, vec![1]);
+    /// # }
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// If `start` isn't a character boundary of `text`.
+    pub fn matches_anchored(&self, text: &str, start: usize) -> SetMatches {
+        assert!(text.is_char_boundary(start));
+        let mut matches = vec![false; self.patterns.len()];
+        let any = self.anchored.exec_many(&mut matches, text, start);
+        SetMatches {
+            matched_any: any,
+            matches: matches,
+        }
+    }
+
+    /// Returns the leftmost match of any regular expression in this set,
+    /// along with the index of the regular expression that matched.
+    ///
+    /// The match is returned as a triple of the index, and the start and end
+    /// byte indices of the match in `text`. When several regular expressions
+    /// match at the leftmost position, the one that comes first in the order
+    /// given to `RegexSet`'s constructor wins (even if another one would
+    /// match more text), just like the alternates of a single regex.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate regex; use regex::RegexSet;
+    /// # fn main() {
+    /// let set = RegexSet::new(&[r"if", r"[a-z]+", r"\d+"]).unwrap();
+    /// assert_eq!(set.find("42 iffy"), Some((2, 0, 2)));
+    /// assert_eq!(set.find("iffy"), Some((0, 0, 2)));
+    /// assert_eq!(set.find("--"), None);
+    /// # }
+    /// ```
+    pub fn find(&self, text: &str) -> Option<(usize, usize, usize)> {
+        self.find_at(text, 0)
+    }
+
+    /// Returns the leftmost match of any regular expression in this set
+    /// that starts at or after `start`, along with the index of the regular
+    /// expression that matched.
+    ///
+    /// This is like `find`, but the text before `start` is still taken into
+    /// account by zero-width assertions like `\b` and `^`. Calling it
+    /// repeatedly with the end of the previous match tokenizes the text.
+    ///
+    /// # Panics
+    ///
+    /// If `start` isn't a character boundary of `text`.
+    pub fn find_at(&self, text: &str, start: usize)
+                  -> Option<(usize, usize, usize)> {
+        assert!(text.is_char_boundary(start));
+        let mut caps = [None, None];
+        self.prog.exec(&mut caps, text, start).map(|i| {
+            (i, caps[0].unwrap(), caps[1].unwrap())
+        })
+    }
+
+    /// Returns the leftmost match of any regular expression in this set,
+    /// along with the index of the regular expression that matched and its
+    /// capture groups.
+    ///
+    /// The regular expression is picked like `find` does, in a single scan
+    /// of the text for all of them. Then only that one is run again, from
+    /// the start of its match, to find its capture groups.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate regex; use regex::RegexSet;
+    /// # fn main() {
+    /// let set = RegexSet::new(&[
+    ///     r"(?P<key>\w+)=(?P<value>\w+)",
+    ///     r"(?P<flag>--\w+)",
+    /// ]).unwrap();
+    /// let (i, caps) = set.captures("run --fast n=3").unwrap();
+    /// assert_eq!(i, 1);
+    /// assert_eq!(caps.name("flag"), Some("--fast"));
+    /// # }
+    /// ```
+    pub fn captures<'t>(&self, text: &'t str)
+                       -> Option<(usize, Captures<'t>)> {
+        self.find(text).map(|(i, s, _)| {
+            // The regex matches at `s` on its own too, and a search from
+            // there finds the same match.
+            (i, self.regexes[i].captures_at(text, s).unwrap())
+        })
+    }
+
+    /// Returns the matches of the regular expressions in this set that start
+    /// at the leftmost position where any of them matches, at or after
+    /// `start`, resolved according to `policy`.
+    ///
+    /// Each match is a triple of the index of the regular expression, and
+    /// the start and end byte indices of the match in `text`. (Each regular
+    /// expression's match is the one it would find on its own.) Unless
+    /// `policy` is `MatchPolicy::All`, at most one match is returned.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate regex; use regex::{MatchPolicy, RegexSet};
+    /// # fn main() {
+    /// let set = RegexSet::new(&[r"if", r"[a-z]+", r"i\w"]).unwrap();
+    /// let find = |policy| set.find_at_with_policy("+ iffy", 0, policy);
+    /// assert_eq!(find(MatchPolicy::FirstDeclared), vec![(0, 2, 4)]);
+    /// assert_eq!(find(MatchPolicy::Longest), vec![(1, 2, 6)]);
+    /// assert_eq!(find(MatchPolicy::All),
+    ///            vec![(0, 2, 4), (1, 2, 6), (2, 2, 4)]);
+    /// # }
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// If `start` isn't a character boundary of `text`.
+    pub fn find_at_with_policy(
+        &self,
+        text: &str,
+        start: usize,
+        policy: MatchPolicy,
+    ) -> Vec<(usize, usize, usize)> {
+        let first = match self.find_at(text, start) {
+            None => return vec![],
+            Some(first) => first,
+        };
+        if policy == MatchPolicy::FirstDeclared {
+            return vec![first];
+        }
+        let s = first.1;
+        let starts = self.anchored.pattern_starts();
+        let mut found = vec![];
+        for i in self.matches_anchored(text, s) {
+            let mut caps = [None, None];
+            if self.anchored.exec_pattern(starts[i], &mut caps, text, s) {
+                found.push((i, s, caps[1].unwrap()));
+            }
+        }
+        if policy == MatchPolicy::Longest {
+            // Ties go to the regular expression declared first.
+            let mut longest = found[0];
+            for &m in &found[1..] {
+                if m.2 > longest.2 {
+                    longest = m;
+                }
+            }
+            return vec![longest];
+        }
+        found
+    }
+
+    /// Returns the number of successive non-overlapping matches in `text` of
+    /// each regular expression in this set.
+    ///
+    /// Each count is the same as what `Regex::count_matches` returns for the
+    /// corresponding regular expression on its own. The counts are indexed in
+    /// the order of the regular expressions given to `RegexSet`'s
+    /// constructor.
+    ///
+    /// The text is searched separately for each regular expression in the
+    /// set, since the matches of one don't stop those of another.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate regex; use regex::RegexSet;
+    /// # fn main() {
+    /// let set = RegexSet::new(&[r"ERROR", r"WARN", r"\d+"]).unwrap();
+    /// let log = "ERROR 1\nWARN 22\nERROR 333\n";
+    /// assert_eq!(set.count_matches(log), vec![2, 1, 3]);
+    /// # }
+    /// ```
+    pub fn count_matches(&self, text: &str) -> Vec<usize> {
+        self.prog.pattern_starts().into_iter()
+            .map(|pc| self.prog.count_all(pc, text, 0))
+            .collect()
+    }
+
+    /// Returns the total number of regular expressions in this set.
+    pub fn len(&self) -> usize {
+        self.patterns.len()
+    }
+
+    /// Returns true if and only if this set contains no regular expressions.
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /// Returns the patterns that this set will match on.
+    pub fn patterns(&self) -> &[String] {
+        &self.patterns
+    }
+}
+
+impl fmt::Debug for RegexSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "RegexSet({:?})", self.patterns)
+    }
+}
+
+/// The flags a regular expression in a `RegexSetBuilder` is compiled with.
+///
+/// Setting a flag is the same as starting the regular expression with the
+/// corresponding inline flag (e.g., `(?i)` for `case_insensitive`), which
+/// the regular expression can still clear. Every flag is cleared by
+/// default.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PatternFlags {
+    case_insensitive: bool,
+    multi_line: bool,
+    dot_matches_new_line: bool,
+    swap_greed: bool,
+    ignore_whitespace: bool,
+}
+
+impl PatternFlags {
+    /// Creates a new set of flags with every flag cleared.
+    pub fn new() -> PatternFlags {
+        PatternFlags::default()
+    }
+
+    /// Sets the case insensitive flag (`i`).
+    pub fn case_insensitive(mut self, yes: bool) -> PatternFlags {
+        self.case_insensitive = yes;
+        self
+    }
+
+    /// Sets the multi-line flag (`m`), which makes `^` and `$` match at the
+    /// beginning and end of every line.
+    pub fn multi_line(mut self, yes: bool) -> PatternFlags {
+        self.multi_line = yes;
+        self
+    }
+
+    /// Sets the flag that lets `.` match `\n` (`s`).
+    pub fn dot_matches_new_line(mut self, yes: bool) -> PatternFlags {
+        self.dot_matches_new_line = yes;
+        self
+    }
+
+    /// Sets the flag that swaps the meaning of `x*` and `x*?` (`U`).
+    pub fn swap_greed(mut self, yes: bool) -> PatternFlags {
+        self.swap_greed = yes;
+        self
+    }
+
+    /// Sets the flag that ignores whitespace and allows comments (`x`).
+    pub fn ignore_whitespace(mut self, yes: bool) -> PatternFlags {
+        self.ignore_whitespace = yes;
+        self
+    }
+
+    fn parse(&self, re: &str) -> Result<syntax::Expr, Error> {
+        syntax::ExprBuilder::new()
+            .case_insensitive(self.case_insensitive)
+            .multi_line(self.multi_line)
+            .dot_matches_new_line(self.dot_matches_new_line)
+            .swap_greed(self.swap_greed)
+            .ignore_whitespace(self.ignore_whitespace)
+            .parse(re)
+            .map_err(Error::from)
+    }
+}
+
+/// A builder for a regex set whose regular expressions each have their own
+/// flags.
+///
+/// This saves rewriting the regular expressions (e.g., by prepending `(?i)`
+/// to some of them) when they come from a source that stores their flags
+/// separately.
+///
+/// # Example
+///
+/// ```rust
+/// # extern crate regex; use regex::{PatternFlags, RegexSetBuilder};
+/// # fn main() {
+/// let set = RegexSetBuilder::new()
+///     .add("error")
+///     .add_with_flags("^warn", PatternFlags::new().case_insensitive(true)
+///                                                 .multi_line(true))
+///     .compile()
+///     .unwrap();
+/// let ms: Vec<_> = set.matches("ok\nWARN: Error").into_iter().collect();
+/// assert_eq!(ms, vec![1]);
+/// assert_eq!(set.patterns(), &["error", "^warn"]);
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct RegexSetBuilder {
+    patterns: Vec<(String, PatternFlags)>,
+    size_limit: usize,
+}
+
+impl RegexSetBuilder {
+    /// Creates a new builder without any regular expressions.
+    pub fn new() -> RegexSetBuilder {
+        RegexSetBuilder {
+            patterns: vec![],
+            size_limit: 10 * (1 << 20),
+        }
+    }
+
+    /// Adds a regular expression with every flag cleared.
+    pub fn add(self, pattern: &str) -> RegexSetBuilder {
+        self.add_with_flags(pattern, PatternFlags::new())
+    }
+
+    /// Adds a regular expression with the given flags.
+    pub fn add_with_flags(
+        mut self,
+        pattern: &str,
+        flags: PatternFlags,
+    ) -> RegexSetBuilder {
+        self.patterns.push((pattern.to_owned(), flags));
+        self
+    }
+
+    /// Sets the size limit of the compiled set. See
+    /// `Regex::with_size_limit`.
+    ///
+    /// The default is 10MB.
+    pub fn size_limit(mut self, limit: usize) -> RegexSetBuilder {
+        self.size_limit = limit;
+        self
+    }
+
+    /// Compiles the regular expressions added so far into a set.
+    ///
+    /// If any of them is invalid or has a backreference, or if the set
+    /// exceeds the size limit, then an error is returned.
+    pub fn compile(&self) -> Result<RegexSet, Error> {
+        let mut patterns = Vec::with_capacity(self.patterns.len());
+        let mut exprs = Vec::with_capacity(self.patterns.len());
+        for &(ref re, ref flags) in &self.patterns {
+            exprs.push(try!(flags.parse(re)));
+            patterns.push(re.clone());
+        }
+        let mut compiler = Compiler::new(self.size_limit);
+        let prog = try!(Program::with_exprs(
+            &mut compiler, &Default::default(), &patterns, &exprs));
+        let mut regexes = Vec::with_capacity(patterns.len());
+        for (re, expr) in patterns.iter().zip(&exprs) {
+            regexes.push(Regex::Dynamic(try!(Program::with_exprs(
+                &mut compiler,
+                &Default::default(),
+                &[re],
+                &[expr.clone()]))));
+        }
+        Ok(RegexSet {
+            patterns: patterns,
+            anchored: prog.anchored(),
+            prog: prog,
+            regexes: regexes,
+        })
+    }
+}
+
+/// How to resolve several regexes in a set matching at the same position.
+///
+/// See `RegexSet::find_at_with_policy`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MatchPolicy {
+    /// The regex that comes first in the set wins, like the alternates of a
+    /// single regex. (This is what `RegexSet::find` does.)
+    FirstDeclared,
+    /// The regex with the longest match wins. Among matches of the same
+    /// length, the regex that comes first in the set wins.
+    Longest,
+    /// Every regex that matches is reported.
+    All,
+}
+
+/// A set of matches returned by a regex set.
+#[derive(Clone, Debug)]
+pub struct SetMatches {
+    matched_any: bool,
+    matches: Vec<bool>,
+}
+
+impl SetMatches {
+    /// Whether this set contains any matches.
+    pub fn matched_any(&self) -> bool {
+        self.matched_any
+    }
+
+    /// Whether the regex at the given index matched.
+    ///
+    /// The index for a regex is determined by its insertion order upon the
+    /// initial construction of a `RegexSet`, starting at `0`.
+    ///
+    /// # Panics
+    ///
+    /// If `regex_index` is greater than or equal to `self.len()`.
+    pub fn matched(&self, regex_index: usize) -> bool {
+        self.matches[regex_index]
+    }
+
+    /// The total number of regexes in the set that created these matches.
+    pub fn len(&self) -> usize {
+        self.matches.len()
+    }
+
+    /// Returns true if and only if the set that created these matches has
+    /// no regexes.
+    pub fn is_empty(&self) -> bool {
+        self.matches.is_empty()
+    }
+
+    /// Returns an iterator over indexes in the regex that matched.
+    pub fn iter(&self) -> SetMatchesIter {
+        SetMatchesIter((&*self.matches).into_iter().enumerate())
+    }
+}
+
+impl IntoIterator for SetMatches {
+    type IntoIter = SetMatchesIntoIter;
+    type Item = usize;
+
+    fn into_iter(self) -> Self::IntoIter {
+        SetMatchesIntoIter(self.matches.into_iter().enumerate())
+    }
+}
+
+impl<'a> IntoIterator for &'a SetMatches {
+    type IntoIter = SetMatchesIter<'a>;
+    type Item = usize;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// An owned iterator over the set of matches from a regex set.
+pub struct SetMatchesIntoIter(iter::Enumerate<::std::vec::IntoIter<bool>>);
+
+impl Iterator for SetMatchesIntoIter {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        loop {
+            match self.0.next() {
+                None => return None,
+                Some((_, false)) => {}
+                Some((i, true)) => return Some(i),
+            }
+        }
+    }
+}
+
+/// A borrowed iterator over the set of matches from a regex set.
+///
+/// The lifetime `'a` refers to the lifetime of a `SetMatches` value.
+pub struct SetMatchesIter<'a>(iter::Enumerate<slice::Iter<'a, bool>>);
+
+impl<'a> Iterator for SetMatchesIter<'a> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        loop {
+            match self.0.next() {
+                None => return None,
+                Some((_, &false)) => {}
+                Some((i, &true)) => return Some(i),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use Regex;
+    use super::{PatternFlags, RegexSet, RegexSetBuilder};
+
+    #[test]
+    fn set_matches() {
+        let set = RegexSet::new(&["[a-z]+@[a-z]+", "[0-9]+", "^x", "y$"])
+                          .unwrap();
+        assert_eq!(set.len(), 4);
+        let ms: Vec<_> = set.matches("foo@bar 42").into_iter().collect();
+        assert_eq!(ms, vec![0, 1]);
+        let ms: Vec<_> = set.matches("x y").into_iter().collect();
+        assert_eq!(ms, vec![2, 3]);
+        let ms = set.matches("nothing here");
+        assert!(!ms.matched_any());
+        assert_eq!(ms.iter().count(), 0);
+    }
+
+    #[test]
+    fn set_overlapping() {
+        let set = RegexSet::new(&["a", "ab", "abc", "bc"]).unwrap();
+        let ms = set.matches("abc");
+        assert!(ms.matched_any());
+        assert!(ms.matched(0) && ms.matched(1) && ms.matched(2));
+        assert!(ms.matched(3));
+        assert!(set.is_match("xbc"));
+        assert!(!set.is_match("xyz"));
+    }
+
+    #[test]
+    fn set_count_matches() {
+        let patterns = &["a", "ab|b", "", r"(?m)^\w", "x*$", "z"];
+        let set = RegexSet::new(patterns).unwrap();
+        for text in &["", "abab\nba", "aaa xx\nb", "☃a☃"] {
+            let expected: Vec<usize> = patterns.iter().map(|re| {
+                Regex::new(re).unwrap().find_iter(text).count()
+            }).collect();
+            assert_eq!(set.count_matches(text), expected);
+        }
+        assert!(RegexSet::new(Vec::<&str>::new()).unwrap()
+                         .count_matches("a").is_empty());
+    }
+
+    #[test]
+    fn set_match_starts() {
+        let patterns = &["a", "ab+c|b", "", r"(?m)^\w", "x*$", "z",
+                         r"\bb", "c.*d", "(?:a|b)*c"];
+        let set = RegexSet::new(patterns).unwrap();
+        for text in &["", "abbbc", "xabab\nba", "zzc cd", "☃a☃bd"] {
+            let expected: Vec<Option<usize>> = patterns.iter().map(|re| {
+                Regex::new(re).unwrap().find(text).map(|(s, _)| s)
+            }).collect();
+            assert_eq!(set.match_starts(text), expected);
+        }
+        assert!(RegexSet::new(Vec::<&str>::new()).unwrap()
+                         .match_starts("a").is_empty());
+    }
+
+    #[test]
+    fn set_captures() {
+        let patterns = &["a", r"(\w)(\d)?", r"(?P<n>\d+)(x)?", "(b)|c"];
+        let set = RegexSet::new(patterns).unwrap();
+        for text in &["", "-9x", "b", "ca1", "☃ab"] {
+            let (i, s, e) = match set.find(text) {
+                None => {
+                    assert!(set.captures(text).is_none());
+                    continue;
+                }
+                Some(m) => m,
+            };
+            let (j, caps) = set.captures(text).unwrap();
+            assert_eq!(i, j);
+            assert_eq!(caps.pos(0), Some((s, e)));
+            let re = Regex::new(patterns[i]).unwrap();
+            let expected = re.captures_at(text, s).unwrap();
+            assert_eq!(caps.iter_pos().collect::<Vec<_>>(),
+                       expected.iter_pos().collect::<Vec<_>>());
+        }
+        let set = RegexSetBuilder::new()
+            .add("x")
+            .add_with_flags("(?P<a>A)", PatternFlags::new()
+                                            .case_insensitive(true))
+            .compile()
+            .unwrap();
+        let (i, caps) = set.captures("-a").unwrap();
+        assert_eq!((i, caps.name("a")), (1, Some("a")));
+        assert!(set.captures("-").is_none());
+    }
+
+    #[test]
+    fn set_matches_anchored() {
+        let set = RegexSet::new(&["a+b", "b", r"\bb", "^", "$", "(?m)^c"])
+                          .unwrap();
+        let ms = |start| -> Vec<usize> {
+            set.matches_anchored("aab b\nc", start).into_iter().collect()
+        };
+        assert_eq!(ms(0), vec![0, 3]);
+        assert_eq!(ms(1), vec![0]);
+        assert_eq!(ms(2), vec![1]);
+        assert_eq!(ms(3), vec![]);
+        assert_eq!(ms(4), vec![1, 2]);
+        assert_eq!(ms(6), vec![5]);
+        assert_eq!(ms(7), vec![4]);
+    }
+
+    #[test]
+    fn set_find() {
+        let set = RegexSet::new(&["ab", "a", "abc", r"\bc", "c+"]).unwrap();
+        assert_eq!(set.find("xabc"), Some((0, 1, 3)));
+        assert_eq!(set.find("xac"), Some((1, 1, 2)));
+        assert_eq!(set.find("c"), Some((3, 0, 1)));
+        assert_eq!(set.find_at("accc", 1), Some((4, 1, 4)));
+        assert_eq!(set.find_at("ab", 2), None);
+        assert_eq!(set.find("xyz"), None);
+        // A set of plain literals must not be handed to the literal search,
+        // which can't tell which regex matched.
+        let set = RegexSet::new(&["foo", "bar"]).unwrap();
+        assert_eq!(set.find("a bar foo"), Some((1, 2, 5)));
+        assert_eq!(RegexSet::new(Vec::<&str>::new()).unwrap().find(""), None);
+    }
+
+    #[test]
+    fn set_find_with_policy() {
+        use super::MatchPolicy::*;
+
+        let set = RegexSet::new(&["a", "b+", "ab", "", "bb"]).unwrap();
+        let find = |start, policy| set.find_at_with_policy("xabbb", start,
+                                                           policy);
+        assert_eq!(find(0, FirstDeclared), vec![(3, 0, 0)]);
+        assert_eq!(find(1, FirstDeclared), vec![(0, 1, 2)]);
+        assert_eq!(find(1, Longest), vec![(2, 1, 3)]);
+        assert_eq!(find(1, All), vec![(0, 1, 2), (2, 1, 3), (3, 1, 1)]);
+        assert_eq!(find(2, Longest), vec![(1, 2, 5)]);
+        assert_eq!(find(2, All),
+                   vec![(1, 2, 5), (3, 2, 2), (4, 2, 4)]);
+        assert_eq!(find(5, All), vec![(3, 5, 5)]);
+        let set = RegexSet::new(&["aa", "a{2}", "a"]).unwrap();
+        assert_eq!(set.find_at_with_policy("aa", 0, Longest),
+                   vec![(0, 0, 2)]);
+        assert!(set.find_at_with_policy("b", 0, All).is_empty());
+    }
+
+    #[test]
+    fn set_builder_flags() {
+        let f = PatternFlags::new();
+        let set = RegexSetBuilder::new()
+            .add("a.b")
+            .add_with_flags("a.b", f.dot_matches_new_line(true))
+            .add_with_flags("^B$", f.case_insensitive(true).multi_line(true))
+            .add_with_flags("c+?", f.swap_greed(true))
+            .add_with_flags("d e # comment", f.ignore_whitespace(true))
+            .add_with_flags("(?-i)f", f.case_insensitive(true))
+            .compile()
+            .unwrap();
+        let ms = |text| -> Vec<usize> {
+            set.matches(text).into_iter().collect()
+        };
+        assert_eq!(ms("a\nb"), vec![1, 2]);
+        assert_eq!(ms("axb"), vec![0, 1]);
+        assert_eq!(ms("de F"), vec![4]);
+        assert_eq!(set.find("ccc"), Some((3, 0, 3)));
+        assert_eq!(set.patterns()[4], "d e # comment");
+        assert!(RegexSetBuilder::new().add("a").add("(").compile().is_err());
+        assert!(RegexSetBuilder::new().add("a{1000}").size_limit(100)
+                                      .compile().is_err());
+    }
+
+    #[test]
+    fn set_empty() {
+        let set = RegexSet::new(Vec::<&str>::new()).unwrap();
+        assert!(set.is_empty());
+        assert!(!set.is_match(""));
+        assert!(!set.matches("abc").matched_any());
+    }
+
+    #[test]
+    fn set_invalid() {
+        assert!(RegexSet::new(&["a", "("]).is_err());
+    }
+}
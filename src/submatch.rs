@@ -0,0 +1,361 @@
+// Copyright 2016 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Resolution of capture groups by POSIX rules.
+//!
+//! The matching engines resolve capture groups by priority: at every choice,
+//! the first alternate (or the greedy choice) that leads to a match wins.
+//! POSIX instead asks for each subexpression, from left to right, to match
+//! the longest text it can while the whole regex still matches the same
+//! text. e.g., `(a|ab)(c|bcd)(d*)` matches `abcd` with `ab`, `c` and `d`
+//! rather than `a`, `bcd` and the empty string.
+//!
+//! None of the engines can track this, so the groups are resolved after the
+//! fact, once the overall match is known. This works directly on the syntax
+//! of the regex, by first finding out which subexpressions can match which
+//! parts of the match (memoized, since the same questions come up over and
+//! over) and then dividing up the match greedily from left to right. The
+//! time this takes grows with the cube of the length of the match in the
+//! worst case, so it's best kept to short matches.
+
+use std::collections::HashMap;
+
+use syntax::{CharClass, ClassRange, Expr, Repeater};
+
+use char::Char;
+
+/// Sets the capture groups in `caps` by POSIX rules for the overall match
+/// of `expr` in `text` that `caps` already holds in its first two slots.
+pub fn resolve(expr: &Expr, text: &str, caps: &mut [Option<usize>]) {
+    let (s, e) = match (caps[0], caps[1]) {
+        (Some(s), Some(e)) => (s, e),
+        _ => return,
+    };
+    for slot in caps[2..].iter_mut() {
+        *slot = None;
+    }
+    let mut r = Resolver {
+        chars: text[s..e].chars().collect(),
+        pos: text[s..e].char_indices().map(|(i, _)| s + i).collect(),
+        before: text[..s].chars().rev().next().into(),
+        after: text[e..].chars().next().into(),
+        memo: HashMap::new(),
+        reps: HashMap::new(),
+    };
+    r.pos.push(e);
+    let n = r.chars.len();
+    if r.matches(expr, 0, n) {
+        r.assign(expr, 0, n, caps);
+    }
+}
+
+/// The state of resolving the capture groups of one match.
+///
+/// Positions are indices into `chars`, from `0` to `chars.len()`.
+struct Resolver {
+    /// The characters of the match.
+    chars: Vec<char>,
+    /// The byte offset in the text of every position.
+    pos: Vec<usize>,
+    /// The character right before the match.
+    before: Char,
+    /// The character right after the match.
+    after: Char,
+    /// Whether an expression (or a sequence of them) matches between two
+    /// positions, keyed by its address, its kind and the positions.
+    memo: HashMap<(usize, usize, usize, usize), bool>,
+    /// Whether a number of repetitions of an expression, between a minimum
+    /// and an optional maximum, matches between two positions.
+    reps: HashMap<(usize, u32, Option<u32>, usize, usize), bool>,
+}
+
+/// Keys of the memo for a single expression.
+const KEY_EXPR: usize = 0;
+
+impl Resolver {
+    /// Returns true if `e` can match exactly the text from `i` to `j`.
+    fn matches(&mut self, e: &Expr, i: usize, j: usize) -> bool {
+        use syntax::Expr::*;
+
+        match *e {
+            Empty => i == j,
+            Literal { ref chars, casei } => {
+                j - i == chars.len()
+                && chars.iter().zip(&self.chars[i..j]).all(|(&c1, &c2)| {
+                    c1 == c2 || (casei && fold(c1).matches(c2))
+                })
+            }
+            AnyChar => j == i + 1,
+            AnyCharNoNL => j == i + 1 && self.chars[i] != '\n',
+            Class(ref cls) => j == i + 1 && cls.matches(self.chars[i]),
+            StartLine | EndLine | StartText | EndText
+            | WordBoundary | NotWordBoundary => {
+                i == j && self.look(e, i)
+            }
+            Group { ref e, .. } => self.matches(e, i, j),
+            Backref { .. } => {
+                unreachable!("POSIX submatches can't be resolved with \
+                              backreferences")
+            }
+            Repeat { .. } | Concat(_) | Alternate(_) => {
+                let key = (e as *const Expr as usize, KEY_EXPR, i, j);
+                if let Some(&yes) = self.memo.get(&key) {
+                    return yes;
+                }
+                let yes = match *e {
+                    Repeat { ref e, r, .. } => {
+                        let (min, max) = bounds(r);
+                        self.repeats(e, min, max, i, j)
+                    }
+                    Concat(ref es) => self.concat(es, i, j),
+                    Alternate(ref es) => {
+                        es.iter().any(|e| self.matches(e, i, j))
+                    }
+                    _ => unreachable!(),
+                };
+                self.memo.insert(key, yes);
+                yes
+            }
+        }
+    }
+
+    /// Returns true if the sequence `es` can match exactly the text from `i`
+    /// to `j`.
+    fn concat(&mut self, es: &[Expr], i: usize, j: usize) -> bool {
+        if es.is_empty() {
+            return i == j;
+        }
+        // The kind of a sequence is its length, which can't be confused
+        // with a single expression since it's never empty.
+        let key = (es.as_ptr() as usize, es.len(), i, j);
+        if let Some(&yes) = self.memo.get(&key) {
+            return yes;
+        }
+        let yes = (i..j + 1).any(|k| {
+            self.matches(&es[0], i, k) && self.concat(&es[1..], k, j)
+        });
+        self.memo.insert(key, yes);
+        yes
+    }
+
+    /// Returns true if at least `min` and at most `max` repetitions of `e`
+    /// can match exactly the text from `i` to `j`.
+    fn repeats(
+        &mut self,
+        e: &Expr,
+        min: u32,
+        max: Option<u32>,
+        i: usize,
+        j: usize,
+    ) -> bool {
+        if i == j {
+            // Any number of repetitions can match nothing.
+            return min == 0 || self.matches(e, i, i);
+        }
+        if max == Some(0) {
+            return false;
+        }
+        let key = (e as *const Expr as usize, min, max, i, j);
+        if let Some(&yes) = self.reps.get(&key) {
+            return yes;
+        }
+        let (min, max) = (min.saturating_sub(1), max.map(|n| n - 1));
+        // There's never a reason for an iteration to match nothing when
+        // there's text left, so only look at those that make progress.
+        let yes = (i + 1..j + 1).any(|k| {
+            self.matches(e, i, k) && self.repeats(e, min, max, k, j)
+        });
+        self.reps.insert(key, yes);
+        yes
+    }
+
+    /// Sets the capture groups in `e`, given that it matches the text from
+    /// `i` to `j`.
+    fn assign(
+        &mut self,
+        e: &Expr,
+        i: usize,
+        j: usize,
+        caps: &mut [Option<usize>],
+    ) {
+        use syntax::Expr::*;
+
+        match *e {
+            Group { ref e, i: Some(g), .. } => {
+                if 2 * g + 1 < caps.len() {
+                    caps[2 * g] = Some(self.pos[i]);
+                    caps[2 * g + 1] = Some(self.pos[j]);
+                }
+                self.assign(e, i, j, caps);
+            }
+            Group { ref e, i: None, .. } => self.assign(e, i, j, caps),
+            Concat(ref es) => {
+                let mut i = i;
+                for (n, e) in es.iter().enumerate() {
+                    // Each subexpression gets the longest text that leaves
+                    // enough for the rest.
+                    let rest = &es[n + 1..];
+                    let k = (i..j + 1).rev().find(|&k| {
+                        self.matches(e, i, k) && self.concat(rest, k, j)
+                    }).unwrap();
+                    self.assign(e, i, k, caps);
+                    i = k;
+                }
+            }
+            Alternate(ref es) => {
+                // POSIX leaves the choice between alternates that match the
+                // same text open, so take the first one like everyone else.
+                let e = es.iter().find(|e| self.matches(e, i, j)).unwrap();
+                self.assign(e, i, j, caps);
+            }
+            Repeat { ref e, r, .. } => {
+                let (min, mut max) = bounds(r);
+                let (mut i, mut count) = (i, 0);
+                while i < j {
+                    // Likewise, each iteration gets the longest text that
+                    // leaves enough for the rest.
+                    let rest_min = min.saturating_sub(count + 1);
+                    let rest_max = max.map(|n| n - 1);
+                    let k = (i + 1..j + 1).rev().find(|&k| {
+                        self.matches(e, i, k)
+                        && self.repeats(e, rest_min, rest_max, k, j)
+                    }).unwrap();
+                    // Only the last iteration is reported, even for groups
+                    // that don't take part in it.
+                    clear(e, caps);
+                    self.assign(e, i, k, caps);
+                    i = k;
+                    count += 1;
+                    max = rest_max;
+                }
+                // A subexpression that can match nothing does so once when
+                // the repetition is empty, e.g., `(a*)*` matching nothing
+                // sets the group.
+                if count == 0 && self.matches(e, i, i) {
+                    self.assign(e, i, i, caps);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Returns true if the zero-width assertion `e` holds at `i`.
+    fn look(&self, e: &Expr, i: usize) -> bool {
+        use syntax::Expr::*;
+
+        let c1 = if i == 0 { self.before } else { self.chars[i - 1].into() };
+        let c2 = if i == self.chars.len() {
+            self.after
+        } else {
+            self.chars[i].into()
+        };
+        match *e {
+            StartLine => c1.is_none() || c1 == '\n',
+            EndLine => c2.is_none() || c2 == '\n',
+            StartText => c1.is_none(),
+            EndText => c2.is_none(),
+            WordBoundary => c1.is_word_char() != c2.is_word_char(),
+            NotWordBoundary => c1.is_word_char() == c2.is_word_char(),
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// Unsets every capture group in `e`.
+fn clear(e: &Expr, caps: &mut [Option<usize>]) {
+    use syntax::Expr::*;
+
+    match *e {
+        Group { ref e, i, .. } => {
+            if let Some(g) = i {
+                if 2 * g + 1 < caps.len() {
+                    caps[2 * g] = None;
+                    caps[2 * g + 1] = None;
+                }
+            }
+            clear(e, caps);
+        }
+        Repeat { ref e, .. } => clear(e, caps),
+        Concat(ref es) | Alternate(ref es) => {
+            for e in es {
+                clear(e, caps);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Returns the minimum and maximum (if any) number of repetitions.
+fn bounds(r: Repeater) -> (u32, Option<u32>) {
+    match r {
+        Repeater::ZeroOrOne => (0, Some(1)),
+        Repeater::ZeroOrMore => (0, None),
+        Repeater::OneOrMore => (1, None),
+        Repeater::Range { min, max } => (min, max),
+    }
+}
+
+/// Returns the class of characters that match `c` case insensitively.
+fn fold(c: char) -> CharClass {
+    CharClass::new(vec![ClassRange { start: c, end: c }]).case_fold()
+}
+
+#[cfg(test)]
+mod tests {
+    use syntax::Expr;
+    use super::resolve;
+
+    fn submatches(re: &str, text: &str, s: usize, e: usize)
+                 -> Vec<Option<(usize, usize)>> {
+        let expr = Expr::parse(re).unwrap();
+        let mut caps = vec![None; 8];
+        caps[0] = Some(s);
+        caps[1] = Some(e);
+        resolve(&expr, text, &mut caps);
+        caps.chunks(2).skip(1)
+            .map(|c| match (c[0], c[1]) {
+                (Some(s), Some(e)) => Some((s, e)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn longest_first() {
+        assert_eq!(submatches("(a|ab)(c|bcd)(d*)", "abcd", 0, 4),
+                   vec![Some((0, 2)), Some((2, 3)), Some((3, 4))]);
+        assert_eq!(submatches("(a*)(a*)", "aa", 0, 2),
+                   vec![Some((0, 2)), Some((2, 2)), None]);
+    }
+
+    #[test]
+    fn last_iteration() {
+        assert_eq!(submatches("(a|ab|c|bcd)*", "abcd", 0, 4),
+                   vec![Some((1, 4)), None, None]);
+        assert_eq!(submatches("(?:(a)|b)*", "ab", 0, 2),
+                   vec![None, None, None]);
+    }
+
+    #[test]
+    fn empty_repetition() {
+        assert_eq!(submatches("(a*)*", "b", 0, 0),
+                   vec![Some((0, 0)), None, None]);
+        assert_eq!(submatches("(a)*", "b", 0, 0),
+                   vec![None, None, None]);
+    }
+
+    #[test]
+    fn assertions() {
+        assert_eq!(submatches(r"(\bx*)(x*)", "xx", 0, 2),
+                   vec![Some((0, 2)), Some((2, 2)), None]);
+        assert_eq!(submatches(r"(?i)(a+)(A*)", "aA", 0, 2),
+                   vec![Some((0, 2)), Some((2, 2)), None]);
+    }
+}
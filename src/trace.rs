@@ -0,0 +1,132 @@
+// Copyright 2016 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Hooks for following what a search does, e.g., to find out why a regex is
+//! much slower on some text than on another.
+//!
+//! The hooks only exist with the `trace` feature. Without it, a `Tracer`
+//! has nothing in it and every call to it compiles to nothing.
+
+use std::fmt;
+#[cfg(feature = "trace")]
+use std::sync::Arc;
+
+use inst::InstIdx;
+use re_builder::Engine;
+
+/// Something that happened during a search with a regex, as reported to the
+/// callback set with `RegexBuilder::trace`.
+///
+/// Positions are byte offsets into the text searched.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TraceEvent {
+    /// A search starting at `start` is run with the given engine.
+    Search {
+        /// The engine, which is never `Engine::Automatic`.
+        engine: Engine,
+        /// Where the search starts.
+        start: usize,
+    },
+    /// A search starting at `start` is answered by looking for the regex's
+    /// literal prefixes alone, without running any engine. This happens when
+    /// the regex is made of nothing but literals.
+    LiteralSearch {
+        /// Where the search starts.
+        start: usize,
+    },
+    /// The engine looked for the next occurrence of the regex's literal
+    /// prefixes to skip ahead to.
+    PrefixScan {
+        /// Where the scan started.
+        from: usize,
+        /// Where the engine resumes, or `None` if there are no more
+        /// occurrences, which ends the search.
+        to: Option<usize>,
+    },
+    /// The engine ran an instruction of the regex's program at a position.
+    ///
+    /// These are only reported if `RegexBuilder::trace_steps` is enabled,
+    /// since there can be many of them for every position in the text.
+    Step {
+        /// The index of the instruction in the program.
+        pc: usize,
+        /// The position of the instruction's thread in the text.
+        pos: usize,
+    },
+}
+
+/// The callback for the events of a search, if any.
+#[derive(Clone, Default)]
+pub struct Tracer {
+    #[cfg(feature = "trace")]
+    callback: Option<Arc<Fn(&TraceEvent) + Send + Sync>>,
+    #[cfg(feature = "trace")]
+    steps: bool,
+}
+
+impl Tracer {
+    /// Sets the callback that events are reported to.
+    #[cfg(feature = "trace")]
+    pub fn set_callback(
+        &mut self,
+        callback: Arc<Fn(&TraceEvent) + Send + Sync>,
+    ) {
+        self.callback = Some(callback);
+    }
+
+    /// Sets whether every step is reported.
+    #[cfg(feature = "trace")]
+    pub fn set_steps(&mut self, yes: bool) {
+        self.steps = yes;
+    }
+
+    /// Reports the event returned by `event`, which is only called if there
+    /// is a callback.
+    #[cfg(feature = "trace")]
+    #[inline]
+    pub fn emit<F: FnOnce() -> TraceEvent>(&self, event: F) {
+        if let Some(ref callback) = self.callback {
+            callback(&event());
+        }
+    }
+
+    #[cfg(not(feature = "trace"))]
+    #[inline(always)]
+    pub fn emit<F: FnOnce() -> TraceEvent>(&self, _: F) {}
+
+    /// Reports that the instruction at `pc` ran at `pos`, if steps are
+    /// reported.
+    #[cfg(feature = "trace")]
+    #[inline]
+    pub fn step(&self, pc: InstIdx, pos: usize) {
+        if self.steps {
+            self.emit(|| TraceEvent::Step { pc: pc as usize, pos: pos });
+        }
+    }
+
+    #[cfg(not(feature = "trace"))]
+    #[inline(always)]
+    pub fn step(&self, _: InstIdx, _: usize) {}
+}
+
+impl fmt::Debug for Tracer {
+    #[cfg(feature = "trace")]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Tracer")
+         .field("callback", &self.callback.is_some())
+         .field("steps", &self.steps)
+         .finish()
+    }
+
+    #[cfg(not(feature = "trace"))]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Tracer")
+    }
+}